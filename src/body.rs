@@ -0,0 +1,13 @@
+use request::Request;
+
+
+/// Receives a request body piece by piece as it arrives on the event loop, instead of buffering it whole like `Request::get_post`.
+/// Both methods are called on the event loop thread, so they should not block for long.
+pub trait BodyReader: Send {
+    
+                                        //kolejny fragment ciała, false przerywa czytanie
+    fn data(&mut self, chunk: &[u8]) -> bool;
+    
+                                        //complete == false gdy czytanie przerwano (timeout albo false zwrócone z data)
+    fn finish(self: Box<Self>, request: Request, complete: bool);
+}
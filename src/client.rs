@@ -0,0 +1,865 @@
+use std::io;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, IpAddr, Ipv6Addr, ToSocketAddrs};
+use std::thread;
+use mio::{Token, Sender, NotifyError};
+use mio::tcp::TcpStream;
+use server::{MioMessage, Event};
+use upstream::{self, BodyDecoder, Framing, ResponseHead};
+use proxy::IdleSocket;
+use response;
+use tunnel;
+
+use std::boxed::FnBox;
+
+
+const TIMEOUT       : u64   = 30 * 1000;
+const MAX_REDIRECTS : u32   = 5;
+const MAX_BODY      : usize = 16 * 1024 * 1024;
+
+
+pub type ClientCallback = Box<FnBox(io::Result<ClientResponse>) + Send + Sync + 'static>;
+
+
+/// HTTP/1.1 client working on the server's event loop: connections are non-blocking, idle ones are kept
+/// for reuse (shared with `ProxyHandler`). Cheap to clone and usable from any thread. Only `http://` urls.
+/// The callback runs on the event loop thread, so it should not block - longer work belongs to another thread.
+/// Host names (also of redirects to another host) are resolved on a separate thread; when that fails,
+/// the callback gets the error on that thread.
+#[derive(Clone)]
+pub struct Client {
+    sender : Sender<MioMessage>,
+}
+
+
+impl Client {
+
+    pub fn new(sender: Sender<MioMessage>) -> Client {
+
+        Client {
+            sender : sender,
+        }
+    }
+
+    pub fn get(&self, url: &str, callback: ClientCallback) {
+
+        match ClientRequest::new("GET", url) {
+            Ok(request) => self.send(request, callback),
+            Err(err) => call(callback, Err(err)),
+        }
+    }
+
+    pub fn send(&self, request: ClientRequest, callback: ClientCallback) {
+        start(self.sender.clone(), request, callback, 0);
+    }
+}
+
+
+                                        //zapytanie DNS blokuje, a Client bywa używany w odbiorniku na event_loop-ie - adres IP
+                                        //trafia do event_loop-a od razu, nazwa hosta jest rozwiązywana w osobnym wątku
+pub fn start(sender: Sender<MioMessage>, request: ClientRequest, callback: ClientCallback, redirects: u32) {
+
+    if let Ok(ip) = request.host.parse::<IpAddr>() {
+        let addr = SocketAddr::new(ip, request.port);
+        post(&sender, addr, request, callback, redirects);
+        return;
+    }
+
+    thread::spawn(move || {
+        match request.resolve() {
+            Ok(addr) => post(&sender, addr, request, callback, redirects),
+            Err(err) => call(callback, Err(err)),
+        }
+    });
+}
+
+
+fn post(sender: &Sender<MioMessage>, addr: SocketAddr, request: ClientRequest, callback: ClientCallback, redirects: u32) {
+
+    match sender.send(MioMessage::ClientRequest(addr, request, callback, redirects)) {
+
+        Ok(()) => {},
+
+                                        //wiadomość wraca - callback dostaje błąd zamiast przepaść
+        Err(NotifyError::Full(message)) | Err(NotifyError::Closed(Some(message))) => {
+            if let MioMessage::ClientRequest(_, _, callback, _) = message {
+                call(callback, Err(io::Error::new(ErrorKind::Other, "event loop is not running")));
+            }
+        },
+
+        Err(_) => {},
+    }
+}
+
+
+pub fn call(callback: ClientCallback, result: io::Result<ClientResponse>) {
+    (callback as Box<FnBox(io::Result<ClientResponse>)>)(result);
+}
+
+
+/// Request sent by `Client`: method, url, headers and a body sent whole with `Content-Length`.
+#[derive(Clone)]
+pub struct ClientRequest {
+    method        : String,
+    host          : String,
+    port          : u16,
+    path          : String,                     //ścieżka z query stringiem
+    headers       : Vec<(String, String)>,
+    body          : Vec<u8>,
+    timeout       : u64,
+    max_redirects : u32,
+    max_body      : usize,
+}
+
+
+impl ClientRequest {
+
+    pub fn new(method: &str, url: &str) -> io::Result<ClientRequest> {
+
+                                        //metoda trafia wprost do linii requestu - tylko znaki tokenu
+        if method.len() == 0 || method.bytes().all(response::is_tchar) == false {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid method: {}", method)));
+        }
+
+        let (host, port, path) = try!(parse_url(url));
+
+        Ok(ClientRequest {
+            method        : method.to_uppercase(),
+            host          : host,
+            port          : port,
+            path          : path,
+            headers       : Vec::new(),
+            body          : Vec::new(),
+            timeout       : TIMEOUT,
+            max_redirects : MAX_REDIRECTS,
+            max_body      : MAX_BODY,
+        })
+    }
+
+                                        //Connection, Content-Length i Transfer-Encoding ustawia klient; znaki CR/LF
+                                        //i inne niedozwolone są usuwane, tak jak w Response::add_header
+    pub fn header(mut self, name: &str, value: &str) -> ClientRequest {
+        self.headers.push((response::header_name(name), response::header_value(value)));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> ClientRequest {
+        self.body = body;
+        self
+    }
+
+                                        //w milisekundach, na całą wymianę łącznie z nawiązaniem połączenia (domyślnie 30 sekund)
+    pub fn timeout(mut self, timeout: u64) -> ClientRequest {
+        self.timeout = timeout;
+        self
+    }
+
+                                        //0 - odpowiedź 3xx trafia do callbacku bez podążania za Location (domyślnie 5)
+    pub fn max_redirects(mut self, max_redirects: u32) -> ClientRequest {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+                                        //dłuższe ciało odpowiedzi kończy się błędem (domyślnie 16MB)
+    pub fn max_body(mut self, max_body: usize) -> ClientRequest {
+        self.max_body = max_body;
+        self
+    }
+
+    pub fn method(&self) -> &String {
+        &(self.method)
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.authority(), self.path)
+    }
+
+    fn authority(&self) -> String {
+
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    fn resolve(&self) -> io::Result<SocketAddr> {
+
+        match try!((&self.host[..], self.port).to_socket_addrs()).next() {
+            Some(addr) => Ok(addr),
+            None => Err(io::Error::new(ErrorKind::NotFound, format!("no address for {}", self.host))),
+        }
+    }
+
+                                        //powtórzenie po zerwanym połączeniu z puli nie zmieni stanu serwera
+    fn is_idempotent(&self) -> bool {
+        match &self.method[..] {
+            "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" => true,
+            _ => false,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+
+        let mut out = format!("{} {} HTTP/1.1\r\n", self.method, self.path);
+
+        if self.headers.iter().any(|&(ref name, _)| name.to_lowercase() == "host") == false {
+            out.push_str(&format!("Host: {}\r\n", self.authority()));
+        }
+
+        for &(ref name, ref value) in self.headers.iter() {
+
+            match &name.to_lowercase()[..] {
+                "connection" | "content-length" | "transfer-encoding" => {},
+                _ => out.push_str(&format!("{}: {}\r\n", name, value)),
+            }
+        }
+
+        let with_body = match &self.method[..] {
+            "POST" | "PUT" | "PATCH" => true,
+            _ => self.body.len() > 0,
+        };
+
+        if with_body {
+            out.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        out.push_str("Connection: keep-alive\r\n\r\n");
+
+        let mut out = out.into_bytes();
+
+        out.extend_from_slice(&self.body);
+
+        out
+    }
+
+                                        //kolejny request według nagłówka Location
+    fn follow(&self, location: &str, status: u16) -> io::Result<ClientRequest> {
+
+        let location = match location.find('#') {
+            Some(pos) => &location[0..pos],
+            None => location,
+        };
+
+        let (host, port, path) = if location.to_lowercase().starts_with("http://") {
+            try!(parse_url(location))
+        } else if location.starts_with("//") {
+            try!(parse_url(&format!("http:{}", location)))
+        } else if location.contains("://") {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("unsupported redirect: {}", location)));
+        } else if location.starts_with('/') {
+            (self.host.clone(), self.port, try!(request_target(location)))
+        } else {
+
+                                        //względem katalogu bieżącej ścieżki
+            let current = match self.path.find('?') {
+                Some(pos) => &self.path[0..pos],
+                None => &self.path[..],
+            };
+
+            let dir = match current.rfind('/') {
+                Some(pos) => &current[0..pos + 1],
+                None => "/",
+            };
+
+            (self.host.clone(), self.port, try!(request_target(&format!("{}{}", dir, location))))
+        };
+
+        let mut next = self.clone();
+
+                                        //dane uwierzytelniające nie wędrują do innego serwera
+        if host != self.host || port != self.port {
+            next.headers.retain(|&(ref name, _)| {
+                match &name.to_lowercase()[..] {
+                    "host" | "authorization" | "cookie" => false,
+                    _ => true,
+                }
+            });
+        }
+
+        next.host = host;
+        next.port = port;
+        next.path = path;
+
+                                        //303, a dla POST także 301/302 - dalej GET bez ciała
+        if status == 303 || ((status == 301 || status == 302) && self.method == "POST") {
+
+            if next.method != "HEAD" {
+                next.method = "GET".to_owned();
+            }
+
+            next.body = Vec::new();
+            next.headers.retain(|&(ref name, _)| name.to_lowercase() != "content-type");
+        }
+
+        Ok(next)
+    }
+}
+
+
+                                        //"http://host:port/path?query" -> (host, port, "/path?query")
+fn parse_url(url: &str) -> io::Result<(String, u16, String)> {
+
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("unsupported url: {:?}", url));
+
+    if url.len() < 7 || url.is_char_boundary(7) == false || url[0..7].to_lowercase() != "http://" {
+        return Err(invalid());
+    }
+
+                                        //spacja, CR czy LF rozbiłyby linię requestu lub nagłówek Host
+    if url.bytes().any(|byte| byte <= b' ' || byte == 0x7f) {
+        return Err(invalid());
+    }
+
+    let rest = &url[7..];
+
+    let (authority, path) = match rest.find(|ch: char| ch == '/' || ch == '?' || ch == '#') {
+        Some(pos) => (&rest[0..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+
+    let path = match path.find('#') {
+        Some(pos) => &path[0..pos],
+        None => path,
+    };
+
+    let path = if path.starts_with('/') {
+        try!(request_target(path))
+    } else {
+        try!(request_target(&format!("/{}", path)))
+    };
+
+    if authority.contains('@') {
+        return Err(invalid());
+    }
+
+    let (host, port) = if authority.starts_with('[') {
+
+        let end = match authority.find(']') {
+            Some(end) => end,
+            None => return Err(invalid()),
+        };
+
+        let port = &authority[end + 1..];
+
+        if port.len() > 0 && port.starts_with(':') == false {
+            return Err(invalid());
+        }
+
+        if authority[1..end].parse::<Ipv6Addr>().is_err() {
+            return Err(invalid());
+        }
+
+        (&authority[1..end], if port.len() > 1 { &port[1..] } else { "" })
+
+    } else {
+
+        match authority.rfind(':') {
+            Some(pos) => (&authority[0..pos], &authority[pos + 1..]),
+            None => (authority, ""),
+        }
+    };
+
+    let port = if port.len() == 0 {
+        80
+    } else {
+        match port.parse::<u16>() {
+            Ok(port) if port > 0 => port,
+            _ => return Err(invalid()),
+        }
+    };
+
+    if host.len() == 0 {
+        return Err(invalid());
+    }
+
+                                        //nazwa hosta lub adres IPv4 - litery, cyfry, '-', '.', '_' (nazwy IDN tylko w punycode)
+    if authority.starts_with('[') == false && host.bytes().all(is_host_char) == false {
+        return Err(invalid());
+    }
+
+    Ok((host.to_lowercase(), port, path))
+}
+
+
+fn is_host_char(byte: u8) -> bool {
+    match byte {
+        b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' | b'-' | b'.' | b'_' => true,
+        _ => false,
+    }
+}
+
+
+                                        //ścieżka do linii requestu: bez znaków sterujących i spacji, bajty spoza ASCII zakodowane procentowo
+fn request_target(path: &str) -> io::Result<String> {
+
+    let mut out = String::with_capacity(path.len());
+
+    for byte in path.bytes() {
+
+        if byte <= b' ' || byte == 0x7f {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid character in path: {:?}", path)));
+        }
+
+        if byte >= 0x80 {
+            out.push_str(&format!("%{:02X}", byte));
+        } else {
+            out.push(byte as char);
+        }
+    }
+
+    Ok(out)
+}
+
+
+/// Response delivered by `Client`, with the whole body.
+pub struct ClientResponse {
+    head : ResponseHead,
+    body : Vec<u8>,
+    url  : String,
+}
+
+
+impl ClientResponse {
+
+    pub fn status(&self) -> u16 {
+        self.head.status()
+    }
+
+    pub fn reason(&self) -> &String {
+        self.head.reason()
+    }
+
+                                        //0 - HTTP/1.0, 1 - HTTP/1.1
+    pub fn version(&self) -> u8 {
+        self.head.version()
+    }
+
+    pub fn headers(&self) -> &Vec<(String, String)> {
+        self.head.headers()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.head.header(name)
+    }
+
+    pub fn body(&self) -> &Vec<u8> {
+        &(self.body)
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+                                        //adres, z którego przyszła odpowiedź - po przekierowaniach inny niż w requeście
+    pub fn url(&self) -> &String {
+        &(self.url)
+    }
+}
+
+
+pub enum ClientStep {
+    Pending,
+    Complete,
+    Failed(io::Error),
+}
+
+
+                                        //co zrobić z zakończoną wymianą
+pub enum Next {
+    Deliver(ClientCallback, io::Result<ClientResponse>),
+    Redirect(SocketAddr, ClientRequest, ClientCallback, u32),
+    Resolve(ClientRequest, ClientCallback, u32),        //przekierowanie do innego hosta - adres przez client::start
+}
+
+
+/// One request of `Client` in progress on the event loop.
+pub struct ClientExchange {
+    request     : ClientRequest,
+    callback    : ClientCallback,
+    redirects   : u32,                          //przekierowania przed tym requestem
+    addr        : SocketAddr,
+    socket      : TcpStream,
+    token       : Token,
+    connected   : bool,
+    replay      : Option<Vec<u8>>,              //request do powtórzenia, gdy połączenie z puli okaże się zamknięte
+    to_server   : Vec<u8>,
+    from_server : Vec<u8>,
+    eof         : bool,
+    head        : Option<ResponseHead>,
+    decoder     : Option<BodyDecoder>,
+    body        : Vec<u8>,
+}
+
+
+impl ClientExchange {
+
+                                        //reused - socket z puli, już połączony
+    pub fn new(addr: SocketAddr, request: ClientRequest, callback: ClientCallback, redirects: u32, socket: TcpStream, token: Token, reused: bool) -> ClientExchange {
+
+        let to_server = request.to_bytes();
+
+        let replay = if reused && request.is_idempotent() {
+            Some(to_server.clone())
+        } else {
+            None
+        };
+
+        ClientExchange {
+            request     : request,
+            callback    : callback,
+            redirects   : redirects,
+            addr        : addr,
+            socket      : socket,
+            token       : token,
+            connected   : reused,
+            replay      : replay,
+            to_server   : to_server,
+            from_server : Vec::new(),
+            eof         : false,
+            head        : None,
+            decoder     : None,
+            body        : Vec::new(),
+        }
+    }
+
+    pub fn socket(&self) -> &TcpStream {
+        &(self.socket)
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.request.timeout
+    }
+
+    pub fn get_event(&self) -> Event {
+
+        if self.connected == false {
+            Event::Write
+        } else if self.to_server.len() > 0 {
+            Event::ReadWrite
+        } else {
+            Event::Read
+        }
+    }
+
+    pub fn fail(self, err: io::Error) {
+        call(self.callback, Err(err));
+    }
+
+    pub fn pump(&mut self) -> ClientStep {
+
+        if self.connected == false {
+
+            if let Err(err) = self.socket.take_socket_error() {
+                return ClientStep::Failed(err);
+            }
+
+            match self.socket.peer_addr() {
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::NotConnected => return ClientStep::Pending,
+                Err(err) => return ClientStep::Failed(err),
+            }
+
+            self.connected = true;
+        }
+
+        let mut activity = false;
+
+        if let Err(err) = tunnel::flush(&mut self.socket, &mut self.to_server, &mut activity) {
+            return self.retry(err);
+        }
+
+        if let Err(err) = tunnel::transfer(&mut self.socket, &mut self.from_server, &mut self.eof, &mut activity) {
+            return self.retry(err);
+        }
+
+        self.process()
+    }
+
+    fn process(&mut self) -> ClientStep {
+
+        while self.head.is_none() {
+
+            match upstream::parse_head(&self.from_server) {
+
+                Ok(Some((head, size))) => {
+
+                    self.from_server.drain(0..size);
+
+                    if head.status() == 101 {
+                        return ClientStep::Failed(io::Error::new(ErrorKind::InvalidData, "unexpected 101 Switching Protocols"));
+                    }
+
+                                        //100 Continue, 103 Early Hints - czekamy na właściwą odpowiedź
+                    if head.status() >= 100 && head.status() < 200 {
+                        continue;
+                    }
+
+                    match upstream::framing(&self.request.method, &head) {
+                        Ok(framing) => self.decoder = Some(BodyDecoder::new(framing)),
+                        Err(err) => return ClientStep::Failed(io::Error::new(ErrorKind::InvalidData, err)),
+                    }
+
+                    self.replay = None;
+                    self.head   = Some(head);
+                },
+
+                Ok(None) => {
+
+                    if self.eof {
+                        return self.retry(io::Error::new(ErrorKind::UnexpectedEof, "connection closed before response"));
+                    }
+
+                    return ClientStep::Pending;
+                },
+
+                Err(err) => return ClientStep::Failed(io::Error::new(ErrorKind::InvalidData, err)),
+            }
+        }
+
+        let (result, done) = match self.decoder {
+            Some(ref mut decoder) => {
+                let result = decoder.decode(&mut self.from_server, &mut self.body);
+                let done   = decoder.is_done() || (self.eof && decoder.finish().is_ok());
+                (result, done)
+            },
+            None => return ClientStep::Pending,
+        };
+
+        if let Err(err) = result {
+            return ClientStep::Failed(io::Error::new(ErrorKind::InvalidData, err));
+        }
+
+        if self.body.len() > self.request.max_body {
+            return ClientStep::Failed(io::Error::new(ErrorKind::InvalidData, "response body too large"));
+        }
+
+        if done {
+            return ClientStep::Complete;
+        }
+
+        if self.eof {
+            return ClientStep::Failed(io::Error::new(ErrorKind::UnexpectedEof, "connection closed before end of body"));
+        }
+
+        ClientStep::Pending
+    }
+
+                                        //połączenie z puli mogło zostać zamknięte przez serwer - jedna próba na nowym
+    fn retry(&mut self, err: io::Error) -> ClientStep {
+
+        let replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => return ClientStep::Failed(err),
+        };
+
+        match TcpStream::connect(&self.addr) {
+
+                                        //stare gniazdo znika z event_loop-a przy zamknięciu, nowe dostaje ten sam token
+            Ok(socket) => {
+                self.socket      = socket;
+                self.connected   = false;
+                self.to_server   = replay;
+                self.from_server = Vec::new();
+                self.eof         = false;
+                ClientStep::Pending
+            },
+
+            Err(err) => ClientStep::Failed(err),
+        }
+    }
+
+                                        //po ClientStep::Complete - odpowiedź lub przekierowanie i socket do puli
+    pub fn finish(self) -> (Next, Option<IdleSocket>) {
+
+        let ClientExchange { request, callback, redirects, addr, socket, token, to_server, from_server, eof, head, decoder, body, .. } = self;
+
+        let head = match head {
+            Some(head) => head,
+            None => return (Next::Deliver(callback, Err(io::Error::new(ErrorKind::Other, "no response"))), None),
+        };
+
+        let framing = decoder.map(|decoder| decoder.framing()).unwrap_or(Framing::UntilClose);
+
+        let reusable = head.keep_alive() && framing != Framing::UntilClose && eof == false &&
+            from_server.len() == 0 && to_server.len() == 0;
+
+        let idle = if reusable {
+            Some(IdleSocket {
+                addr   : addr,
+                socket : socket,
+                token  : token,
+            })
+        } else {
+            None
+        };
+
+        let status   = head.status();
+        let redirect = match status {
+            301 | 302 | 303 | 307 | 308 => head.header("Location").cloned(),
+            _ => None,
+        };
+
+        let response = ClientResponse {
+            head : head,
+            body : body,
+            url  : request.url(),
+        };
+
+        let location = match redirect {
+            Some(ref location) if request.max_redirects > 0 => location,
+            _ => return (Next::Deliver(callback, Ok(response)), idle),
+        };
+
+        if redirects >= request.max_redirects {
+            return (Next::Deliver(callback, Err(io::Error::new(ErrorKind::Other, "too many redirects"))), idle);
+        }
+
+                                        //przekierowania, za którymi klient nie pójdzie (np. https) - odpowiedź 3xx trafia do callbacku
+        let next = match request.follow(location, status) {
+            Ok(next) => next,
+            Err(_) => return (Next::Deliver(callback, Ok(response)), idle),
+        };
+
+        if next.host == request.host && next.port == request.port {
+            (Next::Redirect(addr, next, callback, redirects + 1), idle)
+        } else {
+            (Next::Resolve(next, callback, redirects + 1), idle)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{parse_url, ClientRequest};
+
+    fn url(url: &str) -> (String, u16, String) {
+        parse_url(url).unwrap()
+    }
+
+    fn header<'a>(request: &'a ClientRequest, name: &str) -> Option<&'a str> {
+        request.headers.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| &value[..])
+    }
+
+    #[test]
+    fn parse_host_port_path() {
+        assert_eq!(url("http://example.com"), ("example.com".to_owned(), 80, "/".to_owned()));
+        assert_eq!(url("HTTP://Example.COM:8080/a/b?x=1"), ("example.com".to_owned(), 8080, "/a/b?x=1".to_owned()));
+        assert_eq!(url("http://example.com?x=1"), ("example.com".to_owned(), 80, "/?x=1".to_owned()));
+        assert_eq!(url("http://example.com/a#frag"), ("example.com".to_owned(), 80, "/a".to_owned()));
+        assert_eq!(url("http://[::1]:81/"), ("::1".to_owned(), 81, "/".to_owned()));
+        assert_eq!(url("http://[::1]"), ("::1".to_owned(), 80, "/".to_owned()));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for bad in &["https://example.com/", "http://", "http:/", "http://user@example.com/", "http://example.com:0/",
+                     "http://example.com:abc/", "http://example.com:70000/", "http://[::1/", "http://[::1]x/", "httpąą"] {
+            assert!(parse_url(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_request_splitting() {
+        for bad in &["http://example.com/a b", "http://example.com/a\r\nX-Injected: 1", "http://example.com/\t",
+                     "http://example.com\r\nX-Injected: 1/", "http://exa mple.com/", "http://exa%41mple.com/",
+                     "http://example.com/\u{7f}", "http://[zz]/", "http://[::1\r\n]/"] {
+            assert!(parse_url(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_encodes_non_ascii_path() {
+        assert_eq!(url("http://example.com/zażółć?q=ż").2, "/za%C5%BC%C3%B3%C5%82%C4%87?q=%C5%BC");
+    }
+
+    #[test]
+    fn method_token() {
+
+        assert!(ClientRequest::new("PURGE", "http://example.com/").is_ok());
+        assert_eq!(ClientRequest::new("get", "http://example.com/").unwrap().method(), "GET");
+
+        for bad in &["", "GET /x", "GET\r\nX", "GE(T"] {
+            assert!(ClientRequest::new(bad, "http://example.com/").is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn follow_relative() {
+
+        let request = ClientRequest::new("GET", "http://example.com:8080/a/b?x=1").unwrap();
+
+        let next = request.follow("/c", 301).unwrap();
+        assert_eq!(next.url(), "http://example.com:8080/c");
+
+        let next = request.follow("d?y=2#frag", 302).unwrap();
+        assert_eq!(next.url(), "http://example.com:8080/a/d?y=2");
+
+        assert!(request.follow("https://example.com/", 301).is_err());
+        assert!(request.follow("/a b", 301).is_err());
+        assert!(request.follow("c\td", 301).is_err());
+    }
+
+    #[test]
+    fn follow_other_host() {
+
+        let request = ClientRequest::new("GET", "http://example.com/").unwrap()
+            .header("Host", "example.com")
+            .header("Authorization", "Basic eDp5")
+            .header("Cookie", "a=1")
+            .header("Accept", "*/*");
+
+        let next = request.follow("//other.com/x", 302).unwrap();
+        assert_eq!(next.url(), "http://other.com/x");
+        assert_eq!(header(&next, "Host"), None);
+        assert_eq!(header(&next, "Authorization"), None);
+        assert_eq!(header(&next, "Cookie"), None);
+        assert_eq!(header(&next, "Accept"), Some("*/*"));
+
+        let next = request.follow("http://example.com/y", 302).unwrap();
+        assert_eq!(header(&next, "Authorization"), Some("Basic eDp5"));
+    }
+
+    #[test]
+    fn follow_method() {
+
+        let post = ClientRequest::new("POST", "http://example.com/form").unwrap()
+            .header("Content-Type", "text/plain")
+            .body(b"data".to_vec());
+
+        for status in &[301, 302, 303] {
+            let next = post.follow("/done", *status).unwrap();
+            assert_eq!(next.method(), "GET");
+            assert_eq!(next.body.len(), 0);
+            assert_eq!(header(&next, "Content-Type"), None);
+        }
+
+        let next = post.follow("/again", 307).unwrap();
+        assert_eq!(next.method(), "POST");
+        assert_eq!(next.body, b"data".to_vec());
+        assert_eq!(header(&next, "Content-Type"), Some("text/plain"));
+
+        let head = ClientRequest::new("HEAD", "http://example.com/").unwrap();
+        assert_eq!(head.follow("/other", 303).unwrap().method(), "HEAD");
+    }
+
+    #[test]
+    fn header_sanitized() {
+
+        let request = ClientRequest::new("GET", "http://example.com/").unwrap()
+            .header("X-Bad\r\nInjected", "value\r\nSet-Cookie: a=1");
+
+        let bytes = String::from_utf8(request.to_bytes()).unwrap();
+
+        assert!(bytes.contains("X-BadInjected: valueSet-Cookie: a=1\r\n"));
+        assert_eq!(bytes.matches("\r\n").count(), 5);
+    }
+}
@@ -1,9 +1,23 @@
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Code {
+    Code101,
     Code200,
+    Code206,
+    Code301,
+    Code304,
     Code400,
+    Code403,
     Code404,
+    Code405,
+    Code411,
+    Code412,
+    Code413,
+    Code415,
+    Code416,
+    Code426,
     Code500,
+    Code502,
+    Code504,
 }
 
 //https://en.wikipedia.org/wiki/List_of_HTTP_status_codes
@@ -12,10 +26,24 @@ pub enum Code {
 impl Code {
     pub fn to_str(&self) -> &str {
         match *self {
+            Code::Code101 => "101 Switching Protocols",
             Code::Code200 => "200 OK",
+            Code::Code206 => "206 Partial Content",
+            Code::Code301 => "301 Moved Permanently",
+            Code::Code304 => "304 Not Modified",
             Code::Code400 => "400 Bad Request",
+            Code::Code403 => "403 Forbidden",
             Code::Code404 => "404 Not Found",
+            Code::Code405 => "405 Method Not Allowed",
+            Code::Code411 => "411 Length Required",
+            Code::Code412 => "412 Precondition Failed",
+            Code::Code413 => "413 Payload Too Large",
+            Code::Code415 => "415 Unsupported Media Type",
+            Code::Code416 => "416 Range Not Satisfiable",
+            Code::Code426 => "426 Upgrade Required",
             Code::Code500 => "500 Internal Server Error",
+            Code::Code502 => "502 Bad Gateway",
+            Code::Code504 => "504 Gateway Timeout",
         }
     }
 }
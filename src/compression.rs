@@ -0,0 +1,486 @@
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use flate2;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use request::Request;
+use response::{Response, Body};
+use code::Code;
+use typemod::Type;
+use middleware::Middleware;
+use output::{Source, SourceReader};
+
+//https://tools.ietf.org/html/rfc7231#section-5.3.4
+
+
+const BLOCK : usize = 16 * 1024;
+
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Encoding {
+    Gzip,
+    Deflate,                            //w HTTP "deflate" to strumień zlib
+}
+
+impl Encoding {
+
+    pub fn to_str(&self) -> &str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+
+                                        //q z Accept-Encoding dla danego kodowania, "*" gdy nie wymienione wprost
+fn quality(header: &str, name: &str) -> f32 {
+
+    let mut star     = None;
+    let mut explicit = None;
+
+    for item in header.split(',') {
+
+        let mut params = item.split(';');
+
+        let coding = match params.next() {
+            Some(coding) => coding.trim().to_lowercase(),
+            None => continue,
+        };
+
+        let mut q = 1.0;
+
+        for param in params {
+
+            let param = param.trim();
+
+            if param.starts_with("q=") || param.starts_with("Q=") {
+                q = match param[2..].trim().parse::<f32>() {
+                    Ok(q) if q >= 0.0 && q <= 1.0 => q,
+                    _ => 0.0,
+                };
+            }
+        }
+
+        if coding == name || (name == "gzip" && coding == "x-gzip") {
+            explicit = Some(q);
+        } else if coding == "*" {
+            star = Some(q);
+        }
+    }
+
+    explicit.or(star).unwrap_or(0.0)
+}
+
+
+pub fn accepts(header: &str, encoding: Encoding) -> bool {
+    quality(header, encoding.to_str()) > 0.0
+}
+
+
+/// Picks the coding with the highest q-value from `Accept-Encoding`, gzip winning ties. `None` means the body goes out as is.
+pub fn negotiate(header: &str) -> Option<Encoding> {
+
+    let gzip    = quality(header, "gzip");
+    let deflate = quality(header, "deflate");
+
+    if gzip <= 0.0 && deflate <= 0.0 {
+        None
+    } else if gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else {
+        Some(Encoding::Deflate)
+    }
+}
+
+
+fn level_of(level: u32) -> flate2::Compression {
+
+    if level == 0 {
+        flate2::Compression::None
+    } else if level <= 3 {
+        flate2::Compression::Fast
+    } else if level <= 6 {
+        flate2::Compression::Default
+    } else {
+        flate2::Compression::Best
+    }
+}
+
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+
+    fn new(encoding: Encoding, level: u32) -> Encoder {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level_of(level))),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), level_of(level))),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => encoder.write_all(data),
+            Encoder::Deflate(ref mut encoder) => encoder.write_all(data),
+        }
+    }
+
+                                        //to, co koder zdążył już wyprodukować
+    fn take_output(&mut self) -> Vec<u8> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => mem::replace(encoder.get_mut(), Vec::new()),
+            Encoder::Deflate(ref mut encoder) => mem::replace(encoder.get_mut(), Vec::new()),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+
+pub fn compress(data: &[u8], encoding: Encoding, level: u32) -> io::Result<Vec<u8>> {
+
+    let mut encoder = Encoder::new(encoding, level);
+
+    try!(encoder.write(data));
+
+    encoder.finish()
+}
+
+
+                                        //jeden kawałek Transfer-Encoding: chunked; pusty zostaje pusty, bo oznaczałby koniec ciała
+pub fn chunk(data: Vec<u8>) -> Vec<u8> {
+
+    if data.len() == 0 {
+        return data;
+    }
+
+    let mut out = format!("{:x}\r\n", data.len()).into_bytes();
+
+    out.extend_from_slice(&data);
+    out.extend_from_slice(b"\r\n");
+
+    out
+}
+
+
+fn frame(data: Vec<u8>, chunked: bool) -> Vec<u8> {
+
+    if chunked {
+        chunk(data)
+    } else {
+        data
+    }
+}
+
+
+/// Compresses a body block by block while it is being sent and frames the result with chunked transfer coding.
+pub struct Stream {
+    reader  : Box<Read + Send>,
+    encoder : Option<Encoder>,          //None - ostatni kawałek już wysłany
+    chunked : bool,
+}
+
+impl Stream {
+
+    pub fn new(body: Body, encoding: Encoding, level: u32) -> Stream {
+
+        Stream {
+            reader  : body.into_reader(),
+            encoder : Some(Encoder::new(encoding, level)),
+            chunked : true,
+        }
+    }
+}
+
+
+                                        //skompresowane ciało bez ramek chunked - HTTP/2 ma własne ramki DATA
+pub fn reader(body: Body, encoding: Encoding, level: u32) -> Box<Read + Send> {
+
+    let mut stream = Stream::new(body, encoding, level);
+
+    stream.chunked = false;
+
+    Box::new(SourceReader::new(Box::new(stream)))
+}
+
+
+impl Source for Stream {
+
+    fn next_block(&mut self) -> io::Result<Option<Vec<u8>>> {
+
+        let mut block = vec![0; BLOCK];
+
+        loop {
+
+            let size = match self.encoder {
+                Some(_) => try!(self.reader.read(&mut block)),
+                None => return Ok(None),
+            };
+
+            if size == 0 {
+
+                let data = match self.encoder.take() {
+                    Some(encoder) => try!(encoder.finish()),
+                    None => Vec::new(),
+                };
+
+                let mut out = frame(data, self.chunked);
+
+                if self.chunked {
+                    out.extend_from_slice(b"0\r\n\r\n");
+                }
+
+                return Ok(Some(out));
+            }
+
+            if let Some(ref mut encoder) = self.encoder {
+
+                try!(encoder.write(&block[0..size]));
+
+                let data = encoder.take_output();
+
+                                        //koder mógł jeszcze nic nie wypuścić - czytamy dalej
+                if data.len() > 0 {
+                    return Ok(Some(frame(data, self.chunked)));
+                }
+            }
+        }
+    }
+}
+
+
+/// Opt-in middleware compressing responses with gzip or deflate, as negotiated with `Accept-Encoding`.
+/// Buffered bodies are compressed at once (and kept only if they got smaller), file bodies are compressed
+/// while sending with `Transfer-Encoding: chunked` (HTTP/1.1 clients only). Range requests get the identity body.
+pub struct Compression {
+    min_size  : u64,
+    level     : u32,
+    types     : Option<Vec<Type>>,
+    streaming : bool,
+}
+
+
+impl Compression {
+
+    pub fn new() -> Compression {
+
+        Compression {
+            min_size  : 1024,
+            level     : 6,
+            types     : None,
+            streaming : true,
+        }
+    }
+
+                                        //mniejsze ciała idą bez kompresji
+    pub fn min_size(mut self, min_size: u64) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+                                        //0 - 9, jak w zlib
+    pub fn level(mut self, level: u32) -> Compression {
+        self.level = level;
+        self
+    }
+
+                                        //zamiast Type::is_compressible
+    pub fn types(mut self, types: Vec<Type>) -> Compression {
+        self.types = Some(types);
+        self
+    }
+
+                                        //czy kompresować ciała z plików w trakcie wysyłania
+    pub fn streaming(mut self, streaming: bool) -> Compression {
+        self.streaming = streaming;
+        self
+    }
+
+    fn is_eligible(&self, typ: &Type) -> bool {
+        match self.types {
+            Some(ref types) => types.contains(typ),
+            None => typ.is_compressible(),
+        }
+    }
+}
+
+
+impl Middleware for Compression {
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        self.encode(request.method(), |name| request.header(name).cloned(), request.version(), response)
+    }
+}
+
+
+impl Compression {
+
+                                        //header - wartość nagłówka requestu o danej nazwie
+    fn encode<F>(&self, method: &str, header: F, version: u8, response: &mut Response)
+        where F: Fn(&str) -> Option<String> {
+
+        if response.header("Content-Encoding").is_some() {
+            return;
+        }
+
+        match *response.code() {
+            Code::Code101 | Code::Code206 | Code::Code304 | Code::Code416 => return,
+            _ => {}
+        }
+
+        if self.is_eligible(response.typ()) == false {
+            return;
+        }
+
+        response.add_vary("Accept-Encoding");
+
+        if response.body_len() < self.min_size {
+            return;
+        }
+
+                                        //zakresy (range::apply) liczone są z postaci niekodowanej
+        if (method == "GET" || method == "HEAD") && header("Range").is_some() {
+            return;
+        }
+
+        let encoding = match header("Accept-Encoding").and_then(|header| negotiate(&header)) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let compressed = match response.body() {
+            Some(data) => match compress(data, encoding, self.level) {
+                Ok(compressed) => if compressed.len() < data.len() { Some(compressed) } else { return },
+                Err(_) => return,
+            },
+            None => None,
+        };
+
+        match compressed {
+
+            Some(compressed) => {
+                response.replace_body(Body::Buf(compressed));
+            },
+
+            None => {
+
+                                        //HTTP/1.0 nie zna Transfer-Encoding: chunked
+                if self.streaming == false || version == 0 || response.is_chunked() {
+                    return;
+                }
+
+                let body = response.replace_body(Body::Buf(Vec::new()));
+                response.replace_body(Body::Encoded(Box::new(body), encoding, self.level));
+            }
+        }
+
+        response.add_header("Content-Encoding", encoding.to_str());
+
+                                        //silny ETag musi się różnić między wersją skompresowaną a zwykłą
+        let etag = response.header("ETag").cloned();
+
+        if let Some(etag) = etag {
+
+            if etag.starts_with('"') && etag.ends_with('"') && etag.len() >= 2 {
+                response.remove_header("ETag");
+                response.add_header("ETag", &format!("{}-{}\"", &etag[0..etag.len() - 1], encoding.to_str()));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Read;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use code::Code;
+    use typemod::Type;
+    use response::Response;
+    use super::{negotiate, accepts, compress, chunk, Encoding, Compression};
+
+    #[test]
+    fn negotiate_encoding() {
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0.5, deflate;q=0.8"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("deflate;q=0.5, gzip;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br"), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn negotiate_zero_quality() {
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("*, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=2"), None);
+        assert_eq!(negotiate("gzip;q=abc"), None);
+    }
+
+    #[test]
+    fn accepts_encoding() {
+        assert!(accepts("GZIP; Q=0.1", Encoding::Gzip));
+        assert!(accepts("gzip", Encoding::Deflate) == false);
+    }
+
+    #[test]
+    fn compress_roundtrip() {
+
+        let data = "hello hello hello hello hello".repeat(100).into_bytes();
+
+        let gzip = compress(&data, Encoding::Gzip, 6).unwrap();
+        let mut out = Vec::new();
+        GzDecoder::new(&gzip[..]).unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+
+        let zlib = compress(&data, Encoding::Deflate, 1).unwrap();
+        let mut out = Vec::new();
+        ZlibDecoder::new(&zlib[..]).read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn chunk_framing() {
+        assert_eq!(chunk(b"hello world, hello!".to_vec()), b"13\r\nhello world, hello!\r\n".to_vec());
+        assert_eq!(chunk(Vec::new()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn range_requests_not_compressed() {
+
+        let compression = Compression::new();
+        let text        = "hello hello hello hello hello".repeat(100);
+
+        let encode = |method: &str, headers: Vec<(&'static str, &'static str)>| {
+            let mut response = Response::create(Code::Code200, Type::TextPlain, text.clone());
+            compression.encode(method, |name| headers.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value.to_owned()), 1, &mut response);
+            response
+        };
+
+        let response = encode("GET", vec![("Accept-Encoding", "gzip")]);
+        assert_eq!(response.header("Content-Encoding"), Some(&"gzip".to_owned()));
+
+        let response = encode("GET", vec![("Accept-Encoding", "gzip"), ("Range", "bytes=0-99")]);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some(&"Accept-Encoding".to_owned()));
+        assert_eq!(response.body_len(), text.len() as u64);
+
+                                        //Range jest brany pod uwagę tylko przy GET i HEAD
+        let response = encode("POST", vec![("Accept-Encoding", "gzip"), ("Range", "bytes=0-99")]);
+        assert_eq!(response.header("Content-Encoding"), Some(&"gzip".to_owned()));
+    }
+}
@@ -0,0 +1,289 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+use request::Request;
+use response::{Response, Body};
+use code::Code;
+use typemod::Type;
+use httpdate;
+
+//https://tools.ietf.org/html/rfc7232
+
+
+                                        //większych buforów nie haszujemy przy każdej odpowiedzi - ETag można ustawić samemu
+const MAX_AUTO_ETAG : usize = 64 * 1024;
+
+
+                                        //ETag z zawartości bufora, silny albo słaby (W/"...")
+pub fn etag_for(data: &[u8], weak: bool) -> String {
+
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+
+    let hex = hasher.result_str();
+
+    if weak {
+        format!("W/\"{}\"", &hex[0..32])
+    } else {
+        format!("\"{}\"", &hex[0..32])
+    }
+}
+
+
+                                        //ETag pliku z rozmiaru i czasu modyfikacji - bez czytania zawartości, wspólny dla plików
+                                        //z pamięci podręcznej i bez niej; słaby, bo zmiana w tej samej sekundzie go nie zmienia
+pub fn file_etag(len: u64, modified: SystemTime) -> Option<String> {
+
+    match modified.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => Some(format!("W/\"{:x}-{:x}\"", len, since_epoch.as_secs())),
+        Err(_) => None,
+    }
+}
+
+
+fn opaque(tag: &str) -> &str {
+
+    if tag.starts_with("W/") {
+        &tag[2..]
+    } else {
+        tag
+    }
+}
+
+
+                                        //porównanie silne - oba tagi muszą być silne i identyczne; "*" pasuje do każdego istniejącego zasobu
+fn strong_match(list: &str, exists: bool, etag: Option<&String>) -> bool {
+
+    for tag in list.split(',') {
+
+        let tag = tag.trim();
+
+        if tag == "*" {
+            if exists {
+                return true;
+            }
+            continue;
+        }
+
+        if let Some(etag) = etag {
+            if tag.starts_with("W/") == false && etag.starts_with("W/") == false && tag == etag {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+
+                                        //porównanie słabe - liczy się tylko wartość w cudzysłowach
+fn weak_match(list: &str, exists: bool, etag: Option<&String>) -> bool {
+
+    for tag in list.split(',') {
+
+        let tag = tag.trim();
+
+        if tag == "*" {
+            if exists {
+                return true;
+            }
+            continue;
+        }
+
+        if let Some(etag) = etag {
+            if opaque(tag) == opaque(etag) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+
+/// Evaluates the preconditions of a request against the current validators of a resource, in the order of RFC 7232 section 6.
+/// `exists` tells whether the resource has a current representation, which is what `*` in If-Match and If-None-Match matches.
+/// Returns `Code304` or `Code412` when the request should not be served normally. Handlers of unsafe methods (PUT, DELETE)
+/// should call it through `Request::check_preconditions` before changing anything.
+pub fn evaluate(request: &Request, exists: bool, etag: Option<&String>, last_modified: Option<SystemTime>) -> Option<Code> {
+    preconditions(request.method(), |name| request.header(name).cloned(), exists, etag, last_modified)
+}
+
+
+                                        //header - wartość nagłówka requestu o danej nazwie
+fn preconditions<F>(method: &str, header: F, exists: bool, etag: Option<&String>, last_modified: Option<SystemTime>) -> Option<Code>
+    where F: Fn(&str) -> Option<String> {
+
+    let is_get = method == "GET" || method == "HEAD";
+
+    if let Some(if_match) = header("If-Match") {
+
+        if strong_match(&if_match, exists, etag) == false {
+            return Some(Code::Code412);
+        }
+
+    } else if let Some(since) = header("If-Unmodified-Since").and_then(|value| httpdate::parse(&value)) {
+
+        if let Some(modified) = last_modified {
+            if modified > since {
+                return Some(Code::Code412);
+            }
+        }
+    }
+
+    if let Some(if_none_match) = header("If-None-Match") {
+
+        if weak_match(&if_none_match, exists, etag) {
+            return if is_get { Some(Code::Code304) } else { Some(Code::Code412) };
+        }
+
+    } else if is_get {
+
+        if let Some(since) = header("If-Modified-Since").and_then(|value| httpdate::parse(&value)) {
+
+            if let Some(modified) = last_modified {
+                if modified <= since {
+                    return Some(Code::Code304);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Adds an ETag to a buffered 200 response to GET or HEAD (unless it already has one, or the body is over 64 KB)
+/// and answers 304/412 when the request preconditions say so. Other methods are left to `Request::check_preconditions`,
+/// which has to run before the handler changes anything.
+pub fn apply(request: &Request, mut response: Response) -> Response {
+
+    if *response.code() != Code::Code200 {
+        return response;
+    }
+
+    if request.method() != "GET" && request.method() != "HEAD" {
+        return response;
+    }
+
+    if response.header("ETag").is_none() {
+
+        let etag = match response.body() {
+            Some(data) if data.len() <= MAX_AUTO_ETAG => Some(etag_for(data, response.is_weak_etag())),
+            _ => None,
+        };
+
+        if let Some(etag) = etag {
+            response.add_header("ETag", &etag);
+        }
+    }
+
+    let last_modified = response.header("Last-Modified").and_then(|value| httpdate::parse(value));
+
+    let result = evaluate(request, true, response.header("ETag"), last_modified);
+
+    match result {
+
+        Some(Code::Code304) => {
+            response.replace_body(Body::Buf(Vec::new()));
+            response.set_code(Code::Code304);
+            response
+        },
+
+        Some(code) => {
+            response.replace_body(Body::Buf(code.to_str().to_owned().into_bytes()));
+            response.set_code(code);
+            response.set_type(Type::TextHtml);
+            response.remove_header("ETag");
+            response.remove_header("Last-Modified");
+            response.remove_header("Content-Encoding");
+            response
+        },
+
+        None => response,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{UNIX_EPOCH, Duration};
+    use code::Code;
+    use super::{preconditions, etag_for, file_etag};
+
+    const DATE : &'static str = "Sun, 06 Nov 1994 08:49:37 GMT";
+    const TIME : u64 = 784111777;
+
+    fn check(method: &str, headers: Vec<(&str, &str)>, etag: Option<&str>, modified: u64) -> Option<Code> {
+        check_exists(method, headers, etag.is_some(), etag, modified)
+    }
+
+    fn check_exists(method: &str, headers: Vec<(&str, &str)>, exists: bool, etag: Option<&str>, modified: u64) -> Option<Code> {
+
+        let etag   = etag.map(|etag| etag.to_owned());
+        let header = |name: &str| headers.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value.to_owned());
+
+        preconditions(method, header, exists, etag.as_ref(), Some(UNIX_EPOCH + Duration::from_secs(modified)))
+    }
+
+    #[test]
+    fn no_preconditions() {
+        assert_eq!(check("GET", vec![], Some("\"a\""), TIME), None);
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert_eq!(check("GET", vec![("If-None-Match", "\"a\"")], Some("\"a\""), TIME), Some(Code::Code304));
+        assert_eq!(check("HEAD", vec![("If-None-Match", "\"x\", W/\"a\"")], Some("\"a\""), TIME), Some(Code::Code304));
+        assert_eq!(check("GET", vec![("If-None-Match", "*")], Some("\"a\""), TIME), Some(Code::Code304));
+        assert_eq!(check("GET", vec![("If-None-Match", "\"b\"")], Some("\"a\""), TIME), None);
+        assert_eq!(check("PUT", vec![("If-None-Match", "*")], Some("\"a\""), TIME), Some(Code::Code412));
+        assert_eq!(check("PUT", vec![("If-None-Match", "*")], None, TIME), None);
+    }
+
+    #[test]
+    fn if_match() {
+        assert_eq!(check("PUT", vec![("If-Match", "\"a\"")], Some("\"a\""), TIME), None);
+        assert_eq!(check("PUT", vec![("If-Match", "\"b\"")], Some("\"a\""), TIME), Some(Code::Code412));
+        assert_eq!(check("PUT", vec![("If-Match", "W/\"a\"")], Some("W/\"a\""), TIME), Some(Code::Code412));
+        assert_eq!(check("PUT", vec![("If-Match", "*")], None, TIME), Some(Code::Code412));
+        assert_eq!(check("PUT", vec![("If-Match", "*")], Some("W/\"a\""), TIME), None);
+    }
+
+    #[test]
+    fn star_matches_existing_resource_without_etag() {
+        assert_eq!(check_exists("PUT", vec![("If-Match", "*")], true, None, TIME), None);
+        assert_eq!(check_exists("PUT", vec![("If-Match", "\"a\", *")], true, None, TIME), None);
+        assert_eq!(check_exists("PUT", vec![("If-Match", "\"a\"")], true, None, TIME), Some(Code::Code412));
+        assert_eq!(check_exists("PUT", vec![("If-None-Match", "*")], true, None, TIME), Some(Code::Code412));
+        assert_eq!(check_exists("GET", vec![("If-None-Match", "*")], true, None, TIME), Some(Code::Code304));
+    }
+
+    #[test]
+    fn modified_since() {
+        assert_eq!(check("GET", vec![("If-Modified-Since", DATE)], None, TIME), Some(Code::Code304));
+        assert_eq!(check("GET", vec![("If-Modified-Since", DATE)], None, TIME + 1), None);
+        assert_eq!(check("POST", vec![("If-Modified-Since", DATE)], None, TIME), None);
+        assert_eq!(check("GET", vec![("If-Modified-Since", "garbage")], None, TIME), None);
+    }
+
+    #[test]
+    fn if_none_match_wins_over_modified_since() {
+        assert_eq!(check("GET", vec![("If-None-Match", "\"b\""), ("If-Modified-Since", DATE)], Some("\"a\""), TIME), None);
+    }
+
+    #[test]
+    fn unmodified_since() {
+        assert_eq!(check("DELETE", vec![("If-Unmodified-Since", DATE)], None, TIME), None);
+        assert_eq!(check("DELETE", vec![("If-Unmodified-Since", DATE)], None, TIME + 1), Some(Code::Code412));
+        assert_eq!(check("DELETE", vec![("If-Match", "\"a\""), ("If-Unmodified-Since", DATE)], Some("\"a\""), TIME + 1), None);
+    }
+
+    #[test]
+    fn etag_format() {
+        assert_eq!(etag_for(b"abc", false), "\"ba7816bf8f01cfea414140de5dae2223\"");
+        assert_eq!(etag_for(b"abc", true), "W/\"ba7816bf8f01cfea414140de5dae2223\"");
+        assert_eq!(file_etag(1000, UNIX_EPOCH + Duration::from_secs(TIME)), Some("W/\"3e8-2ebc98a1\"".to_owned()));
+    }
+}
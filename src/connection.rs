@@ -1,22 +1,53 @@
-use mio::{EventSet, TryRead, TryWrite};
-use mio::tcp::{TcpStream};
+use mio::{EventSet, Token, TryRead, TryWrite};
+use stream::Stream;
 use httparse;
 use server::Event;
 use request::PreRequest;
 use response;
 use std::cmp::min;
 use request::Request;
+use body::BodyReader;
+use output::Output;
+use http2::{self, Http2};
+use websocket::{WsConnection, WebSocket, WebSocketHandler, Frame};
+use event_stream::{self, OpenGuard};
+use upgrade::{Upgraded, UpgradedConnection, UpgradeHandler};
+use tunnel::{self, TunnelConnection};
+use proxy::{self, ProxyExchange, ProxyTarget, IdleSocket};
+use code::Code;
+use typemod::Type;
+use mio::tcp::TcpStream;
+use std::net::SocketAddr;
+use compression;
 
 use std::boxed::FnBox;
 
+
+const EVENT_LIMIT : usize = 1024 * 1024;           //zdarzenia SSE czekające na wolnego klienta
+
 enum ConnectionMode {
 
+                                                    //uzgadnianie sesji TLS
+    Handshake,
+
                                                     //czytanie requestu
     ReadingRequest([u8; 2048], usize),
                                                     //oczekiwanie na wygenerowanie odpowiedzi serwera (bool to keep alive)
     WaitingForServerResponse(bool, ConnectionPost),
                                                     //wysyłanie odpowiedz (bool to keep alive)
-    SendingResponse(bool, Vec<u8>, usize),
+    SendingResponse(bool, Output),
+                                                    //HTTP/2 - wiele strumieni na jednym połączeniu
+    Http2(Box<Http2>),
+                                                    //WebSocket po odpowiedzi 101
+    WebSocket(Box<WsConnection>),
+                                                    //text/event-stream (bool to chunked, bool to koniec strumienia) - po wysłaniu wszystkiego połączenie jest zamykane
+    EventStream(Output, bool, bool, OpenGuard),
+                                                    //połączenie przejęte przez aplikację po odpowiedzi 101/200
+    Upgraded(Box<UpgradedConnection>),
+                                                    //CONNECT - połączenie z celem i dane w obu kierunkach
+    Tunnel(Box<TunnelConnection>),
+                                                    //request przekazywany do innego serwera (reverse proxy)
+    Proxy(Box<ProxyExchange>),
 }
 
 enum ConnectionPost {
@@ -24,6 +55,8 @@ enum ConnectionPost {
     None,
     Data(Vec<u8>, usize),
     Reading(Vec<u8>, usize, Request, Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>),
+    Stream(usize, usize, Request, Box<BodyReader>),               //ilość odebranych bajtów, długość ciała
+    Extra(Vec<u8>),                                               //request bez ciała, bajty odebrane za nagłówkami
     Complete,
 }
 
@@ -31,6 +64,8 @@ pub enum TimerMode {
     In,                 //czytanie nagłówków requestu
     Out,                //wysyłanie danych do przeglądarki
     Post,               //czytanie parametrów post-a
+    Heartbeat,          //strumień zdarzeń - komentarz co jakiś czas
+    Idle(u64),          //tunel, proxy - brak ruchu przez podaną ilość milisekund
     None,
 }
 
@@ -44,27 +79,55 @@ pub enum LogMessage {
 
 
 pub struct Connection {
-    pub stream: TcpStream,
+    pub stream: Stream,
     mode: ConnectionMode,
+    released: Option<IdleSocket>,                   //połączenie z serwerem docelowym do oddania do puli
 }
 
 
 impl Connection {
 
 
-    pub fn new(stream: TcpStream) -> Connection {
+    pub fn new(stream: Stream) -> Connection {
+
+        let mode = if stream.is_handshaking() {
+            ConnectionMode::Handshake
+        } else {
+            ConnectionMode::ReadingRequest([0u8; 2048], 0)
+        };
 
         Connection {
-            stream : stream,
-            mode   : ConnectionMode::ReadingRequest([0u8; 2048], 0),
+            stream   : stream,
+            mode     : mode,
+            released : None,
         }
     }
+    
+                                                    //odszyfrowane dane czekają w buforze TLS, a połączenie chce je czytać
+    pub fn has_buffered_input(&self) -> bool {
+        
+        let reading = match self.mode {
+            ConnectionMode::ReadingRequest(_, _) => true,
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Reading(_,_,_,_)) => true,
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Stream(_,_,_,_)) => true,
+            ConnectionMode::Http2(_) => true,
+            ConnectionMode::WebSocket(_) => true,
+            ConnectionMode::EventStream(_, _, _, _) => true,
+            ConnectionMode::Upgraded(_) => true,
+            ConnectionMode::Tunnel(ref tunnel) => tunnel.client_wants_read(),
+            ConnectionMode::Proxy(ref proxy) => proxy.client_wants_read(),
+            _ => false,
+        };
+        
+        reading && self.stream.has_buffered()
+    }
 
-    fn make(stream: TcpStream, mode: ConnectionMode) -> Connection {
+    fn make(stream: Stream, mode: ConnectionMode) -> Connection {
 
         Connection {
-            stream : stream,
-            mode   : mode,
+            stream   : stream,
+            mode     : mode,
+            released : None,
         }
     }
     
@@ -78,10 +141,14 @@ impl Connection {
                 let connection_post_close = match connection_post {
                     
                     ConnectionPost::None => false,
+                    ConnectionPost::Extra(_) => false,
                     ConnectionPost::Data(_, _) => true,         //nieodebrane dane z posta, zamknij połączenie
                     ConnectionPost::Reading(_,_,_,_) => {
                         unreachable!();
                     },
+                    ConnectionPost::Stream(_,_,_,_) => {
+                        unreachable!();
+                    },
                     ConnectionPost::Complete => false,
                 };
                 
@@ -91,10 +158,7 @@ impl Connection {
                     keep_alive
                 };
                 
-                let conn = Connection {
-                    stream : self.stream,
-                    mode   : ConnectionMode::SendingResponse(new_keep_alive, response.as_bytes(), 0),
-                };
+                let conn = Connection::make(self.stream, ConnectionMode::SendingResponse(new_keep_alive, response.into_output()));
                 
                 (conn, LogMessage::None)
             }
@@ -109,10 +173,372 @@ impl Connection {
     }
 
     
-    pub fn timeout_trigger(self) -> (Result<Connection, TcpStream>, LogMessage) {
+                                                    //odpowiedź na jeden ze strumieni HTTP/2
+    pub fn send_stream_response(self, stream_id: u32, response: response::Response) -> (Connection, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::Http2(mut http2) => {
+                
+                http2.respond(stream_id, response);
+                
+                (Connection::make(self.stream, ConnectionMode::Http2(http2)), LogMessage::None)
+            },
+            
+            _ => {
+                
+                (self, LogMessage::Error("send_stream_response: incorect state".to_owned()))
+            }
+        }
+    }
+    
+                                                    //odpowiedź 101 - połączenie przechodzi w tryb ramek WebSocket
+    pub fn upgrade_websocket(self, response: response::Response, socket: WebSocket, handler: Box<WebSocketHandler>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Extra(_)) |
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Complete) => {
+                
+                let ws = WsConnection::new(response.as_bytes(), socket, handler);
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::WebSocket(Box::new(ws)))), LogMessage::Message("websocket upgrade".to_owned()))
+            },
+            
+            ConnectionMode::WaitingForServerResponse(_, _) => {
+                
+                                                    //nieodebrane ciało requestu pomieszałoby się z ramkami
+                (Err(self.stream), LogMessage::Error("upgrade_websocket: request body not consumed".to_owned()))
+            },
+            
+            _ => {
+                
+                (Ok(self), LogMessage::Error("upgrade_websocket: incorect state".to_owned()))
+            }
+        }
+    }
+    
+                                                    //ramka od aplikacji przez uchwyt WebSocket
+    pub fn websocket_send(self, frame: Frame) -> (Connection, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::WebSocket(mut ws) => {
+                
+                ws.queue(frame);
+                
+                let mut stream = self.stream;
+                
+                let log_message = match ws.write_to(&mut stream) {
+                    Ok(()) => LogMessage::None,
+                    Err(err) => LogMessage::Error(format!("error write to socket, {:?}", err)),
+                };
+                
+                (Connection::make(stream, ConnectionMode::WebSocket(ws)), log_message)
+            },
+            
+                                                    //połączenie już zamknięte lub w innym trybie - ramka przepada
+            _ => (self, LogMessage::None),
+        }
+    }
+    
+                                                    //nagłówki text/event-stream, dalsze dane przez event_stream_send
+    pub fn start_event_stream(self, stream_id: Option<u32>, response: response::Response, guard: OpenGuard, chunked: bool) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match (self.mode, stream_id) {
+            
+            (ConnectionMode::Http2(mut http2), Some(stream_id)) => {
+                
+                http2.respond_event_stream(stream_id, response, guard);
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::Http2(http2))), LogMessage::None)
+            },
+            
+            (ConnectionMode::WaitingForServerResponse(_, ConnectionPost::None), None) |
+            (ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Extra(_)), None) |
+            (ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Complete), None) => {
+                
+                let mode = ConnectionMode::EventStream(Output::from_bytes(response.stream_head(chunked)), chunked, false, guard);
+                
+                (Ok(Connection::make(self.stream, mode)), LogMessage::Message("event stream started".to_owned()))
+            },
+            
+            (ConnectionMode::WaitingForServerResponse(_, _), None) => {
+                
+                                                    //nieodebrane ciało requestu - nie da się go już pominąć
+                (Err(self.stream), LogMessage::Error("start_event_stream: request body not consumed".to_owned()))
+            },
+            
+            (mode, _) => {
+                
+                (Ok(Connection::make(self.stream, mode)), LogMessage::Error("start_event_stream: incorect state".to_owned()))
+            }
+        }
+    }
+    
+                                                    //dane zdarzenia od EventStream, None - koniec strumienia
+    pub fn event_stream_send(self, stream_id: Option<u32>, data: Option<Vec<u8>>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match (self.mode, stream_id) {
+            
+            (ConnectionMode::Http2(mut http2), Some(stream_id)) => {
+                
+                http2.event_stream_data(stream_id, data);
+                
+                let mut stream = self.stream;
+                
+                if let Err(err) = http2.write_to(&mut stream) {
+                    return (Err(stream), LogMessage::Error(format!("error write to socket, {:?}", err)));
+                }
+                
+                (Ok(Connection::make(stream, ConnectionMode::Http2(http2))), LogMessage::None)
+            },
+            
+            (ConnectionMode::EventStream(mut output, chunked, end, guard), None) => {
+                
+                if end {
+                    return (Ok(Connection::make(self.stream, ConnectionMode::EventStream(output, chunked, end, guard))), LogMessage::None);
+                }
+                
+                let end = match data {
+                    Some(data) => {
+                        output.push_bytes(event_chunk(chunked, data));
+                        false
+                    },
+                    None => {
+                        output.push_bytes(event_end(chunked));
+                        true
+                    }
+                };
+                
+                if output.pending_bytes() > EVENT_LIMIT {
+                    return (Err(self.stream), LogMessage::Error("event stream client too slow".to_owned()));
+                }
+                
+                let mut stream = self.stream;
+                
+                if let Err(err) = output.write_to(&mut stream) {
+                    return (Err(stream), LogMessage::Error(format!("error write to socket, {:?}", err)));
+                }
+                
+                if end && output.is_empty() {
+                    return (Err(stream), LogMessage::Message("event stream closed".to_owned()));
+                }
+                
+                (Ok(Connection::make(stream, ConnectionMode::EventStream(output, chunked, end, guard))), LogMessage::None)
+            },
+            
+                                                    //połączenie już zamknięte lub w innym trybie - dane przepadają
+            (mode, _) => (Ok(Connection::make(self.stream, mode)), LogMessage::None),
+        }
+    }
+    
+                                                    //odpowiedź 101/200, dalej surowe dane obsługiwane przez handler
+    pub fn upgrade(self, response: response::Response, socket: Upgraded, guard: OpenGuard, handler: Box<UpgradeHandler>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        let buffered = match self.mode {
+            
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Complete) => Vec::new(),
+            
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Extra(buffered)) => buffered,
+            
+            ConnectionMode::WaitingForServerResponse(_, _) => {
+                
+                                                    //nieodebrane ciało requestu pomieszałoby się z danymi protokołu
+                return (Err(self.stream), LogMessage::Error("upgrade: request body not consumed".to_owned()));
+            },
+            
+            mode => {
+                
+                return (Ok(Connection::make(self.stream, mode)), LogMessage::Error("upgrade: incorect state".to_owned()));
+            }
+        };
+        
+        let upgraded = UpgradedConnection::new(response.upgrade_head(), buffered, socket, guard, handler);
+        
+        (Ok(Connection::make(self.stream, ConnectionMode::Upgraded(Box::new(upgraded)))), LogMessage::Message("connection upgraded".to_owned()))
+    }
+    
+                                                    //dane od aplikacji przez uchwyt Upgraded, None - zamknięcie
+    pub fn upgraded_send(self, data: Option<Vec<u8>>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::Upgraded(mut upgraded) => {
+                
+                upgraded.queue(data);
+                
+                transform_upgraded(self.stream, upgraded, false)
+            },
+            
+                                                    //połączenie już zamknięte lub w innym trybie - dane przepadają
+            mode => (Ok(Connection::make(self.stream, mode)), LogMessage::None),
+        }
+    }
+    
+                                                    //CONNECT - łączenie z celem, odpowiedź 200 dopiero po nawiązaniu połączenia
+    pub fn open_tunnel(self, addr: SocketAddr, token: Token, response: response::Response, idle_timeout: u64) -> (Result<Connection, Stream>, LogMessage) {
+        
+        let buffered = match self.mode {
+            
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Complete) => Vec::new(),
+            
+            ConnectionMode::WaitingForServerResponse(_, ConnectionPost::Extra(buffered)) => buffered,
+            
+            ConnectionMode::WaitingForServerResponse(_, _) => {
+                return (Err(self.stream), LogMessage::Error("open_tunnel: request body not consumed".to_owned()));
+            },
+            
+            mode => {
+                return (Ok(Connection::make(self.stream, mode)), LogMessage::Error("open_tunnel: incorect state".to_owned()));
+            }
+        };
+        
+        match TunnelConnection::connect(&addr, token, response, buffered, idle_timeout) {
+            
+            Ok(tunnel) => {
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::Tunnel(Box::new(tunnel)))), LogMessage::Message(format!("tunnel connecting to {}", addr)))
+            },
+            
+            Err(err) => {
+                
+                let response = response::Response::create(Code::Code502, Type::TextHtml, "502 Bad Gateway".to_owned());
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::SendingResponse(false, response.into_output()))), LogMessage::Error(format!("tunnel connect to {}, {:?}", addr, err)))
+            }
+        }
+    }
+    
+                                                    //reverse proxy - socket z puli albo nowe połączenie, ciało requestu płynie dalej od klienta
+    pub fn open_proxy(self, target: ProxyTarget, token: Token, socket: Option<TcpStream>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        let (keep_alive, mut initial) = match self.mode {
+            
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Complete) => (keep_alive, Vec::new()),
+            
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Extra(buffered)) => (keep_alive, buffered),
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Data(buffered, _)) => (keep_alive, buffered),
+            
+            ConnectionMode::WaitingForServerResponse(_, _) => {
+                return (Err(self.stream), LogMessage::Error("open_proxy: request body already consumed".to_owned()));
+            },
+            
+            mode => {
+                return (Ok(Connection::make(self.stream, mode)), LogMessage::Error("open_proxy: incorect state".to_owned()));
+            }
+        };
+        
+                                                    //za ciałem był już kolejny request - przepada, więc połączenie zostanie zamknięte
+        let keep_alive = if initial.len() as u64 > target.body_len {
+            initial.truncate(target.body_len as usize);
+            false
+        } else {
+            keep_alive
+        };
+        
+        let addr = target.addr;
+        
+        match ProxyExchange::start(target, token, socket, initial, keep_alive) {
+            
+            Ok(exchange) => {
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::Proxy(Box::new(exchange)))), LogMessage::Message(format!("proxy to {}", addr)))
+            },
+            
+            Err(err) => {
+                
+                let response = response::Response::create(Code::Code502, Type::TextHtml, "502 Bad Gateway".to_owned());
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::SendingResponse(false, response.into_output()))), LogMessage::Error(err))
+            }
+        }
+    }
+    
+                                                    //gniazdo wychodzące do rejestracji w event_loop-ie: socket, jego token, oczekiwane zdarzenie
+    pub fn outbound(&self) -> Option<(&TcpStream, Token, Event)> {
+        
+        match self.mode {
+            
+            ConnectionMode::Tunnel(ref tunnel) => {
+                
+                let event = match tunnel.upstream_interest() {
+                    (true, true) => Event::ReadWrite,
+                    (true, false) => Event::Read,
+                    (false, true) => Event::Write,
+                    (false, false) => Event::None,
+                };
+                
+                Some((tunnel.upstream(), tunnel.token(), event))
+            },
+            
+            ConnectionMode::Proxy(ref proxy) => {
+                
+                let event = match proxy.upstream_interest() {
+                    (true, true) => Event::ReadWrite,
+                    (true, false) => Event::Read,
+                    (false, true) => Event::Write,
+                    (false, false) => Event::None,
+                };
+                
+                Some((proxy.upstream(), proxy.token(), event))
+            },
+            
+            _ => None,
+        }
+    }
+    
+                                                    //zdarzenie na gnieździe wychodzącym
+    pub fn ready_outbound(self, server_down: bool) -> (Result<Connection, Stream>, LogMessage) {
         
         match self.mode {
             
+            ConnectionMode::Tunnel(tunnel) => transform_tunnel(self.stream, tunnel),
+            
+            ConnectionMode::Proxy(proxy) => transform_proxy(self.stream, proxy, server_down),
+            
+            mode => (Ok(Connection::make(self.stream, mode)), LogMessage::None),
+        }
+    }
+    
+                                                    //socket z zakończonej wymiany proxy, do oddania do puli serwera
+    pub fn take_released(&mut self) -> Option<IdleSocket> {
+        self.released.take()
+    }
+    
+                                                    //requesty ze strumieni HTTP/2 odebranych w całości
+    pub fn take_requests(&mut self) -> Vec<PreRequest> {
+        
+        match self.mode {
+            ConnectionMode::Http2(ref mut http2) => {
+                
+                let remote_addr  = self.stream.tcp().peer_addr().ok();
+                let mut requests = http2.take_requests();
+                
+                for request in requests.iter_mut() {
+                    request.set_remote_addr(remote_addr);
+                }
+                
+                requests
+            },
+            _ => Vec::new(),
+        }
+    }
+    
+    
+    pub fn timeout_trigger(self, server_down: bool) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::Handshake => {
+                
+                (Err(self.stream), LogMessage::Message("timeout trigger - tls handshake".to_owned()))
+            },
+            
             ConnectionMode::ReadingRequest(_,_) => {
                 
                 (Err(self.stream), LogMessage::Message("timeout trigger - reading request".to_owned()))
@@ -127,13 +553,141 @@ impl Connection {
                 (Ok(Connection::make(self.stream, new_mode)), LogMessage::Message("timeout trigger - reading post data".to_owned()))
             },
             
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Stream(received, len, request, reader)) => {
+                
+                reader.finish(request, false);
+                
+                                                    //reszta ciała zostaje w sockecie, po odpowiedzi połączenie zostanie zamknięte
+                let new_mode = ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Data(Vec::new(), len - received));
+                
+                (Ok(Connection::make(self.stream, new_mode)), LogMessage::Message("timeout trigger - streaming post data".to_owned()))
+            },
+            
             ConnectionMode::WaitingForServerResponse(_,_) => {
                 unreachable!();
             },
             
-            ConnectionMode::SendingResponse(_,_,_) => {
+            ConnectionMode::SendingResponse(keep_alive, mut output) => {
+                
+                                                    //duże odpowiedzi - timeout liczony od ostatniego postępu
+                if output.has_progress() {
+                    return (Ok(Connection::make(self.stream, ConnectionMode::SendingResponse(keep_alive, output))), LogMessage::None);
+                }
                 
                 (Err(self.stream), LogMessage::Message("timeout trigger - sending request".to_owned()))
+            },
+            
+            ConnectionMode::Http2(mut http2) => {
+                
+                let mut stream = self.stream;
+                
+                if http2.is_idle() {
+                    
+                    http2.shutdown();
+                    let _ = http2.write_to(&mut stream);
+                    
+                    return (Err(stream), LogMessage::Message("timeout trigger - http2 idle".to_owned()));
+                }
+                
+                if http2.wants_write() && http2.has_progress() == false {
+                    return (Err(stream), LogMessage::Message("timeout trigger - http2 sending".to_owned()));
+                }
+                
+                if server_down {
+                    http2.shutdown();
+                } else {
+                    http2.heartbeat();
+                }
+                
+                if let Err(err) = http2.write_to(&mut stream) {
+                    return (Err(stream), LogMessage::Error(format!("error write to socket, {:?}", err)));
+                }
+                
+                (Ok(Connection::make(stream, ConnectionMode::Http2(http2))), LogMessage::None)
+            },
+            
+            ConnectionMode::WebSocket(mut ws) => {
+                
+                if ws.is_closing() {
+                    
+                    ws.abort();
+                    
+                    return (Err(self.stream), LogMessage::Message("timeout trigger - websocket closing".to_owned()));
+                }
+                
+                if (ws.wants_write() || self.stream.wants_write()) && ws.has_progress() == false {
+                    
+                    ws.abort();
+                    
+                    return (Err(self.stream), LogMessage::Message("timeout trigger - websocket sending".to_owned()));
+                }
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::WebSocket(ws))), LogMessage::None)
+            },
+            
+            ConnectionMode::EventStream(mut output, chunked, end, guard) => {
+                
+                if output.is_empty() == false && output.has_progress() == false {
+                    return (Err(self.stream), LogMessage::Message("timeout trigger - event stream sending".to_owned()));
+                }
+                
+                let end = if end {
+                    true
+                } else if server_down {
+                    output.push_bytes(event_end(chunked));
+                    true
+                } else {
+                    output.push_bytes(event_chunk(chunked, event_stream::format_comment("")));
+                    false
+                };
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::EventStream(output, chunked, end, guard))), LogMessage::None)
+            },
+            
+            ConnectionMode::Upgraded(mut upgraded) => {
+                
+                if upgraded.wants_write() && upgraded.has_progress() == false {
+                    return (Err(self.stream), LogMessage::Message("timeout trigger - upgraded sending".to_owned()));
+                }
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::Upgraded(upgraded))), LogMessage::None)
+            },
+            
+            ConnectionMode::Tunnel(mut tunnel) => {
+                
+                if tunnel.is_connected() == false {
+                    
+                    let response = response::Response::create(Code::Code504, Type::TextHtml, "504 Gateway Timeout".to_owned());
+                    let new_mode = ConnectionMode::SendingResponse(false, response.into_output());
+                    
+                    return (Ok(Connection::make(self.stream, new_mode)), LogMessage::Message("timeout trigger - tunnel connecting".to_owned()));
+                }
+                
+                if tunnel.has_activity() == false {
+                    return (Err(self.stream), LogMessage::Message("timeout trigger - tunnel idle".to_owned()));
+                }
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::Tunnel(tunnel))), LogMessage::None)
+            },
+            
+            ConnectionMode::Proxy(mut proxy) => {
+                
+                if proxy.has_activity() {
+                    return (Ok(Connection::make(self.stream, ConnectionMode::Proxy(proxy))), LogMessage::None);
+                }
+                
+                                                    //odpowiedź jeszcze nie ruszyła - klient dostaje 504
+                if proxy.is_responding() == false {
+                    
+                    proxy.timed_out();
+                    
+                    let response = response::Response::create(Code::Code504, Type::TextHtml, "504 Gateway Timeout".to_owned());
+                    let new_mode = ConnectionMode::SendingResponse(false, response.into_output());
+                    
+                    return (Ok(Connection::make(self.stream, new_mode)), LogMessage::Message("timeout trigger - proxy waiting for upstream".to_owned()));
+                }
+                
+                (Err(self.stream), LogMessage::Message("timeout trigger - proxy idle".to_owned()))
             }
         }
     }
@@ -143,19 +697,81 @@ impl Connection {
 
         match self.mode {
             
+            ConnectionMode::Handshake => {
+                if self.stream.wants_write() {
+                    Event::Write
+                } else {
+                    Event::Read
+                }
+            },
+            
             ConnectionMode::ReadingRequest(_, _) => Event::Read,
             
             ConnectionMode::WaitingForServerResponse(_, ref connection_post) => {
                 
                 match *connection_post {
                     ConnectionPost::None => Event::None,
+                    ConnectionPost::Extra(_) => Event::None,
                     ConnectionPost::Data(_,_) => Event::None,
                     ConnectionPost::Reading(_,_,_,_) => Event::Read,
+                    ConnectionPost::Stream(_,_,_,_) => Event::Read,
                     ConnectionPost::Complete => Event::None,
                 }
                 
             },
-            ConnectionMode::SendingResponse(_, _, _) => Event::Write,
+            ConnectionMode::SendingResponse(_, _) => Event::Write,
+            
+            ConnectionMode::Http2(ref http2) => {
+                if http2.wants_write() || self.stream.wants_write() {
+                    Event::Write
+                } else {
+                    Event::Read
+                }
+            },
+            
+            ConnectionMode::WebSocket(ref ws) => {
+                if ws.wants_write() || self.stream.wants_write() {
+                    Event::Write
+                } else {
+                    Event::Read
+                }
+            },
+            
+                                                    //czytanie tylko po to, żeby zauważyć rozłączenie klienta
+            ConnectionMode::EventStream(ref output, _, _, _) => {
+                if output.is_empty() == false || self.stream.wants_write() {
+                    Event::Write
+                } else {
+                    Event::Read
+                }
+            },
+            
+            ConnectionMode::Upgraded(ref upgraded) => {
+                match (upgraded.wants_read(), upgraded.wants_write() || self.stream.wants_write()) {
+                    (true, true) => Event::ReadWrite,
+                    (true, false) => Event::Read,
+                    (false, true) => Event::Write,
+                    (false, false) => Event::None,
+                }
+            },
+            
+            ConnectionMode::Tunnel(ref tunnel) => {
+                match (tunnel.client_wants_read(), tunnel.client_wants_write() || self.stream.wants_write()) {
+                    (true, true) => Event::ReadWrite,
+                    (true, false) => Event::Read,
+                    (false, true) => Event::Write,
+                    (false, false) => Event::None,
+                }
+            },
+            
+            ConnectionMode::Proxy(ref proxy) => {
+                match (proxy.client_wants_read(), proxy.client_wants_write() || self.stream.wants_write()) {
+                    (true, true) => Event::ReadWrite,
+                    (true, false) => Event::Read,
+                    (false, true) => Event::Write,
+                    (false, false) => Event::None,
+                }
+            },
         }
     }
     
@@ -163,20 +779,69 @@ impl Connection {
         
         match self.mode {
             
+            ConnectionMode::Handshake => TimerMode::In,
+            
             ConnectionMode::ReadingRequest(_, _) => TimerMode::In,
             
             ConnectionMode::WaitingForServerResponse(_, ref connection_post) => {
                 
                 match *connection_post {
                     ConnectionPost::None => TimerMode::None,
+                    ConnectionPost::Extra(_) => TimerMode::None,
                     ConnectionPost::Data(_,_) => TimerMode::None,
                     ConnectionPost::Reading(_,_,_,_) => TimerMode::Post,
+                    ConnectionPost::Stream(_,_,_,_) => TimerMode::Post,
                     ConnectionPost::Complete => TimerMode::None,
                 }
                 
             },
             
-            ConnectionMode::SendingResponse(_, _, _) => TimerMode::Out,
+            ConnectionMode::SendingResponse(_, _) => TimerMode::Out,
+            
+            ConnectionMode::Http2(ref http2) => {
+                if http2.wants_write() || self.stream.wants_write() {
+                    TimerMode::Out
+                } else if http2.is_idle() {
+                    TimerMode::In
+                } else if http2.has_event_streams() {
+                    TimerMode::Heartbeat
+                } else {
+                    TimerMode::None
+                }
+            },
+            
+            ConnectionMode::WebSocket(ref ws) => {
+                if ws.wants_write() || self.stream.wants_write() {
+                    TimerMode::Out
+                } else if ws.is_closing() {
+                    TimerMode::In
+                } else {
+                    TimerMode::None
+                }
+            },
+            
+                                                    //ten sam timer pilnuje też wysyłania - patrz timeout_trigger
+            ConnectionMode::EventStream(_, _, _, _) => TimerMode::Heartbeat,
+            
+                                                    //bezczynność obsługuje protokół aplikacji
+            ConnectionMode::Upgraded(ref upgraded) => {
+                if upgraded.wants_write() || self.stream.wants_write() {
+                    TimerMode::Out
+                } else {
+                    TimerMode::None
+                }
+            },
+            
+                                                    //nawiązywanie połączenia pilnuje timeout czytania
+            ConnectionMode::Tunnel(ref tunnel) => {
+                if tunnel.is_connected() {
+                    TimerMode::Idle(tunnel.idle_timeout())
+                } else {
+                    TimerMode::In
+                }
+            },
+            
+            ConnectionMode::Proxy(ref proxy) => TimerMode::Idle(proxy.timeout()),
         }
     }
     
@@ -184,37 +849,95 @@ impl Connection {
         
         match self.mode {
             
+            ConnectionMode::Handshake => "Handshake",
+            
             ConnectionMode::ReadingRequest(_, _) => "ReadingRequest",
             
             ConnectionMode::WaitingForServerResponse(_, ref connection_post) => {
                 
                 match *connection_post {
                     ConnectionPost::None => "WaitingForServerResponse (post none)",
+                    ConnectionPost::Extra(_) => "WaitingForServerResponse (post extra)",
                     ConnectionPost::Data(_,_) => "WaitingForServerResponse (post data)",
                     ConnectionPost::Reading(_,_,_,_) => "WaitingForServerResponse (post reading)",
+                    ConnectionPost::Stream(_,_,_,_) => "WaitingForServerResponse (post stream)",
                     ConnectionPost::Complete => "WaitingForServerResponse (post complete)",
                 }
             },
             
-            ConnectionMode::SendingResponse(_, _, _) => "SendingResponse"
+            ConnectionMode::SendingResponse(_, _) => "SendingResponse",
+            
+            ConnectionMode::Http2(_) => "Http2",
+            
+            ConnectionMode::WebSocket(_) => "WebSocket",
+            
+            ConnectionMode::EventStream(_, _, _, _) => "EventStream",
+            
+            ConnectionMode::Upgraded(_) => "Upgraded",
+            
+            ConnectionMode::Tunnel(_) => "Tunnel",
+            
+            ConnectionMode::Proxy(_) => "Proxy",
+        }
+    }
+    
+    
+    pub fn set_callback_post(self, request: Request, callback: Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>) -> (Result<Connection, Stream>, LogMessage) {
+        
+        match self.mode {
+            
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Extra(_)) => {
+                
+                                                    //request bez ciała - od razu zwracamy pusty bufor
+                (callback as Box<FnBox(Request, Option<Vec<u8>>)>)(request, Some(Vec::new()));
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Complete))), LogMessage::None)
+            },
+            
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Data(vector, len)) => {
+                
+                (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Reading(vector, len, request, callback)))), LogMessage::None)
+            },
+                        
+            _ => {
+                (Err(self.stream), LogMessage::Error("Nieprawidłowy stan".to_owned()))
+            }
         }
     }
     
     
-    pub fn set_callback_post(self, request: Request, callback: Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>) -> (Result<Connection, TcpStream>, LogMessage) {
+    pub fn set_body_reader(self, request: Request, mut reader: Box<BodyReader>) -> (Result<Connection, Stream>, LogMessage) {
         
         match self.mode {
             
-            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::None) => {
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::None) |
+            ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Extra(_)) => {
+                
+                reader.finish(request, true);
                 
                 (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Complete))), LogMessage::None)
             },
             
             ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Data(vector, len)) => {
                 
-                (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Reading(vector, len, request, callback)))), LogMessage::None)
+                                                    //dane które przyszły razem z nagłówkami
+                if reader.data(&vector) {
+                    
+                    let connection_post = ConnectionPost::Stream(vector.len(), len, request, reader);
+                    
+                    (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, connection_post))), LogMessage::None)
+                    
+                } else {
+                    
+                    reader.finish(request, false);
+                    
+                    let connection_post = ConnectionPost::Data(Vec::new(), len - vector.len());
+                    
+                    (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, connection_post))), LogMessage::Message("body reader stopped".to_owned()))
+                }
             },
-                        
+            
             _ => {
                 (Err(self.stream), LogMessage::Error("Nieprawidłowy stan".to_owned()))
             }
@@ -222,7 +945,7 @@ impl Connection {
     }
     
     
-    pub fn ready(mut self, events: EventSet, server_down: bool) -> (Result<Connection, TcpStream>, Option<PreRequest>, LogMessage) {
+    pub fn ready(mut self, events: EventSet, server_down: bool) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
         
         if events.is_error() {
             
@@ -241,6 +964,34 @@ impl Connection {
         
         match self.mode {
             
+            ConnectionMode::Handshake => {
+                
+                match self.stream.handshake() {
+                    
+                    Ok(true) => {
+                        
+                        let tls   = self.stream.tls_info();
+                        let is_h2 = tls.as_ref().and_then(|tls| tls.alpn_protocol()).map(|protocol| protocol == "h2") == Some(true);
+                        
+                                                    //protokół wybrany przez ALPN
+                        if is_h2 {
+                            return transform_http2(self.stream, Box::new(Http2::new(tls, Vec::new())), server_down);
+                        }
+                        
+                                                    //dalsze dane (request) mogą już leżeć w buforze - odczyta je serwer przez has_buffered_input
+                        (Ok(Connection::make(self.stream, ConnectionMode::ReadingRequest([0u8; 2048], 0))), None, LogMessage::Message("tls handshake complete".to_owned()))
+                    },
+                    
+                    Ok(false) => {
+                        (Ok(Connection::make(self.stream, ConnectionMode::Handshake)), None, LogMessage::None)
+                    },
+                    
+                    Err(err) => {
+                        (Err(self.stream), None, LogMessage::Error(format!("tls handshake error, {:?}", err)))
+                    }
+                }
+            },
+            
             ConnectionMode::ReadingRequest(buf, done) => {
 
                 transform_from_waiting_for_user(self.stream, events, buf, done)
@@ -268,6 +1019,15 @@ impl Connection {
                         
                         match self.stream.try_read(&mut buf[0..max_index]) {
 
+                            Ok(Some(0)) => {
+                                
+                                (callback_post as Box<FnBox(Request, Option<Vec<u8>>)>)(request, None);
+                                
+                                let message = format!("connection closed before end of post, received = {}, len = {}", vec.len(), len);
+                                
+                                return (Err(self.stream), None, LogMessage::Message(message));
+                            },
+
                             Ok(Some(size)) => {
                                 
                                 if size > max_index {
@@ -303,16 +1063,57 @@ impl Connection {
                     return (Ok(new_conn), None, LogMessage::None);
                 }
                 
+                if let ConnectionPost::Stream(received, len, request, reader) = connection_post {
+                    
+                    return read_body_stream(self.stream, keep_alive, received, len, request, reader);
+                }
+                
                 
                 (Ok(Connection::make(self.stream, ConnectionMode::WaitingForServerResponse(keep_alive, connection_post))), None, LogMessage::None)
             },
             
-            ConnectionMode::SendingResponse(keep_alive, str, done) => {
+            ConnectionMode::SendingResponse(keep_alive, output) => {
 
-                let (new_conn, log_mess) = transform_from_sending_to_user(self.stream, keep_alive, events, str, done, server_down);
+                let (new_conn, log_mess) = transform_from_sending_to_user(self.stream, keep_alive, events, output, server_down);
                 
                 (new_conn, None, log_mess)
             },
+            
+            ConnectionMode::Http2(http2) => {
+                
+                transform_http2(self.stream, http2, server_down)
+            },
+            
+            ConnectionMode::WebSocket(ws) => {
+                
+                transform_websocket(self.stream, ws, server_down)
+            },
+            
+            ConnectionMode::EventStream(output, chunked, end, guard) => {
+                
+                transform_event_stream(self.stream, output, chunked, end, guard, server_down)
+            },
+            
+            ConnectionMode::Upgraded(upgraded) => {
+                
+                let (conn, log_message) = transform_upgraded(self.stream, upgraded, server_down);
+                
+                (conn, None, log_message)
+            },
+            
+            ConnectionMode::Tunnel(tunnel) => {
+                
+                let (conn, log_message) = transform_tunnel(self.stream, tunnel);
+                
+                (conn, None, log_message)
+            },
+            
+            ConnectionMode::Proxy(proxy) => {
+                
+                let (conn, log_message) = transform_proxy(self.stream, proxy, server_down);
+                
+                (conn, None, log_message)
+            },
         }
     }
     
@@ -336,12 +1137,306 @@ impl Connection {
             return Connection::make(self.stream, new_mode);
         }
         
+        if let ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Stream(received, len, request, reader)) = self.mode {
+            
+            let new_mode = if received == len {
+                
+                reader.finish(request, true);
+                ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Complete)
+                
+            } else {
+                
+                ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Stream(received, len, request, reader))
+            };
+            
+            return Connection::make(self.stream, new_mode);
+        }
+        
         self
     }
 
 }
 
-fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut buf: [u8; 2048], done: usize) -> (Result<Connection, TcpStream>, Option<PreRequest>, LogMessage) {
+fn read_body_stream(mut stream: Stream, keep_alive: bool, mut received: usize, len: usize, request: Request, mut reader: Box<BodyReader>) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
+    
+    let mut buf : [u8; 2048] = [0; 2048];
+    
+    while received < len {
+        
+        let max_index = min(len - received, 2048);
+        
+        match stream.try_read(&mut buf[0..max_index]) {
+            
+            Ok(Some(0)) => {
+                
+                                                    //klient zamknął połączenie przed końcem ciała
+                reader.finish(request, false);
+                
+                let message = format!("connection closed before end of body, received = {}, len = {}", received, len);
+                
+                return (Err(stream), None, LogMessage::Message(message));
+            },
+            
+            Ok(Some(size)) => {
+                
+                received = received + size;
+                
+                if reader.data(&buf[0..size]) == false {
+                    
+                    reader.finish(request, false);
+                    
+                    let connection_post = ConnectionPost::Data(Vec::new(), len - received);
+                    let new_conn        = Connection::make(stream, ConnectionMode::WaitingForServerResponse(keep_alive, connection_post));
+                    
+                    return (Ok(new_conn), None, LogMessage::Message("body reader stopped".to_owned()));
+                }
+            },
+            
+            Ok(None) => {
+                break;
+            },
+            
+            Err(err) => {
+                
+                let message  = format!("error read from socket, {:?}", err);
+                let new_conn = Connection::make(stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Stream(received, len, request, reader)));
+                
+                return (Ok(new_conn), None, LogMessage::Error(message));
+            }
+        }
+    }
+    
+                                                    //zakończenie obsługuje check_post
+    let new_conn = Connection::make(stream, ConnectionMode::WaitingForServerResponse(keep_alive, ConnectionPost::Stream(received, len, request, reader)));
+    
+    (Ok(new_conn), None, LogMessage::None)
+}
+
+
+                                                    //HTTP/2 nie zależy od rodzaju zdarzenia - zawsze czytamy, przetwarzamy ramki i wysyłamy co się da
+fn transform_http2(mut stream: Stream, mut http2: Box<Http2>, server_down: bool) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
+    
+    if server_down {
+        http2.shutdown();
+    }
+    
+    match http2.read_from(&mut stream) {
+        
+        Ok(true) => {},
+        
+        Ok(false) => {
+            return (Err(stream), None, LogMessage::Message("http2 connection closed by client".to_owned()));
+        },
+        
+        Err(err) => {
+            return (Err(stream), None, LogMessage::Error(format!("error read from socket, {:?}", err)));
+        }
+    }
+    
+                                                    //przetwarzanie wstrzymane przy pełnym output wznawiamy, gdy socket przyjął dane
+    let mut log_message = LogMessage::None;
+    
+    loop {
+        
+        if let Err(message) = http2.process() {
+            log_message = LogMessage::Error(format!("http2 error, {}", message));
+        }
+        
+        if let Err(err) = http2.write_to(&mut stream) {
+            return (Err(stream), None, LogMessage::Error(format!("error write to socket, {:?}", err)));
+        }
+        
+        if http2.wants_process() == false {
+            break;
+        }
+    }
+    
+    if http2.is_finished() {
+        return (Err(stream), None, log_message);
+    }
+    
+    (Ok(Connection::make(stream, ConnectionMode::Http2(http2))), None, log_message)
+}
+
+
+                                                    //tak jak HTTP/2 - czytanie, ramki, wysyłanie niezależnie od rodzaju zdarzenia
+fn transform_websocket(mut stream: Stream, mut ws: Box<WsConnection>, server_down: bool) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
+    
+    if server_down {
+        ws.going_away();
+    }
+    
+    match ws.read_from(&mut stream) {
+        
+        Ok(true) => {},
+        
+        Ok(false) => {
+            ws.abort();
+            return (Err(stream), None, LogMessage::Message("websocket connection closed by client".to_owned()));
+        },
+        
+        Err(err) => {
+            ws.abort();
+            return (Err(stream), None, LogMessage::Error(format!("error read from socket, {:?}", err)));
+        }
+    }
+    
+    let log_message = match ws.process() {
+        Ok(()) => LogMessage::None,
+        Err(message) => LogMessage::Error(format!("websocket error, {}", message)),
+    };
+    
+    if let Err(err) = ws.write_to(&mut stream) {
+        ws.abort();
+        return (Err(stream), None, LogMessage::Error(format!("error write to socket, {:?}", err)));
+    }
+    
+    if ws.is_finished() {
+        return (Err(stream), None, log_message);
+    }
+    
+    (Ok(Connection::make(stream, ConnectionMode::WebSocket(ws))), None, log_message)
+}
+
+
+                                                    //zdarzenie jako kawałek chunked albo surowe dane (HTTP/1.0)
+fn event_chunk(chunked: bool, data: Vec<u8>) -> Vec<u8> {
+    if chunked { compression::chunk(data) } else { data }
+}
+
+
+                                                    //bez chunked koniec ciała oznacza samo zamknięcie połączenia
+fn event_end(chunked: bool) -> Vec<u8> {
+    if chunked { b"0\r\n\r\n".to_vec() } else { Vec::new() }
+}
+
+
+fn transform_event_stream(mut stream: Stream, mut output: Output, chunked: bool, mut end: bool, guard: OpenGuard, server_down: bool) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
+    
+    if server_down && end == false {
+        output.push_bytes(event_end(chunked));
+        end = true;
+    }
+    
+                                                    //klient nic już nie wysyła - dane są pomijane, liczy się tylko koniec połączenia
+    let mut buf = [0u8; 2048];
+    
+    loop {
+        match stream.try_read(&mut buf) {
+            Ok(Some(0)) => {
+                return (Err(stream), None, LogMessage::Message("event stream closed by client".to_owned()));
+            },
+            Ok(Some(_)) => {},
+            Ok(None) => break,
+            Err(err) => {
+                return (Err(stream), None, LogMessage::Error(format!("error read from socket, {:?}", err)));
+            }
+        }
+    }
+    
+    if let Err(err) = output.write_to(&mut stream) {
+        return (Err(stream), None, LogMessage::Error(format!("error write to socket, {:?}", err)));
+    }
+    
+    if end && output.is_empty() {
+        return (Err(stream), None, LogMessage::Message("event stream closed".to_owned()));
+    }
+    
+    (Ok(Connection::make(stream, ConnectionMode::EventStream(output, chunked, end, guard))), None, LogMessage::None)
+}
+
+
+fn transform_upgraded(mut stream: Stream, mut upgraded: Box<UpgradedConnection>, server_down: bool) -> (Result<Connection, Stream>, LogMessage) {
+    
+    if server_down {
+        upgraded.queue(None);
+    }
+    
+    if let Err(err) = upgraded.read_from(&mut stream) {
+        return (Err(stream), LogMessage::Error(format!("error read from socket, {:?}", err)));
+    }
+    
+    if let Err(err) = upgraded.write_to(&mut stream) {
+        return (Err(stream), LogMessage::Error(format!("error write to socket, {:?}", err)));
+    }
+    
+    if upgraded.is_overloaded() {
+        return (Err(stream), LogMessage::Error("upgraded connection client too slow".to_owned()));
+    }
+    
+    if upgraded.is_finished() {
+        return (Err(stream), LogMessage::Message("upgraded connection closed".to_owned()));
+    }
+    
+    (Ok(Connection::make(stream, ConnectionMode::Upgraded(upgraded))), LogMessage::None)
+}
+
+
+fn transform_tunnel(mut stream: Stream, mut tunnel: Box<TunnelConnection>) -> (Result<Connection, Stream>, LogMessage) {
+    
+    match tunnel.pump(&mut stream) {
+        
+        Ok(tunnel::Step::Open) => {
+            (Ok(Connection::make(stream, ConnectionMode::Tunnel(tunnel))), LogMessage::None)
+        },
+        
+                                                    //gniazdo wychodzące znika razem z tunelem
+        Ok(tunnel::Step::Failed(response, message)) => {
+            (Ok(Connection::make(stream, ConnectionMode::SendingResponse(false, response.into_output()))), LogMessage::Error(format!("tunnel connect failed, {}", message)))
+        },
+        
+        Ok(tunnel::Step::Closed) => {
+            (Err(stream), LogMessage::Message("tunnel closed".to_owned()))
+        },
+        
+        Err(err) => {
+            (Err(stream), LogMessage::Error(format!("tunnel error, {:?}", err)))
+        }
+    }
+}
+
+
+fn transform_proxy(mut stream: Stream, mut proxy: Box<ProxyExchange>, server_down: bool) -> (Result<Connection, Stream>, LogMessage) {
+    
+    match proxy.pump(&mut stream) {
+        
+        Ok(proxy::Step::Open) => {
+            (Ok(Connection::make(stream, ConnectionMode::Proxy(proxy))), LogMessage::None)
+        },
+        
+        Ok(proxy::Step::Failed(response, message)) => {
+            (Ok(Connection::make(stream, ConnectionMode::SendingResponse(false, response.into_output()))), LogMessage::Error(format!("proxy upstream failed, {}", message)))
+        },
+        
+                                                    //klient może wysłać kolejny request, połączenie z serwerem docelowym wraca do puli
+        Ok(proxy::Step::Done(keep_alive)) => {
+            
+            if server_down == false && keep_alive == true {
+                
+                let mut new_conn = Connection::make(stream, ConnectionMode::ReadingRequest([0u8; 2048], 0));
+                
+                new_conn.released = (*proxy).finish();
+                
+                (Ok(new_conn), LogMessage::Message("proxy keep alive".to_owned()))
+                
+            } else {
+                
+                (Err(stream), LogMessage::None)
+            }
+        },
+        
+        Ok(proxy::Step::Closed) => {
+            (Err(stream), LogMessage::Error("proxy response interrupted".to_owned()))
+        },
+        
+        Err(err) => {
+            (Err(stream), LogMessage::Error(format!("proxy error, {:?}", err)))
+        }
+    }
+}
+
+
+fn transform_from_waiting_for_user(mut stream: Stream, events: EventSet, mut buf: [u8; 2048], done: usize) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
 
     if events.is_readable() {
 
@@ -355,16 +1450,34 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
 
                     let done = done + size;
                     
+                                                    //h2c - klient od razu mówi HTTP/2 (prior knowledge), tylko bez TLS
+                    if stream.is_plain() {
+                        
+                        let prefix = min(done, http2::PREFACE.len());
+                        
+                        if &buf[0..prefix] == &http2::PREFACE[0..prefix] {
+                            
+                            if prefix < http2::PREFACE.len() {
+                                return (Ok(Connection::make(stream, ConnectionMode::ReadingRequest(buf, done))), None, LogMessage::None);
+                            }
+                            
+                            return transform_http2(stream, Box::new(Http2::new(None, buf[0..done].to_vec())), false);
+                        }
+                    }
+                    
                     let mut headers = [httparse::EMPTY_HEADER; 100];
                     let mut req     = httparse::Request::new(&mut headers);
                     
-                    match req.parse(&buf[0..size]) {
+                    match req.parse(&buf[0..done]) {
                         
                         Ok(httparse::Status::Complete(size_parse)) => {
                             
                             match PreRequest::new(req) {
 
-                                Ok(pre_request) => {
+                                Ok(mut pre_request) => {
+                                    
+                                    pre_request.set_tls(stream.tls_info());
+                                    pre_request.set_remote_addr(stream.tcp().peer_addr().ok());
                                     
                                     
                                     let keep_alive = pre_request.is_header_set("Connection", "keep-alive");
@@ -372,11 +1485,11 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
                                     
                                     let connection_post = if pre_request.is_post() {
                                         
-                                        if size > size_parse {
+                                        if done > size_parse {
                                             
                                             let mut post_data = vec![];
                                             
-                                            post_data.extend_from_slice(&buf[size_parse..size]);
+                                            post_data.extend_from_slice(&buf[size_parse..done]);
                                             
                                             
                                             if let Some(req_len) = pre_request.get_header("Content-Length".to_owned()) {
@@ -388,22 +1501,31 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
                                                 } else {
                                                     
                                                     let response_400 = response::Response::create_400();
-                                                    let new_connection = ConnectionMode::SendingResponse(false, response_400.as_bytes(), 0);
+                                                    let new_connection = ConnectionMode::SendingResponse(false, response_400.into_output());
                                                     return (Ok(Connection::make(stream, new_connection)), None, LogMessage::None)
                                                 }
                                                 
                                             } else {
                                                 
                                                 let response_400 = response::Response::create_400();
-                                                let new_connection = ConnectionMode::SendingResponse(false, response_400.as_bytes(), 0);
+                                                let new_connection = ConnectionMode::SendingResponse(false, response_400.into_output());
                                                 return (Ok(Connection::make(stream, new_connection)), None, LogMessage::None)
                                             }
                                             
                                         } else {
                                             
-                                            ConnectionPost::None
+                                                            //ciało posta nie zdążyło jeszcze dotrzeć
+                                            match pre_request.get_header("Content-Length".to_owned()) {
+                                                Some(req_len) if req_len > 0 => ConnectionPost::Data(vec![], req_len),
+                                                _ => ConnectionPost::None,
+                                            }
                                         }
                                         
+                                    } else if done > size_parse {
+                                        
+                                                            //kolejny request lub dane protokołu po przejęciu połączenia
+                                        ConnectionPost::Extra(buf[size_parse..done].to_vec())
+                                        
                                     } else {
                                         
                                         ConnectionPost::None
@@ -419,7 +1541,7 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
                                     let log_mess = format!("error prepare request, {:?}", err);
                                     
                                     let response_400   = response::Response::create_400();
-                                    let new_connection = ConnectionMode::SendingResponse(false, response_400.as_bytes(), 0);
+                                    let new_connection = ConnectionMode::SendingResponse(false, response_400.into_output());
                                     
                                     (Ok(Connection::make(stream, new_connection)), None, LogMessage::Error(log_mess))
                                 }
@@ -432,7 +1554,7 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
                             if buf.len() == done {
                                 
                                 let response_400 = response::Response::create_400();
-                                let new_connection = ConnectionMode::SendingResponse(false, response_400.as_bytes(), 0);
+                                let new_connection = ConnectionMode::SendingResponse(false, response_400.into_output());
                                 return (Ok(Connection::make(stream, new_connection)), None, LogMessage::None)
                             }
                             
@@ -456,7 +1578,7 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
                             /* HeaderName, HeaderValue, NewLine, Status, Token, TooManyHeaders, Version */
                             
                             let response_400 = response::Response::create_400();
-                            let new_connection = ConnectionMode::SendingResponse(false, response_400.as_bytes(), 0);
+                            let new_connection = ConnectionMode::SendingResponse(false, response_400.into_output());
                             (Ok(Connection::make(stream, new_connection)), None, LogMessage::None)
                         }
                     }
@@ -493,59 +1615,34 @@ fn transform_from_waiting_for_user(mut stream: TcpStream, events: EventSet, mut
 }
 
 
-fn transform_from_sending_to_user(mut stream: TcpStream, keep_alive: bool, events: EventSet, str: Vec<u8>, done: usize, server_down: bool) -> (Result<Connection, TcpStream>, LogMessage) {
+fn transform_from_sending_to_user(mut stream: Stream, keep_alive: bool, events: EventSet, mut output: Output, server_down: bool) -> (Result<Connection, Stream>, LogMessage) {
 
     if events.is_writable() {
 
-        match stream.try_write(&str[done..str.len()]) {
-
-            Ok(Some(size)) => {
-                
-                if size > 0 {
-
-                    let done = done + size;
+        match output.write_to(&mut stream) {
 
                                                     //send all data to browser
-                    if done == str.len() {
+            Ok(true) => {
 
                                                     //keep connection
-                        
-                        if server_down == false && keep_alive == true {
+                if server_down == false && keep_alive == true {
 
-                            let mess = format!("keep alive");
-                            
-                            let new_conn = Connection::make(stream, (ConnectionMode::ReadingRequest([0u8; 2048], 0)));
-                            
-                            return (Ok(new_conn), LogMessage::Message(mess));
-                            
+                    let mess = format!("keep alive");
+                    
+                    let new_conn = Connection::make(stream, (ConnectionMode::ReadingRequest([0u8; 2048], 0)));
+                    
+                    (Ok(new_conn), LogMessage::Message(mess))
+                    
                                                     //close connection
-                        } else {
-                            
-                            return (Err(stream), LogMessage::None);
-                        }
-
-                    } else if done < str.len() {
-                        
-                        let new_conn = Connection::make(stream, (ConnectionMode::SendingResponse(keep_alive, str, done)));
-                        
-                        return (Ok(new_conn), LogMessage::None);
-
-                    } else {
-
-                        unreachable!();
-                    }
-
                 } else {
                     
-                    let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, str, done));
-                    
-                    return (Ok(new_conn), LogMessage::None);
+                    (Err(stream), LogMessage::None)
                 }
             }
 
-            Ok(None) => {
+            Ok(false) => {
 
-                let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, str, done));
+                let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, output));
                 
                 (Ok(new_conn), LogMessage::None)
             }
@@ -554,15 +1651,13 @@ fn transform_from_sending_to_user(mut stream: TcpStream, keep_alive: bool, event
 
                 let message = format!("error write to socket, {:?}", err);
                 
-                let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, str, done));
-                
-                return (Ok(new_conn), LogMessage::Error(message));
+                (Err(stream), LogMessage::Error(message))
             }
         }
 
     } else {
 
-        let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, str, done));
+        let new_conn = Connection::make(stream, ConnectionMode::SendingResponse(keep_alive, output));
         
         (Ok(new_conn), LogMessage::None)
     }
@@ -0,0 +1,228 @@
+use std::time::SystemTime;
+use httpdate;
+use response::header_name;
+
+//https://tools.ietf.org/html/rfc6265
+//https://tools.ietf.org/html/draft-ietf-httpbis-rfc6265bis-02#section-5.3.7
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn to_str(&self) -> &str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax    => "Lax",
+            SameSite::None   => "None",
+        }
+    }
+}
+
+
+/// A cookie sent to the browser with `Set-Cookie`. Characters not allowed in a cookie (controls, whitespace, `"`, `,`, `;`, `\\`)
+/// are dropped from the name and the value when the header is written.
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    name      : String,
+    value     : String,
+    path      : Option<String>,
+    domain    : Option<String>,
+    max_age   : Option<u64>,
+    expires   : Option<SystemTime>,
+    secure    : bool,
+    http_only : bool,
+    same_site : Option<SameSite>,
+}
+
+
+impl Cookie {
+
+    pub fn new(name: &str, value: &str) -> Cookie {
+
+        Cookie {
+            name      : name.to_owned(),
+            value     : value.to_owned(),
+            path      : None,
+            domain    : None,
+            max_age   : None,
+            expires   : None,
+            secure    : false,
+            http_only : false,
+            same_site : None,
+        }
+    }
+
+                                        //ciastko usuwające wcześniej ustawione ciastko o tej nazwie
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(0).expires(::std::time::UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> Cookie {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &String {
+        &(self.name)
+    }
+
+    pub fn value(&self) -> &String {
+        &(self.value)
+    }
+
+                                        //wartość nagłówka Set-Cookie
+    pub fn to_header(&self) -> String {
+
+        let mut out = format!("{}={}", header_name(&self.name), cookie_octets(&self.value));
+
+        if let Some(ref path) = self.path {
+            out.push_str(&format!("; Path={}", attribute_value(path)));
+        }
+
+        if let Some(ref domain) = self.domain {
+            out.push_str(&format!("; Domain={}", attribute_value(domain)));
+        }
+
+        if let Some(max_age) = self.max_age {
+            out.push_str(&format!("; Max-Age={}", max_age));
+        }
+
+        if let Some(expires) = self.expires {
+            out.push_str(&format!("; Expires={}", httpdate::format(expires)));
+        }
+
+                                        //SameSite=None wymaga Secure
+        if self.secure || self.same_site == Some(SameSite::None) {
+            out.push_str("; Secure");
+        }
+
+        if self.http_only {
+            out.push_str("; HttpOnly");
+        }
+
+        if let Some(same_site) = self.same_site {
+            out.push_str(&format!("; SameSite={}", same_site.to_str()));
+        }
+
+        out
+    }
+}
+
+
+                                        //cookie-octet z RFC 6265 4.1.1
+fn cookie_octets(value: &str) -> String {
+    value.chars().filter(|c| match *c {
+        '!' | '#' ... '+' | '-' ... ':' | '<' ... '[' | ']' ... '~' => true,
+        _ => false,
+    }).collect()
+}
+
+
+                                        //Path i Domain - bez znaków sterujących i średnika, który zacząłby nowy atrybut
+fn attribute_value(value: &str) -> String {
+    value.chars().filter(|c| *c != ';' && c.is_control() == false).collect()
+}
+
+
+                                        //pary nazwa-wartość z nagłówka Cookie, w kolejności wystąpienia
+pub fn parse(header: &str) -> Vec<(String, String)> {
+
+    let mut out = Vec::new();
+
+    for pair in header.split(';') {
+
+        let pair = pair.trim();
+
+        if let Some(pos) = pair.find('=') {
+
+            let name  = pair[0..pos].trim();
+            let value = pair[pos + 1..].trim();
+
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+
+            if name.len() > 0 {
+                out.push((name.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{UNIX_EPOCH, Duration};
+    use super::{Cookie, SameSite, parse};
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn parse_pairs() {
+        assert_eq!(parse("a=1; b=\"two\";c=; =x; broken; d = 4 "), vec![pair("a", "1"), pair("b", "two"), pair("c", ""), pair("d", "4")]);
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("a=b=c"), vec![pair("a", "b=c")]);
+    }
+
+    #[test]
+    fn header_attributes() {
+        let cookie = Cookie::new("sid", "abc").path("/").domain("example.com").max_age(60)
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777)).http_only(true).same_site(SameSite::Lax);
+
+        assert_eq!(cookie.to_header(), "sid=abc; Path=/; Domain=example.com; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT; HttpOnly; SameSite=Lax");
+    }
+
+    #[test]
+    fn same_site_none_is_secure() {
+        assert_eq!(Cookie::new("a", "b").same_site(SameSite::None).to_header(), "a=b; Secure; SameSite=None");
+    }
+
+    #[test]
+    fn header_injection() {
+        let cookie = Cookie::new("a\r\nX-Evil: 1", "v;Domain=evil.com,\r\nX: y").path("/\r\n; Secure").domain("a.com;\n");
+
+        assert_eq!(cookie.to_header(), "aX-Evil1=vDomain=evil.comX:y; Path=/ Secure; Domain=a.com");
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use mio::{Token, Sender};
+use server::MioMessage;
+
+//https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+
+/// Handle of an open `text/event-stream` response. Cheap to clone and usable from any thread;
+/// events are queued through the event loop channel. Sending after the client went away is silently ignored.
+#[derive(Clone)]
+pub struct EventStream {
+    token     : Token,
+    stream_id : Option<u32>,                    //strumień HTTP/2
+    sender    : Sender<MioMessage>,
+    open      : Arc<AtomicBool>,
+}
+
+
+                                        //trzymany przez połączenie - przy jego usunięciu strumień zostaje oznaczony jako zamknięty
+pub struct OpenGuard {
+    open : Arc<AtomicBool>,
+}
+
+impl OpenGuard {
+
+                                        //strażnik i flaga dla uchwytu
+    pub fn new() -> (OpenGuard, Arc<AtomicBool>) {
+
+        let open = Arc::new(AtomicBool::new(true));
+
+        (OpenGuard { open: open.clone() }, open)
+    }
+}
+
+impl Drop for OpenGuard {
+    fn drop(&mut self) {
+        self.open.store(false, Ordering::SeqCst);
+    }
+}
+
+
+impl EventStream {
+
+    pub fn new(token: Token, stream_id: Option<u32>, sender: Sender<MioMessage>) -> (EventStream, OpenGuard) {
+
+        let (guard, open) = OpenGuard::new();
+
+        let event_stream = EventStream {
+            token     : token,
+            stream_id : stream_id,
+            sender    : sender,
+            open      : open,
+        };
+
+        (event_stream, guard)
+    }
+
+                                        //pełne zdarzenie; każda linia `data` trafia do osobnego pola "data:"
+    pub fn send(&self, event: Option<&str>, data: &str, id: Option<&str>) {
+        self.push(format_event(event, data, id));
+    }
+
+    pub fn data(&self, data: &str) {
+        self.send(None, data, None);
+    }
+
+    pub fn event(&self, event: &str, data: &str) {
+        self.send(Some(event), data, None);
+    }
+
+                                        //czas ponownego połączenia przeglądarki po zerwaniu, w milisekundach
+    pub fn retry(&self, millis: u64) {
+        self.push(format!("retry: {}\n\n", millis).into_bytes());
+    }
+
+                                        //komentarz - ignorowany przez przeglądarkę
+    pub fn comment(&self, text: &str) {
+        self.push(format_comment(text));
+    }
+
+                                        //koniec strumienia - przeglądarka połączy się ponownie po czasie z retry
+    pub fn close(&self) {
+        let _ = self.sender.send(MioMessage::EventStream(self.token, self.stream_id, None));
+    }
+
+                                        //false - klient się rozłączył lub serwer zamknął strumień
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn push(&self, data: Vec<u8>) {
+        if self.is_open() {
+            let _ = self.sender.send(MioMessage::EventStream(self.token, self.stream_id, Some(data)));
+        }
+    }
+}
+
+
+                                        //znaki nowej linii w nazwie lub id zepsułyby ramkę
+fn single_line(value: &str) -> String {
+    value.chars().filter(|ch| *ch != '\r' && *ch != '\n').collect()
+}
+
+
+pub fn format_event(event: Option<&str>, data: &str, id: Option<&str>) -> Vec<u8> {
+
+    let mut out = String::new();
+
+    if let Some(event) = event {
+        out.push_str(&format!("event: {}\n", single_line(event)));
+    }
+
+    for line in data.split("\r\n").flat_map(|line| line.split(|ch| ch == '\r' || ch == '\n')) {
+        out.push_str(&format!("data: {}\n", line));
+    }
+
+    if let Some(id) = id {
+        out.push_str(&format!("id: {}\n", single_line(id)));
+    }
+
+    out.push('\n');
+
+    out.into_bytes()
+}
+
+
+pub fn format_comment(text: &str) -> Vec<u8> {
+
+    let mut out = String::new();
+
+    for line in text.split("\r\n").flat_map(|line| line.split(|ch| ch == '\r' || ch == '\n')) {
+        out.push_str(&format!(": {}\n", line));
+    }
+
+    out.push('\n');
+
+    out.into_bytes()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::Ordering;
+    use super::{format_event, format_comment, OpenGuard};
+
+    #[test]
+    fn event_fields() {
+        assert_eq!(format_event(None, "hello", None), b"data: hello\n\n".to_vec());
+        assert_eq!(format_event(Some("update"), "1", Some("42")), b"event: update\ndata: 1\nid: 42\n\n".to_vec());
+        assert_eq!(format_event(None, "", None), b"data: \n\n".to_vec());
+    }
+
+    #[test]
+    fn multiline_data() {
+        assert_eq!(format_event(None, "a\nb\r\nc\rd", None), b"data: a\ndata: b\ndata: c\ndata: d\n\n".to_vec());
+        assert_eq!(format_event(None, "a\n", None), b"data: a\ndata: \n\n".to_vec());
+    }
+
+    #[test]
+    fn newlines_removed_from_event_and_id() {
+        assert_eq!(format_event(Some("up\r\ndate"), "x", Some("4\n2")), b"event: update\ndata: x\nid: 42\n\n".to_vec());
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(format_comment(""), b": \n\n".to_vec());
+        assert_eq!(format_comment("one\ntwo"), b": one\n: two\n\n".to_vec());
+    }
+
+    #[test]
+    fn guard_closes_stream() {
+
+        let (guard, open) = OpenGuard::new();
+
+        assert!(open.load(Ordering::SeqCst));
+        drop(guard);
+        assert_eq!(open.load(Ordering::SeqCst), false);
+    }
+}
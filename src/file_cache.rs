@@ -0,0 +1,362 @@
+use std::io;
+use std::io::Read;
+use std::fs::{File, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, BTreeMap};
+use response::Response;
+use code::Code;
+use typemod::Type;
+use compression::{self, Encoding};
+use conditional;
+use httpdate;
+
+
+struct Entry {
+    data     : Arc<Vec<u8>>,
+    gzip     : Option<Arc<Vec<u8>>>,        //tylko gdy wyszło mniejsze od oryginału
+    etag     : Option<String>,
+    modified : SystemTime,
+    len      : u64,
+    used     : u64,                         //licznik ostatniego użycia, klucz w Inner::order
+}
+
+impl Entry {
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64 + self.gzip.as_ref().map(|gzip| gzip.len() as u64).unwrap_or(0)
+    }
+}
+
+
+struct Inner {
+    map   : HashMap<PathBuf, Entry>,
+    order : BTreeMap<u64, PathBuf>,         //ścieżki od najdawniej używanej (LRU)
+    size  : u64,
+    tick  : u64,
+}
+
+
+impl Inner {
+
+    fn remove(&mut self, path: &Path) {
+
+        if let Some(entry) = self.map.remove(path) {
+            self.order.remove(&entry.used);
+            self.size = self.size - entry.size();
+        }
+    }
+
+    fn remove_oldest(&mut self) -> bool {
+
+        let oldest = match self.order.iter().next() {
+            Some((_, path)) => path.clone(),
+            None => return false,
+        };
+
+        self.remove(&oldest);
+        true
+    }
+}
+
+
+/// Contents of a cached file, ready to be turned into a response.
+pub struct Cached {
+    data     : Arc<Vec<u8>>,
+    gzip     : Option<Arc<Vec<u8>>>,
+    etag     : Option<String>,
+    modified : SystemTime,
+}
+
+
+impl Cached {
+
+                                        //gzip - czy klient akceptuje skompresowaną wersję
+    pub fn into_response(self, typ: Type, gzip: bool) -> Response {
+
+        let has_gzip = self.gzip.is_some();
+
+        let mut response = match (self.gzip, gzip) {
+
+            (Some(data), true) => {
+                let mut response = Response::create_from_shared(Code::Code200, typ, data);
+                response.add_header("Content-Encoding", Encoding::Gzip.to_str());
+                if let Some(ref etag) = self.etag {
+                    response.add_header("ETag", &format!("{}-gzip\"", &etag[0..etag.len() - 1]));
+                }
+                response
+            },
+
+            _ => {
+                let mut response = Response::create_from_shared(Code::Code200, typ, self.data);
+                if let Some(ref etag) = self.etag {
+                    response.add_header("ETag", etag);
+                }
+                response
+            }
+        };
+
+        response.add_header("Last-Modified", &httpdate::format(self.modified));
+
+        if has_gzip {
+            response.add_vary("Accept-Encoding");
+        }
+
+        response
+    }
+}
+
+
+/// Size-bounded LRU cache of file contents for `StaticFiles`. Each entry keeps a gzip variant for compressible types and
+/// the same ETag an uncached file gets. An entry is reloaded when the size or modification time of the file no longer match.
+pub struct FileCache {
+    max_size      : u64,
+    max_file_size : u64,
+    compress      : bool,
+    inner         : Mutex<Inner>,
+}
+
+
+impl FileCache {
+
+    pub fn new(max_size: u64) -> FileCache {
+
+        FileCache {
+            max_size      : max_size,
+            max_file_size : 1024 * 1024,
+            compress      : true,
+            inner         : Mutex::new(Inner {
+                map   : HashMap::new(),
+                order : BTreeMap::new(),
+                size  : 0,
+                tick  : 0,
+            }),
+        }
+    }
+
+                                        //większe pliki idą prosto z dysku (sendfile)
+    pub fn max_file_size(mut self, max_file_size: u64) -> FileCache {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+                                        //czy trzymać też wersję gzip dla typów tekstowych
+    pub fn compress(mut self, compress: bool) -> FileCache {
+        self.compress = compress;
+        self
+    }
+
+                                        //łączny rozmiar trzymanych danych
+    pub fn size(&self) -> u64 {
+        self.inner.lock().unwrap().size
+    }
+
+    pub fn clear(&self) {
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.map.clear();
+        inner.order.clear();
+        inner.size = 0;
+    }
+
+    /// Returns the cached contents of `path`, loading them when missing or stale. `None` means the file should be
+    /// served from disk: it is too large, its modification time is unknown or it changed while being read.
+    pub fn get(&self, path: &Path, metadata: &Metadata, typ: Type) -> io::Result<Option<Cached>> {
+
+        let len = metadata.len();
+
+        if len > self.max_file_size || len > self.max_size {
+            return Ok(None);
+        }
+
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return Ok(None),
+        };
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+
+            inner.tick = inner.tick + 1;
+
+            let tick = inner.tick;
+
+            let hit = match inner.map.get_mut(path) {
+
+                Some(entry) => {
+
+                    if entry.modified == modified && entry.len == len {
+
+                        let used   = entry.used;
+                        entry.used = tick;
+
+                        Some((used, Cached {
+                            data     : entry.data.clone(),
+                            gzip     : entry.gzip.clone(),
+                            etag     : entry.etag.clone(),
+                            modified : entry.modified,
+                        }))
+                    } else {
+                        None
+                    }
+                },
+
+                None => None,
+            };
+
+            match hit {
+                Some((used, cached)) => {
+                                        //przeniesienie na koniec kolejki LRU
+                    inner.order.remove(&used);
+                    inner.order.insert(tick, path.to_path_buf());
+                    return Ok(Some(cached));
+                },
+                None => {
+                    inner.remove(path);
+                },
+            }
+        }
+
+                                        //wczytanie i kompresja poza blokadą
+        let mut data = Vec::with_capacity(len as usize);
+
+        let mut file = try!(File::open(path));
+        try!(file.read_to_end(&mut data));
+
+        if data.len() as u64 != len {
+            return Ok(None);
+        }
+
+        let gzip = if self.compress && typ.is_compressible() {
+            match compression::compress(&data, Encoding::Gzip, 9) {
+                Ok(gzip) => if gzip.len() < data.len() { Some(Arc::new(gzip)) } else { None },
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        let entry = Entry {
+            etag     : conditional::file_etag(len, modified),
+            data     : Arc::new(data),
+            gzip     : gzip,
+            modified : modified,
+            len      : len,
+            used     : 0,
+        };
+
+        let cached = Cached {
+            data     : entry.data.clone(),
+            gzip     : entry.gzip.clone(),
+            etag     : entry.etag.clone(),
+            modified : modified,
+        };
+
+        self.insert(path, entry);
+
+        Ok(Some(cached))
+    }
+
+    fn insert(&self, path: &Path, mut entry: Entry) {
+
+        let size = entry.size();
+
+        if size > self.max_size {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.remove(path);
+
+                                        //usuwamy najdawniej używane aż zmieści się nowy wpis
+        while inner.size + size > self.max_size {
+            if inner.remove_oldest() == false {
+                break;
+            }
+        }
+
+        inner.tick = inner.tick + 1;
+        entry.used = inner.tick;
+
+        inner.size = inner.size + size;
+        inner.order.insert(entry.used, path.to_path_buf());
+        inner.map.insert(path.to_path_buf(), entry);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use typemod::Type;
+    use super::FileCache;
+
+    fn create(name: &str, size: usize) -> PathBuf {
+
+        let path = env::temp_dir().join(format!("miohttp-file-cache-{}-{}", name, ::std::process::id()));
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&vec![b'x'; size]).unwrap();
+
+        path
+    }
+
+    fn cached(cache: &FileCache, path: &PathBuf) -> bool {
+        let metadata = fs::metadata(path).unwrap();
+        cache.get(path, &metadata, Type::ImagePng).unwrap().is_some()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+
+        let a = create("a", 400);
+        let b = create("b", 400);
+        let c = create("c", 400);
+
+        let cache = FileCache::new(1000);
+
+        assert!(cached(&cache, &a));
+        assert!(cached(&cache, &b));
+        assert_eq!(cache.size(), 800);
+
+        assert!(cached(&cache, &a));            //a używane później niż b
+        assert!(cached(&cache, &c));            //wypiera b
+
+        assert_eq!(cache.size(), 800);
+
+        {
+            let inner = cache.inner.lock().unwrap();
+            assert!(inner.map.contains_key(&a));
+            assert!(inner.map.contains_key(&b) == false);
+            assert!(inner.map.contains_key(&c));
+            assert_eq!(inner.order.len(), 2);
+        }
+
+        cache.clear();
+        assert_eq!(cache.size(), 0);
+
+        for path in vec![a, b, c] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn skips_large_files() {
+
+        let big = create("big", 2000);
+
+        let cache = FileCache::new(1000);
+
+        assert!(cached(&cache, &big) == false);
+        assert_eq!(cache.size(), 0);
+
+        let _ = fs::remove_file(big);
+    }
+}
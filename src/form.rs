@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+
+/// Decoded fields of an `application/x-www-form-urlencoded` body. A name may occur more than once.
+pub struct Form {
+    fields : HashMap<String, Vec<String>>,
+}
+
+
+impl Form {
+    
+    pub fn new(pairs: Vec<(String, String)>) -> Form {
+        
+        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+        
+        for (name, value) in pairs {
+            fields.entry(name).or_insert(Vec::new()).push(value);
+        }
+        
+        Form {
+            fields : fields
+        }
+    }
+    
+                                            //pierwsza wartość pola
+    pub fn get(&self, name: &str) -> Option<&String> {
+        
+        match self.fields.get(name) {
+            Some(values) => values.first(),
+            None => None,
+        }
+    }
+    
+    pub fn get_all(&self, name: &str) -> &[String] {
+        
+        match self.fields.get(name) {
+            Some(values) => values,
+            None => &[],
+        }
+    }
+    
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
+    }
+    
+    pub fn names(&self) -> Vec<&String> {
+        self.fields.keys().collect()
+    }
+    
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use urlencoded::parse_form;
+    use super::Form;
+
+    fn form(body: &str) -> Form {
+        Form::new(parse_form(body.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn repeated_names() {
+
+        let form = form("tag=a&name=x+y&tag=b%26c&tag=");
+
+        assert_eq!(form.len(), 2);
+        assert_eq!(form.get("tag"), Some(&"a".to_owned()));
+        assert_eq!(form.get_all("tag"), &["a".to_owned(), "b&c".to_owned(), "".to_owned()]);
+        assert_eq!(form.get("name"), Some(&"x y".to_owned()));
+
+        let mut names = form.names();
+        names.sort();
+        assert_eq!(names, vec!["name", "tag"]);
+    }
+
+    #[test]
+    fn missing_fields() {
+
+        let form = form("a=1");
+
+        assert_eq!(form.get("b"), None);
+        assert!(form.get_all("b").is_empty());
+        assert!(form.contains("a"));
+        assert!(form.contains("b") == false);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(form("").is_empty());
+        assert!(form("a").is_empty() == false);
+    }
+}
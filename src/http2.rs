@@ -0,0 +1,1764 @@
+use std::io;
+use std::io::{Read, Write, ErrorKind};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use hpack::Decoder;
+use stream::{Stream, TlsInfo};
+use request::PreRequest;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use event_stream::{self, OpenGuard};
+
+//https://tools.ietf.org/html/rfc7540
+//https://tools.ietf.org/html/rfc7541
+
+
+pub const PREFACE : &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_DATA          : u8 = 0x0;
+const FRAME_HEADERS       : u8 = 0x1;
+const FRAME_PRIORITY      : u8 = 0x2;
+const FRAME_RST_STREAM    : u8 = 0x3;
+const FRAME_SETTINGS      : u8 = 0x4;
+const FRAME_PUSH_PROMISE  : u8 = 0x5;
+const FRAME_PING          : u8 = 0x6;
+const FRAME_GOAWAY        : u8 = 0x7;
+const FRAME_WINDOW_UPDATE : u8 = 0x8;
+const FRAME_CONTINUATION  : u8 = 0x9;
+
+const FLAG_END_STREAM  : u8 = 0x1;
+const FLAG_ACK         : u8 = 0x1;
+const FLAG_END_HEADERS : u8 = 0x4;
+const FLAG_PADDED      : u8 = 0x8;
+const FLAG_PRIORITY    : u8 = 0x20;
+
+const NO_ERROR            : u32 = 0x0;
+const PROTOCOL_ERROR      : u32 = 0x1;
+const INTERNAL_ERROR      : u32 = 0x2;
+const FLOW_CONTROL_ERROR  : u32 = 0x3;
+const STREAM_CLOSED       : u32 = 0x5;
+const FRAME_SIZE_ERROR    : u32 = 0x6;
+const REFUSED_STREAM      : u32 = 0x7;
+const CANCEL              : u32 = 0x8;
+const COMPRESSION_ERROR   : u32 = 0x9;
+const ENHANCE_YOUR_CALM   : u32 = 0xb;
+
+const SETTINGS_HEADER_TABLE_SIZE      : u16 = 0x1;
+const SETTINGS_ENABLE_PUSH            : u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS : u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE    : u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE         : u16 = 0x5;
+
+const DEFAULT_WINDOW    : i64   = 65535;
+const MAX_WINDOW        : i64   = 0x7fffffff;
+const MAX_FRAME         : usize = 16384;               //nie ogłaszamy większych ramek
+const MAX_STREAMS       : usize = 100;
+const MAX_HEADER_BLOCK  : usize = 64 * 1024;
+const MAX_BODY          : usize = 8 * 1024 * 1024;     //ciało requestu trzymane w pamięci do końca strumienia
+const MAX_BUFFERED      : usize = 16 * 1024 * 1024;    //suma ciał wszystkich strumieni połączenia czekających na END_STREAM
+const OUTPUT_LIMIT      : usize = 64 * 1024;           //ile ramek trzymamy zanim poczekamy na socket
+const READ_LIMIT        : usize = 64 * 1024;           //ile czytamy z socketu w jednym zdarzeniu
+const EVENT_LIMIT       : usize = 1024 * 1024;         //zdarzenia SSE czekające na okno klienta
+const CONTROL_LIMIT     : usize = 1000;                //ramki SETTINGS, PING, RST_STREAM i wymuszające nasz RST_STREAM, na sekundę
+
+
+enum State {
+    Receiving(PreRequest, Vec<u8>),                     //nagłówki odebrane, czekamy na END_STREAM
+    Waiting,                                            //request przekazany do odbiornika
+    Sending(Box<Read + Send>, Vec<u8>, bool),           //ciało odpowiedzi, odczytany kawałek, koniec ciała
+    Streaming(Vec<u8>, bool, OpenGuard),                //text/event-stream: dane od EventStream, koniec strumienia
+    Cancelled,                                          //zresetowany, ale request jest w odbiorniku - miejsce zwalnia dopiero odpowiedź
+}
+
+
+struct H2Stream {
+    state       : State,
+    send_window : i64,
+    recv_window : i64,                                  //ile klient może jeszcze wysłać w tym strumieniu
+    remote_open : bool,                                 //klient jeszcze nie zakończył swojej strony
+}
+
+
+/// State of one HTTP/2 connection: frame parsing, HPACK, streams and flow control.
+/// Completed requests are collected by `take_requests`, responses come back through `respond`.
+pub struct Http2 {
+    input       : Vec<u8>,
+    output      : Vec<u8>,                              //zakodowane ramki czekające na socket
+    preface     : bool,                                 //czy odebrano preambułę klienta
+    decoder     : Decoder<'static>,
+    streams     : HashMap<u32, H2Stream>,
+    last_stream : u32,                                  //najwyższy numer strumienia otwartego przez klienta
+    block       : Option<(u32, bool, Vec<u8>)>,         //nagłówki dzielone na CONTINUATION: strumień, END_STREAM, dane
+    send_window : i64,
+    recv_window : i64,                                  //ile klient może jeszcze wysłać w ramkach DATA
+    window_init : i64,                                  //SETTINGS_INITIAL_WINDOW_SIZE klienta
+    frame_size  : usize,                                //SETTINGS_MAX_FRAME_SIZE klienta
+    go_away     : bool,                                 //GOAWAY wysłany lub odebrany - nowych strumieni nie przyjmujemy
+    closing     : bool,                                 //błąd połączenia - po wysłaniu GOAWAY zamykamy
+    control     : (Instant, usize),                     //początek bieżącej sekundy, ramki kontrolne odebrane od tego czasu
+    ready       : Vec<PreRequest>,
+    tls         : Option<TlsInfo>,
+    written     : u64,
+    checked     : u64,
+}
+
+
+impl Http2 {
+
+                                        //input - dane odczytane przed przełączeniem (h2c z preambułą)
+    pub fn new(tls: Option<TlsInfo>, input: Vec<u8>) -> Http2 {
+
+        let mut http2 = Http2 {
+            input       : input,
+            output      : Vec::new(),
+            preface     : false,
+            decoder     : Decoder::new(),
+            streams     : HashMap::new(),
+            last_stream : 0,
+            block       : None,
+            send_window : DEFAULT_WINDOW,
+            recv_window : MAX_BUFFERED as i64,
+            window_init : DEFAULT_WINDOW,
+            frame_size  : MAX_FRAME,
+            go_away     : false,
+            closing     : false,
+            control     : (Instant::now(), 0),
+            ready       : Vec::new(),
+            tls         : tls,
+            written     : 0,
+            checked     : 0,
+        };
+
+                                        //preambuła serwera
+        let mut settings = Vec::new();
+        push_setting(&mut settings, SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS as u32);
+        push_setting(&mut settings, SETTINGS_ENABLE_PUSH, 0);
+        http2.frame(FRAME_SETTINGS, 0, 0, &settings);
+
+                                        //okno połączenia obejmuje cały budżet ciał w pamięci
+        http2.window_update(0, MAX_BUFFERED - DEFAULT_WINDOW as usize);
+
+        http2
+    }
+
+    pub fn take_requests(&mut self) -> Vec<PreRequest> {
+        self.ready.drain(..).collect()
+    }
+
+    pub fn wants_write(&self) -> bool {
+        self.output.len() > 0
+    }
+
+                                        //przetwarzanie wstrzymane przez pełny output, a output już się zwolnił
+    pub fn wants_process(&self) -> bool {
+
+        if self.closing || self.preface == false || self.output.len() >= OUTPUT_LIMIT || self.input.len() < 9 {
+            return false;
+        }
+
+        let len = ((self.input[0] as usize) << 16) | ((self.input[1] as usize) << 8) | self.input[2] as usize;
+
+        len > MAX_FRAME || self.input.len() >= 9 + len
+    }
+
+                                        //brak strumieni i danych do wysłania - dotyczy go timeout bezczynności
+    pub fn is_idle(&self) -> bool {
+        self.streams.len() == 0 && self.output.len() == 0 && self.input.len() == 0
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.output.len() == 0 && (self.closing || (self.go_away && self.streams.len() == 0))
+    }
+
+                                        //czy od poprzedniego wywołania coś zostało wysłane
+    pub fn has_progress(&mut self) -> bool {
+
+        let progress = self.written != self.checked;
+        self.checked = self.written;
+        progress
+    }
+
+                                        //łagodne zamknięcie - rozpoczęte strumienie są dokańczane, strumienie zdarzeń kończone
+    pub fn shutdown(&mut self) {
+
+        for h2_stream in self.streams.values_mut() {
+            if let State::Streaming(_, ref mut end, _) = h2_stream.state {
+                *end = true;
+            }
+        }
+
+        if self.go_away == false {
+            let last_stream = self.last_stream;
+            self.goaway(last_stream, NO_ERROR);
+        }
+    }
+
+                                        //nieprzetworzone ramki zostają w sockecie, dopóki klient nie odbierze odpowiedzi
+    pub fn read_from(&mut self, stream: &mut Stream) -> io::Result<bool> {
+
+        let mut buf = [0u8; 4096];
+
+        while self.input.len() < READ_LIMIT {
+
+            match stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(size) => {
+                    self.input.extend_from_slice(&buf[0..size]);
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn write_to(&mut self, stream: &mut Stream) -> io::Result<()> {
+
+        loop {
+
+            self.fill_output();
+
+            if self.output.len() == 0 {
+                break;
+            }
+
+            match stream.write(&self.output) {
+                Ok(0) => break,
+                Ok(size) => {
+                    self.output.drain(0..size);
+                    self.written = self.written + size as u64;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        try!(stream.flush_tls());
+
+        Ok(())
+    }
+
+                                        //przetwarza odebrane ramki, Err - błąd połączenia (GOAWAY już w kolejce)
+    pub fn process(&mut self) -> Result<(), String> {
+
+        if self.closing {
+            self.input.clear();
+            return Ok(());
+        }
+
+        if self.preface == false {
+
+            let size = min(self.input.len(), PREFACE.len());
+
+            if &self.input[0..size] != &PREFACE[0..size] {
+                return self.fail(PROTOCOL_ERROR, "invalid connection preface".to_owned());
+            }
+
+            if size < PREFACE.len() {
+                return Ok(());
+            }
+
+            self.input.drain(0..PREFACE.len());
+            self.preface = true;
+        }
+
+                                        //ramki kontrolne dokładają odpowiedzi do output - przy pełnym czekamy na socket
+        while self.input.len() >= 9 && self.output.len() < OUTPUT_LIMIT {
+
+            let len   = ((self.input[0] as usize) << 16) | ((self.input[1] as usize) << 8) | self.input[2] as usize;
+            let typ   = self.input[3];
+            let flags = self.input[4];
+            let id    = read_u32(&self.input[5..9]) & 0x7fffffff;
+
+            if len > MAX_FRAME {
+                return self.fail(FRAME_SIZE_ERROR, format!("frame too large: {}", len));
+            }
+
+            if self.input.len() < 9 + len {
+                break;
+            }
+
+            let payload: Vec<u8> = self.input[9..9 + len].to_vec();
+            self.input.drain(0..9 + len);
+
+            if let Err((code, message)) = self.on_frame(typ, flags, id, payload) {
+                return self.fail(code, message);
+            }
+
+            self.refund_window();
+
+            if self.closing {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+                                        //odpowiedź z odbiornika; strumień zresetowany przez klienta - odpowiedź przepada
+    pub fn respond(&mut self, id: u32, response: Response) {
+
+        if self.take_waiting(id) == false {
+            return;
+        }
+
+        let (fields, body) = response.into_http2();
+
+        let block = encode_headers(&fields);
+
+        self.headers_frames(id, &block, body.is_none());
+
+        match body {
+
+            Some(body) => {
+                if let Some(h2_stream) = self.streams.get_mut(&id) {
+                    h2_stream.state = State::Sending(body, Vec::new(), false);
+                }
+            },
+
+            None => self.close_stream(id),
+        }
+    }
+
+                                        //nagłówki text/event-stream bez END_STREAM, dalsze dane przez event_stream_data
+    pub fn respond_event_stream(&mut self, id: u32, response: Response, guard: OpenGuard) {
+
+        if self.take_waiting(id) == false {
+            return;
+        }
+
+        let (fields, _) = response.into_http2();
+
+        let fields = fields.into_iter().filter(|&(ref name, _)| name != "content-length").collect();
+
+        let block = encode_headers(&fields);
+
+        self.headers_frames(id, &block, false);
+
+        if let Some(h2_stream) = self.streams.get_mut(&id) {
+            h2_stream.state = State::Streaming(Vec::new(), false, guard);
+        }
+    }
+
+                                        //None - koniec strumienia
+    pub fn event_stream_data(&mut self, id: u32, data: Option<Vec<u8>>) {
+
+        let overflow = match self.streams.get_mut(&id) {
+
+            Some(&mut H2Stream { state: State::Streaming(ref mut pending, ref mut end, _), .. }) => {
+
+                match data {
+                    Some(data) => pending.extend_from_slice(&data),
+                    None => *end = true,
+                }
+
+                pending.len() > EVENT_LIMIT
+            },
+
+            _ => false,
+        };
+
+                                        //klient nie odbiera danych
+        if overflow {
+            self.reset(id, CANCEL);
+        }
+    }
+
+    pub fn has_event_streams(&self) -> bool {
+        self.streams.values().any(|h2_stream| match h2_stream.state {
+            State::Streaming(_, _, _) => true,
+            _ => false,
+        })
+    }
+
+                                        //komentarz w każdym strumieniu zdarzeń - pośrednicy nie zamkną bezczynnego połączenia
+    pub fn heartbeat(&mut self) {
+
+        for h2_stream in self.streams.values_mut() {
+            if let State::Streaming(ref mut pending, false, _) = h2_stream.state {
+                pending.extend_from_slice(&event_stream::format_comment(""));
+            }
+        }
+    }
+
+                                        //czy strumień czeka na odpowiedź; strumień zresetowany w międzyczasie zwalnia miejsce
+    fn take_waiting(&mut self, id: u32) -> bool {
+
+        let cancelled = match self.streams.get(&id) {
+            Some(&H2Stream { state: State::Waiting, .. }) => return true,
+            Some(&H2Stream { state: State::Cancelled, .. }) => true,
+            _ => false,
+        };
+
+        if cancelled {
+            self.streams.remove(&id);
+        }
+
+        false
+    }
+
+    fn fail(&mut self, code: u32, message: String) -> Result<(), String> {
+
+        let last_stream = self.last_stream;
+
+        self.goaway(last_stream, code);
+        self.closing = true;
+        self.input.clear();
+
+        Err(message)
+    }
+
+    fn frame(&mut self, typ: u8, flags: u8, id: u32, payload: &[u8]) {
+
+        let len = payload.len();
+
+        self.output.push((len >> 16) as u8);
+        self.output.push((len >> 8) as u8);
+        self.output.push(len as u8);
+        self.output.push(typ);
+        self.output.push(flags);
+        push_u32(&mut self.output, id & 0x7fffffff);
+        self.output.extend_from_slice(payload);
+    }
+
+    fn goaway(&mut self, last_stream: u32, code: u32) {
+
+        let mut payload = Vec::new();
+        push_u32(&mut payload, last_stream);
+        push_u32(&mut payload, code);
+
+        self.frame(FRAME_GOAWAY, 0, 0, &payload);
+        self.go_away = true;
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+
+        let mut payload = Vec::new();
+        push_u32(&mut payload, code);
+
+        self.frame(FRAME_RST_STREAM, 0, id, &payload);
+        self.drop_stream(id);
+    }
+
+                                        //request przekazany do odbiornika zajmuje miejsce w MAX_STREAMS aż do odpowiedzi,
+                                        //inaczej szybkie resety pozwalałyby zlecać odbiornikowi pracę bez ograniczeń
+    fn drop_stream(&mut self, id: u32) {
+
+        let dispatched = match self.streams.get(&id) {
+            Some(&H2Stream { state: State::Waiting, .. }) => true,
+            Some(&H2Stream { state: State::Cancelled, .. }) => true,
+            _ => false,
+        };
+
+        if dispatched {
+            if let Some(h2_stream) = self.streams.get_mut(&id) {
+                h2_stream.state = State::Cancelled;
+            }
+        } else {
+            self.streams.remove(&id);
+        }
+    }
+
+                                        //SETTINGS, PING i RST_STREAM kosztują nas odpowiedź lub pracę - ich liczba jest ograniczona,
+                                        //tak samo ramki, na które odpowiadamy własnym RST_STREAM
+    fn count_control(&mut self) -> Result<(), (u32, String)> {
+
+        let now = Instant::now();
+
+        if now.duration_since(self.control.0) >= Duration::from_secs(1) {
+            self.control = (now, 0);
+        }
+
+        self.control.1 = self.control.1 + 1;
+
+        if self.control.1 > CONTROL_LIMIT {
+            return Err((ENHANCE_YOUR_CALM, "too many control frames".to_owned()));
+        }
+
+        Ok(())
+    }
+
+                                        //ciała strumieni czekających na END_STREAM
+    fn buffered(&self) -> usize {
+        self.streams.values().fold(0, |sum, h2_stream| match h2_stream.state {
+            State::Receiving(_, ref body) => sum + body.len(),
+            _ => sum,
+        })
+    }
+
+                                        //okno połączenia odnawiamy dopiero, gdy ciało trafi do odbiornika lub zostanie
+                                        //odrzucone - ciała w buforach razem z oknem klienta nie przekroczą MAX_BUFFERED
+    fn refund_window(&mut self) {
+
+                                        //limit wyczerpany, żadne ciało się nie dokończy - największe odrzucamy, klient może ponowić
+        while self.buffered() >= MAX_BUFFERED {
+
+            let largest = self.streams.iter().filter_map(|(id, h2_stream)| match h2_stream.state {
+                State::Receiving(_, ref body) => Some((body.len(), *id)),
+                _ => None,
+            }).max();
+
+            match largest {
+                Some((_, id)) => self.reset(id, REFUSED_STREAM),
+                None => break,
+            }
+        }
+
+        let target = MAX_BUFFERED as i64 - self.buffered() as i64;
+
+        if target > self.recv_window {
+            let increment = (target - self.recv_window) as usize;
+            self.window_update(0, increment);
+            self.recv_window = target;
+        }
+    }
+
+                                        //okno strumienia oddajemy porcjami, gdy spadnie do połowy
+    fn refund_stream(&mut self, id: u32) {
+
+        let increment = match self.streams.get_mut(&id) {
+            Some(h2_stream) if h2_stream.recv_window <= DEFAULT_WINDOW / 2 => {
+                let increment = DEFAULT_WINDOW - h2_stream.recv_window;
+                h2_stream.recv_window = DEFAULT_WINDOW;
+                increment as usize
+            },
+            _ => 0,
+        };
+
+        self.window_update(id, increment);
+    }
+
+    fn window_update(&mut self, id: u32, increment: usize) {
+
+        if increment > 0 {
+            let mut payload = Vec::new();
+            push_u32(&mut payload, increment as u32);
+            self.frame(FRAME_WINDOW_UPDATE, 0, id, &payload);
+        }
+    }
+
+                                        //koniec odpowiedzi; gdy klient nadal wysyła ciało, przerywamy jego stronę
+    fn close_stream(&mut self, id: u32) {
+
+        let remote_open = match self.streams.remove(&id) {
+            Some(h2_stream) => h2_stream.remote_open,
+            None => false,
+        };
+
+        if remote_open {
+            let mut payload = Vec::new();
+            push_u32(&mut payload, NO_ERROR);
+            self.frame(FRAME_RST_STREAM, 0, id, &payload);
+        }
+    }
+
+    fn headers_frames(&mut self, id: u32, block: &[u8], end_stream: bool) {
+
+        let frame_size = self.frame_size;
+        let mut parts  = block.chunks(frame_size).peekable();
+        let mut first  = true;
+
+        if block.len() == 0 {
+            let flags = FLAG_END_HEADERS | if end_stream { FLAG_END_STREAM } else { 0 };
+            self.frame(FRAME_HEADERS, flags, id, &[]);
+            return;
+        }
+
+        while let Some(part) = parts.next() {
+
+            let last = parts.peek().is_none();
+            let mut flags = if last { FLAG_END_HEADERS } else { 0 };
+
+            if first && end_stream {
+                flags = flags | FLAG_END_STREAM;
+            }
+
+            let typ = if first { FRAME_HEADERS } else { FRAME_CONTINUATION };
+
+            self.frame(typ, flags, id, part);
+            first = false;
+        }
+    }
+
+                                        //odpowiedź wygenerowana przez samo połączenie (np. 413), bez udziału odbiornika
+    fn respond_error(&mut self, id: u32, code: Code, message: &str) {
+
+        if let Some(h2_stream) = self.streams.get_mut(&id) {
+            h2_stream.state = State::Waiting;
+        }
+
+        self.respond(id, Response::create(code, Type::TextHtml, message.to_owned()));
+    }
+
+                                        //ciała odpowiedzi dzielone na ramki DATA w granicach okien kontroli przepływu
+    fn fill_output(&mut self) {
+
+        let mut ids: Vec<u32> = self.streams.iter().filter(|&(_, h2_stream)| match h2_stream.state {
+            State::Sending(_, _, _) => true,
+            State::Streaming(_, _, _) => true,
+            _ => false,
+        }).map(|(id, _)| *id).collect();
+
+        ids.sort();
+
+        let mut progress = true;
+
+        while progress && self.output.len() < OUTPUT_LIMIT {
+
+            progress = false;
+
+            for id in ids.iter() {
+
+                if self.output.len() >= OUTPUT_LIMIT {
+                    break;
+                }
+
+                match self.next_data(*id) {
+                    Ok(Some((data, end_stream))) => {
+
+                        let flags = if end_stream { FLAG_END_STREAM } else { 0 };
+
+                        self.frame(FRAME_DATA, flags, *id, &data);
+
+                        if end_stream {
+                            self.close_stream(*id);
+                        } else {
+                            progress = true;
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(_) => self.reset(*id, INTERNAL_ERROR),
+                }
+            }
+
+            ids.retain(|id| self.streams.contains_key(id));
+        }
+    }
+
+                                        //kolejna ramka DATA strumienia: dane, END_STREAM; None - okno wyczerpane
+    fn next_data(&mut self, id: u32) -> io::Result<Option<(Vec<u8>, bool)>> {
+
+        let frame_size  = self.frame_size;
+        let conn_window = self.send_window;
+
+        let (data, end_stream) = {
+
+            let h2_stream = match self.streams.get_mut(&id) {
+                Some(h2_stream) => h2_stream,
+                None => return Ok(None),
+            };
+
+            let window = min(conn_window, h2_stream.send_window);
+
+            match h2_stream.state {
+
+                State::Sending(ref mut body, ref mut pending, ref mut eof) => {
+
+                    try!(refill(body, pending, eof, frame_size));
+
+                    if pending.len() == 0 && *eof {
+                        (Vec::new(), true)
+                    } else if window <= 0 {
+                        return Ok(None);
+                    } else {
+
+                        let size = min(min(pending.len(), frame_size), window as usize);
+                        let data: Vec<u8> = pending.drain(0..size).collect();
+
+                                        //doczytujemy, żeby ostatnia ramka z danymi niosła END_STREAM
+                        try!(refill(body, pending, eof, frame_size));
+
+                        let end_stream = pending.len() == 0 && *eof;
+
+                        h2_stream.send_window = h2_stream.send_window - size as i64;
+
+                        (data, end_stream)
+                    }
+                },
+
+                State::Streaming(ref mut pending, end, _) => {
+
+                    if pending.len() == 0 && end {
+                        (Vec::new(), true)
+                    } else if pending.len() == 0 || window <= 0 {
+                        return Ok(None);
+                    } else {
+
+                        let size = min(min(pending.len(), frame_size), window as usize);
+                        let data: Vec<u8> = pending.drain(0..size).collect();
+
+                        h2_stream.send_window = h2_stream.send_window - size as i64;
+
+                        (data, pending.len() == 0 && end)
+                    }
+                },
+
+                _ => return Ok(None),
+            }
+        };
+
+        self.send_window = self.send_window - data.len() as i64;
+
+        Ok(Some((data, end_stream)))
+    }
+
+    fn on_frame(&mut self, typ: u8, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if self.block.is_some() && typ != FRAME_CONTINUATION {
+            return Err((PROTOCOL_ERROR, "expected CONTINUATION frame".to_owned()));
+        }
+
+        match typ {
+            FRAME_DATA          => self.on_data(flags, id, payload),
+            FRAME_HEADERS       => self.on_headers(flags, id, payload),
+            FRAME_PRIORITY      => self.on_priority(id, payload),
+            FRAME_RST_STREAM    => self.on_rst_stream(id, payload),
+            FRAME_SETTINGS      => self.on_settings(flags, id, payload),
+            FRAME_PUSH_PROMISE  => Err((PROTOCOL_ERROR, "PUSH_PROMISE from client".to_owned())),
+            FRAME_PING          => self.on_ping(flags, id, payload),
+            FRAME_GOAWAY        => self.on_goaway(id),
+            FRAME_WINDOW_UPDATE => self.on_window_update(id, payload),
+            FRAME_CONTINUATION  => self.on_continuation(flags, id, payload),
+            _                   => Ok(()),                 //nieznane typy ramek są ignorowane
+        }
+    }
+
+    fn on_data(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id == 0 {
+            return Err((PROTOCOL_ERROR, "DATA on stream 0".to_owned()));
+        }
+
+        let len  = payload.len();
+
+        if len as i64 > self.recv_window {
+            return Err((FLOW_CONTROL_ERROR, "connection window exceeded".to_owned()));
+        }
+
+                                        //okno połączenia wraca w refund_window, gdy ciało trafi do odbiornika
+        self.recv_window = self.recv_window - len as i64;
+
+        let data = try!(strip_padding(flags, payload));
+
+        if self.streams.contains_key(&id) == false {
+
+            if id > self.last_stream {
+                return Err((PROTOCOL_ERROR, format!("DATA on idle stream {}", id)));
+            }
+
+            try!(self.count_control());
+            self.reset(id, STREAM_CLOSED);
+            return Ok(());
+        }
+
+        let overrun = match self.streams.get_mut(&id) {
+            Some(h2_stream) => {
+                h2_stream.recv_window = h2_stream.recv_window - len as i64;
+                h2_stream.recv_window < 0
+            },
+            None => false,
+        };
+
+        if overrun {
+            try!(self.count_control());
+            self.reset(id, FLOW_CONTROL_ERROR);
+            return Ok(());
+        }
+
+        let too_large = match self.streams.get_mut(&id) {
+
+            Some(h2_stream) => {
+
+                if flags & FLAG_END_STREAM != 0 {
+                    h2_stream.remote_open = false;
+                }
+
+                match h2_stream.state {
+
+                    State::Receiving(_, ref mut body) => {
+
+                        if body.len() + data.len() > MAX_BODY {
+                            Some(true)
+                        } else {
+                            body.extend_from_slice(&data);
+                            Some(false)
+                        }
+                    },
+
+                                        //odpowiedź już wysłana (np. 413) - resztę ciała pomijamy
+                    _ => None,
+                }
+            },
+
+            None => None,
+        };
+
+        match too_large {
+
+            Some(true) => {
+                self.respond_error(id, Code::Code413, "413 Payload Too Large");
+            },
+
+            Some(false) => {
+
+                if flags & FLAG_END_STREAM != 0 {
+                    self.dispatch(id);
+                } else {
+                    self.refund_stream(id);
+                }
+            },
+
+            None => {},
+        }
+
+        Ok(())
+    }
+
+    fn on_headers(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id == 0 {
+            return Err((PROTOCOL_ERROR, "HEADERS on stream 0".to_owned()));
+        }
+
+        let mut data = try!(strip_padding(flags, payload));
+
+        if flags & FLAG_PRIORITY != 0 {
+
+            if data.len() < 5 {
+                return Err((FRAME_SIZE_ERROR, "HEADERS priority too short".to_owned()));
+            }
+
+            data.drain(0..5);
+        }
+
+        let end_stream = flags & FLAG_END_STREAM != 0;
+
+        if flags & FLAG_END_HEADERS != 0 {
+            self.on_header_block(id, end_stream, data)
+        } else {
+            self.block = Some((id, end_stream, data));
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        let (block_id, end_stream, mut data) = match self.block.take() {
+            Some(block) => block,
+            None => return Err((PROTOCOL_ERROR, "unexpected CONTINUATION frame".to_owned())),
+        };
+
+        if block_id != id {
+            return Err((PROTOCOL_ERROR, "CONTINUATION on a different stream".to_owned()));
+        }
+
+        data.extend_from_slice(&payload);
+
+        if data.len() > MAX_HEADER_BLOCK {
+            return Err((ENHANCE_YOUR_CALM, "header block too large".to_owned()));
+        }
+
+        if flags & FLAG_END_HEADERS != 0 {
+            self.on_header_block(id, end_stream, data)
+        } else {
+            self.block = Some((id, end_stream, data));
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, id: u32, end_stream: bool, block: Vec<u8>) -> Result<(), (u32, String)> {
+
+                                        //dekodujemy zawsze - tablica dynamiczna HPACK musi pozostać zgodna z klientem
+        let fields = match self.decoder.decode(&block) {
+            Ok(fields) => fields,
+            Err(err) => return Err((COMPRESSION_ERROR, format!("hpack error: {:?}", err))),
+        };
+
+        let receiving = self.streams.get(&id).map(|h2_stream| match h2_stream.state {
+            State::Receiving(_, _) => true,
+            _ => false,
+        });
+
+        if receiving == Some(false) {
+            try!(self.count_control());
+            self.reset(id, STREAM_CLOSED);
+            return Ok(());
+        }
+
+                                        //trailery - kończą ciało, ich treść pomijamy
+        if receiving == Some(true) {
+
+            if end_stream == false {
+                return Err((PROTOCOL_ERROR, "trailers without END_STREAM".to_owned()));
+            }
+
+            if let Some(h2_stream) = self.streams.get_mut(&id) {
+                h2_stream.remote_open = false;
+            }
+
+            self.dispatch(id);
+            return Ok(());
+        }
+
+        if id % 2 == 0 || id <= self.last_stream {
+            return Err((PROTOCOL_ERROR, format!("invalid stream id {}", id)));
+        }
+
+        self.last_stream = id;
+
+        if self.go_away {
+            return Ok(());
+        }
+
+        if self.streams.len() >= MAX_STREAMS {
+            let mut payload = Vec::new();
+            push_u32(&mut payload, REFUSED_STREAM);
+            self.frame(FRAME_RST_STREAM, 0, id, &payload);
+            return Ok(());
+        }
+
+        let pre_request = match self.request(id, fields) {
+            Some(pre_request) => pre_request,
+            None => {
+                let mut payload = Vec::new();
+                push_u32(&mut payload, PROTOCOL_ERROR);
+                self.frame(FRAME_RST_STREAM, 0, id, &payload);
+                return Ok(());
+            }
+        };
+
+        let window_init = self.window_init;
+
+        match pre_request {
+
+            Ok(pre_request) => {
+
+                self.streams.insert(id, H2Stream {
+                    state       : State::Receiving(pre_request, Vec::new()),
+                    send_window : window_init,
+                    recv_window : DEFAULT_WINDOW,
+                    remote_open : end_stream == false,
+                });
+
+                if end_stream {
+                    self.dispatch(id);
+                }
+            },
+
+            Err(_) => {
+
+                self.streams.insert(id, H2Stream {
+                    state       : State::Waiting,
+                    send_window : window_init,
+                    recv_window : DEFAULT_WINDOW,
+                    remote_open : end_stream == false,
+                });
+
+                self.respond_error(id, Code::Code400, "400 Bad Request");
+            }
+        }
+
+        Ok(())
+    }
+
+                                        //None - nieprawidłowe pola (błąd strumienia), Some(Err) - request nie do obsłużenia (400)
+    fn request(&self, id: u32, fields: Vec<(Vec<u8>, Vec<u8>)>) -> Option<io::Result<PreRequest>> {
+
+        let mut method    = None;
+        let mut path      = None;
+        let mut authority = None;
+        let mut headers   = Vec::new();
+
+        for (name, value) in fields.into_iter() {
+
+            let (name, value) = match (String::from_utf8(name), String::from_utf8(value)) {
+                (Ok(name), Ok(value)) => (name, value),
+                _ => return None,
+            };
+
+            if name.starts_with(':') {
+
+                if headers.len() > 0 {
+                    return None;                //pseudo-nagłówki muszą być przed zwykłymi
+                }
+
+                match &name[..] {
+                    ":method"    => method = Some(value),
+                    ":path"      => path = Some(value),
+                    ":authority" => authority = Some(value),
+                    ":scheme"    => {},
+                    _            => return None,
+                }
+
+                continue;
+            }
+
+            if name.chars().any(|ch| ch.is_uppercase()) {
+                return None;
+            }
+
+            match &name[..] {
+                "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => return None,
+                "te" if value != "trailers" => return None,
+                _ => headers.push((name, value)),
+            }
+        }
+
+        let (method, path) = match (method, path) {
+            (Some(method), Some(path)) => (method, path),
+            _ => return None,
+        };
+
+        if path.len() == 0 {
+            return None;
+        }
+
+        let mut pre_request = match PreRequest::new_http2(&method, &path, authority, headers, id) {
+            Ok(pre_request) => pre_request,
+            Err(err) => return Some(Err(err)),
+        };
+
+        pre_request.set_tls(self.tls.clone());
+
+        Some(Ok(pre_request))
+    }
+
+                                        //strumień odebrany w całości - request trafia do odbiornika
+    fn dispatch(&mut self, id: u32) {
+
+        let state = match self.streams.get_mut(&id) {
+            Some(h2_stream) => ::std::mem::replace(&mut h2_stream.state, State::Waiting),
+            None => return,
+        };
+
+        match state {
+            State::Receiving(mut pre_request, body) => {
+                pre_request.set_body(body);
+                self.ready.push(pre_request);
+            },
+            other => {
+                if let Some(h2_stream) = self.streams.get_mut(&id) {
+                    h2_stream.state = other;
+                }
+            }
+        }
+    }
+
+    fn on_priority(&mut self, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id == 0 {
+            return Err((PROTOCOL_ERROR, "PRIORITY on stream 0".to_owned()));
+        }
+
+        if payload.len() != 5 {
+            try!(self.count_control());
+            self.reset(id, FRAME_SIZE_ERROR);
+        }
+
+        Ok(())
+    }
+
+    fn on_rst_stream(&mut self, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id == 0 {
+            return Err((PROTOCOL_ERROR, "RST_STREAM on stream 0".to_owned()));
+        }
+
+        if payload.len() != 4 {
+            return Err((FRAME_SIZE_ERROR, "RST_STREAM with invalid length".to_owned()));
+        }
+
+        if id > self.last_stream {
+            return Err((PROTOCOL_ERROR, format!("RST_STREAM on idle stream {}", id)));
+        }
+
+        try!(self.count_control());
+
+        self.drop_stream(id);
+
+        Ok(())
+    }
+
+    fn on_settings(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id != 0 {
+            return Err((PROTOCOL_ERROR, "SETTINGS on a stream".to_owned()));
+        }
+
+        if flags & FLAG_ACK != 0 {
+
+            if payload.len() != 0 {
+                return Err((FRAME_SIZE_ERROR, "SETTINGS ack with payload".to_owned()));
+            }
+
+            return Ok(());
+        }
+
+        if payload.len() % 6 != 0 {
+            return Err((FRAME_SIZE_ERROR, "SETTINGS with invalid length".to_owned()));
+        }
+
+        try!(self.count_control());
+
+        for setting in payload.chunks(6) {
+
+            let key   = ((setting[0] as u16) << 8) | setting[1] as u16;
+            let value = read_u32(&setting[2..6]);
+
+            match key {
+
+                SETTINGS_HEADER_TABLE_SIZE => {},   //nagłówki kodujemy bez tablicy dynamicznej
+
+                SETTINGS_ENABLE_PUSH => {
+                    if value > 1 {
+                        return Err((PROTOCOL_ERROR, "invalid SETTINGS_ENABLE_PUSH".to_owned()));
+                    }
+                },
+
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+
+                    if value as i64 > MAX_WINDOW {
+                        return Err((FLOW_CONTROL_ERROR, "invalid SETTINGS_INITIAL_WINDOW_SIZE".to_owned()));
+                    }
+
+                                        //zmiana dotyczy też okien otwartych strumieni
+                    let delta = value as i64 - self.window_init;
+
+                    for (_, h2_stream) in self.streams.iter_mut() {
+                        h2_stream.send_window = h2_stream.send_window + delta;
+                    }
+
+                    self.window_init = value as i64;
+                },
+
+                SETTINGS_MAX_FRAME_SIZE => {
+
+                    if value < 16384 || value > 16777215 {
+                        return Err((PROTOCOL_ERROR, "invalid SETTINGS_MAX_FRAME_SIZE".to_owned()));
+                    }
+
+                    self.frame_size = min(value as usize, MAX_FRAME);
+                },
+
+                _ => {},
+            }
+        }
+
+        self.frame(FRAME_SETTINGS, FLAG_ACK, 0, &[]);
+
+        Ok(())
+    }
+
+    fn on_ping(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if id != 0 {
+            return Err((PROTOCOL_ERROR, "PING on a stream".to_owned()));
+        }
+
+        if payload.len() != 8 {
+            return Err((FRAME_SIZE_ERROR, "PING with invalid length".to_owned()));
+        }
+
+        if flags & FLAG_ACK == 0 {
+            try!(self.count_control());
+            self.frame(FRAME_PING, FLAG_ACK, 0, &payload);
+        }
+
+        Ok(())
+    }
+
+    fn on_goaway(&mut self, id: u32) -> Result<(), (u32, String)> {
+
+        if id != 0 {
+            return Err((PROTOCOL_ERROR, "GOAWAY on a stream".to_owned()));
+        }
+
+                                        //klient kończy - dokańczamy rozpoczęte strumienie
+        self.go_away = true;
+
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, id: u32, payload: Vec<u8>) -> Result<(), (u32, String)> {
+
+        if payload.len() != 4 {
+            return Err((FRAME_SIZE_ERROR, "WINDOW_UPDATE with invalid length".to_owned()));
+        }
+
+        let increment = (read_u32(&payload) & 0x7fffffff) as i64;
+
+        if id == 0 {
+
+            if increment == 0 {
+                return Err((PROTOCOL_ERROR, "WINDOW_UPDATE with zero increment".to_owned()));
+            }
+
+            self.send_window = self.send_window + increment;
+
+            if self.send_window > MAX_WINDOW {
+                return Err((FLOW_CONTROL_ERROR, "connection window overflow".to_owned()));
+            }
+
+            return Ok(());
+        }
+
+        let overflow = match self.streams.get_mut(&id) {
+            Some(h2_stream) => {
+                h2_stream.send_window = h2_stream.send_window + increment;
+                increment == 0 || h2_stream.send_window > MAX_WINDOW
+            },
+            None => false,
+        };
+
+        if overflow {
+            let code = if increment == 0 { PROTOCOL_ERROR } else { FLOW_CONTROL_ERROR };
+            try!(self.count_control());
+            self.reset(id, code);
+        }
+
+        Ok(())
+    }
+}
+
+
+fn refill(body: &mut Box<Read + Send>, pending: &mut Vec<u8>, eof: &mut bool, block: usize) -> io::Result<()> {
+
+    if pending.len() > 0 || *eof {
+        return Ok(());
+    }
+
+    let mut buf = vec![0u8; block];
+
+    loop {
+        match body.read(&mut buf) {
+            Ok(0) => {
+                *eof = true;
+                return Ok(());
+            },
+            Ok(size) => {
+                pending.extend_from_slice(&buf[0..size]);
+                return Ok(());
+            },
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+
+fn strip_padding(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, (u32, String)> {
+
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+
+    if payload.len() == 0 {
+        return Err((PROTOCOL_ERROR, "padded frame without pad length".to_owned()));
+    }
+
+    let pad = payload[0] as usize;
+
+    if pad + 1 > payload.len() {
+        return Err((PROTOCOL_ERROR, "padding longer than frame".to_owned()));
+    }
+
+    let end = payload.len() - pad;
+
+    payload.truncate(end);
+    payload.remove(0);
+
+    Ok(payload)
+}
+
+
+fn read_u32(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32
+}
+
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+
+fn push_setting(out: &mut Vec<u8>, key: u16, value: u32) {
+    out.push((key >> 8) as u8);
+    out.push(key as u8);
+    push_u32(out, value);
+}
+
+
+                                        //RFC 7541 5.1
+fn push_integer(out: &mut Vec<u8>, mut value: usize, prefix: u8, first: u8) {
+
+    let max = (1usize << prefix) - 1;
+
+    if value < max {
+        out.push(first | value as u8);
+        return;
+    }
+
+    out.push(first | max as u8);
+    value = value - max;
+
+    while value >= 128 {
+        out.push((value % 128 + 128) as u8);
+        value = value / 128;
+    }
+
+    out.push(value as u8);
+}
+
+
+                                        //"literal without indexing, new name" - bez tablicy dynamicznej, więc rozmiar
+                                        //tablicy ustawiony przez klienta nie ma znaczenia
+fn encode_headers(fields: &Vec<(String, String)>) -> Vec<u8> {
+
+    let mut out = Vec::new();
+
+    for &(ref name, ref value) in fields.iter() {
+
+        out.push(0x00);
+
+        push_integer(&mut out, name.len(), 7, 0x00);
+        out.extend_from_slice(name.as_bytes());
+
+        push_integer(&mut out, value.len(), 7, 0x00);
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use hpack::{Decoder, Encoder};
+    use response::Response;
+    use code::Code;
+    use typemod::Type;
+    use super::*;
+
+    type Frame = (u8, u8, u32, Vec<u8>);
+
+    fn frame(typ: u8, flags: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+
+        let len = payload.len();
+        let mut out = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, typ, flags];
+
+        push_u32(&mut out, id);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn frames(data: &[u8]) -> Vec<Frame> {
+
+        let mut out = Vec::new();
+        let mut pos = 0;
+
+        while pos + 9 <= data.len() {
+            let len = ((data[pos] as usize) << 16) | ((data[pos + 1] as usize) << 8) | data[pos + 2] as usize;
+            out.push((data[pos + 3], data[pos + 4], read_u32(&data[pos + 5..pos + 9]), data[pos + 9..pos + 9 + len].to_vec()));
+            pos = pos + 9 + len;
+        }
+
+        assert_eq!(pos, data.len());
+        out
+    }
+
+                                        //połączenie po preambule i SETTINGS klienta, z pustym output
+    fn connection() -> Http2 {
+
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(&frame(FRAME_SETTINGS, 0, 0, &[]));
+
+        let mut http2 = Http2::new(None, input);
+
+        assert_eq!(http2.process(), Ok(()));
+        http2.output.clear();
+        http2
+    }
+
+    fn send(http2: &mut Http2, data: &[u8]) -> Vec<Frame> {
+
+        http2.input.extend_from_slice(data);
+
+        let _ = http2.process();
+        let out = frames(&http2.output);
+
+        http2.output.clear();
+        out
+    }
+
+    fn goaway_code(frames: &Vec<Frame>) -> Option<u32> {
+        frames.iter().find(|frame| frame.0 == FRAME_GOAWAY).map(|frame| read_u32(&frame.3[4..8]))
+    }
+
+    fn request_block(method: &str, path: &str) -> Vec<u8> {
+        encode_headers(&vec![
+            (":method".to_owned(), method.to_owned()),
+            (":scheme".to_owned(), "https".to_owned()),
+            (":path".to_owned(), path.to_owned()),
+            (":authority".to_owned(), "example.com".to_owned()),
+        ])
+    }
+
+    fn open_stream(http2: &mut Http2, id: u32, end_stream: bool) -> Vec<Frame> {
+        let flags = FLAG_END_HEADERS | if end_stream { FLAG_END_STREAM } else { 0 };
+        send(http2, &frame(FRAME_HEADERS, flags, id, &request_block("POST", "/upload")))
+    }
+
+    #[test]
+    fn integer_encoding() {
+
+        let mut out = Vec::new();
+        push_integer(&mut out, 10, 5, 0x00);
+        assert_eq!(out, vec![0x0a]);
+
+        let mut out = Vec::new();
+        push_integer(&mut out, 1337, 5, 0x00);
+        assert_eq!(out, vec![0x1f, 0x9a, 0x0a]);
+
+        let mut out = Vec::new();
+        push_integer(&mut out, 42, 8, 0x00);
+        assert_eq!(out, vec![0x2a]);
+
+        let mut out = Vec::new();
+        push_integer(&mut out, 127, 7, 0x00);
+        assert_eq!(out, vec![0x7f, 0x00]);
+    }
+
+    #[test]
+    fn encode_headers_roundtrip() {
+
+        let long: String = ::std::iter::repeat('a').take(300).collect();
+
+        let fields = vec![
+            (":status".to_owned(), "200".to_owned()),
+            ("content-type".to_owned(), "text/html".to_owned()),
+            ("x-long".to_owned(), long.clone()),
+        ];
+
+        let block = encode_headers(&fields);
+
+        assert_eq!(&block[0..9], b"\x00\x07:status");
+
+        let decoded = Decoder::new().decode(&block).unwrap();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = fields.into_iter().map(|(name, value)| (name.into_bytes(), value.into_bytes())).collect();
+
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn server_preface() {
+
+        let mut http2 = Http2::new(None, Vec::new());
+        let out = frames(&http2.output);
+
+        assert_eq!(out[0].0, FRAME_SETTINGS);
+        assert_eq!(out[0].3, vec![0, 3, 0, 0, 0, 100, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(out[1].0, FRAME_WINDOW_UPDATE);
+        assert_eq!(read_u32(&out[1].3) as usize, MAX_BUFFERED - DEFAULT_WINDOW as usize);
+
+        http2.input.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        http2.output.clear();
+
+        assert!(http2.process().is_err());
+        assert_eq!(goaway_code(&frames(&http2.output)), Some(PROTOCOL_ERROR));
+    }
+
+    #[test]
+    fn settings_and_ping() {
+
+        let mut http2 = connection();
+
+        let mut settings = Vec::new();
+        push_setting(&mut settings, SETTINGS_INITIAL_WINDOW_SIZE, 1000);
+        push_setting(&mut settings, SETTINGS_MAX_FRAME_SIZE, 32768);
+
+        assert_eq!(send(&mut http2, &frame(FRAME_SETTINGS, 0, 0, &settings)), vec![(FRAME_SETTINGS, FLAG_ACK, 0, vec![])]);
+        assert_eq!(http2.window_init, 1000);
+        assert_eq!(http2.frame_size, MAX_FRAME);
+
+        assert_eq!(send(&mut http2, &frame(FRAME_PING, 0, 0, b"12345678")), vec![(FRAME_PING, FLAG_ACK, 0, b"12345678".to_vec())]);
+        assert_eq!(send(&mut http2, &frame(FRAME_PING, FLAG_ACK, 0, b"12345678")), vec![]);
+
+        let out = send(&mut http2, &frame(FRAME_PING, 0, 0, b"1234567"));
+        assert_eq!(goaway_code(&out), Some(FRAME_SIZE_ERROR));
+    }
+
+    #[test]
+    fn invalid_frames() {
+
+        let mut http2 = connection();
+        assert_eq!(goaway_code(&send(&mut http2, &frame(FRAME_DATA, 0, 0, b"x"))), Some(PROTOCOL_ERROR));
+
+        let mut http2 = connection();
+        let mut header = frame(FRAME_DATA, 0, 1, &[]);
+        header[0] = 1;
+        assert_eq!(goaway_code(&send(&mut http2, &header)), Some(FRAME_SIZE_ERROR));
+
+        let mut http2 = connection();
+        assert_eq!(goaway_code(&send(&mut http2, &frame(FRAME_HEADERS, FLAG_END_HEADERS, 2, &request_block("GET", "/")))), Some(PROTOCOL_ERROR));
+
+        let mut http2 = connection();
+        let out = send(&mut http2, &frame(FRAME_HEADERS, 0, 1, &request_block("GET", "/")));
+        assert_eq!(out, vec![]);
+        assert_eq!(goaway_code(&send(&mut http2, &frame(FRAME_PING, 0, 0, b"12345678"))), Some(PROTOCOL_ERROR));
+
+        let mut http2 = connection();
+        assert_eq!(goaway_code(&send(&mut http2, &frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, b"\xff\xff\xff"))), Some(COMPRESSION_ERROR));
+    }
+
+    #[test]
+    fn request_from_hpack_block() {
+
+        let mut http2 = connection();
+
+        let fields: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b":method".to_vec(), b"GET".to_vec()),
+            (b":scheme".to_vec(), b"https".to_vec()),
+            (b":path".to_vec(), b"/index.html?a=1".to_vec()),
+            (b":authority".to_vec(), b"example.com".to_vec()),
+            (b"accept".to_vec(), b"text/html".to_vec()),
+        ];
+
+        let mut encoder = Encoder::new();
+        let block = encoder.encode(&fields);
+
+        send(&mut http2, &frame(FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 1, &block));
+
+                                        //druga kopia trafia do tablicy dynamicznej dekodera
+        let block = encoder.encode(&fields);
+
+        send(&mut http2, &frame(FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 3, &block));
+
+        let requests = http2.take_requests();
+
+        assert_eq!(requests.len(), 2);
+
+        for (request, id) in requests.iter().zip(vec![1, 3]) {
+            assert_eq!(request.method(), "GET");
+            assert_eq!(request.path(), "/index.html");
+            assert_eq!(request.header("host"), Some(&"example.com".to_owned()));
+            assert_eq!(request.header("accept"), Some(&"text/html".to_owned()));
+            assert_eq!(request.stream_id(), Some(id));
+        }
+    }
+
+    #[test]
+    fn continuation_and_padding() {
+
+        let mut http2 = connection();
+        let block = request_block("GET", "/a");
+
+        let mut first = vec![3];
+        first.extend_from_slice(&[0, 0, 0, 0, 16]);
+        first.extend_from_slice(&block[0..10]);
+        first.extend_from_slice(&[0, 0, 0]);
+
+        send(&mut http2, &frame(FRAME_HEADERS, FLAG_PADDED | FLAG_PRIORITY | FLAG_END_STREAM, 1, &first));
+        assert_eq!(http2.take_requests().len(), 0);
+
+        send(&mut http2, &frame(FRAME_CONTINUATION, FLAG_END_HEADERS, 1, &block[10..]));
+
+        let requests = http2.take_requests();
+
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path(), "/a");
+    }
+
+    #[test]
+    fn malformed_request() {
+
+        let mut http2 = connection();
+
+        let block = encode_headers(&vec![
+            (":method".to_owned(), "GET".to_owned()),
+            (":path".to_owned(), "/".to_owned()),
+            ("X-Upper".to_owned(), "1".to_owned()),
+        ]);
+
+        let out = send(&mut http2, &frame(FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 1, &block));
+
+        assert_eq!(out, vec![(FRAME_RST_STREAM, 0, 1, vec![0, 0, 0, PROTOCOL_ERROR as u8])]);
+        assert_eq!(http2.take_requests().len(), 0);
+    }
+
+    #[test]
+    fn window_refunded_when_body_dispatched() {
+
+        let mut http2 = connection();
+
+        open_stream(&mut http2, 1, false);
+
+        let out = send(&mut http2, &frame(FRAME_DATA, 0, 1, &[7u8; 1000]));
+
+                                        //okno strumienia wraca dopiero, gdy spadnie do połowy
+        assert_eq!(out, vec![]);
+        assert_eq!(http2.recv_window, MAX_BUFFERED as i64 - 1000);
+        assert_eq!(http2.streams[&1].recv_window, DEFAULT_WINDOW - 1000);
+
+        let mut padded = vec![10];
+        padded.extend_from_slice(&[7u8; 500]);
+        padded.extend_from_slice(&[0u8; 10]);
+
+        let out = send(&mut http2, &frame(FRAME_DATA, FLAG_PADDED | FLAG_END_STREAM, 1, &padded));
+
+        assert_eq!(out, vec![(FRAME_WINDOW_UPDATE, 0, 0, vec![0, 0, 0x05, 0xe7])]);
+        assert_eq!(http2.recv_window, MAX_BUFFERED as i64);
+        assert_eq!(http2.take_requests().len(), 1);
+
+        http2.recv_window = 10;
+
+        assert_eq!(goaway_code(&send(&mut http2, &frame(FRAME_DATA, 0, 1, &[0u8; 11]))), Some(FLOW_CONTROL_ERROR));
+    }
+
+    #[test]
+    fn stream_window_enforced() {
+
+        let mut http2 = connection();
+        let chunk = vec![1u8; MAX_FRAME];
+
+        open_stream(&mut http2, 1, false);
+
+        assert_eq!(send(&mut http2, &frame(FRAME_DATA, 0, 1, &chunk)), vec![]);
+
+        let out = send(&mut http2, &frame(FRAME_DATA, 0, 1, &chunk));
+        assert_eq!(out, vec![(FRAME_WINDOW_UPDATE, 0, 1, vec![0, 0, 0x80, 0])]);
+        assert_eq!(http2.streams[&1].recv_window, DEFAULT_WINDOW);
+
+                                        //klient ignoruje okno strumienia - tylko ten strumień jest przerywany
+        http2.streams.get_mut(&1).unwrap().recv_window = 10;
+
+        let out = send(&mut http2, &frame(FRAME_DATA, 0, 1, &[0u8; 11]));
+
+        assert_eq!(out[0], (FRAME_RST_STREAM, 0, 1, vec![0, 0, 0, FLOW_CONTROL_ERROR as u8]));
+        assert_eq!(goaway_code(&out), None);
+        assert_eq!(http2.streams.contains_key(&1), false);
+        assert_eq!(http2.recv_window, MAX_BUFFERED as i64);
+    }
+
+    #[test]
+    fn buffered_bodies_bounded() {
+
+        let mut http2 = connection();
+        let chunk = vec![1u8; MAX_FRAME];
+
+        open_stream(&mut http2, 1, false);
+        open_stream(&mut http2, 3, false);
+
+        for _ in 0..(MAX_BODY / MAX_FRAME) {
+            send(&mut http2, &frame(FRAME_DATA, 0, 1, &chunk));
+        }
+
+        let mut out = Vec::new();
+
+        for _ in 0..(MAX_BODY / MAX_FRAME) {
+            out = send(&mut http2, &frame(FRAME_DATA, 0, 3, &chunk));
+        }
+
+                                        //oba ciała wyczerpały budżet - jedno zostaje odrzucone, a okno zwolnione
+        assert!(out.contains(&(FRAME_RST_STREAM, 0, 3, vec![0, 0, 0, REFUSED_STREAM as u8])));
+        assert!(out.contains(&(FRAME_WINDOW_UPDATE, 0, 0, vec![0, 0x80, 0, 0])));
+        assert_eq!(http2.buffered(), MAX_BODY);
+        assert_eq!(http2.recv_window, (MAX_BUFFERED - MAX_BODY) as i64);
+
+        let out = send(&mut http2, &frame(FRAME_DATA, 0, 1, b"x"));
+        assert_eq!(out[0].0, FRAME_HEADERS);
+        assert_eq!(http2.buffered(), 0);
+    }
+
+    #[test]
+    fn control_frame_flood() {
+
+        let mut http2 = connection();
+        let mut input = Vec::new();
+
+                                        //SETTINGS klienta z connection() też się liczy
+        for _ in 0..CONTROL_LIMIT {
+            input.extend_from_slice(&frame(FRAME_PING, 0, 0, b"12345678"));
+        }
+
+        let out = send(&mut http2, &input);
+
+        assert_eq!(out.len(), CONTROL_LIMIT);
+        assert_eq!(goaway_code(&out), Some(ENHANCE_YOUR_CALM));
+        assert!(http2.closing);
+    }
+
+    #[test]
+    fn closed_stream_data_flood() {
+
+        let mut http2 = connection();
+
+        open_stream(&mut http2, 1, false);
+        send(&mut http2, &frame(FRAME_RST_STREAM, 0, 1, &[0, 0, 0, CANCEL as u8]));
+
+        let mut input = Vec::new();
+
+        for _ in 0..CONTROL_LIMIT {
+            input.extend_from_slice(&frame(FRAME_DATA, 0, 1, b"x"));
+        }
+
+        let out = send(&mut http2, &input);
+
+        assert_eq!(out[0], (FRAME_RST_STREAM, 0, 1, vec![0, 0, 0, STREAM_CLOSED as u8]));
+        assert_eq!(goaway_code(&out), Some(ENHANCE_YOUR_CALM));
+        assert!(http2.closing);
+    }
+
+    #[test]
+    fn full_output_pauses_processing() {
+
+        let mut http2 = connection();
+
+        http2.output = vec![0u8; OUTPUT_LIMIT];
+        http2.input.extend_from_slice(&frame(FRAME_PING, 0, 0, b"12345678"));
+
+        assert_eq!(http2.process(), Ok(()));
+        assert_eq!(http2.output.len(), OUTPUT_LIMIT);
+        assert_eq!(http2.wants_process(), false);
+
+        http2.output.clear();
+
+        assert!(http2.wants_process());
+        assert_eq!(http2.process(), Ok(()));
+        assert_eq!(frames(&http2.output), vec![(FRAME_PING, FLAG_ACK, 0, b"12345678".to_vec())]);
+        assert_eq!(http2.wants_process(), false);
+    }
+
+    #[test]
+    fn reset_dispatched_streams_keep_their_slots() {
+
+        let mut http2 = connection();
+
+        for index in 0..MAX_STREAMS as u32 {
+            let id = 2 * index + 1;
+            open_stream(&mut http2, id, true);
+            send(&mut http2, &frame(FRAME_RST_STREAM, 0, id, &[0, 0, 0, CANCEL as u8]));
+        }
+
+        assert_eq!(http2.take_requests().len(), MAX_STREAMS);
+        assert_eq!(http2.streams.len(), MAX_STREAMS);
+
+        let out = open_stream(&mut http2, 201, true);
+        assert_eq!(out, vec![(FRAME_RST_STREAM, 0, 201, vec![0, 0, 0, REFUSED_STREAM as u8])]);
+
+                                        //odpowiedź na zresetowany strumień nie jest wysyłana, ale zwalnia miejsce
+        http2.respond(1, Response::create(Code::Code200, Type::TextHtml, "ok".to_owned()));
+
+        assert_eq!(http2.output.len(), 0);
+        assert_eq!(http2.streams.len(), MAX_STREAMS - 1);
+
+        open_stream(&mut http2, 203, true);
+        assert_eq!(http2.take_requests().len(), 1);
+    }
+}
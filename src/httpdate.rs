@@ -0,0 +1,199 @@
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
+
+//https://tools.ietf.org/html/rfc7231#section-7.1.1.1
+//http://howardhinnant.github.io/date_algorithms.html
+
+
+const DAYS   : [&'static str; 7]  = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS : [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+
+    let z   = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp  = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year  = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u32, day as u32)
+}
+
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+
+    let year = if month <= 2 { year - 1 } else { year };
+    let era  = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe  = year - era * 400;
+    let mp   = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy  = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe  = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+
+/// Formats a time as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format(time: SystemTime) -> String {
+
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0,
+    };
+
+    let days = secs / 86400;
+    let rest = secs % 86400;
+
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rest / 3600,
+        (rest % 3600) / 60,
+        rest % 60
+    )
+}
+
+
+pub fn now() -> String {
+    format(SystemTime::now())
+}
+
+
+fn parse_month(name: &str) -> Option<u32> {
+
+    for (index, month) in MONTHS.iter().enumerate() {
+        if *month == name {
+            return Some(index as u32 + 1);
+        }
+    }
+
+    None
+}
+
+
+fn parse_time(value: &str) -> Option<(u64, u64, u64)> {
+
+    let parts: Vec<&str> = value.split(':').collect();
+
+    if parts.len() != 3 {
+        return None;
+    }
+
+    match (parts[0].parse(), parts[1].parse(), parts[2].parse()) {
+        (Ok(hour), Ok(min), Ok(sec)) if hour < 24 && min < 60 && sec < 61 => Some((hour, min, sec)),
+        _ => None,
+    }
+}
+
+
+fn make_time(year: i64, month: u32, day: u32, time: (u64, u64, u64)) -> Option<SystemTime> {
+
+    if year < 1970 || day == 0 || day > 31 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let (hour, min, sec) = time;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + min * 60 + sec))
+}
+
+
+/// Parses the three date formats a server has to accept: IMF-fixdate, RFC 850 and asctime.
+pub fn parse(value: &str) -> Option<SystemTime> {
+
+    let parts: Vec<&str> = value.trim().split_whitespace().collect();
+
+    match parts.len() {
+
+                                        //Sun, 06 Nov 1994 08:49:37 GMT
+        6 if parts[5] == "GMT" => {
+
+            let day   = match parts[1].parse() { Ok(day) => day, Err(_) => return None };
+            let month = match parse_month(parts[2]) { Some(month) => month, None => return None };
+            let year  = match parts[3].parse() { Ok(year) => year, Err(_) => return None };
+            let time  = match parse_time(parts[4]) { Some(time) => time, None => return None };
+
+            make_time(year, month, day, time)
+        },
+
+                                        //Sunday, 06-Nov-94 08:49:37 GMT
+        4 if parts[3] == "GMT" => {
+
+            let date: Vec<&str> = parts[1].split('-').collect();
+
+            if date.len() != 3 {
+                return None;
+            }
+
+            let day   = match date[0].parse() { Ok(day) => day, Err(_) => return None };
+            let month = match parse_month(date[1]) { Some(month) => month, None => return None };
+            let year: i64 = match date[2].parse() { Ok(year) => year, Err(_) => return None };
+            let time  = match parse_time(parts[2]) { Some(time) => time, None => return None };
+
+            let year = if year < 70 { year + 2000 } else if year < 100 { year + 1900 } else { year };
+
+            make_time(year, month, day, time)
+        },
+
+                                        //Sun Nov  6 08:49:37 1994
+        5 => {
+
+            let month = match parse_month(parts[1]) { Some(month) => month, None => return None };
+            let day   = match parts[2].parse() { Ok(day) => day, Err(_) => return None };
+            let time  = match parse_time(parts[3]) { Some(time) => time, None => return None };
+            let year  = match parts[4].parse() { Ok(year) => year, Err(_) => return None };
+
+            make_time(year, month, day, time)
+        },
+
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::{UNIX_EPOCH, Duration};
+    use super::{format, parse};
+
+    #[test]
+    fn format_date() {
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(UNIX_EPOCH + Duration::from_secs(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(UNIX_EPOCH + Duration::from_secs(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_formats() {
+        let time = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), time);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), time);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), time);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse("Thu, 01 Jan 1960 00:00:00 GMT"), None);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(parse(&format(time)), Some(time));
+    }
+}
@@ -4,6 +4,15 @@ extern crate mio;
 extern crate httparse;
 extern crate net2;
 extern crate libc;
+extern crate crypto;
+extern crate rand;
+extern crate rustc_serialize;
+extern crate flate2;
+extern crate hpack;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate webpki;
 
 mod token_gen;
 mod request;
@@ -15,14 +24,68 @@ mod miostart;
 mod miodown;
 mod typemod;
 mod code;
+mod urlencoded;
+mod form;
+mod body;
+mod multipart;
+mod httpdate;
+mod cookie;
+mod session;
+mod router;
+mod middleware;
+mod static_files;
+mod output;
+mod stream;
+mod http2;
+mod websocket;
+mod event_stream;
+mod upgrade;
+mod tunnel;
+mod upstream;
+mod proxy;
+mod client;
+#[cfg(feature = "tls")]
+mod x509;
+#[cfg(feature = "tls")]
+mod tls;
+mod range;
+mod conditional;
+mod compression;
+mod listing;
+mod file_cache;
+mod virtual_hosts;
 
-pub use server::new_server;
+pub use server::{new_server, FnReceiver};
+#[cfg(feature = "tls")]
+pub use server::new_tls_server;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+pub use stream::TlsInfo;
+#[cfg(feature = "tls")]
+pub use x509::ClientCert;
 pub use request::Request;
-pub use response::Response;
+pub use response::{Response, Body};
 pub use miostart::MioStart;
 pub use miodown::MioDown;
 pub use typemod::Type;
 pub use code::Code;
+pub use form::Form;
+pub use body::BodyReader;
+pub use multipart::{Multipart, MultipartLimits, Field, FilePart};
+pub use cookie::{Cookie, SameSite};
+pub use session::{Session, SessionStore, SessionManager, CookieStore, MemoryStore};
+pub use router::Router;
+pub use virtual_hosts::VirtualHosts;
+pub use middleware::{Middleware, Chain};
+pub use static_files::StaticFiles;
+pub use file_cache::FileCache;
+pub use compression::{Compression, Encoding};
+pub use websocket::{WebSocket, WebSocketHandler, Message};
+pub use event_stream::EventStream;
+pub use upgrade::{Upgraded, UpgradeHandler};
+pub use tunnel::Tunnel;
+pub use proxy::{ProxyHandler, Balance};
+pub use client::{Client, ClientRequest, ClientResponse};
 
 
 
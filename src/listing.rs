@@ -0,0 +1,323 @@
+use std::io;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use rustc_serialize::json::Json;
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use urlencoded;
+use httpdate;
+
+
+pub struct Entry {
+    name     : String,
+    is_dir   : bool,
+    size     : u64,
+    modified : Option<SystemTime>,
+    typ      : Option<Type>,                    //tylko dla plików
+}
+
+
+#[derive(PartialEq, Clone, Copy)]
+enum Sort {
+    Name,
+    Size,
+    Modified,
+}
+
+
+/// Reads the entries of `dir`. Entries leading outside of `root` (symlinks) and, unless `show_hidden` is set,
+/// names starting with a dot are left out. Names which are not valid UTF-8 are skipped as well.
+pub fn read(dir: &Path, root: &Path, show_hidden: bool) -> io::Result<Vec<Entry>> {
+
+    let mut list = Vec::new();
+
+    for item in try!(fs::read_dir(dir)) {
+
+        let item = try!(item);
+
+        let name = match item.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if show_hidden == false && name.starts_with('.') {
+            continue;
+        }
+
+        let path = item.path();
+
+                                        //te same zabezpieczenia co przy wysyłaniu pliku
+        match fs::canonicalize(&path) {
+            Ok(ref canonical) if canonical.starts_with(root) => {},
+            _ => continue,
+        }
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        list.push(Entry {
+            typ      : if metadata.is_dir() { None } else { Some(Type::create_from_path(&path)) },
+            name     : name,
+            is_dir   : metadata.is_dir(),
+            size     : if metadata.is_dir() { 0 } else { metadata.len() },
+            modified : metadata.modified().ok(),
+        });
+    }
+
+    Ok(list)
+}
+
+
+fn compare(a: &Entry, b: &Entry, sort: Sort, desc: bool) -> Ordering {
+
+                                        //katalogi zawsze przed plikami
+    if a.is_dir != b.is_dir {
+        return if a.is_dir { Ordering::Less } else { Ordering::Greater };
+    }
+
+    let ordering = match sort {
+        Sort::Name => Ordering::Equal,
+        Sort::Size => a.size.cmp(&b.size),
+        Sort::Modified => a.modified.cmp(&b.modified),
+    };
+
+    let ordering = match ordering {
+        Ordering::Equal => a.name.cmp(&b.name),
+        ordering => ordering,
+    };
+
+    if desc { ordering.reverse() } else { ordering }
+}
+
+
+                                        //?sort=name|size|mtime&order=asc|desc
+fn sort(request: &Request, list: &mut Vec<Entry>) {
+
+    let sort = match request.query_param("sort").map(|value| &value[..]) {
+        Some("size") => Sort::Size,
+        Some("mtime") | Some("modified") => Sort::Modified,
+        _ => Sort::Name,
+    };
+
+    let desc = request.query_param("order").map(|value| value == "desc") == Some(true);
+
+    list.sort_by(|a, b| compare(a, b, sort, desc));
+}
+
+
+pub fn escape_html(text: &str) -> String {
+
+    let mut out = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&'  => out.push_str("&amp;"),
+            '<'  => out.push_str("&lt;"),
+            '>'  => out.push_str("&gt;"),
+            '"'  => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _    => out.push(ch),
+        }
+    }
+
+    out
+}
+
+
+fn unix_time(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|duration| duration.as_secs())
+}
+
+
+fn href(entry: &Entry) -> String {
+
+    if entry.is_dir {
+        urlencoded::encode(&entry.name) + "/"
+    } else {
+        urlencoded::encode(&entry.name)
+    }
+}
+
+
+fn sort_link(request: &Request, column: &str, label: &str) -> String {
+
+    let current = request.query_param("sort").map(|value| &value[..]).unwrap_or("name") == column;
+    let desc    = request.query_param("order").map(|value| value == "desc") == Some(true);
+
+                                        //drugie kliknięcie w tę samą kolumnę odwraca kolejność
+    let order = if current && desc == false { "desc" } else { "asc" };
+
+    format!("<a href=\"?sort={}&amp;order={}\">{}</a>", column, order, label)
+}
+
+
+fn render_html(request: &Request, list: &Vec<Entry>, is_root: bool) -> Response {
+
+    let title = escape_html(request.path());
+
+    let mut out = String::new();
+
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>Index of {}</title>\n", title));
+    out.push_str("</head>\n<body>\n");
+    out.push_str(&format!("<h1>Index of {}</h1>\n", title));
+    out.push_str("<table>\n<tr>");
+    out.push_str(&format!("<th>{}</th><th>{}</th><th>{}</th><th>Type</th>", sort_link(request, "name", "Name"), sort_link(request, "size", "Size"), sort_link(request, "mtime", "Modified")));
+    out.push_str("</tr>\n");
+
+    if is_root == false {
+        out.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in list.iter() {
+
+        let name = if entry.is_dir { entry.name.clone() + "/" } else { entry.name.clone() };
+        let size = if entry.is_dir { "-".to_owned() } else { format!("{}", entry.size) };
+
+        let modified = match entry.modified {
+            Some(modified) => httpdate::format(modified),
+            None => "".to_owned(),
+        };
+
+        let typ = match entry.typ {
+            Some(typ) => escape_html(typ.to_str()),
+            None => "".to_owned(),
+        };
+
+        out.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>\n", escape_html(&href(entry)), escape_html(&name), size, modified, typ));
+    }
+
+    out.push_str("</table>\n</body>\n</html>\n");
+
+    Response::create(Code::Code200, Type::TextHtml, out)
+}
+
+
+fn render_json(request: &Request, list: &Vec<Entry>) -> Response {
+
+    let mut entries = Vec::new();
+
+    for entry in list.iter() {
+
+        let mut object = BTreeMap::new();
+
+        object.insert("name".to_owned(), Json::String(entry.name.clone()));
+        object.insert("href".to_owned(), Json::String(href(entry)));
+        object.insert("dir".to_owned(), Json::Boolean(entry.is_dir));
+        object.insert("size".to_owned(), Json::U64(entry.size));
+
+        object.insert("modified".to_owned(), match unix_time(entry.modified) {
+            Some(time) => Json::U64(time),
+            None => Json::Null,
+        });
+
+        object.insert("type".to_owned(), match entry.typ {
+            Some(typ) => Json::String(typ.to_str().to_owned()),
+            None => Json::Null,
+        });
+
+        entries.push(Json::Object(object));
+    }
+
+    let mut root = BTreeMap::new();
+
+    root.insert("path".to_owned(), Json::String(request.path().clone()));
+    root.insert("entries".to_owned(), Json::Array(entries));
+
+    Response::create(Code::Code200, Type::ApplicationJson, Json::Object(root).to_string())
+}
+
+
+                                        //JSON dla ?format=json albo Accept: application/json (o ile włączony)
+fn wants_json(request: &Request) -> bool {
+
+    match request.query_param("format").map(|value| &value[..]) {
+        Some("json") => return true,
+        Some(_) => return false,
+        None => {},
+    }
+
+    match request.header("Accept") {
+        Some(accept) => accept.split(',').any(|item| item.split(';').next().map(|typ| typ.trim()) == Some("application/json")),
+        None => false,
+    }
+}
+
+
+/// Builds the listing of `dir` for the request, as HTML or (when `json` is enabled and asked for) JSON.
+pub fn render(request: &Request, mut list: Vec<Entry>, is_root: bool, json: bool) -> Response {
+
+    sort(request, &mut list);
+
+    if json && wants_json(request) {
+        render_json(request, &list)
+    } else {
+        render_html(request, &list, is_root)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Entry, Sort, compare, escape_html, href};
+
+    fn entry(name: &str, is_dir: bool, size: u64) -> Entry {
+        Entry {
+            name     : name.to_owned(),
+            is_dir   : is_dir,
+            size     : size,
+            modified : None,
+            typ      : None,
+        }
+    }
+
+    fn sorted(sort: Sort, desc: bool) -> Vec<String> {
+
+        let mut list = vec![entry("b.txt", false, 1), entry("zeta", true, 0), entry("a.txt", false, 30), entry("alpha", true, 0), entry("c.txt", false, 2)];
+
+        list.sort_by(|a, b| compare(a, b, sort, desc));
+
+        list.iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_html("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(escape_html("zwykły tekst"), "zwykły tekst");
+        assert_eq!(escape_html("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn href_encoded() {
+        assert_eq!(href(&entry("plain-name_1.txt", false, 0)), "plain-name_1.txt");
+        assert_eq!(href(&entry("my file.txt", false, 0)), "my%20file.txt");
+        assert_eq!(href(&entry("a#b?c.txt", false, 0)), "a%23b%3Fc.txt");
+        assert_eq!(href(&entry("zażółć.txt", false, 0)), "za%C5%BC%C3%B3%C5%82%C4%87.txt");
+        assert_eq!(href(&entry("50%.txt", false, 0)), "50%25.txt");
+    }
+
+    #[test]
+    fn href_trailing_slash() {
+        assert_eq!(href(&entry("docs", true, 0)), "docs/");
+        assert_eq!(href(&entry("my docs", true, 0)), "my%20docs/");
+        assert_eq!(href(&entry("docs", false, 0)), "docs");
+    }
+
+    #[test]
+    fn dirs_first() {
+        assert_eq!(sorted(Sort::Name, false), vec!["alpha", "zeta", "a.txt", "b.txt", "c.txt"]);
+        assert_eq!(sorted(Sort::Size, false), vec!["alpha", "zeta", "b.txt", "c.txt", "a.txt"]);
+
+                                        //odwrócona kolejność nie przenosi katalogów na koniec
+        assert_eq!(sorted(Sort::Name, true), vec!["zeta", "alpha", "c.txt", "b.txt", "a.txt"]);
+        assert_eq!(sorted(Sort::Size, true), vec!["zeta", "alpha", "a.txt", "c.txt", "b.txt"]);
+    }
+}
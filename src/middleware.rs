@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use request::Request;
+use response::Response;
+use server::FnReceiver;
+
+
+/// Code run around the receiver. `before` gets the request first and may answer it on its own by returning a response,
+/// `after` gets every response sent for the request (including the automatic 500) just before it is handed to the event loop.
+pub trait Middleware: Send + Sync {
+    
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+    
+    fn after(&self, _request: &Request, _response: &mut Response) {
+    }
+}
+
+
+/// Middleware applied in the order they were added: `before` hooks from first to last, `after` hooks from last to first.
+pub struct Chain {
+    list : Vec<Arc<Middleware>>,
+}
+
+
+impl Chain {
+    
+    pub fn new() -> Chain {
+        
+        Chain {
+            list : Vec::new(),
+        }
+    }
+    
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain {
+        self.list.push(Arc::new(middleware));
+        self
+    }
+    
+    pub fn wrap(self, fn_receiver: FnReceiver) -> FnReceiver {
+        
+        let list = self.list;
+        
+        Box::new(move|mut request: Request| {
+            
+            for (index, middleware) in list.iter().enumerate() {
+                
+                if let Some(response) = middleware.before(&mut request) {
+                    
+                                        //after tylko dla tych, których before już się wykonało
+                    request.add_after(&list[0..index]);
+                    request.send(response);
+                    return;
+                }
+            }
+            
+            request.add_after(&list);
+            
+            fn_receiver(request);
+        })
+    }
+}
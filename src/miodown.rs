@@ -1,6 +1,16 @@
 use mio::Sender;
 use server::MioMessage;
+use client::Client;
+#[cfg(feature = "tls")]
+use std::io;
+#[cfg(feature = "tls")]
+use std::thread;
+#[cfg(feature = "tls")]
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use tls::TlsConfig;
 
+#[derive(Clone)]
 pub struct MioDown {
     chan : Sender<MioMessage>,
 }
@@ -14,6 +24,78 @@ impl MioDown {
         }
     }
     
+    /// Swaps the certificates used for new TLS connections, open connections are not affected.
+    /// When the new config can't be built the error is returned and the server keeps the old one.
+    #[cfg(feature = "tls")]
+    pub fn reload_tls(&self, config: TlsConfig) -> io::Result<()> {
+        
+        let acceptor = try!(config.build());
+        
+        match self.chan.send(MioMessage::ReloadTls(acceptor)) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "event loop is not running")),
+        }
+    }
+    
+    /// Checks the certificate and key files of `config` every `interval` and reloads them after a change.
+    /// A broken file is reported through the server's `FnLog` and skipped - the previous certificates stay in use until the next change.
+    #[cfg(feature = "tls")]
+    pub fn watch_tls(&self, config: TlsConfig, interval: Duration) -> thread::JoinHandle<()> {
+        
+        let mio_down = self.clone();
+        
+        thread::spawn(move || {
+            
+            let mut config = config;
+            let mut last   = config.modified();
+            
+            loop {
+                
+                thread::sleep(interval);
+                
+                let modified = config.modified();
+                
+                if modified == last {
+                    continue;
+                }
+                
+                last = modified;
+                
+                let new_config = match config.reload() {
+                    Ok(new_config) => new_config,
+                    Err(err) => {
+                        mio_down.log_error(format!("tls reload failed, keeping previous certificates: {}", err));
+                        continue;
+                    }
+                };
+                
+                if let Err(err) = mio_down.reload_tls(new_config.clone()) {
+                    
+                                        //Other - event loop już nie działa, koniec obserwowania
+                    if err.kind() == io::ErrorKind::Other {
+                        return;
+                    }
+                    
+                    mio_down.log_error(format!("tls reload failed, keeping previous certificates: {}", err));
+                    continue;
+                }
+                
+                config = new_config;
+            }
+        })
+    }
+    
+                                        //błąd z wątku obserwującego trafia do fn_log serwera
+    #[cfg(feature = "tls")]
+    fn log_error(&self, mess: String) {
+        let _ = self.chan.send(MioMessage::Log(true, mess));
+    }
+    
+    /// HTTP client using the server's event loop, for code running outside of request handlers.
+    pub fn client(&self) -> Client {
+        Client::new(self.chan.clone())
+    }
+    
     pub fn shoutdown(self) {
         
         self.chan.send(MioMessage::Down).unwrap();
@@ -0,0 +1,867 @@
+use std::io;
+use std::io::{Write, ErrorKind};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::env;
+use std::mem;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use httparse;
+use body::BodyReader;
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use urlencoded;
+
+use std::boxed::FnBox;
+
+//https://tools.ietf.org/html/rfc7578
+//https://tools.ietf.org/html/rfc2046#section-5.1.1
+
+
+static TEMP_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+const MAX_HEADERS_SIZE : usize = 8192;
+
+
+pub struct MultipartLimits {
+    pub max_field_size : usize,                 //pole tekstowe trzymane w pamięci
+    pub max_file_size  : usize,                 //pojedynczy plik zapisywany na dysk
+    pub max_total_size : usize,                 //całe ciało requestu
+    pub max_parts      : usize,
+    pub temp_dir       : PathBuf,
+}
+
+impl MultipartLimits {
+
+    pub fn new() -> MultipartLimits {
+
+        MultipartLimits {
+            max_field_size : 64 * 1024,
+            max_file_size  : 10 * 1024 * 1024,
+            max_total_size : 20 * 1024 * 1024,
+            max_parts      : 100,
+            temp_dir       : env::temp_dir(),
+        }
+    }
+}
+
+
+pub struct Field {
+    pub name    : String,
+    pub value   : String,
+    pub headers : Vec<(String, String)>,
+}
+
+
+/// A file part written to a temporary file. The file is removed when the part is dropped, unless it was moved away with `persist`.
+pub struct FilePart {
+    pub name         : String,
+    pub filename     : String,                  //nazwa podana przez klienta - nie należy jej ufać
+    pub content_type : Option<String>,
+    pub headers      : Vec<(String, String)>,
+    path             : PathBuf,
+    size             : usize,
+    keep             : bool,
+}
+
+impl FilePart {
+
+    pub fn path(&self) -> &Path {
+        &(self.path)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn persist(mut self, dest: &Path) -> io::Result<()> {
+
+        try!(fs::rename(&self.path, dest));
+        self.keep = true;
+        Ok(())
+    }
+}
+
+impl Drop for FilePart {
+
+    fn drop(&mut self) {
+
+        if self.keep == false {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+
+pub struct Multipart {
+    fields : Vec<Field>,
+    files  : Vec<FilePart>,
+}
+
+impl Multipart {
+
+    pub fn fields(&self) -> &Vec<Field> {
+        &(self.fields)
+    }
+
+    pub fn files(&self) -> &Vec<FilePart> {
+        &(self.files)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&String> {
+
+        for field in self.fields.iter() {
+            if field.name == name {
+                return Some(&(field.value));
+            }
+        }
+
+        None
+    }
+
+    pub fn file(&self, name: &str) -> Option<&FilePart> {
+
+        for file in self.files.iter() {
+            if file.name == name {
+                return Some(file);
+            }
+        }
+
+        None
+    }
+
+    pub fn into_files(self) -> Vec<FilePart> {
+        self.files
+    }
+}
+
+
+pub enum MultipartError {
+    TooLarge,
+    Malformed(String),
+    Io(io::Error),
+}
+
+
+                                        //boundary z nagłówka Content-Type: multipart/form-data; boundary=...
+pub fn boundary(content_type: &str) -> Option<String> {
+
+    let mut parts = content_type.split(';');
+
+    match parts.next() {
+        Some(media_type) if media_type.trim().to_lowercase() == "multipart/form-data" => {},
+        _ => return None,
+    }
+
+    for param in parts {
+
+        let param = param.trim();
+
+        if let Some(pos) = param.find('=') {
+
+            if param[0..pos].trim().to_lowercase() == "boundary" {
+
+                let value = param[pos + 1..].trim().trim_matches('"');
+
+                if value.len() > 0 && value.len() <= 70 {
+                    return Some(value.to_owned());
+                }
+
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+
+    if haystack.len() < needle.len() {
+        return None;
+    }
+
+    for index in 0..(haystack.len() - needle.len() + 1) {
+        if &haystack[index..index + needle.len()] == needle {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+
+                                        //parametry nagłówka Content-Disposition, wartości mogą być w cudzysłowach
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+
+    let mut out   = Vec::new();
+    let mut chars = value.chars().peekable();
+
+                                        //pomijamy typ (form-data)
+    while let Some(c) = chars.next() {
+        if c == ';' {
+            break;
+        }
+    }
+
+    loop {
+
+        let mut name = String::new();
+
+        while let Some(c) = chars.next() {
+            if c == '=' {
+                break;
+            }
+            name.push(c);
+        }
+
+        let name = name.trim().to_lowercase();
+
+        if name.len() == 0 {
+            return out;
+        }
+
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+
+        let mut value = String::new();
+
+        if chars.peek() == Some(&'"') {
+
+            chars.next();
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            value.push(escaped);
+                        }
+                    },
+                    _ => value.push(c),
+                }
+            }
+
+            while let Some(c) = chars.next() {
+                if c == ';' {
+                    break;
+                }
+            }
+
+        } else {
+
+            while let Some(c) = chars.next() {
+                if c == ';' {
+                    break;
+                }
+                value.push(c);
+            }
+        }
+
+        out.push((name, value.trim().to_owned()));
+    }
+}
+
+
+fn create_temp_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+
+    loop {
+
+        let number = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let path   = dir.join(format!("miohttp-upload-{}-{}", process::id(), number));
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+
+enum PartSink {
+    Memory(String, Vec<(String, String)>, Vec<u8>),
+    File(FilePart, File),
+}
+
+enum State {
+    Preamble,
+    AfterBoundary,
+    Headers,
+    Body(PartSink),
+    End,
+}
+
+
+struct Parser {
+    limits    : MultipartLimits,
+    delimiter : Vec<u8>,
+    buffer    : Vec<u8>,
+    state     : State,
+    total     : usize,
+    parts     : usize,
+    result    : Multipart,
+}
+
+
+impl Parser {
+
+    fn new(boundary: String, limits: MultipartLimits) -> Parser {
+
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        Parser {
+            limits    : limits,
+            delimiter : delimiter,
+                                        //pierwszy separator nie ma poprzedzającego CRLF
+            buffer    : b"\r\n".to_vec(),
+            state     : State::Preamble,
+            total     : 0,
+            parts     : 0,
+            result    : Multipart {
+                fields : Vec::new(),
+                files  : Vec::new(),
+            },
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), MultipartError> {
+
+        self.total = self.total + chunk.len();
+
+        if self.total > self.limits.max_total_size {
+            return Err(MultipartError::TooLarge);
+        }
+
+        self.buffer.extend_from_slice(chunk);
+
+        loop {
+
+            match mem::replace(&mut self.state, State::End) {
+
+                State::Preamble => {
+
+                    match find(&self.buffer, &self.delimiter) {
+
+                        Some(pos) => {
+                            self.consume(pos + self.delimiter.len());
+                            self.state = State::AfterBoundary;
+                        },
+
+                        None => {
+                            let keep = self.delimiter.len() - 1;
+                            if self.buffer.len() > keep {
+                                let len = self.buffer.len();
+                                self.consume(len - keep);
+                            }
+                            self.state = State::Preamble;
+                            return Ok(());
+                        }
+                    }
+                },
+
+                State::AfterBoundary => {
+
+                    if self.buffer.len() < 2 {
+                        self.state = State::AfterBoundary;
+                        return Ok(());
+                    }
+
+                    if &self.buffer[0..2] == b"--" {
+                                        //ostatni separator, reszta to epilog
+                        self.buffer.clear();
+                        self.state = State::End;
+                        return Ok(());
+                    }
+
+                    match find(&self.buffer, b"\r\n") {
+
+                        Some(pos) => {
+
+                            for byte in self.buffer[0..pos].iter() {
+                                if *byte != b' ' && *byte != b'\t' {
+                                    return Err(MultipartError::Malformed("invalid boundary line".to_owned()));
+                                }
+                            }
+
+                            self.consume(pos + 2);
+                            self.state = State::Headers;
+                        },
+
+                        None => {
+                            if self.buffer.len() > MAX_HEADERS_SIZE {
+                                return Err(MultipartError::Malformed("invalid boundary line".to_owned()));
+                            }
+                            self.state = State::AfterBoundary;
+                            return Ok(());
+                        }
+                    }
+                },
+
+                State::Headers => {
+
+                    match find(&self.buffer, b"\r\n\r\n") {
+
+                        Some(pos) => {
+
+                            let sink = try!(self.start_part(pos + 4));
+                            self.consume(pos + 4);
+                            self.state = State::Body(sink);
+                        },
+
+                        None => {
+                            if self.buffer.len() > MAX_HEADERS_SIZE {
+                                return Err(MultipartError::Malformed("part headers too large".to_owned()));
+                            }
+                            self.state = State::Headers;
+                            return Ok(());
+                        }
+                    }
+                },
+
+                State::Body(mut sink) => {
+
+                    match find(&self.buffer, &self.delimiter) {
+
+                        Some(pos) => {
+
+                            try!(self.write_part(&mut sink, pos));
+                            try!(self.finish_part(sink));
+                            self.consume(pos + self.delimiter.len());
+                            self.state = State::AfterBoundary;
+                        },
+
+                        None => {
+                                        //koniec bufora może być początkiem separatora
+                            let keep = self.delimiter.len() - 1;
+
+                            if self.buffer.len() > keep {
+                                let len = self.buffer.len() - keep;
+                                try!(self.write_part(&mut sink, len));
+                                self.consume(len);
+                            }
+
+                            self.state = State::Body(sink);
+                            return Ok(());
+                        }
+                    }
+                },
+
+                State::End => {
+                    self.buffer.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(0..len);
+    }
+
+    fn start_part(&mut self, headers_len: usize) -> Result<PartSink, MultipartError> {
+
+        self.parts = self.parts + 1;
+
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::TooLarge);
+        }
+
+        let mut headers_buf = [httparse::EMPTY_HEADER; 16];
+
+        let parsed = match httparse::parse_headers(&self.buffer[0..headers_len], &mut headers_buf) {
+            Ok(httparse::Status::Complete((_, parsed))) => parsed,
+            _ => return Err(MultipartError::Malformed("invalid part headers".to_owned())),
+        };
+
+        let mut headers = Vec::new();
+
+        for header in parsed.iter() {
+            match String::from_utf8(header.value.to_vec()) {
+                Ok(value) => headers.push((header.name.to_owned(), value.trim().to_owned())),
+                Err(_) => return Err(MultipartError::Malformed(format!("header {}, error utf8 sequence", header.name))),
+            }
+        }
+
+        let mut name         = None;
+        let mut filename     = None;
+        let mut content_type = None;
+
+        for &(ref key, ref value) in headers.iter() {
+
+            match &(key.to_lowercase())[..] {
+
+                "content-disposition" => {
+
+                    for (param, param_value) in disposition_params(value) {
+
+                        match &param[..] {
+                            "name" => name = Some(param_value),
+                            "filename" => {
+                                if filename.is_none() {
+                                    filename = Some(param_value);
+                                }
+                            },
+                                        //RFC 5987: filename*=UTF-8''nazwa%20pliku
+                            "filename*" => {
+                                if let Some(pos) = param_value.find("''") {
+                                    if let Ok(decoded) = urlencoded::decode(&param_value[pos + 2..]) {
+                                        filename = Some(decoded);
+                                    }
+                                }
+                            },
+                            _ => {}
+                        }
+                    }
+                },
+
+                "content-type" => {
+                    content_type = Some(value.clone());
+                },
+
+                _ => {}
+            }
+        }
+
+        let name = match name {
+            Some(name) => name,
+            None => return Err(MultipartError::Malformed("part without name".to_owned())),
+        };
+
+        match filename {
+
+            Some(filename) => {
+
+                let (path, file) = match create_temp_file(&self.limits.temp_dir) {
+                    Ok(created) => created,
+                    Err(err) => return Err(MultipartError::Io(err)),
+                };
+
+                let part = FilePart {
+                    name         : name,
+                    filename     : filename,
+                    content_type : content_type,
+                    headers      : headers,
+                    path         : path,
+                    size         : 0,
+                    keep         : false,
+                };
+
+                Ok(PartSink::File(part, file))
+            },
+
+            None => {
+                Ok(PartSink::Memory(name, headers, Vec::new()))
+            }
+        }
+    }
+
+    fn write_part(&self, sink: &mut PartSink, len: usize) -> Result<(), MultipartError> {
+
+        let data = &self.buffer[0..len];
+
+        match *sink {
+
+            PartSink::Memory(_, _, ref mut value) => {
+
+                if value.len() + data.len() > self.limits.max_field_size {
+                    return Err(MultipartError::TooLarge);
+                }
+
+                value.extend_from_slice(data);
+            },
+
+            PartSink::File(ref mut part, ref mut file) => {
+
+                if part.size + data.len() > self.limits.max_file_size {
+                    return Err(MultipartError::TooLarge);
+                }
+
+                if let Err(err) = file.write_all(data) {
+                    return Err(MultipartError::Io(err));
+                }
+
+                part.size = part.size + data.len();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish_part(&mut self, sink: PartSink) -> Result<(), MultipartError> {
+
+        match sink {
+
+            PartSink::Memory(name, headers, value) => {
+
+                match String::from_utf8(value) {
+
+                    Ok(value) => {
+                        self.result.fields.push(Field {
+                            name    : name,
+                            value   : value,
+                            headers : headers,
+                        });
+                        Ok(())
+                    },
+
+                    Err(_) => Err(MultipartError::Malformed(format!("field {}, error utf8 sequence", name))),
+                }
+            },
+
+            PartSink::File(part, mut file) => {
+
+                if let Err(err) = file.flush() {
+                    return Err(MultipartError::Io(err));
+                }
+
+                self.result.files.push(part);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self, complete: bool) -> Result<Multipart, MultipartError> {
+
+        match (complete, self.state) {
+            (true, State::End) => Ok(self.result),
+            (_, _) => Err(MultipartError::Malformed("unexpected end of body".to_owned())),
+        }
+    }
+}
+
+
+/// `BodyReader` parsing `multipart/form-data`. Text fields are kept in memory, file parts are streamed to temporary files.
+/// Parsing, file writes and the callback run on a separate thread, the event loop only passes the data on.
+pub struct MultipartReader {
+    jobs   : Sender<Job>,
+    failed : Arc<AtomicBool>,                   //parser zgłosił błąd - dalsze dane są zbędne
+}
+
+
+enum Job {
+    Data(Vec<u8>),
+    Finish(Request, bool),
+}
+
+
+impl MultipartReader {
+
+    pub fn new(boundary: String, limits: MultipartLimits, callback: Box<FnBox(Request, Multipart) + Send + Sync + 'static>) -> MultipartReader {
+
+        let (jobs, receiver) = channel();
+        let failed           = Arc::new(AtomicBool::new(false));
+        let parser           = Parser::new(boundary, limits);
+        let failed_worker    = failed.clone();
+
+                                        //kolejka jest ograniczona przez Content-Length, sprawdzony względem max_total_size
+        thread::spawn(move || {
+            work(parser, receiver, failed_worker, callback);
+        });
+
+        MultipartReader {
+            jobs   : jobs,
+            failed : failed,
+        }
+    }
+}
+
+
+impl BodyReader for MultipartReader {
+
+    fn data(&mut self, chunk: &[u8]) -> bool {
+
+        if self.failed.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        self.jobs.send(Job::Data(chunk.to_vec())).is_ok()
+    }
+
+    fn finish(self: Box<Self>, request: Request, complete: bool) {
+
+        let _ = self.jobs.send(Job::Finish(request, complete));
+    }
+}
+
+
+                                        //reader porzucony bez finish - parser usuwa pliki tymczasowe przy drop
+fn work(mut parser: Parser, jobs: Receiver<Job>, failed: Arc<AtomicBool>, callback: Box<FnBox(Request, Multipart) + Send + Sync + 'static>) {
+
+    let mut error = None;
+
+    for job in jobs.iter() {
+
+        match job {
+
+            Job::Data(chunk) => {
+
+                if error.is_none() {
+                    if let Err(err) = parser.feed(&chunk) {
+                        failed.store(true, Ordering::SeqCst);
+                        error = Some(err);
+                    }
+                }
+            },
+
+            Job::Finish(request, complete) => {
+
+                let result = match error {
+                    Some(err) => Err(err),
+                    None => parser.finish(complete),
+                };
+
+                respond(request, result, callback);
+                return;
+            }
+        }
+    }
+}
+
+
+fn respond(request: Request, result: Result<Multipart, MultipartError>, callback: Box<FnBox(Request, Multipart) + Send + Sync + 'static>) {
+
+    match result {
+
+        Ok(multipart) => {
+            (callback as Box<FnBox(Request, Multipart)>)(request, multipart);
+        },
+
+        Err(MultipartError::TooLarge) => {
+            request.send(Response::create(Code::Code413, Type::TextHtml, "413 Payload Too Large".to_owned()));
+        },
+
+        Err(MultipartError::Malformed(_)) => {
+            request.send(Response::create_400());
+        },
+
+        Err(MultipartError::Io(_)) => {
+            request.send(Response::create_500());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use super::{boundary, disposition_params, Parser, MultipartLimits, MultipartError};
+
+    const BODY : &'static [u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\nworld\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        --XyZ is not a boundary here\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    fn limits() -> MultipartLimits {
+        let mut limits = MultipartLimits::new();
+        limits.temp_dir = env::temp_dir();
+        limits
+    }
+
+    fn check(parser: Parser) {
+
+        let multipart = match parser.finish(true) {
+            Ok(multipart) => multipart,
+            Err(_) => panic!("parse error"),
+        };
+
+        assert_eq!(multipart.fields().len(), 1);
+        assert_eq!(multipart.field("title"), Some(&"hello\r\nworld".to_owned()));
+
+        let file = multipart.file("upload").unwrap();
+        assert_eq!(file.filename, "a.txt");
+        assert_eq!(file.content_type, Some("text/plain".to_owned()));
+        assert_eq!(file.size(), 28);
+
+        let mut data = Vec::new();
+        File::open(file.path()).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"--XyZ is not a boundary here".to_vec());
+
+        let path = file.path().to_path_buf();
+        drop(multipart);
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn parse_whole_body() {
+        let mut parser = Parser::new("XyZ".to_owned(), limits());
+        assert!(parser.feed(BODY).is_ok());
+        check(parser);
+    }
+
+    #[test]
+    fn parse_byte_by_byte() {
+        let mut parser = Parser::new("XyZ".to_owned(), limits());
+        for byte in BODY.iter() {
+            assert!(parser.feed(&[*byte]).is_ok());
+        }
+        check(parser);
+    }
+
+    #[test]
+    fn missing_final_boundary() {
+        let mut parser = Parser::new("XyZ".to_owned(), limits());
+        assert!(parser.feed(b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue").is_ok());
+        match parser.finish(true) {
+            Err(MultipartError::Malformed(_)) => {},
+            _ => panic!("expected Malformed"),
+        }
+    }
+
+    #[test]
+    fn field_too_large() {
+        let mut limits = limits();
+        limits.max_field_size = 4;
+        let mut parser = Parser::new("XyZ".to_owned(), limits);
+        match parser.feed(b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789\r\n--XyZ--") {
+            Err(MultipartError::TooLarge) => {},
+            _ => panic!("expected TooLarge"),
+        }
+    }
+
+    #[test]
+    fn part_without_name() {
+        let mut parser = Parser::new("XyZ".to_owned(), limits());
+        match parser.feed(b"--XyZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--") {
+            Err(MultipartError::Malformed(_)) => {},
+            _ => panic!("expected Malformed"),
+        }
+    }
+
+    #[test]
+    fn boundary_param() {
+        assert_eq!(boundary("multipart/form-data; boundary=abc"), Some("abc".to_owned()));
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\""), Some("a b".to_owned()));
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn disposition() {
+        let params = disposition_params("form-data; name=\"a\\\"b\"; filename=x.txt; filename*=UTF-8''%C5%BC.txt");
+        assert_eq!(params, vec![
+            ("name".to_owned(), "a\"b".to_owned()),
+            ("filename".to_owned(), "x.txt".to_owned()),
+            ("filename*".to_owned(), "UTF-8''%C5%BC.txt".to_owned()),
+        ]);
+    }
+}
@@ -0,0 +1,358 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, ErrorKind};
+use std::fs::File;
+use std::collections::VecDeque;
+use std::cmp::min;
+use std::sync::Arc;
+use mio::TryWrite;
+use mio::tcp::TcpStream;
+use stream::Stream;
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use libc;
+
+
+const FILE_BLOCK : u64 = 64 * 1024;
+
+
+/// `Read` over a `Source`, for places that need the produced data as a plain reader.
+pub struct SourceReader {
+    source : Box<Source>,
+    block  : Vec<u8>,
+    done   : usize,
+}
+
+impl SourceReader {
+
+    pub fn new(source: Box<Source>) -> SourceReader {
+
+        SourceReader {
+            source : source,
+            block  : Vec::new(),
+            done   : 0,
+        }
+    }
+}
+
+impl Read for SourceReader {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        while self.done >= self.block.len() {
+
+            match try!(self.source.next_block()) {
+                Some(block) => {
+                    self.block = block;
+                    self.done  = 0;
+                },
+                None => return Ok(0),
+            }
+        }
+
+        let size = min(buf.len(), self.block.len() - self.done);
+
+        buf[0..size].copy_from_slice(&self.block[self.done..self.done + size]);
+        self.done = self.done + size;
+
+        Ok(size)
+    }
+}
+
+
+pub enum Chunk {
+    Bytes(Vec<u8>, usize),              //dane, ilość wysłanych bajtów
+    Shared(Arc<Vec<u8>>, usize, usize), //dane współdzielone (np. z pamięci podręcznej plików), bez kopiowania; pozycja, koniec
+    File(File, u64, u64),               //plik, bieżąca pozycja, koniec zakresu
+    Source(Box<Source>),                //dane wytwarzane w trakcie wysyłania
+}
+
+
+/// Produces the data of a chunk while it is being sent, e.g. a compressed stream. `Ok(None)` ends the chunk.
+pub trait Source: Send {
+    fn next_block(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+
+/// Data waiting to be written to the socket. File chunks go out with `sendfile(2)` where possible,
+/// otherwise they are read block by block, so a large file never sits in memory as a whole.
+pub struct Output {
+    chunks   : VecDeque<Chunk>,
+    sendfile : bool,
+    written  : u64,
+    checked  : u64,                     //wartość written przy ostatnim has_progress
+}
+
+
+enum Step {
+    Continue,
+    Next,
+    Blocked,
+    Fill(Vec<u8>),
+}
+
+
+impl Output {
+
+    pub fn new() -> Output {
+
+        Output {
+            chunks   : VecDeque::new(),
+            sendfile : cfg!(target_os = "linux"),
+            written  : 0,
+            checked  : 0,
+        }
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Output {
+
+        let mut output = Output::new();
+        output.push_bytes(data);
+        output
+    }
+
+    pub fn push_bytes(&mut self, data: Vec<u8>) {
+
+        if data.len() > 0 {
+            self.chunks.push_back(Chunk::Bytes(data, 0));
+        }
+    }
+
+    pub fn push_shared(&mut self, data: Arc<Vec<u8>>, offset: usize, len: usize) {
+
+        if len > 0 {
+            self.chunks.push_back(Chunk::Shared(data, offset, offset + len));
+        }
+    }
+
+    pub fn push_file(&mut self, file: File, offset: u64, len: u64) {
+
+        if len > 0 {
+            self.chunks.push_back(Chunk::File(file, offset, offset + len));
+        }
+    }
+
+    pub fn push_source(&mut self, source: Box<Source>) {
+        self.chunks.push_back(Chunk::Source(source));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.len() == 0
+    }
+
+                                        //dane z pamięci jeszcze nie wysłane (bez plików i źródeł)
+    pub fn pending_bytes(&self) -> usize {
+        self.chunks.iter().map(|chunk| match *chunk {
+            Chunk::Bytes(ref data, done) => data.len() - done,
+            Chunk::Shared(_, pos, end) => end - pos,
+            _ => 0,
+        }).sum()
+    }
+
+                                        //czy od poprzedniego wywołania coś zostało wysłane
+    pub fn has_progress(&mut self) -> bool {
+
+        let progress = self.written != self.checked;
+        self.checked = self.written;
+        progress
+    }
+
+                                        //wyłącza sendfile, np. gdy dane muszą przejść przez warstwę szyfrującą
+    pub fn disable_sendfile(&mut self) {
+        self.sendfile = false;
+    }
+
+                                        //Ok(true) - wszystko wysłane, Ok(false) - socket przestał przyjmować dane
+    pub fn write_to(&mut self, stream: &mut Stream) -> io::Result<bool> {
+
+        loop {
+
+            let step = match self.chunks.front_mut() {
+
+                                        //przy TLS część danych może jeszcze czekać w sesji
+                None => return stream.flush_tls(),
+
+                Some(&mut Chunk::Bytes(ref data, ref mut done)) => {
+                    try!(write_bytes(stream, data, done, &mut self.written))
+                },
+
+                Some(&mut Chunk::Shared(ref data, ref mut pos, end)) => {
+                    try!(write_bytes(stream, &data[0..end], pos, &mut self.written))
+                },
+
+                Some(&mut Chunk::File(ref mut file, ref mut pos, end)) => {
+
+                    if *pos >= end {
+
+                        Step::Next
+
+                    } else if self.sendfile && stream.is_plain() {
+
+                        match send_file(stream.tcp(), file, *pos, end - *pos) {
+
+                            Ok(Some(0)) => {
+                                return Err(io::Error::new(ErrorKind::UnexpectedEof, "file shorter than declared length"));
+                            },
+
+                            Ok(Some(size)) => {
+                                *pos = *pos + size;
+                                self.written = self.written + size;
+                                Step::Continue
+                            },
+
+                            Ok(None) => Step::Blocked,
+
+                            Err(ref err) if err.kind() == ErrorKind::Interrupted => Step::Continue,
+
+                            Err(ref err) if sendfile_unsupported(err) => {
+                                                //sendfile nieobsługiwany dla tego pliku - dalej zwykłym odczytem
+                                self.sendfile = false;
+                                Step::Continue
+                            },
+
+                                                //EPIPE, ECONNRESET itp. - połączenie do zamknięcia
+                            Err(err) => return Err(err),
+                        }
+
+                    } else {
+
+                        let block = try!(read_block(file, *pos, min(end - *pos, FILE_BLOCK)));
+
+                        *pos = *pos + block.len() as u64;
+
+                        Step::Fill(block)
+                    }
+                },
+
+                Some(&mut Chunk::Source(ref mut source)) => {
+
+                    match try!(source.next_block()) {
+                        Some(block) => Step::Fill(block),
+                        None => Step::Next,
+                    }
+                },
+            };
+
+            match step {
+                Step::Continue => {},
+                Step::Next => {
+                    self.chunks.pop_front();
+                },
+                Step::Blocked => {
+                    return Ok(false);
+                },
+                Step::Fill(block) => {
+                    if block.len() > 0 {
+                        self.chunks.push_front(Chunk::Bytes(block, 0));
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+fn write_bytes(stream: &mut Stream, data: &[u8], done: &mut usize, written: &mut u64) -> io::Result<Step> {
+
+    if *done >= data.len() {
+        return Ok(Step::Next);
+    }
+
+    match try!(stream.try_write(&data[*done..])) {
+        Some(0) => Ok(Step::Blocked),
+        Some(size) => {
+            *done    = *done + size;
+            *written = *written + size as u64;
+            Ok(Step::Continue)
+        },
+        None => Ok(Step::Blocked),
+    }
+}
+
+
+fn read_block(file: &mut File, pos: u64, len: u64) -> io::Result<Vec<u8>> {
+
+    try!(file.seek(SeekFrom::Start(pos)));
+
+    let mut block = Vec::with_capacity(len as usize);
+
+    try!(file.take(len).read_to_end(&mut block));
+
+    if block.len() == 0 {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "file shorter than declared length"));
+    }
+
+    Ok(block)
+}
+
+
+#[cfg(target_os = "linux")]
+fn send_file(stream: &TcpStream, file: &File, pos: u64, len: u64) -> io::Result<Option<u64>> {
+
+    let mut offset = pos as libc::off_t;
+    let count      = min(len, 0x7ffff000) as libc::size_t;
+
+    let result = unsafe {
+        libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &mut offset, count)
+    };
+
+    if result < 0 {
+
+        let err = io::Error::last_os_error();
+
+        if err.kind() == ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+
+        return Err(err);
+    }
+
+    Ok(Some(result as u64))
+}
+
+
+                                        //tylko te błędy oznaczają brak obsługi sendfile (np. plik z /proc, system plików bez splice)
+#[cfg(target_os = "linux")]
+fn sendfile_unsupported(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => true,
+        _ => false,
+    }
+}
+
+
+#[cfg(not(target_os = "linux"))]
+fn sendfile_unsupported(_err: &io::Error) -> bool {
+    true
+}
+
+
+#[cfg(not(target_os = "linux"))]
+fn send_file(_stream: &TcpStream, _file: &File, _pos: u64, _len: u64) -> io::Result<Option<u64>> {
+    Err(io::Error::new(ErrorKind::Other, "sendfile not supported"))
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+
+    use std::io;
+    use libc;
+    use super::sendfile_unsupported;
+
+    fn unsupported(code: i32) -> bool {
+        sendfile_unsupported(&io::Error::from_raw_os_error(code))
+    }
+
+    #[test]
+    fn fallback_errors() {
+        assert!(unsupported(libc::EINVAL));
+        assert!(unsupported(libc::ENOSYS));
+
+        assert!(unsupported(libc::EPIPE) == false);
+        assert!(unsupported(libc::ECONNRESET) == false);
+        assert!(unsupported(libc::EBADF) == false);
+        assert!(unsupported(libc::EIO) == false);
+    }
+}
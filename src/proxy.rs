@@ -0,0 +1,787 @@
+use std::io;
+use std::io::{Read, ErrorKind};
+use std::cmp::min;
+use std::net::{SocketAddr, IpAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use mio::Token;
+use mio::tcp::TcpStream;
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use server::FnReceiver;
+use stream::Stream;
+use tunnel;
+use upstream::{self, BodyDecoder, Framing, ResponseHead};
+use compression;
+
+
+const BUFFER_LIMIT : usize = 64 * 1024;             //dane czekające na drugą stronę - powyżej przestajemy czytać
+const TIMEOUT      : u64   = 60 * 1000;
+const MAX_FAILS    : u32   = 3;
+const FAIL_TIMEOUT : u64   = 10 * 1000;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Balance {
+    RoundRobin,
+    LeastConnections,                               //najmniej trwających wymian, remisy po kolei
+}
+
+
+struct Health {
+    fails      : u32,
+    down_until : Option<Instant>,
+}
+
+
+struct UpstreamState {
+    addr   : SocketAddr,
+    active : AtomicUsize,                           //wymiany w toku (klienci obsługiwani przez ten serwer)
+    health : Mutex<Health>,
+}
+
+
+impl UpstreamState {
+
+    fn is_available(&self) -> bool {
+
+        match self.health.lock().unwrap().down_until {
+            Some(down_until) => Instant::now() >= down_until,
+            None => true,
+        }
+    }
+}
+
+
+/// Marks one exchange with an upstream as active and reports its outcome to the passive health check.
+pub struct ActiveGuard {
+    state        : Arc<UpstreamState>,
+    max_fails    : u32,
+    fail_timeout : u64,
+}
+
+
+impl ActiveGuard {
+
+    fn new(state: Arc<UpstreamState>, max_fails: u32, fail_timeout: u64) -> ActiveGuard {
+
+        state.active.fetch_add(1, Ordering::SeqCst);
+
+        ActiveGuard {
+            state        : state,
+            max_fails    : max_fails,
+            fail_timeout : fail_timeout,
+        }
+    }
+
+    pub fn success(&self) {
+
+        let mut health = self.state.health.lock().unwrap();
+
+        health.fails      = 0;
+        health.down_until = None;
+    }
+
+                                        //po max_fails porażkach z rzędu serwer jest pomijany przez fail_timeout
+    pub fn failure(&self) {
+
+        let mut health = self.state.health.lock().unwrap();
+
+        health.fails = health.fails + 1;
+
+        if health.fails >= self.max_fails {
+            health.fails      = 0;
+            health.down_until = Some(Instant::now() + Duration::from_millis(self.fail_timeout));
+        }
+    }
+}
+
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.state.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Everything the event loop needs to forward one request.
+pub struct ProxyTarget {
+    pub addr           : SocketAddr,
+    pub head           : Vec<u8>,               //nagłówki requestu dla serwera docelowego
+    pub method         : String,
+    pub body_len       : u64,
+    pub client_version : u8,
+    pub timeout        : u64,
+    pub guard          : ActiveGuard,
+}
+
+
+/// Reverse proxy: forwards requests to one of several HTTP/1.1 upstream servers over non-blocking sockets
+/// on the server's event loop and streams the responses back. Bodies are streamed both ways with backpressure,
+/// idle upstream connections are kept for reuse. Hop-by-hop headers are dropped and `X-Forwarded-For`,
+/// `X-Forwarded-Host`, `X-Forwarded-Proto` and `Forwarded` are added. Incoming `X-Forwarded-For` and `Forwarded`
+/// are kept and extended only when the peer is one of `trusted_proxies` - otherwise a client could forge its origin,
+/// so they are replaced. An upstream which fails `max_fails`
+/// times in a row is skipped for `fail_timeout`. The client gets 502 when the upstream can't be reached
+/// or answers garbage and 504 when it doesn't answer within `timeout`.
+pub struct ProxyHandler {
+    upstreams    : Vec<Arc<UpstreamState>>,
+    balance      : Balance,
+    next         : AtomicUsize,
+    timeout      : u64,
+    max_fails    : u32,
+    fail_timeout : u64,
+    trusted      : Vec<IpAddr>,
+}
+
+
+impl ProxyHandler {
+
+    pub fn new(upstreams: Vec<SocketAddr>) -> ProxyHandler {
+
+        let upstreams = upstreams.into_iter().map(|addr| {
+            Arc::new(UpstreamState {
+                addr   : addr,
+                active : AtomicUsize::new(0),
+                health : Mutex::new(Health {
+                    fails      : 0,
+                    down_until : None,
+                }),
+            })
+        }).collect();
+
+        ProxyHandler {
+            upstreams    : upstreams,
+            balance      : Balance::RoundRobin,
+            next         : AtomicUsize::new(0),
+            timeout      : TIMEOUT,
+            max_fails    : MAX_FAILS,
+            fail_timeout : FAIL_TIMEOUT,
+            trusted      : Vec::new(),
+        }
+    }
+
+    pub fn balance(mut self, balance: Balance) -> ProxyHandler {
+        self.balance = balance;
+        self
+    }
+
+                                        //w milisekundach, bez ruchu na połączeniu z serwerem docelowym (domyślnie 60 sekund)
+    pub fn timeout(mut self, timeout: u64) -> ProxyHandler {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_fails(mut self, max_fails: u32) -> ProxyHandler {
+        self.max_fails = if max_fails > 0 { max_fails } else { 1 };
+        self
+    }
+
+                                        //w milisekundach (domyślnie 10 sekund)
+    pub fn fail_timeout(mut self, fail_timeout: u64) -> ProxyHandler {
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+                                        //proxy przed serwerem, którym wierzymy w X-Forwarded-For i Forwarded (domyślnie żadne)
+    pub fn trusted_proxies(mut self, trusted: Vec<IpAddr>) -> ProxyHandler {
+        self.trusted = trusted;
+        self
+    }
+
+    pub fn build(self) -> FnReceiver {
+
+        Box::new(move|request: Request| {
+            self.handle(request);
+        })
+    }
+
+    pub fn handle(&self, request: Request) {
+
+                                        //ciała chunked serwer nie czyta, więc nie ma czego przekazać
+        if request.header("Transfer-Encoding").is_some() {
+            request.send(Response::create(Code::Code411, Type::TextHtml, "411 Length Required".to_owned()));
+            return;
+        }
+
+        let body_len = match request.header("Content-Length") {
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(body_len) => body_len,
+                Err(_) => {
+                    request.send(Response::create_400());
+                    return;
+                }
+            },
+            None => 0,
+        };
+
+        let state = match self.select() {
+            Some(state) => state,
+            None => {
+                request.send(Response::create(Code::Code502, Type::TextHtml, "502 Bad Gateway".to_owned()));
+                return;
+            }
+        };
+
+        let trusted = match request.remote_addr() {
+            Some(remote) => self.trusted.contains(&remote.ip()),
+            None => false,
+        };
+
+        let target = ProxyTarget {
+            addr           : state.addr,
+            head           : request_head(&request, &state.addr, trusted),
+            method         : request.method().clone(),
+            body_len       : body_len,
+            client_version : request.version(),
+            timeout        : self.timeout,
+            guard          : ActiveGuard::new(state, self.max_fails, self.fail_timeout),
+        };
+
+        request.proxy(target);
+    }
+
+                                        //gdy wszystkie serwery są oznaczone jako niedostępne, próbujemy mimo to
+    fn select(&self) -> Option<Arc<UpstreamState>> {
+
+        if self.upstreams.len() == 0 {
+            return None;
+        }
+
+        let available: Vec<&Arc<UpstreamState>> = self.upstreams.iter().filter(|state| state.is_available()).collect();
+
+        let candidates = if available.len() > 0 {
+            available
+        } else {
+            self.upstreams.iter().collect()
+        };
+
+        let offset = self.next.fetch_add(1, Ordering::SeqCst);
+
+        let index = match self.balance {
+
+            Balance::RoundRobin => offset % candidates.len(),
+
+            Balance::LeastConnections => {
+
+                let mut best = offset % candidates.len();
+
+                for step in 1..candidates.len() {
+
+                    let index = (offset + step) % candidates.len();
+
+                    if candidates[index].active.load(Ordering::SeqCst) < candidates[best].active.load(Ordering::SeqCst) {
+                        best = index;
+                    }
+                }
+
+                best
+            },
+        };
+
+        Some(candidates[index].clone())
+    }
+}
+
+
+                                        //nagłówki requestu przekazywanego dalej; trusted - klient jest zaufanym proxy
+fn request_head(request: &Request, addr: &SocketAddr, trusted: bool) -> Vec<u8> {
+
+    let tokens = match request.header("Connection") {
+        Some(value) => upstream::connection_tokens(&vec![("Connection".to_owned(), value.clone())]),
+        None => Vec::new(),
+    };
+
+    let mut out = format!("{} {} HTTP/1.1\r\n", request.method(), request.raw_target());
+
+    let mut forwarded_for = None;
+    let mut forwarded     = None;
+
+    for (name, value) in request.headers() {
+
+        let lower = name.to_lowercase();
+
+        if upstream::is_hop_by_hop(name, &tokens) || lower == "expect" || lower == "x-forwarded-host" || lower == "x-forwarded-proto" {
+            continue;
+        }
+
+                                        //łańcuch od zaufanego proxy - dopisujemy się na końcu listy, od innych klientów - odrzucany
+        if lower == "x-forwarded-for" {
+            if trusted {
+                forwarded_for = Some(join(forwarded_for, value.to_owned()));
+            }
+            continue;
+        }
+
+        if lower == "forwarded" {
+            if trusted {
+                forwarded = Some(join(forwarded, value.to_owned()));
+            }
+            continue;
+        }
+
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    let host = request.header("Host").cloned();
+
+                                        //HTTP/1.0 bez Host - serwer docelowy dostaje swój adres
+    if host.is_none() {
+        out.push_str(&format!("Host: {}\r\n", addr));
+    }
+
+    let proto = if request.is_secure() { "https" } else { "http" };
+
+    let client = request.remote_addr().map(|remote| remote.ip());
+
+                                        //bez adresu klienta (trusted jest wtedy false) X-Forwarded-For nie ma czego podać
+    if let Some(ref ip) = client {
+        out.push_str(&format!("X-Forwarded-For: {}\r\n", join(forwarded_for, format!("{}", ip))));
+    }
+
+    if let Some(ref host) = host {
+        out.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+    }
+
+    out.push_str(&format!("X-Forwarded-Proto: {}\r\n", proto));
+
+    let mut node = format!("for={}", forwarded_node(client.as_ref()));
+
+    if let Some(ref host) = host {
+        node.push_str(&format!(";host=\"{}\"", host.replace('"', "")));
+    }
+
+    node.push_str(&format!(";proto={}", proto));
+
+    out.push_str(&format!("Forwarded: {}\r\n", join(forwarded, node)));
+    out.push_str("Connection: keep-alive\r\n\r\n");
+
+    out.into_bytes()
+}
+
+
+fn join(prev: Option<String>, value: String) -> String {
+    match prev {
+        Some(prev) => format!("{}, {}", prev, value),
+        None => value,
+    }
+}
+
+
+                                        //RFC 7239 - adres IPv6 w nawiasach i cudzysłowie
+fn forwarded_node(ip: Option<&IpAddr>) -> String {
+    match ip {
+        Some(&IpAddr::V4(ref ip)) => format!("{}", ip),
+        Some(&IpAddr::V6(ref ip)) => format!("\"[{}]\"", ip),
+        None => "unknown".to_owned(),
+    }
+}
+
+
+                                        //powtórzenie po zerwanym połączeniu z puli nie zmieni stanu serwera
+fn is_idempotent(method: &str) -> bool {
+    match method {
+        "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" => true,
+        _ => false,
+    }
+}
+
+
+/// Upstream connection left open after a complete exchange, waiting in the server's pool.
+pub struct IdleSocket {
+    pub addr   : SocketAddr,
+    pub socket : TcpStream,
+    pub token  : Token,
+}
+
+
+pub enum Step {
+    Open,
+    Failed(Response, String),                           //odpowiedź jeszcze nie ruszyła - klient dostaje błąd, opis do logu
+    Done(bool),                                         //odpowiedź przekazana, bool to keep alive klienta
+    Closed,                                             //błąd w trakcie odpowiedzi - zostaje tylko zamknięcie połączenia
+}
+
+
+/// Connection side of a proxied request: the upstream socket and the bytes waiting in each direction.
+pub struct ProxyExchange {
+    upstream          : TcpStream,
+    addr              : SocketAddr,
+    token             : Token,                          //token gniazda wychodzącego w event_loop-ie
+    guard             : ActiveGuard,
+    method            : String,
+    client_version    : u8,
+    client_keep_alive : bool,
+    timeout           : u64,
+    connected         : bool,
+    replay            : Option<Vec<u8>>,                //request do powtórzenia, gdy połączenie z puli okaże się zamknięte
+    to_upstream       : Vec<u8>,
+    body_left         : u64,                            //bajty ciała requestu, które klient jeszcze wyśle
+    from_upstream     : Vec<u8>,
+    upstream_eof      : bool,
+    decoder           : Option<BodyDecoder>,            //Some - nagłówki odpowiedzi przekazane klientowi
+    upstream_reusable : bool,
+    chunked           : bool,                           //ciało do klienta w kawałkach
+    to_client         : Vec<u8>,
+    finished          : bool,                           //cała odpowiedź w to_client
+    activity          : bool,
+}
+
+
+impl ProxyExchange {
+
+                                        //socket - połączenie z puli, initial - początek ciała odebrany razem z nagłówkami
+    pub fn start(target: ProxyTarget, token: Token, socket: Option<TcpStream>, initial: Vec<u8>, keep_alive: bool) -> Result<ProxyExchange, String> {
+
+        let ProxyTarget { addr, head, method, body_len, client_version, timeout, guard } = target;
+
+        let (upstream, reused) = match socket {
+            Some(socket) => (socket, true),
+            None => match TcpStream::connect(&addr) {
+                Ok(socket) => (socket, false),
+                Err(err) => {
+                    guard.failure();
+                    return Err(format!("proxy connect to {}, {:?}", addr, err));
+                }
+            },
+        };
+
+        let body_left = body_len - min(body_len, initial.len() as u64);
+
+        let mut to_upstream = head;
+        to_upstream.extend_from_slice(&initial);
+
+        let replay = if reused && body_left == 0 && is_idempotent(&method) {
+            Some(to_upstream.clone())
+        } else {
+            None
+        };
+
+        Ok(ProxyExchange {
+            upstream          : upstream,
+            addr              : addr,
+            token             : token,
+            guard             : guard,
+            method            : method,
+            client_version    : client_version,
+            client_keep_alive : keep_alive,
+            timeout           : timeout,
+            connected         : reused,
+            replay            : replay,
+            to_upstream       : to_upstream,
+            body_left         : body_left,
+            from_upstream     : Vec::new(),
+            upstream_eof      : false,
+            decoder           : None,
+            upstream_reusable : false,
+            chunked           : false,
+            to_client         : Vec::new(),
+            finished          : false,
+            activity          : false,
+        })
+    }
+
+    pub fn upstream(&self) -> &TcpStream {
+        &(self.upstream)
+    }
+
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    pub fn client_wants_read(&self) -> bool {
+        self.connected && self.body_left > 0 && self.to_upstream.len() < BUFFER_LIMIT
+    }
+
+    pub fn client_wants_write(&self) -> bool {
+        self.to_client.len() > 0
+    }
+
+                                        //(czytanie, pisanie); przed nawiązaniem połączenia czekamy na gotowość do zapisu
+    pub fn upstream_interest(&self) -> (bool, bool) {
+
+        if self.connected == false {
+            return (false, true);
+        }
+
+        (self.finished == false && self.upstream_eof == false && self.to_client.len() < BUFFER_LIMIT, self.to_upstream.len() > 0)
+    }
+
+                                        //nagłówki odpowiedzi już poszły do klienta - na błąd zostaje zamknięcie połączenia
+    pub fn is_responding(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    pub fn has_activity(&mut self) -> bool {
+
+        let activity = self.activity;
+        self.activity = false;
+        activity
+    }
+
+                                        //serwer docelowy nie odpowiedział w czasie
+    pub fn timed_out(&self) {
+        self.guard.failure();
+    }
+
+                                        //połączenie z serwerem docelowym do ponownego użycia
+    pub fn finish(self) -> Option<IdleSocket> {
+
+        let reusable = self.upstream_reusable && self.finished && self.upstream_eof == false &&
+            self.from_upstream.len() == 0 && self.body_left == 0 && self.to_upstream.len() == 0;
+
+        if reusable {
+            Some(IdleSocket {
+                addr   : self.addr,
+                socket : self.upstream,
+                token  : self.token,
+            })
+        } else {
+            None
+        }
+    }
+
+                                        //zdarzenie na którymkolwiek z gniazd
+    pub fn pump(&mut self, client: &mut Stream) -> io::Result<Step> {
+
+        if self.connected == false {
+
+            if let Err(err) = self.upstream.take_socket_error() {
+                return Ok(self.fail(format!("connect error: {}", err)));
+            }
+
+            match self.upstream.peer_addr() {
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::NotConnected => return Ok(Step::Open),
+                Err(err) => return Ok(self.fail(format!("connect error: {}", err))),
+            }
+
+            self.connected = true;
+        }
+
+        if try!(self.read_body(client)) == false {
+            return Ok(Step::Closed);
+        }
+
+        if let Err(err) = tunnel::flush(&mut self.upstream, &mut self.to_upstream, &mut self.activity) {
+            return self.upstream_error(err);
+        }
+
+        if self.finished == false && self.to_client.len() < BUFFER_LIMIT {
+            if let Err(err) = tunnel::transfer(&mut self.upstream, &mut self.from_upstream, &mut self.upstream_eof, &mut self.activity) {
+                return self.upstream_error(err);
+            }
+        }
+
+        if let Some(step) = self.process() {
+            return Ok(step);
+        }
+
+        try!(tunnel::flush(client, &mut self.to_client, &mut self.activity));
+        try!(client.flush_tls());
+
+        if self.finished && self.to_client.len() == 0 {
+            return Ok(Step::Done(self.client_keep_alive && self.body_left == 0));
+        }
+
+        Ok(Step::Open)
+    }
+
+                                        //false - klient rozłączył się w trakcie wysyłania ciała
+    fn read_body(&mut self, client: &mut Stream) -> io::Result<bool> {
+
+        let mut buf = [0u8; 4096];
+
+        while self.body_left > 0 && self.to_upstream.len() < BUFFER_LIMIT {
+
+            let max = min(self.body_left, buf.len() as u64) as usize;
+
+            match client.read(&mut buf[0..max]) {
+                Ok(0) => return Ok(false),
+                Ok(size) => {
+                    self.to_upstream.extend_from_slice(&buf[0..size]);
+                    self.body_left = self.body_left - size as u64;
+                    self.activity  = true;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+
+                                        //nagłówki odpowiedzi i kolejne kawałki ciała
+    fn process(&mut self) -> Option<Step> {
+
+        while self.decoder.is_none() {
+
+            match upstream::parse_head(&self.from_upstream) {
+
+                Ok(Some((head, size))) => {
+
+                    self.from_upstream.drain(0..size);
+
+                    if head.status() == 101 {
+                        return Some(self.fail("unexpected 101 Switching Protocols".to_owned()));
+                    }
+
+                                        //100 Continue, 103 Early Hints - czekamy na właściwą odpowiedź
+                    if head.status() >= 100 && head.status() < 200 {
+                        continue;
+                    }
+
+                    match upstream::framing(&self.method, &head) {
+                        Ok(framing) => self.start_response(head, framing),
+                        Err(err) => return Some(self.fail(err)),
+                    }
+                },
+
+                Ok(None) => {
+
+                    if self.upstream_eof {
+                        return Some(self.retry("upstream closed connection".to_owned()));
+                    }
+
+                    return None;
+                },
+
+                Err(err) => return Some(self.fail(err)),
+            }
+        }
+
+        if self.finished {
+            return None;
+        }
+
+        let mut body = Vec::new();
+
+        let (result, done) = match self.decoder {
+            Some(ref mut decoder) => {
+                let result = decoder.decode(&mut self.from_upstream, &mut body);
+                let done   = decoder.is_done() || (self.upstream_eof && decoder.finish().is_ok());
+                (result, done)
+            },
+            None => return None,
+        };
+
+        if result.is_err() {
+            return Some(Step::Closed);
+        }
+
+        if body.len() > 0 {
+            if self.chunked {
+                self.to_client.extend_from_slice(&compression::chunk(body));
+            } else {
+                self.to_client.extend_from_slice(&body);
+            }
+        }
+
+        if done {
+
+            if self.chunked {
+                self.to_client.extend_from_slice(b"0\r\n\r\n");
+            }
+
+            self.finished = true;
+
+        } else if self.upstream_eof {
+
+                                        //ciało urwane - klient musi to zauważyć po zamknięciu połączenia
+            return Some(Step::Closed);
+        }
+
+        None
+    }
+
+    fn start_response(&mut self, head: ResponseHead, framing: Framing) {
+
+        self.guard.success();
+        self.replay = None;
+
+        self.upstream_reusable = head.keep_alive() && framing != Framing::UntilClose;
+
+        let tokens    = upstream::connection_tokens(head.headers());
+        let unbounded = framing == Framing::Chunked || framing == Framing::UntilClose;
+
+        let mut out = format!("HTTP/1.1 {} {}\r\n", head.status(), head.reason());
+
+        for &(ref name, ref value) in head.headers().iter() {
+
+            if upstream::is_hop_by_hop(name, &tokens) || (unbounded && name.to_lowercase() == "content-length") {
+                continue;
+            }
+
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+                                        //HTTP/1.0 nie zna chunked - koniec ciała wyznacza zamknięcie połączenia
+        if unbounded {
+            if self.client_version >= 1 {
+                out.push_str("Transfer-Encoding: chunked\r\n");
+                self.chunked = true;
+            } else {
+                self.client_keep_alive = false;
+            }
+        }
+
+        if self.client_keep_alive {
+            out.push_str("Connection: keep-alive\r\n\r\n");
+        } else {
+            out.push_str("Connection: close\r\n\r\n");
+        }
+
+        self.to_client.extend_from_slice(out.as_bytes());
+
+        self.decoder = Some(BodyDecoder::new(framing));
+    }
+
+    fn upstream_error(&mut self, err: io::Error) -> io::Result<Step> {
+
+        if self.decoder.is_none() {
+            return Ok(self.retry(format!("upstream error: {}", err)));
+        }
+
+        Err(err)
+    }
+
+                                        //połączenie z puli mogło zostać zamknięte przez serwer docelowy - jedna próba na nowym
+    fn retry(&mut self, message: String) -> Step {
+
+        let replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => return self.fail(message),
+        };
+
+        match TcpStream::connect(&self.addr) {
+
+                                        //stare gniazdo znika z event_loop-a przy zamknięciu, nowe dostaje ten sam token
+            Ok(socket) => {
+                self.upstream      = socket;
+                self.connected     = false;
+                self.to_upstream   = replay;
+                self.from_upstream = Vec::new();
+                self.upstream_eof  = false;
+                Step::Open
+            },
+
+            Err(err) => self.fail(format!("reconnect error: {}", err)),
+        }
+    }
+
+                                        //szczegóły błędu trafiają tylko do logu serwera
+    fn fail(&mut self, message: String) -> Step {
+
+        self.guard.failure();
+
+        Step::Failed(Response::create(Code::Code502, Type::TextHtml, "502 Bad Gateway".to_owned()), message)
+    }
+}
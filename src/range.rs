@@ -0,0 +1,494 @@
+use std::cmp::min;
+use request::Request;
+use response::{Response, Body};
+use code::Code;
+use typemod::{Type, BYTERANGES_BOUNDARY};
+use httpdate;
+
+//https://tools.ietf.org/html/rfc7233
+
+
+const MAX_RANGES : usize = 16;
+
+
+pub enum Ranges {
+    Satisfiable(Vec<(u64, u64)>),       //zakresy włącznie z końcem, posortowane i bez nakładania
+    Unsatisfiable,
+}
+
+
+/// Parses `Range: bytes=...` against a body of `len` bytes. `None` means the header should be ignored
+/// (other unit, bad syntax or too many ranges) and the full body sent.
+pub fn parse(header: &str, len: u64) -> Option<Ranges> {
+
+    let header = header.trim();
+
+    if header.starts_with("bytes=") == false {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+
+    for spec in header[6..].split(',') {
+
+        let spec = spec.trim();
+
+        if spec.len() == 0 {
+            continue;
+        }
+
+        let pos = match spec.find('-') {
+            Some(pos) => pos,
+            None => return None,
+        };
+
+        let (first, last) = (spec[0..pos].trim(), spec[pos + 1..].trim());
+
+        if first.len() == 0 {
+                                        //bytes=-500 - ostatnie 500 bajtów
+            let suffix: u64 = match last.parse() {
+                Ok(suffix) => suffix,
+                Err(_) => return None,
+            };
+
+            if suffix > 0 && len > 0 {
+                let start = if suffix >= len { 0 } else { len - suffix };
+                ranges.push((start, len - 1));
+            }
+
+            continue;
+        }
+
+        let start: u64 = match first.parse() {
+            Ok(start) => start,
+            Err(_) => return None,
+        };
+
+        let end: u64 = if last.len() == 0 {
+            if len > 0 { len - 1 } else { 0 }
+        } else {
+            match last.parse() {
+                Ok(end) => end,
+                Err(_) => return None,
+            }
+        };
+
+                                        //bytes=1000- przy krótszym ciele to zakres niespełnialny, a nie błąd składni
+        if last.len() > 0 && end < start {
+            return None;
+        }
+
+        if start < len {
+            ranges.push((start, if end >= len { len - 1 } else { end }));
+        }
+    }
+
+    if ranges.len() == 0 {
+        return Some(Ranges::Unsatisfiable);
+    }
+
+    ranges.sort();
+
+                                        //sklejamy nakładające się i sąsiednie zakresy
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+
+    for (start, end) in ranges {
+
+        if let Some(last) = merged.last_mut() {
+            if start <= last.1 + 1 {
+                if end > last.1 {
+                    last.1 = end;
+                }
+                continue;
+            }
+        }
+
+        merged.push((start, end));
+    }
+
+    if merged.len() > MAX_RANGES {
+        return None;
+    }
+
+    Some(Ranges::Satisfiable(merged))
+}
+
+
+                                        //If-Range: zakres tylko gdy walidator się zgadza (silny ETag albo dokładna data)
+fn if_range_matches(value: &str, response: &Response) -> bool {
+
+    let value = value.trim();
+
+    if value.starts_with('"') {
+        match response.header("ETag") {
+            Some(etag) => etag == value,
+            None => false,
+        }
+    } else if value.starts_with("W/") {
+        false
+    } else {
+        match (httpdate::parse(value), response.header("Last-Modified").and_then(|date| httpdate::parse(date))) {
+            (Some(since), Some(modified)) => since == modified,
+            _ => false,
+        }
+    }
+}
+
+
+                                        //start i end włącznie, względem początku ciała
+fn slice(body: &Body, start: u64, end: u64) -> Option<Body> {
+
+    match *body {
+
+        Body::Buf(ref data) => {
+            Some(Body::Buf(data[start as usize..(end + 1) as usize].to_vec()))
+        },
+
+        Body::Shared(ref data, offset, _) => {
+            Some(Body::Shared(data.clone(), offset + start as usize, (end - start + 1) as usize))
+        },
+
+        Body::File(ref file, offset, _) => {
+            match file.try_clone() {
+                Ok(file) => Some(Body::File(file, offset + start, end - start + 1)),
+                Err(_) => None,
+            }
+        },
+
+        Body::Parts(ref parts) => {
+
+            let mut sliced = Vec::new();
+            let mut pos    = 0;
+
+            for part in parts.iter() {
+
+                let len = part.len();
+
+                if len > 0 && pos <= end && pos + len > start {
+
+                    let from = if start > pos { start - pos } else { 0 };
+                    let to   = min(end, pos + len - 1) - pos;
+
+                    match slice(part, from, to) {
+                        Some(part) => sliced.push(part),
+                        None => return None,
+                    }
+                }
+
+                pos = pos + len;
+            }
+
+            Some(Body::Parts(sliced))
+        },
+
+                                        //apply wcześniej zamienia kompresję w locie na postać niekodowaną
+        Body::Encoded(_, _, _) => None,
+    }
+}
+
+
+/// Turns a full 200 response into 206 or 416 according to the `Range` and `If-Range` headers of the request.
+/// Ranges always refer to the identity body: a body compressed while sending is sent without compression.
+pub fn apply(request: &Request, response: Response) -> Response {
+    partial(request.method(), |name| request.header(name).cloned(), response)
+}
+
+
+                                        //header - wartość nagłówka requestu o danej nazwie
+fn partial<F>(method: &str, header: F, mut response: Response) -> Response
+    where F: Fn(&str) -> Option<String> {
+
+    if *response.code() != Code::Code200 {
+        return response;
+    }
+
+    if method != "GET" && method != "HEAD" {
+        return response;
+    }
+
+    response.add_header("Accept-Ranges", "bytes");
+
+    let range = match header("Range") {
+        Some(range) => range,
+        None => return response,
+    };
+
+    if let Some(if_range) = header("If-Range") {
+        if if_range_matches(&if_range, &response) == false {
+            return response;
+        }
+    }
+
+    let len = response.body_len();
+
+    match parse(&range, len) {
+
+        None => response,
+
+        Some(Ranges::Unsatisfiable) => {
+
+            response.replace_body(Body::Buf("416 Range Not Satisfiable".to_owned().into_bytes()));
+            response.set_code(Code::Code416);
+            response.set_type(Type::TextHtml);
+            response.add_header("Content-Range", &format!("bytes */{}", len));
+            response.remove_header("Content-Encoding");
+            response
+        },
+
+        Some(Ranges::Satisfiable(ranges)) => {
+
+                                        //długość skompresowanego strumienia nie jest znana z góry - zakresy z postaci niekodowanej
+            let body = match response.replace_body(Body::Buf(Vec::new())) {
+                Body::Encoded(body, _, _) => {
+                    response.remove_header("Content-Encoding");
+                    *body
+                },
+                body => body,
+            };
+
+            if ranges.len() == 1 {
+
+                let (start, end) = ranges[0];
+
+                match slice(&body, start, end) {
+                    Some(part) => {
+                        response.replace_body(part);
+                        response.set_code(Code::Code206);
+                        response.add_header("Content-Range", &format!("bytes {}-{}/{}", start, end, len));
+                    },
+                    None => {
+                        response.replace_body(body);
+                    }
+                }
+
+                return response;
+            }
+
+                                        //kilka zakresów - multipart/byteranges
+            let typ       = response.typ().to_str().to_owned();
+            let mut parts = Vec::new();
+
+            for &(start, end) in ranges.iter() {
+
+                let head = format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", BYTERANGES_BOUNDARY, typ, start, end, len);
+
+                parts.push(Body::Buf(head.into_bytes()));
+
+                match slice(&body, start, end) {
+                    Some(part) => parts.push(part),
+                    None => {
+                        response.replace_body(body);
+                        return response;
+                    }
+                }
+            }
+
+            parts.push(Body::Buf(format!("\r\n--{}--\r\n", BYTERANGES_BOUNDARY).into_bytes()));
+
+            response.replace_body(Body::Parts(parts));
+            response.set_code(Code::Code206);
+            response.set_type(Type::MultipartByteranges);
+
+            response
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Read;
+    use std::sync::Arc;
+    use code::Code;
+    use typemod::{Type, BYTERANGES_BOUNDARY};
+    use response::{Response, Body};
+    use compression::Encoding;
+    use super::{parse, partial, Ranges};
+
+    fn ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse(header, len) {
+            Some(Ranges::Satisfiable(ranges)) => Some(ranges),
+            Some(Ranges::Unsatisfiable) => Some(vec![]),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn single_range() {
+        assert_eq!(ranges("bytes=0-499", 1000), Some(vec![(0, 499)]));
+        assert_eq!(ranges("bytes=500-", 1000), Some(vec![(500, 999)]));
+        assert_eq!(ranges("bytes=900-2000", 1000), Some(vec![(900, 999)]));
+        assert_eq!(ranges(" bytes= 10 - 20 ", 1000), Some(vec![(10, 20)]));
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(ranges("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(ranges("bytes=-5000", 1000), Some(vec![(0, 999)]));
+        assert_eq!(ranges("bytes=-0", 1000), Some(vec![]));
+    }
+
+    #[test]
+    fn merged_ranges() {
+        assert_eq!(ranges("bytes=500-600,0-99,100-199,550-700", 1000), Some(vec![(0, 199), (500, 700)]));
+        assert_eq!(ranges("bytes=0-0,-1", 1000), Some(vec![(0, 0), (999, 999)]));
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(ranges("bytes=0-10", 0), Some(vec![]));
+    }
+
+    #[test]
+    fn ignored() {
+        assert_eq!(ranges("items=0-10", 1000), None);
+        assert_eq!(ranges("bytes=10-5", 1000), None);
+        assert_eq!(ranges("bytes=abc", 1000), None);
+        assert_eq!(ranges("bytes=a-b", 1000), None);
+
+        let many: Vec<String> = (0..20).map(|index| format!("{}-{}", index * 10, index * 10 + 1)).collect();
+        assert_eq!(ranges(&format!("bytes={}", many.join(",")), 1000), None);
+    }
+
+    fn request(method: &str, headers: Vec<(&str, &str)>, response: Response) -> Response {
+        let header = |name: &str| headers.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value.to_owned());
+        partial(method, header, response)
+    }
+
+    fn digits() -> Response {
+        let mut response = Response::create(Code::Code200, Type::TextPlain, "0123456789".to_owned());
+        response.add_header("ETag", "\"v1\"");
+        response.add_header("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT");
+        response
+    }
+
+    fn body(response: &mut Response) -> String {
+        let mut data = String::new();
+        response.replace_body(Body::Buf(Vec::new())).into_reader().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    fn header(response: &Response, name: &str) -> Option<String> {
+        response.header(name).cloned()
+    }
+
+    #[test]
+    fn single_range_response() {
+
+        let mut response = request("GET", vec![("Range", "bytes=2-5")], digits());
+
+        assert_eq!(*response.code(), Code::Code206);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 2-5/10".to_owned()));
+        assert_eq!(header(&response, "Accept-Ranges"), Some("bytes".to_owned()));
+        assert_eq!(body(&mut response), "2345");
+
+        let mut response = request("GET", vec![("Range", "bytes=-3")], digits());
+
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 7-9/10".to_owned()));
+        assert_eq!(body(&mut response), "789");
+    }
+
+    #[test]
+    fn multiple_ranges_response() {
+
+        let mut response = request("GET", vec![("Range", "bytes=0-1,-2")], digits());
+
+        assert_eq!(*response.code(), Code::Code206);
+        assert_eq!(*response.typ(), Type::MultipartByteranges);
+
+        let expected = format!("\r\n--{0}\r\nContent-Type: {1}\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                                \r\n--{0}\r\nContent-Type: {1}\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                                \r\n--{0}--\r\n", BYTERANGES_BOUNDARY, Type::TextPlain.to_str());
+
+        assert_eq!(body(&mut response), expected);
+    }
+
+    #[test]
+    fn unsatisfiable_response() {
+
+        let response = request("GET", vec![("Range", "bytes=10-")], digits());
+
+        assert_eq!(*response.code(), Code::Code416);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes */10".to_owned()));
+    }
+
+    #[test]
+    fn if_range() {
+
+        let response = request("GET", vec![("Range", "bytes=2-5"), ("If-Range", "\"v1\"")], digits());
+        assert_eq!(*response.code(), Code::Code206);
+
+        let response = request("GET", vec![("Range", "bytes=2-5"), ("If-Range", "Sun, 06 Nov 1994 08:49:37 GMT")], digits());
+        assert_eq!(*response.code(), Code::Code206);
+
+                                        //nieaktualny walidator - całe ciało
+        let mut response = request("GET", vec![("Range", "bytes=2-5"), ("If-Range", "\"v0\"")], digits());
+        assert_eq!(*response.code(), Code::Code200);
+        assert_eq!(body(&mut response), "0123456789");
+
+        let response = request("GET", vec![("Range", "bytes=2-5"), ("If-Range", "Sat, 05 Nov 1994 08:49:37 GMT")], digits());
+        assert_eq!(*response.code(), Code::Code200);
+
+        let response = request("GET", vec![("Range", "bytes=2-5"), ("If-Range", "W/\"v1\"")], digits());
+        assert_eq!(*response.code(), Code::Code200);
+    }
+
+    #[test]
+    fn other_requests_untouched() {
+
+        let response = request("POST", vec![("Range", "bytes=2-5")], digits());
+        assert_eq!(*response.code(), Code::Code200);
+        assert_eq!(header(&response, "Accept-Ranges"), None);
+
+        let response = request("GET", vec![("Range", "items=2-5")], digits());
+        assert_eq!(*response.code(), Code::Code200);
+    }
+
+    #[test]
+    fn shared_body_not_copied() {
+
+        let data     = Arc::new(b"0123456789".to_vec());
+        let response = Response::create_from_shared(Code::Code200, Type::TextPlain, data.clone());
+
+        let mut response = request("GET", vec![("Range", "bytes=3-4,6-7")], response);
+
+        match response.replace_body(Body::Buf(Vec::new())) {
+            Body::Parts(parts) => {
+                match parts[1] {
+                    Body::Shared(ref shared, 3, 2) => assert!(Arc::ptr_eq(shared, &data)),
+                    ref other => panic!("{:?}", other),
+                }
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parts_body() {
+
+        let mut response = Response::create(Code::Code200, Type::TextPlain, "".to_owned());
+        response.replace_body(Body::Parts(vec![Body::Buf(b"0123".to_vec()), Body::Buf(Vec::new()), Body::Buf(b"456789".to_vec())]));
+
+        let mut response = request("GET", vec![("Range", "bytes=2-6")], response);
+
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 2-6/10".to_owned()));
+        assert_eq!(body(&mut response), "23456");
+    }
+
+    #[test]
+    fn encoded_body_sent_as_identity() {
+
+        let mut response = Response::create(Code::Code200, Type::TextPlain, "".to_owned());
+        response.replace_body(Body::Encoded(Box::new(Body::Buf(b"0123456789".to_vec())), Encoding::Gzip, 6));
+        response.add_header("Content-Encoding", "gzip");
+
+        let mut response = request("GET", vec![("Range", "bytes=5-")], response);
+
+        assert_eq!(*response.code(), Code::Code206);
+        assert_eq!(header(&response, "Content-Encoding"), None);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 5-9/10".to_owned()));
+        assert_eq!(body(&mut response), "56789");
+    }
+}
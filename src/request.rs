@@ -1,10 +1,34 @@
 use std;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::any::{Any, TypeId};
+use std::mem;
 use httparse;
 use std::io::{Error, ErrorKind};
 use mio::{Token, Sender};
 use server::MioMessage;
 use response::Response;
+use urlencoded;
+use form::Form;
+use code::Code;
+use typemod::Type;
+use body::BodyReader;
+use multipart::{self, Multipart, MultipartLimits, MultipartReader};
+use cookie;
+use session::{Session, SessionManager};
+use middleware::Middleware;
+use range;
+use conditional;
+use stream::TlsInfo;
+#[cfg(feature = "tls")]
+use x509::ClientCert;
+use websocket::{self, WebSocket, WebSocketHandler};
+use event_stream::EventStream;
+use upgrade::{Upgraded, UpgradeHandler};
+use proxy::ProxyTarget;
+use client::Client;
+use std::net::SocketAddr;
 
 use std::boxed::FnBox;
 
@@ -15,10 +39,18 @@ https://github.com/tailhook/rotor-http/blob/master/src/http1.rs
 */
 
 pub struct PreRequest {
-    method  : String,
-    path    : String,
-    version : u8,
-    headers : HashMap<Box<String>, String>,
+    method      : String,
+    target      : String,
+    path        : String,
+    query       : Option<String>,
+    query_pairs : Vec<(String, String)>,
+    version     : u8,
+    headers     : HashMap<Box<String>, String>,
+    cookies     : Vec<(String, String)>,
+    tls         : Option<TlsInfo>,
+    stream_id   : Option<u32>,              //HTTP/2 - numer strumienia w połączeniu
+    body        : Option<Vec<u8>>,          //HTTP/2 - ciało odebrane w całości przed wywołaniem odbiornika
+    remote_addr : Option<SocketAddr>,
 }
 
 impl PreRequest { 
@@ -29,33 +61,21 @@ impl PreRequest {
 
             (Some(method), Some(path), Some(version)) => {
 
-                let mut headers = HashMap::new();
+                let mut headers = Vec::new();
 
-                for header in req.headers {
-
-                    let key   = header.name.to_owned();
+                for header in req.headers.iter() {
 
                     let value = match std::str::from_utf8(header.value) {
                         Ok(value) => value.to_owned(),
                         Err(err) => {
-                            return Err(Error::new(ErrorKind::InvalidInput, format!("header {}, error utf8 sequence: {}", key, err)))
+                            return Err(Error::new(ErrorKind::InvalidInput, format!("header {}, error utf8 sequence: {}", header.name, err)))
                         }
                     };
 
-                    match headers.insert(Box::new(key.clone()), value) {
-                        None => {}      //insert ok
-                        Some(_) => {
-                            return Err(Error::new(ErrorKind::InvalidInput, format!("double header: {}", &key)));
-                        }
-                    };
+                    headers.push((header.name.to_owned(), value));
                 }
 
-                Ok(PreRequest{
-                    method  : method.to_owned(),
-                    path    : path.to_owned(),
-                    version : version,
-                    headers : headers,
-                })
+                PreRequest::build(method, path, version, headers)
             }
             _ => {
 
@@ -65,6 +85,92 @@ impl PreRequest {
         }
     }
     
+                                        //strumień HTTP/2 - pola :method i :path już wyciągnięte, :authority trafia do Host
+    pub fn new_http2(method: &str, path: &str, authority: Option<String>, mut headers: Vec<(String, String)>, stream_id: u32) -> Result<PreRequest, Error> {
+        
+        if let Some(authority) = authority {
+            if headers.iter().any(|&(ref name, _)| name == "host") == false {
+                headers.push(("host".to_owned(), authority));
+            }
+        }
+        
+        let mut pre_request = try!(PreRequest::build(method, path, 2, headers));
+        
+        pre_request.stream_id = Some(stream_id);
+        
+        Ok(pre_request)
+    }
+    
+    fn build(method: &str, path: &str, version: u8, list: Vec<(String, String)>) -> Result<PreRequest, Error> {
+        
+        let mut headers = HashMap::new();
+
+        for (key, value) in list.into_iter() {
+
+            if key.to_lowercase() == "cookie" {
+                
+                                        //wiele nagłówków Cookie sklejamy w jeden
+                let joined = match headers.remove(&Box::new(key.clone())) {
+                    Some(prev) => prev + "; " + &value,
+                    None => value,
+                };
+                
+                headers.insert(Box::new(key), joined);
+                continue;
+            }
+            
+            match headers.insert(Box::new(key.clone()), value) {
+                None => {}      //insert ok
+                Some(_) => {
+                    return Err(Error::new(ErrorKind::InvalidInput, format!("double header: {}", &key)));
+                }
+            };
+        }
+
+                                        //RFC 7230 5.4 - HTTP/1.1 wymaga dokładnie jednego nagłówka Host
+        let host_count = headers.keys().filter(|key| key.to_lowercase() == "host").count();
+        
+        if host_count > 1 || (version == 1 && host_count == 0) {
+            return Err(Error::new(ErrorKind::InvalidInput, "missing or duplicated Host header"));
+        }
+        
+        let (path_part, query) = match path.find('?') {
+            Some(pos) => (&path[0..pos], Some(path[pos + 1..].to_owned())),
+            None      => (path, None),
+        };
+
+        let path_decoded = try!(urlencoded::decode(path_part));
+
+                                        //niepoprawny UTF-8 w parametrach - request odrzucany (400), tak jak w ścieżce
+        let query_pairs = match query {
+            Some(ref query) => try!(urlencoded::parse_form(query.as_bytes())),
+            None => Vec::new(),
+        };
+
+        let mut cookies = Vec::new();
+        
+        for (key, value) in headers.iter() {
+            if key.to_lowercase() == "cookie" {
+                cookies.extend(cookie::parse(value));
+            }
+        }
+        
+        Ok(PreRequest{
+            method      : method.to_owned(),
+            target      : path.to_owned(),
+            path        : path_decoded,
+            query       : query,
+            query_pairs : query_pairs,
+            version     : version,
+            headers     : headers,
+            cookies     : cookies,
+            tls         : None,
+            stream_id   : None,
+            body        : None,
+            remote_addr : None,
+        })
+    }
+    
     pub fn bind(self, token: Token, sender :  Sender<MioMessage>) -> Request {
         
         Request {
@@ -72,13 +178,31 @@ impl PreRequest {
             pre_request : self,
             token       : token,
             sender      : sender,
+            session     : None,
+            params      : Vec::new(),
+            after       : Vec::new(),
+            extensions  : HashMap::new(),
         }
     }
     
 
+                                        //nazwy nagłówków nie rozróżniają wielkości liter
+    pub fn header(&self, name: &str) -> Option<&String> {
+        
+        let name = name.to_lowercase();
+        
+        for (key, value) in self.headers.iter() {
+            if key.to_lowercase() == name {
+                return Some(value);
+            }
+        }
+        
+        None
+    }
+    
     pub fn is_header_set(&self, name: &str, value: &str) -> bool {
         
-        match self.headers.get(&Box::new(name.to_owned())) {
+        match self.header(name) {
             
             Some(get_value) => {
                 get_value == value.trim()
@@ -88,10 +212,102 @@ impl PreRequest {
         }
     }
     
+                                        //wszystkie nagłówki, w przypadkowej kolejności
+    pub fn headers(&self) -> Vec<(&str, &str)> {
+        self.headers.iter().map(|(key, value)| (&key[..], &value[..])).collect()
+    }
+    
     pub fn path(&self) -> &String {
         &(self.path)
     }
     
+                                        //nazwa z nagłówka Host, małymi literami i bez portu
+    pub fn host(&self) -> Option<String> {
+        
+        let host = match self.header("Host") {
+            Some(host) => host.trim().to_lowercase(),
+            None => return None,
+        };
+        
+        let name = if host.starts_with('[') {
+                                        //adres IPv6, np. [::1]:8080
+            match host.find(']') {
+                Some(pos) => host[0..pos + 1].to_owned(),
+                None => host,
+            }
+        } else {
+            match host.rfind(':') {
+                Some(pos) => host[0..pos].to_owned(),
+                None => host,
+            }
+        };
+        
+        Some(name.trim_right_matches('.').to_owned())
+    }
+    
+    pub fn raw_target(&self) -> &String {
+        &(self.target)
+    }
+    
+    pub fn set_tls(&mut self, tls: Option<TlsInfo>) {
+        self.tls = tls;
+    }
+    
+                                        //None - request przyszedł zwykłym http
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+    
+    pub fn stream_id(&self) -> Option<u32> {
+        self.stream_id
+    }
+    
+    pub fn set_remote_addr(&mut self, remote_addr: Option<SocketAddr>) {
+        self.remote_addr = remote_addr;
+    }
+    
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+    
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = Some(body);
+    }
+    
+    pub fn query(&self) -> Option<&String> {
+        self.query.as_ref()
+    }
+    
+    pub fn query_pairs(&self) -> &Vec<(String, String)> {
+        &(self.query_pairs)
+    }
+    
+    pub fn query_param(&self, name: &str) -> Option<&String> {
+        
+        for &(ref key, ref value) in self.query_pairs.iter() {
+            if key == name {
+                return Some(value);
+            }
+        }
+        
+        None
+    }
+    
+    pub fn cookies(&self) -> &Vec<(String, String)> {
+        &(self.cookies)
+    }
+    
+    pub fn cookie(&self, name: &str) -> Option<&String> {
+        
+        for &(ref key, ref value) in self.cookies.iter() {
+            if key == name {
+                return Some(value);
+            }
+        }
+        
+        None
+    }
+    
     pub fn is_post(&self) -> bool {
         self.method == "POST".to_owned()
     }
@@ -104,9 +320,24 @@ impl PreRequest {
         self.version.clone()
     }
     
+                                        //typ z nagłówka Content-Type, bez parametrów
+    pub fn content_type(&self) -> Option<String> {
+        
+        match self.header("Content-Type") {
+            Some(value) => {
+                let media_type = match value.find(';') {
+                    Some(pos) => &value[0..pos],
+                    None => &value[..],
+                };
+                Some(media_type.trim().to_lowercase())
+            },
+            None => None,
+        }
+    }
+    
     pub fn get_header(&self, header: String) -> Option<usize> {
         
-        match self.headers.get(&Box::new(header)) {
+        match self.header(&header) {
             
             Some(value) => {
                 
@@ -127,12 +358,22 @@ pub struct Request {
     pre_request : PreRequest,
     token       : Token,
     sender      : Sender<MioMessage>,
+    session     : Option<(Session, Arc<SessionManager>)>,
+    params      : Vec<(String, String)>,
+    after       : Vec<Arc<Middleware>>,
+    extensions  : HashMap<TypeId, Box<Any + Send + Sync>>,
 }
 
 
 impl Request {    
 
-    pub fn get_post(self, callback: Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>) {
+    pub fn get_post(mut self, callback: Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>) {
+        
+                                        //HTTP/2 - ciało jest już odebrane
+        if let Some(body) = self.pre_request.body.take() {
+            (callback as Box<FnBox(Request, Option<Vec<u8>>)>)(self, Some(body));
+            return;
+        }
         
         let token  = self.token.clone();
         let sender = self.sender.clone();
@@ -140,10 +381,158 @@ impl Request {
         sender.send(MioMessage::GetPost(token, self, callback)).unwrap();
     }
     
+                                        //odczytuje ciało posta jako application/x-www-form-urlencoded
+                                        //415 gdy typ jest inny, 413 gdy ciało przekracza limit, 400 gdy kodowanie jest nieprawidłowe
+    pub fn get_form(self, limit: usize, callback: Box<FnBox(Request, Form) + Send + Sync + 'static>) {
+        
+        if self.pre_request.content_type() != Some("application/x-www-form-urlencoded".to_owned()) {
+            self.send(Response::create(Code::Code415, Type::TextHtml, "415 Unsupported Media Type".to_owned()));
+            return;
+        }
+        
+        if let Some(length) = self.pre_request.get_header("Content-Length".to_owned()) {
+            if length > limit {
+                self.send(Response::create(Code::Code413, Type::TextHtml, "413 Payload Too Large".to_owned()));
+                return;
+            }
+        }
+        
+        self.get_post(Box::new(move|request: Request, data: Option<Vec<u8>>| {
+            
+            let pairs = match data {
+                Some(data) => urlencoded::parse_form(&data),
+                None => {
+                                        //timeout czytania posta
+                    request.send(Response::create_400());
+                    return;
+                }
+            };
+            
+            match pairs {
+                Ok(pairs) => {
+                    (callback as Box<FnBox(Request, Form)>)(request, Form::new(pairs));
+                },
+                Err(_) => {
+                    request.send(Response::create_400());
+                }
+            }
+        }));
+    }
+    
+                                        //ciało requestu przekazywane do readera kawałkami, bez buforowania całości
+    pub fn get_body(mut self, mut reader: Box<BodyReader>) {
+        
+        if let Some(body) = self.pre_request.body.take() {
+            let complete = body.len() == 0 || reader.data(&body);
+            reader.finish(self, complete);
+            return;
+        }
+        
+        let token  = self.token.clone();
+        let sender = self.sender.clone();
+        
+        sender.send(MioMessage::GetBody(token, self, reader)).unwrap();
+    }
+    
+                                        //odczytuje ciało jako multipart/form-data, pliki trafiają do plików tymczasowych
+                                        //415 gdy typ jest inny, 413 gdy zostanie przekroczony któryś z limitów, 400 gdy ciało jest nieprawidłowe
+    pub fn get_multipart(self, limits: MultipartLimits, callback: Box<FnBox(Request, Multipart) + Send + Sync + 'static>) {
+        
+        let boundary = match self.pre_request.header("Content-Type") {
+            Some(content_type) => multipart::boundary(content_type),
+            None => None,
+        };
+        
+        let boundary = match boundary {
+            Some(boundary) => boundary,
+            None => {
+                self.send(Response::create(Code::Code415, Type::TextHtml, "415 Unsupported Media Type".to_owned()));
+                return;
+            }
+        };
+        
+        if let Some(length) = self.pre_request.get_header("Content-Length".to_owned()) {
+            if length > limits.max_total_size {
+                self.send(Response::create(Code::Code413, Type::TextHtml, "413 Payload Too Large".to_owned()));
+                return;
+            }
+        }
+        
+        self.get_body(Box::new(MultipartReader::new(boundary, limits, callback)));
+    }
+    
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.pre_request.header(name)
+    }
+    
+    pub fn headers(&self) -> Vec<(&str, &str)> {
+        self.pre_request.headers()
+    }
+    
+                                        //dla PUT/DELETE itp. - sprawdzenie If-Match/If-Unmodified-Since przed wprowadzeniem zmian
+                                        //exists - czy zasób istnieje (do niego pasuje "*"), nawet gdy nie ma ETag
+    pub fn check_preconditions(&self, exists: bool, etag: Option<&String>, last_modified: Option<SystemTime>) -> Option<Code> {
+        conditional::evaluate(self, exists, etag, last_modified)
+    }
+    
+    pub fn cookies(&self) -> &Vec<(String, String)> {
+        self.pre_request.cookies()
+    }
+    
+    pub fn cookie(&self, name: &str) -> Option<&String> {
+        self.pre_request.cookie(name)
+    }
+    
+    pub fn content_type(&self) -> Option<String> {
+        self.pre_request.content_type()
+    }
+    
+                                        //ścieżka po zdekodowaniu, bez query stringa
     pub fn path(&self) -> &String {
         self.pre_request.path()
     }
     
+                                        //ścieżka w postaci przesłanej przez klienta
+    pub fn raw_target(&self) -> &String {
+        self.pre_request.raw_target()
+    }
+    
+    pub fn host(&self) -> Option<String> {
+        self.pre_request.host()
+    }
+    
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.pre_request.tls()
+    }
+    
+                                        //adres klienta (ostatniego proxy, jeśli serwer stoi za innym)
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.pre_request.remote_addr()
+    }
+    
+    pub fn is_secure(&self) -> bool {
+        self.pre_request.tls().is_some()
+    }
+    
+                                        //zweryfikowany certyfikat klienta (mTLS)
+    #[cfg(feature = "tls")]
+    pub fn client_cert(&self) -> Option<&ClientCert> {
+        self.pre_request.tls().and_then(|tls| tls.client_cert())
+    }
+    
+    pub fn query(&self) -> Option<&String> {
+        self.pre_request.query()
+    }
+    
+    pub fn query_pairs(&self) -> &Vec<(String, String)> {
+        self.pre_request.query_pairs()
+    }
+    
+    pub fn query_param(&self, name: &str) -> Option<&String> {
+        self.pre_request.query_param(name)
+    }
+    
+                                        //0 - HTTP/1.0, 1 - HTTP/1.1, 2 - HTTP/2
     pub fn version(&self) -> u8 {
         self.pre_request.version()
     }
@@ -156,11 +545,240 @@ impl Request {
         self.pre_request.is_post()
     }
     
-    pub fn send(mut self, response: Response) {
+                                        //parametry ścieżki wyciągnięte przez Router
+    pub fn params(&self) -> &Vec<(String, String)> {
+        &(self.params)
+    }
+    
+    pub fn param(&self, name: &str) -> Option<&String> {
+        
+        for &(ref key, ref value) in self.params.iter() {
+            if key == name {
+                return Some(value);
+            }
+        }
+        
+        None
+    }
+    
+    pub fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+    
+                                        //None gdy odbiornik nie jest opakowany przez SessionManager
+    pub fn session(&mut self) -> Option<&mut Session> {
+        
+        match self.session {
+            Some((ref mut session, _)) => Some(session),
+            None => None,
+        }
+    }
+    
+    pub fn set_session(&mut self, session: Session, manager: Arc<SessionManager>) {
+        self.session = Some((session, manager));
+    }
+    
+                                        //dowolne dane dołączone do requestu, np. przez middleware
+    pub fn set_extension<T: Any + Send + Sync>(&mut self, value: T) {
+        self.extensions.insert(TypeId::of::<T>(), Box::new(value));
+    }
+    
+    pub fn extension<T: Any + Send + Sync>(&self) -> Option<&T> {
+        
+        match self.extensions.get(&TypeId::of::<T>()) {
+            Some(value) => value.downcast_ref::<T>(),
+            None => None,
+        }
+    }
+    
+    pub fn add_after(&mut self, list: &[Arc<Middleware>]) {
+        self.after.extend_from_slice(list);
+    }
+    
+    fn run_after(&mut self, response: &mut Response) {
+        
+        let after = mem::replace(&mut self.after, Vec::new());
+        
+        for middleware in after.iter().rev() {
+            middleware.after(self, response);
+        }
+    }
+    
+                                        //podprotokoły z Sec-WebSocket-Protocol, do wyboru przed websocket()
+    pub fn websocket_protocols(&self) -> Vec<String> {
+        websocket::protocols(self)
+    }
+    
+    /// Accepts a WebSocket handshake (RFC 6455) and hands the connection over to `handler`. When the request is not
+    /// a valid handshake the client gets 400 (or 426 for an unsupported version) and the handler is dropped.
+    /// `protocol` is echoed in `Sec-WebSocket-Protocol` and should be one of `websocket_protocols`.
+    pub fn websocket(mut self, protocol: Option<&str>, handler: Box<WebSocketHandler>) {
+        
+        let mut response = match websocket::handshake(&self, protocol) {
+            Ok(response) => response,
+            Err(response) => {
+                self.send(response);
+                return;
+            }
+        };
+        
+        self.is_send = true;
+        
+        if let Some((session, manager)) = self.session.take() {
+            manager.finish(session, &mut response);
+        }
+        
+        self.run_after(&mut response);
+        
+        let socket = WebSocket::new(self.token, self.sender.clone());
+        
+        (self.sender).send(MioMessage::WebSocketOpen(self.token, response, socket, handler)).unwrap();
+    }
+    
+    /// Takes over the connection: `response` (101 Switching Protocols, or 200 for CONNECT) is sent without body headers
+    /// and from then on raw bytes go to `handler`, called on a thread of its own, and back through its `Upgraded` handle.
+    /// Bytes the client sent right after the request headers are passed to the first `on_data`.
+    /// Other response codes are sent as usual and the handler is dropped; HTTP/2 requests get 400.
+    pub fn upgrade(mut self, mut response: Response, handler: Box<UpgradeHandler>) {
+        
+        if self.pre_request.stream_id().is_some() {
+            self.send(Response::create_400());
+            return;
+        }
+        
+        match *response.code() {
+            Code::Code101 | Code::Code200 => {},
+            _ => {
+                self.send(response);
+                return;
+            }
+        }
         
         self.is_send = true;
         
-        (self.sender).send(MioMessage::Response(self.token, response)).unwrap();
+        if let Some((session, manager)) = self.session.take() {
+            manager.finish(session, &mut response);
+        }
+        
+        self.run_after(&mut response);
+        
+        let (socket, guard) = Upgraded::new(self.token, self.sender.clone());
+        
+        (self.sender).send(MioMessage::UpgradeOpen(self.token, response, socket, guard, handler)).unwrap();
+    }
+    
+    /// Opens a tunnel for a `CONNECT` request: the event loop connects to `addr` and, once connected, answers 200
+    /// and passes bytes both ways until either side closes or nothing moves for `idle_timeout` milliseconds.
+    /// Usually called by `Tunnel`, which also checks the target. HTTP/2 requests get 400.
+    pub fn tunnel(mut self, addr: SocketAddr, idle_timeout: u64) {
+        
+        if self.pre_request.stream_id().is_some() {
+            self.send(Response::create_400());
+            return;
+        }
+        
+        let mut response = Response::create(Code::Code200, Type::TextPlain, "".to_owned());
+        
+        self.is_send = true;
+        
+        if let Some((session, manager)) = self.session.take() {
+            manager.finish(session, &mut response);
+        }
+        
+        self.run_after(&mut response);
+        
+        (self.sender).send(MioMessage::TunnelOpen(self.token, addr, response, idle_timeout)).unwrap();
+    }
+    
+    /// Forwards the request to another server (see `ProxyHandler`, which prepares `target`): the event loop sends
+    /// the request head and the body as it arrives, then streams the upstream response back to the client.
+    /// Middleware registered with `add_after` doesn't see the proxied response. HTTP/2 requests get 400.
+    pub fn proxy(mut self, target: ProxyTarget) {
+        
+        if self.pre_request.stream_id().is_some() {
+            self.send(Response::create_400());
+            return;
+        }
+        
+        self.is_send = true;
+        
+        (self.sender).send(MioMessage::ProxyOpen(self.token, target)).unwrap();
+    }
+    
+                                        //klient HTTP na tym samym event loop-ie co serwer
+    pub fn client(&self) -> Client {
+        Client::new(self.sender.clone())
+    }
+    
+                                        //id ostatniego zdarzenia odebranego przez przeglądarkę przed ponownym połączeniem
+    pub fn last_event_id(&self) -> Option<&String> {
+        self.header("Last-Event-ID")
+    }
+    
+    /// Answers with a `text/event-stream` response that stays open; events are pushed through the returned handle.
+    /// The stream ends with `EventStream::close`, when the client disconnects or when the server goes down.
+    /// A comment is sent every few seconds of silence so that proxies don't drop the connection.
+    /// HTTP/1.0 clients get the events without chunked encoding, the end of the body is the end of the connection.
+    pub fn event_stream(mut self) -> EventStream {
+        
+        let mut response = Response::create(Code::Code200, Type::TextEventStream, "".to_owned());
+        
+        response.add_header("Cache-Control", "no-cache");
+        response.add_header("X-Accel-Buffering", "no");     //nginx - bez buforowania odpowiedzi
+        
+        let (event_stream, guard) = EventStream::new(self.token, self.pre_request.stream_id(), self.sender.clone());
+        
+        if self.pre_request.method() == "HEAD" {
+            self.send(response);
+            return event_stream;                        //guard usunięty - strumień od razu zamknięty
+        }
+        
+        self.is_send = true;
+        
+        if let Some((session, manager)) = self.session.take() {
+            manager.finish(session, &mut response);
+        }
+        
+        self.run_after(&mut response);
+        
+                                                    //HTTP/1.0 nie zna chunked - surowe zdarzenia, koniec strumienia zamyka połączenie
+        let chunked = self.pre_request.version() != 0;
+        
+        let message = MioMessage::EventStreamOpen(self.token, self.pre_request.stream_id(), response, guard, chunked);
+        
+        (self.sender).send(message).unwrap();
+        
+        event_stream
+    }
+    
+    pub fn send(mut self, mut response: Response) {
+        
+        self.is_send = true;
+        
+        if let Some((session, manager)) = self.session.take() {
+            manager.finish(session, &mut response);
+        }
+        
+        self.run_after(&mut response);
+        
+        let response     = conditional::apply(&self, response);
+        let mut response = range::apply(&self, response);
+        
+        if self.pre_request.method() == "HEAD" {
+            response.set_head();
+        }
+        
+        let message = self.message(response);
+        
+        (self.sender).send(message).unwrap();
+    }
+    
+    fn message(&self, response: Response) -> MioMessage {
+        
+        match self.pre_request.stream_id() {
+            Some(stream_id) => MioMessage::StreamResponse(self.token, stream_id, response),
+            None => MioMessage::Response(self.token, response),
+        }
     }
 }
 
@@ -171,8 +789,13 @@ impl Drop for Request {
         
         if self.is_send == false {
             
-            let resp500 = Response::create_500();
-            (self.sender).send(MioMessage::Response(self.token, resp500)).unwrap();
+                                                    //sesja nie jest zapisywana - zmiany z przerwanej obsługi przepadają
+            let mut resp500 = Response::create_500();
+            self.run_after(&mut resp500);
+            
+            let message = self.message(resp500);
+            
+            (self.sender).send(message).unwrap();
         }
     }
 }
@@ -196,3 +819,42 @@ impl Drop for Request {
                 };
             })));
             */
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Error;
+    use httparse;
+    use super::PreRequest;
+
+    fn parse(head: &[u8]) -> Result<PreRequest, Error> {
+
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let mut req     = httparse::Request::new(&mut headers);
+
+        req.parse(head).unwrap();
+
+        PreRequest::new(req)
+    }
+
+    #[test]
+    fn path_and_query() {
+
+        let pre_request = parse(b"GET /a%20b/c?x=1+2&y=%C5%BC&x=3 HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+
+        assert_eq!(pre_request.path(), "/a b/c");
+        assert_eq!(pre_request.raw_target(), "/a%20b/c?x=1+2&y=%C5%BC&x=3");
+        assert_eq!(pre_request.query(), Some(&"x=1+2&y=%C5%BC&x=3".to_owned()));
+        assert_eq!(pre_request.query_param("x"), Some(&"1 2".to_owned()));
+        assert_eq!(pre_request.query_param("y"), Some(&"ż".to_owned()));
+        assert_eq!(pre_request.query_pairs().len(), 3);
+    }
+
+    #[test]
+    fn invalid_utf8_rejected() {
+        assert!(parse(b"GET /?a=%FF HTTP/1.1\r\nHost: example.com\r\n\r\n").is_err());
+        assert!(parse(b"GET /?a=1&%FF=b HTTP/1.1\r\nHost: example.com\r\n\r\n").is_err());
+        assert!(parse(b"GET /%FF HTTP/1.1\r\nHost: example.com\r\n\r\n").is_err());
+    }
+}
@@ -1,61 +1,548 @@
+use std::io;
+use std::mem;
+use std::io::{Read, Seek, SeekFrom, Cursor};
+use std::fs::File;
+use std::cmp::min;
+use std::sync::Arc;
 use typemod::Type;
 use code::Code;
+use cookie::Cookie;
+use httpdate;
+use conditional;
+use output::{Output, Source, SourceReader};
+use compression::{self, Encoding};
+
+
+                                        //nazwa nagłówka - tylko znaki tokenu (RFC 7230 3.2.6)
+pub fn header_name(name: &str) -> String {
+    name.chars().filter(|c| (*c as u32) < 128 && is_tchar(*c as u8)).collect()
+}
+
+
+pub fn is_tchar(byte: u8) -> bool {
+    match byte {
+        b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' => true,
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => false,
+    }
+}
+
+
+                                        //wartość nagłówka bez znaków sterujących (poza tabulatorem)
+pub fn header_value(value: &str) -> String {
+    value.chars().filter(|c| *c == '\t' || c.is_control() == false).collect()
+}
+
+
+#[derive(Debug)]
+pub enum Body {
+    Buf(Vec<u8>),
+    Shared(Arc<Vec<u8>>, usize, usize),         //bufor współdzielony, np. z FileCache - wysyłany bez kopiowania; offset, długość
+    File(File, u64, u64),                       //plik, offset, długość
+    Parts(Vec<Body>),                           //kolejne kawałki wysyłane jeden po drugim
+    Encoded(Box<Body>, Encoding, u32),          //kodowanie, poziom; kompresowane w trakcie wysyłania, Transfer-Encoding: chunked
+}
+
+impl Body {
+    
+    pub fn len(&self) -> u64 {
+        match *self {
+            Body::Buf(ref data) => data.len() as u64,
+            Body::Shared(_, _, len) => len as u64,
+            Body::File(_, _, len) => len,
+            Body::Parts(ref parts) => parts.iter().map(|part| part.len()).sum(),
+            Body::Encoded(ref body, _, _) => body.len(),      //rozmiar przed kompresją
+        }
+    }
+    
+    pub fn is_chunked(&self) -> bool {
+        match *self {
+            Body::Encoded(_, _, _) => true,
+            _ => false,
+        }
+    }
+    
+                                        //pliki czytane dopiero przy odczycie, bez trzymania całości w pamięci
+    pub fn into_reader(self) -> Box<Read + Send> {
+        
+        match self {
+            
+            Body::Buf(data) => Box::new(Cursor::new(data)),
+            
+            Body::Shared(data, offset, len) => Box::new(SharedReader {
+                data : data,
+                pos  : offset,
+                end  : offset + len,
+            }),
+            
+            Body::File(file, offset, len) => Box::new(FileReader {
+                file : file,
+                pos  : offset,
+                end  : offset + len,
+            }),
+            
+            Body::Parts(parts) => {
+                
+                let mut reader: Box<Read + Send> = Box::new(Cursor::new(Vec::new()));
+                
+                for part in parts {
+                    reader = Box::new(reader.chain(part.into_reader()));
+                }
+                
+                reader
+            },
+            
+            Body::Encoded(body, encoding, level) => Box::new(SourceReader::new(Box::new(compression::Stream::new(*body, encoding, level)))),
+        }
+    }
+    
+    fn read_into(self, message: &mut Vec<u8>) -> io::Result<()> {
+        
+        match self {
+            
+            Body::Buf(mut data) => {
+                message.append(&mut data);
+            },
+            
+            Body::Shared(data, offset, len) => {
+                message.extend_from_slice(&data[offset..offset + len]);
+            },
+            
+            Body::File(mut file, offset, len) => {
+                try!(file.seek(SeekFrom::Start(offset)));
+                try!(file.take(len).read_to_end(message));
+            },
+            
+            Body::Parts(parts) => {
+                for part in parts {
+                    try!(part.read_into(message));
+                }
+            },
+            
+            Body::Encoded(body, encoding, level) => {
+                
+                let mut stream = compression::Stream::new(*body, encoding, level);
+                
+                while let Some(mut block) = try!(stream.next_block()) {
+                    message.append(&mut block);
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn push_into(self, output: &mut Output) {
+        
+        match self {
+            Body::Buf(data) => output.push_bytes(data),
+            Body::Shared(data, offset, len) => output.push_shared(data, offset, len),
+            Body::File(file, offset, len) => output.push_file(file, offset, len),
+            Body::Parts(parts) => {
+                for part in parts {
+                    part.push_into(output);
+                }
+            },
+            Body::Encoded(body, encoding, level) => output.push_source(Box::new(compression::Stream::new(*body, encoding, level))),
+        }
+    }
+}
+
+
+struct SharedReader {
+    data : Arc<Vec<u8>>,
+    pos  : usize,
+    end  : usize,
+}
+
+impl Read for SharedReader {
+    
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        
+        let size = min(buf.len(), self.end - self.pos);
+        
+        buf[0..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+        self.pos = self.pos + size;
+        
+        Ok(size)
+    }
+}
+
+
+struct FileReader {
+    file : File,
+    pos  : u64,
+    end  : u64,
+}
+
+impl Read for FileReader {
+    
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        
+        if self.pos >= self.end {
+            return Ok(0);
+        }
+        
+        try!(self.file.seek(SeekFrom::Start(self.pos)));
+        
+        let max  = min(buf.len() as u64, self.end - self.pos) as usize;
+        let size = try!(self.file.read(&mut buf[0..max]));
+        
+        self.pos = self.pos + size as u64;
+        
+        Ok(size)
+    }
+}
+
+
+                                        //skąd klient wie, gdzie kończy się ciało
+enum Framing {
+    Length,
+    Chunked,
+    Close,
+}
 
 
 #[derive(Debug)]
 pub struct Response {
     close_connection : bool,
-    message          : Vec<u8>,
+    code             : Code,
+    typ              : Type,
+    headers          : Vec<(String, String)>,
+    body             : Body,
+    head             : bool,                    //odpowiedź na HEAD - same nagłówki
+    weak_etag        : bool,
 }
 
 
 impl Response {
     
-    pub fn as_bytes(self) -> Vec<u8> {
-        self.message
+    fn head_bytes(&self) -> Vec<u8> {
+        self.head_bytes_with(if self.body.is_chunked() { Framing::Chunked } else { Framing::Length })
+    }
+    
+                                        //nagłówki odpowiedzi, której ciało powstaje po ich wysłaniu (text/event-stream)
+                                        //bez chunked (HTTP/1.0) ciało kończy się wraz z zamknięciem połączenia
+    pub fn stream_head(&self, chunked: bool) -> Vec<u8> {
+        self.head_bytes_with(if chunked { Framing::Chunked } else { Framing::Close })
+    }
+    
+                                        //przejęcie połączenia (101, 200 na CONNECT) - bez nagłówków ciała, dalej idą surowe dane
+    pub fn upgrade_head(&self) -> Vec<u8> {
+        
+        let mut message = Vec::new();
+        
+        append_string(&mut message, "HTTP/1.1 ".to_owned() + self.code.to_str());
+        append_string(&mut message, "Date: ".to_owned() + &httpdate::now());
+        
+        for &(ref name, ref value) in self.headers.iter() {
+            append_string(&mut message, name.clone() + ": " + value);
+        }
+        
+        append_string(&mut message, "".to_owned());
+        
+        message
+    }
+    
+    fn head_bytes_with(&self, framing: Framing) -> Vec<u8> {
+        
+        let mut message = Vec::new();
+        
+        append_string(&mut message, "HTTP/1.1 ".to_owned() + self.code.to_str());
+        append_string(&mut message, "Date: ".to_owned() + &httpdate::now());
+        if self.code == Code::Code101 {
+                                        //zmiana protokołu - Upgrade i Connection są w nagłówkach odpowiedzi
+        } else if self.code == Code::Code304 {
+                                        //304 nie ma ciała, nagłówki reprezentacji pochodzą z poprzedniej odpowiedzi
+            append_string(&mut message, "Connection: keep-alive".to_owned());
+        } else {
+            append_string(&mut message, "Content-Type: ".to_owned() + self.typ.to_str());
+            
+            match framing {
+                Framing::Length => {
+                    append_string(&mut message, "Connection: keep-alive".to_owned());
+                    append_string(&mut message, format!("Content-length: {}", self.body.len()).to_owned());
+                },
+                Framing::Chunked => {
+                    append_string(&mut message, "Connection: keep-alive".to_owned());
+                    append_string(&mut message, "Transfer-Encoding: chunked".to_owned());
+                },
+                Framing::Close => {
+                    append_string(&mut message, "Connection: close".to_owned());
+                },
+            }
+        }
+        
+        for &(ref name, ref value) in self.headers.iter() {
+            append_string(&mut message, name.clone() + ": " + value);
+        }
+        
+        append_string(&mut message, "".to_owned());
+        
+        message
+    }
+    
+                                        //cała odpowiedź w pamięci - ciało z pliku zostanie wczytane
+                                        //nagłówki powstają dopiero po wczytaniu ciała; gdy to się nie uda, wysyłamy 500
+    pub fn as_bytes(mut self) -> Vec<u8> {
+        
+        if self.head {
+            return self.head_bytes();
+        }
+        
+        let mut body = Vec::new();
+        
+        match mem::replace(&mut self.body, Body::Buf(Vec::new())).read_into(&mut body) {
+            
+            Ok(()) => {
+                self.body = Body::Buf(body);
+                
+                let mut message = self.head_bytes();
+                
+                if let Body::Buf(ref mut body) = self.body {
+                    message.append(body);
+                }
+                
+                message
+            },
+            
+            Err(_) => Response::create_500().as_bytes(),
+        }
+    }
+    
+                                        //HTTP/2 - pola nagłówka (bez nagłówków dotyczących połączenia) i ciało wysyłane ramkami DATA
+    pub fn into_http2(self) -> (Vec<(String, String)>, Option<Box<Read + Send>>) {
+        
+        let mut fields = Vec::new();
+        
+        fields.push((":status".to_owned(), self.code.to_str()[0..3].to_owned()));
+        fields.push(("date".to_owned(), httpdate::now()));
+        
+        if self.code != Code::Code304 {
+            
+            fields.push(("content-type".to_owned(), self.typ.to_str().to_owned()));
+            
+            if self.body.is_chunked() == false {
+                fields.push(("content-length".to_owned(), format!("{}", self.body.len())));
+            }
+        }
+        
+        for (name, value) in self.headers.into_iter() {
+            
+            let name = name.to_lowercase();
+            
+            match &name[..] {
+                "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => {},
+                _ => fields.push((name, value)),
+            }
+        }
+        
+        if self.head || self.code == Code::Code304 || (self.body.is_chunked() == false && self.body.len() == 0) {
+            return (fields, None);
+        }
+        
+        let reader = match self.body {
+            Body::Encoded(body, encoding, level) => compression::reader(*body, encoding, level),
+            body => body.into_reader(),
+        };
+        
+        (fields, Some(reader))
     }
     
-    fn append_string(&mut self, line: String) {
-        self.message.append(&mut (line + "\r\n").into_bytes());
+                                        //nagłówki i ciało do wysłania przez Connection, plik nie jest wczytywany do pamięci
+    pub fn into_output(self) -> Output {
+        
+        let mut output = Output::from_bytes(self.head_bytes());
+        
+        if self.head == false {
+            self.body.push_into(&mut output);
+        }
+        
+        output
     }
     
     pub fn close_connection(&self) -> bool {
         self.close_connection
     }
     
-    fn create_headers(code: Code, typ: Type, length: usize) -> Response {
+    pub fn code(&self) -> &Code {
+        &(self.code)
+    }
+    
+    pub fn typ(&self) -> &Type {
+        &(self.typ)
+    }
+    
+                                        //None gdy ciało jest w pliku
+    pub fn body(&self) -> Option<&[u8]> {
+        match self.body {
+            Body::Buf(ref data) => Some(data),
+            Body::Shared(ref data, offset, len) => Some(&data[offset..offset + len]),
+            _ => None,
+        }
+    }
+    
+    pub fn replace_body(&mut self, body: Body) -> Body {
+        mem::replace(&mut self.body, body)
+    }
+    
+    pub fn set_code(&mut self, code: Code) {
+        self.code = code;
+    }
+    
+    pub fn set_type(&mut self, typ: Type) {
+        self.typ = typ;
+    }
+    
+    pub fn body_len(&self) -> u64 {
+        self.body.len()
+    }
+    
+                                        //długość nieznana z góry - ciało idzie kawałkami
+    pub fn is_chunked(&self) -> bool {
+        self.body.is_chunked()
+    }
+    
+    pub fn headers(&self) -> &Vec<(String, String)> {
+        &(self.headers)
+    }
+    
+                                        //nagłówek dopisywany po standardowych (Date, Content-Type, Connection, Content-length)
+                                        //znaki sterujące (CR, LF) są usuwane, żeby nie dało się dokleić własnych nagłówków
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((header_name(name), header_value(value)));
+    }
+    
+    pub fn remove_header(&mut self, name: &str) {
+        
+        let name = name.to_lowercase();
+        
+        self.headers.retain(|&(ref key, _)| key.to_lowercase() != name);
+    }
+    
+                                        //dopisuje nazwę nagłówka do Vary, bez powtórzeń
+    pub fn add_vary(&mut self, name: &str) {
+        
+        let current = match self.header("Vary") {
+            Some(current) => current.clone(),
+            None => {
+                self.add_header("Vary", name);
+                return;
+            }
+        };
+        
+        let lower = name.to_lowercase();
+        
+        if current.split(',').any(|item| item.trim() == "*" || item.trim().to_lowercase() == lower) {
+            return;
+        }
+        
+        self.remove_header("Vary");
+        self.add_header("Vary", &format!("{}, {}", current, name));
+    }
+    
+                                        //automatyczny ETag dla tej odpowiedzi będzie słaby (W/"...")
+    pub fn set_weak_etag(&mut self) {
+        self.weak_etag = true;
+    }
+    
+    pub fn is_weak_etag(&self) -> bool {
+        self.weak_etag
+    }
+    
+    pub fn header(&self, name: &str) -> Option<&String> {
+        
+        let name = name.to_lowercase();
+        
+        for &(ref key, ref value) in self.headers.iter() {
+            if key.to_lowercase() == name {
+                return Some(value);
+            }
+        }
+        
+        None
+    }
+    
+                                        //każde ciastko idzie w osobnym nagłówku Set-Cookie
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.headers.push(("Set-Cookie".to_owned(), cookie.to_header()));
+    }
+    
+                                        //Content-length zostaje, ale ciało nie zostanie wysłane
+    pub fn set_head(&mut self) {
+        self.head = true;
+    }
+    
+    fn create_headers(code: Code, typ: Type) -> Response {
         
         let close_connection = code == Code::Code500 || code == Code::Code400;
         
-        let mut response = Response {
+        Response {
             close_connection : close_connection,
-            message          : Vec::new(),
-        };
+            code             : code,
+            typ              : typ,
+            headers          : Vec::new(),
+            body             : Body::Buf(Vec::new()),
+            head             : false,
+            weak_etag        : false,
+        }
+    }
+    
+    pub fn create(code: Code, typ: Type, body: String) -> Response {
+        
+        let mut response = Response::create_headers(code, typ);
         
-        response.append_string("HTTP/1.1 ".to_owned() + code.to_str());
-        response.append_string("Date: Thu, 20 Dec 2001 12:04:30 GMT".to_owned());
-        response.append_string("Content-Type: ".to_owned() + typ.to_str());
-        response.append_string("Connection: keep-alive".to_owned());
-        response.append_string(format!("Content-length: {}", length).to_owned());
-        response.append_string("".to_owned());
+        response.body = Body::Buf(body.into_bytes());
         
         response
     }
     
-    pub fn create(code: Code, typ: Type, body: String) -> Response {
+    pub fn create_from_buf(code: Code, typ: Type, body: Vec<u8>) -> Response {
         
-        let mut response = Response::create_headers(code, typ, body.len());
+        let mut response = Response::create_headers(code, typ);
         
-        response.append_string(body);
+        response.body = Body::Buf(body);
         
         response
     }
     
-    pub fn create_from_buf(code: Code, typ: Type, mut body: Vec<u8>) -> Response {
+                                        //ciało współdzielone z innymi odpowiedziami, bez kopiowania
+    pub fn create_from_shared(code: Code, typ: Type, body: Arc<Vec<u8>>) -> Response {
+        
+        let mut response = Response::create_headers(code, typ);
         
-        let mut response = Response::create_headers(code, typ, body.len());
+        let len = body.len();
         
-        response.message.append(&mut body);
+        response.body = Body::Shared(body, 0, len);
+        
+        response
+    }
+    
+                                        //ciało wysyłane prosto z pliku (sendfile), bez wczytywania do pamięci
+    pub fn create_from_file(code: Code, typ: Type, file: File) -> io::Result<Response> {
+        
+        let metadata = try!(file.metadata());
+        let len      = metadata.len();
+        
+        let mut response = Response::create_from_file_range(code, typ, file, 0, len);
+        
+        if let Ok(modified) = metadata.modified() {
+            
+            if let Some(etag) = conditional::file_etag(len, modified) {
+                response.add_header("ETag", &etag);
+            }
+            
+            response.add_header("Last-Modified", &httpdate::format(modified));
+        }
+        
+        Ok(response)
+    }
+    
+    pub fn create_from_file_range(code: Code, typ: Type, file: File, offset: u64, len: u64) -> Response {
+        
+        let mut response = Response::create_headers(code, typ);
+        
+        response.body = Body::File(file, offset, len);
         
         response
     }
@@ -135,3 +622,7 @@ impl Response {
     */
 }
 
+
+fn append_string(message: &mut Vec<u8>, line: String) {
+    message.append(&mut (line + "\r\n").into_bytes());
+}
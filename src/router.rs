@@ -0,0 +1,303 @@
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use server::FnReceiver;
+use urlencoded;
+
+
+enum Segment {
+    Static(String),
+    Param(String),                      // :id
+    Wildcard(String),                   // *path - reszta ścieżki, niezdekodowana
+}
+
+
+struct Route {
+    method   : String,
+    segments : Vec<Segment>,
+    handler  : FnReceiver,
+}
+
+
+                                        //puste segmenty są pomijane, więc ukośnik na końcu jest opcjonalny - /users/ pasuje do /users
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|segment| segment.len() > 0).collect()
+}
+
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+
+    let mut out = Vec::new();
+
+    for segment in split_path(pattern) {
+
+        if segment.starts_with(':') {
+            out.push(Segment::Param(segment[1..].to_owned()));
+        } else if segment.starts_with('*') {
+            out.push(Segment::Wildcard(segment[1..].to_owned()));
+            break;
+        } else {
+            out.push(Segment::Static(segment.to_owned()));
+        }
+    }
+
+    out
+}
+
+
+                                        //path - surowe (niezdekodowane) segmenty ścieżki; dekodujemy dopiero pojedyncze segmenty,
+                                        //żeby %2F czy zakodowane .. nie tworzyły nowych segmentów
+fn match_segments(segments: &[Segment], path: &[&str]) -> Option<Vec<(String, String)>> {
+
+    let mut params = Vec::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+
+        match *segment {
+
+            Segment::Static(ref value) => {
+                if index >= path.len() {
+                    return None;
+                }
+                match urlencoded::decode(path[index]) {
+                    Ok(ref decoded) if decoded == value => {},
+                    _ => return None,
+                }
+            },
+
+            Segment::Param(ref name) => {
+                if index >= path.len() {
+                    return None;
+                }
+                match urlencoded::decode(path[index]) {
+                    Ok(decoded) => params.push((name.clone(), decoded)),
+                    Err(_) => return None,
+                }
+            },
+
+                                        //po zdekodowaniu %2F nie dałoby się odróżnić od separatora
+            Segment::Wildcard(ref name) => {
+                for part in path[index..].iter() {
+                    if urlencoded::decode(part).is_err() {
+                        return None;
+                    }
+                }
+                params.push((name.clone(), path[index..].join("/")));
+                return Some(params);
+            }
+        }
+    }
+
+    if segments.len() == path.len() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+
+                                        //HEAD jest obsługiwany przez trasy GET
+fn allow_header(allowed: &[String]) -> String {
+
+    let mut methods = allowed.to_vec();
+
+    if methods.iter().any(|method| method == "GET") && methods.iter().any(|method| method == "HEAD") == false {
+        methods.push("HEAD".to_owned());
+    }
+
+    methods.join(", ")
+}
+
+
+/// Dispatches requests by method and path pattern. Patterns are matched in the order they were added,
+/// `:name` captures one decoded segment and `*name` the rest of the path, left percent-encoded so that `%2F`
+/// stays distinguishable from `/`; captured values are available from `Request::param`.
+pub struct Router {
+    routes    : Vec<Route>,
+    not_found : Option<FnReceiver>,
+}
+
+
+impl Router {
+
+    pub fn new() -> Router {
+
+        Router {
+            routes    : Vec::new(),
+            not_found : None,
+        }
+    }
+
+    pub fn add(mut self, method: &str, pattern: &str, handler: FnReceiver) -> Router {
+
+        self.routes.push(Route {
+            method   : method.to_uppercase(),
+            segments : parse_pattern(pattern),
+            handler  : handler,
+        });
+
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: FnReceiver) -> Router {
+        self.add("GET", pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: FnReceiver) -> Router {
+        self.add("POST", pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: FnReceiver) -> Router {
+        self.add("PUT", pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: FnReceiver) -> Router {
+        self.add("DELETE", pattern, handler)
+    }
+
+                                        //obsługa ścieżek do których nie pasuje żaden wzorzec (domyślnie 404)
+    pub fn not_found(mut self, handler: FnReceiver) -> Router {
+        self.not_found = Some(handler);
+        self
+    }
+
+    pub fn build(self) -> FnReceiver {
+
+        Box::new(move|request: Request| {
+            self.handle(request);
+        })
+    }
+
+    pub fn handle(&self, mut request: Request) {
+
+        let mut allowed: Vec<String> = Vec::new();
+
+        let found = {
+
+            let target = request.raw_target();
+            let raw    = match target.find('?') {
+                Some(pos) => &target[0..pos],
+                None => &target[..],
+            };
+            let path   = split_path(raw);
+            let method = request.method().to_uppercase();
+
+            let mut found = None;
+
+            for route in self.routes.iter() {
+
+                if let Some(params) = match_segments(&route.segments, &path) {
+
+                                        //HEAD obsługujemy tak jak GET
+                    if route.method == method || (method == "HEAD" && route.method == "GET") {
+                        found = Some((route, params));
+                        break;
+                    }
+
+                    if allowed.contains(&route.method) == false {
+                        allowed.push(route.method.clone());
+                    }
+                }
+            }
+
+            found
+        };
+
+        match found {
+
+            Some((route, params)) => {
+                request.set_params(params);
+                (route.handler)(request);
+            },
+
+            None if allowed.len() > 0 => {
+                let mut response = Response::create(Code::Code405, Type::TextHtml, "405 Method Not Allowed".to_owned());
+                response.add_header("Allow", &allow_header(&allowed));
+                request.send(response);
+            },
+
+            None => {
+                match self.not_found {
+                    Some(ref handler) => handler(request),
+                    None => request.send(Response::create(Code::Code404, Type::TextHtml, "404 Not Found".to_owned())),
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{split_path, parse_pattern, match_segments, allow_header};
+
+    fn matches(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        match_segments(&parse_pattern(pattern), &split_path(path))
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn static_segments() {
+        assert_eq!(matches("/users", "/users"), Some(vec![]));
+        assert_eq!(matches("/users", "/users/"), Some(vec![]));
+        assert_eq!(matches("/users", "//users"), Some(vec![]));
+        assert_eq!(matches("/users", "/user"), None);
+        assert_eq!(matches("/users", "/users/1"), None);
+        assert_eq!(matches("/users/list", "/users"), None);
+        assert_eq!(matches("/", "/"), Some(vec![]));
+    }
+
+    #[test]
+    fn static_segment_is_decoded() {
+        assert_eq!(matches("/a b", "/a%20b"), Some(vec![]));
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(matches("/users/:id", "/users/12"), Some(vec![pair("id", "12")]));
+        assert_eq!(matches("/users/:id/posts/:post", "/users/1/posts/2"), Some(vec![pair("id", "1"), pair("post", "2")]));
+        assert_eq!(matches("/users/:id", "/users"), None);
+        assert_eq!(matches("/users/:id", "/users/1/2"), None);
+    }
+
+    #[test]
+    fn encoded_slash_stays_in_param() {
+        assert_eq!(matches("/files/:name", "/files/a%2Fb"), Some(vec![pair("name", "a/b")]));
+        assert_eq!(matches("/files/:name", "/files/%2E%2E"), Some(vec![pair("name", "..")]));
+        assert_eq!(matches("/a/:x/c", "/a/b%2Fc"), None);
+    }
+
+    #[test]
+    fn invalid_utf8_param() {
+        assert_eq!(matches("/users/:id", "/users/%FF"), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(matches("/static/*path", "/static/css/site.css"), Some(vec![pair("path", "css/site.css")]));
+        assert_eq!(matches("/static/*path", "/static"), Some(vec![pair("path", "")]));
+        assert_eq!(matches("/static/*path", "/static/a%20b/c"), Some(vec![pair("path", "a%20b/c")]));
+        assert_eq!(matches("/static/*path", "/other/a"), None);
+    }
+
+    #[test]
+    fn wildcard_keeps_encoded_slash() {
+        assert_eq!(matches("/static/*path", "/static/a%2Fb/c"), Some(vec![pair("path", "a%2Fb/c")]));
+        assert_eq!(matches("/static/*path", "/static/a/%FF"), None);
+    }
+
+    #[test]
+    fn allow_includes_head() {
+
+        let methods = |list: &[&str]| list.iter().map(|method| method.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(allow_header(&methods(&["GET", "POST"])), "GET, POST, HEAD");
+        assert_eq!(allow_header(&methods(&["HEAD", "GET"])), "HEAD, GET");
+        assert_eq!(allow_header(&methods(&["PUT", "DELETE"])), "PUT, DELETE");
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::io;
 use mio::{Token, EventLoop, EventSet, PollOpt, Handler, Timeout};
-use mio::tcp::{TcpListener, TcpStream};
+use mio::tcp::TcpListener;
 //use mio::util::Slab;                 //TODO - użyć tego modułu zamiast hashmapy
 use std::mem;
 use response;
@@ -12,12 +12,29 @@ use new_socket::new_socket;
 use miostart::MioStart;
 use miodown::MioDown;
 use std::time::Duration;
+use body::BodyReader;
+use websocket::{self, WebSocket, WebSocketHandler};
+use event_stream::OpenGuard;
+use upgrade::{Upgraded, UpgradeHandler};
+use proxy::{ProxyTarget, IdleSocket};
+use client::{self, ClientRequest, ClientCallback, ClientExchange, ClientStep, Next};
+use mio::tcp::TcpStream;
+use std::net::SocketAddr;
+use stream::{Stream, TlsAcceptor};
+#[cfg(feature = "tls")]
+use tls::TlsConfig;
 
 use std::boxed::FnBox;
 
 pub type FnReceiver   = Box<Fn(Request) + Send + Sync + 'static>;
 pub type FnLog        = Box<Fn(bool, String) + Send + Sync + 'static>;
-pub type TransformOut = (Result<Connection, TcpStream>, Option<PreRequest>, LogMessage);
+                                                            //odstęp komentarzy w strumieniach text/event-stream, w milisekundach
+const HEARTBEAT_INTERVAL : u64 = 15000;
+                                                            //bezczynne połączenia z jednym serwerem docelowym proxy
+const POOL_LIMIT : usize = 16;
+
+
+pub type TransformOut = (Result<Connection, Stream>, Option<PreRequest>, LogMessage);
 
 
 // Define a handler to process the events
@@ -25,11 +42,16 @@ pub struct MyHandler {
     token           : Token,
     server          : Option<TcpListener>,                  //Some - serwer nasłuchuje, None - jest w trybie wyłączania
     hash            : HashMap<Token, (Connection, Event, Option<Timeout>)>,
+    outbound        : HashMap<Token, Token>,                //gniazda wychodzące (tunele, proxy) -> token połączenia klienta
+    pool            : HashMap<SocketAddr, Vec<(TcpStream, Token)>>,     //bezczynne połączenia z serwerami docelowymi proxy
+    pooled          : HashMap<Token, SocketAddr>,
+    clients         : HashMap<Token, (ClientExchange, Option<Timeout>)>,   //requesty Client-a w toku, po tokenie gniazda
     tokens          : TokenGen,
     timeout_reading : u64,
     timeout_writing : u64,
     fn_log          : Option<FnLog>,
     fn_receiver     : FnReceiver,
+    tls             : Option<TlsAcceptor>,                  //Some - połączenia są szyfrowane (https)
 }
 
 
@@ -40,18 +62,49 @@ pub enum Event {
     Init,
     Write,
     Read,
+    ReadWrite,
     None
 }
 
 
 pub enum MioMessage {
     Response(Token, response::Response),
+    StreamResponse(Token, u32, response::Response),         //odpowiedź na strumień HTTP/2
     Down,
     GetPost(Token, Request, Box<FnBox(Request, Option<Vec<u8>>) + Send + Sync + 'static>),
+    GetBody(Token, Request, Box<BodyReader>),
+    ReloadTls(TlsAcceptor),                                 //nowa konfiguracja dla kolejnych handshake-ów
+    Log(bool, String),                                      //komunikat z innego wątku dla fn_log, true - błąd
+    WebSocketOpen(Token, response::Response, WebSocket, Box<WebSocketHandler>),
+    WebSocket(Token, websocket::Frame),
+    EventStreamOpen(Token, Option<u32>, response::Response, OpenGuard, bool),         //bool - chunked (false dla HTTP/1.0)
+    EventStream(Token, Option<u32>, Option<Vec<u8>>),      //dane zdarzenia, None - koniec strumienia
+    UpgradeOpen(Token, response::Response, Upgraded, OpenGuard, Box<UpgradeHandler>),
+    Upgraded(Token, Option<Vec<u8>>),                       //dane dla przejętego połączenia, None - zamknięcie
+    TunnelOpen(Token, SocketAddr, response::Response, u64), //CONNECT - cel, odpowiedź po połączeniu, timeout bezczynności
+    ProxyOpen(Token, ProxyTarget),                          //reverse proxy - przekazanie requestu do serwera docelowego
+    ClientRequest(SocketAddr, ClientRequest, ClientCallback, u32),  //request wysyłany przez Client, liczba przekierowań
 }
 
 
 pub fn new_server(addres: String, timeout_reading: u64, timeout_writing:u64, fn_log: Option<FnLog>, fn_receiver : FnReceiver) -> (MioStart, MioDown) {
+    
+    create_server(addres, timeout_reading, timeout_writing, None, fn_log, fn_receiver)
+}
+
+
+/// Same as `new_server`, but every accepted connection goes through a TLS handshake first.
+/// Fails when the certificates in `tls` can't be used.
+#[cfg(feature = "tls")]
+pub fn new_tls_server(addres: String, timeout_reading: u64, timeout_writing:u64, tls: TlsConfig, fn_log: Option<FnLog>, fn_receiver : FnReceiver) -> io::Result<(MioStart, MioDown)> {
+    
+    let acceptor = try!(tls.build());
+    
+    Ok(create_server(addres, timeout_reading, timeout_writing, Some(acceptor), fn_log, fn_receiver))
+}
+
+
+fn create_server(addres: String, timeout_reading: u64, timeout_writing:u64, tls: Option<TlsAcceptor>, fn_log: Option<FnLog>, fn_receiver : FnReceiver) -> (MioStart, MioDown) {
 
     let mut event_loop = EventLoop::new().unwrap();
 
@@ -71,11 +124,16 @@ pub fn new_server(addres: String, timeout_reading: u64, timeout_writing:u64, fn_
             token           : token,
             server          : Some(server),
             hash            : HashMap::new(),
+            outbound        : HashMap::new(),
+            pool            : HashMap::new(),
+            pooled          : HashMap::new(),
+            clients         : HashMap::new(),
             tokens          : tokens,
             timeout_reading : timeout_reading,
             timeout_writing : timeout_writing,
             fn_log          : fn_log,
             fn_receiver     : fn_receiver,
+            tls             : tls,
         };
 
         event_loop.run(&mut inst).unwrap();
@@ -98,6 +156,26 @@ impl Handler for MyHandler {
             
             self.new_connection(event_loop);
             
+        } else if let Some(client) = self.outbound.get(&token).cloned() {
+            
+            let server_down = self.server.is_none();
+            
+            self.transform_connection(event_loop, &client, move|connection_prev : Connection| -> TransformOut {
+                
+                let (conn, log_message) = connection_prev.ready_outbound(server_down);
+                
+                (conn, None, log_message)
+            });
+            
+        } else if self.clients.contains_key(&token) {
+            
+            self.client_ready(event_loop, &token);
+            
+        } else if self.pooled.contains_key(&token) {
+            
+                                        //bezczynne połączenie z puli - serwer docelowy je zamknął albo przysłał coś nieoczekiwanego
+            self.drop_pooled(&token);
+            
         } else {
             
             let server_down = self.server.is_none();
@@ -123,6 +201,118 @@ impl Handler for MyHandler {
                 });
             },
             
+            MioMessage::StreamResponse(token, stream_id, response) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (new_conn, log_message) = connection_prev.send_stream_response(stream_id, response);
+                    
+                    (Ok(new_conn), None, log_message)
+                });
+            },
+            
+            MioMessage::WebSocketOpen(token, response, socket, handler) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.upgrade_websocket(response, socket, handler);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::WebSocket(token, frame) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (new_conn, log_message) = connection_prev.websocket_send(frame);
+                    
+                    (Ok(new_conn), None, log_message)
+                });
+            },
+            
+            MioMessage::EventStreamOpen(token, stream_id, response, guard, chunked) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.start_event_stream(stream_id, response, guard, chunked);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::EventStream(token, stream_id, data) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.event_stream_send(stream_id, data);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::UpgradeOpen(token, response, socket, guard, handler) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.upgrade(response, socket, guard, handler);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::Upgraded(token, data) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.upgraded_send(data);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::TunnelOpen(token, addr, response, idle_timeout) => {
+                
+                let outbound_token = self.tokens.get();
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.open_tunnel(addr, outbound_token, response, idle_timeout);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::ProxyOpen(token, target) => {
+                
+                let reused = if self.hash.contains_key(&token) {
+                    self.take_pooled(&target.addr)
+                } else {
+                    None
+                };
+                
+                                        //socket z puli jest już zarejestrowany - set_outbound go tylko przezbroi
+                let (socket, outbound_token) = match reused {
+                    Some((socket, outbound_token)) => {
+                        self.outbound.insert(outbound_token, token);
+                        (Some(socket), outbound_token)
+                    },
+                    None => (None, self.tokens.get()),
+                };
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_message) = connection_prev.open_proxy(target, outbound_token, socket);
+                    
+                    (conn, None, log_message)
+                });
+            },
+            
+            MioMessage::ClientRequest(addr, request, callback, redirects) => {
+                
+                self.client_start(event_loop, addr, request, callback, redirects);
+            },
+            
             MioMessage::Down => {
                 
                 match mem::replace(&mut self.server, None) {
@@ -131,6 +321,9 @@ impl Handler for MyHandler {
 
                         event_loop.deregister(&server).unwrap();
                         
+                        self.pool.clear();
+                        self.pooled.clear();
+                        
                         self.test_close_mio(event_loop);
                         
                     },
@@ -147,6 +340,39 @@ impl Handler for MyHandler {
                     
                     let (conn, log_mess) = connection_prev.set_callback_post(request, callback);
                     
+                    (conn, None, log_mess)
+                });
+            },
+            
+            MioMessage::Log(is_error, mess) => {
+                
+                if is_error {
+                    self.log_error(&self.token, mess);
+                } else {
+                    self.log_mess(&self.token, mess);
+                }
+            },
+            
+            MioMessage::ReloadTls(acceptor) => {
+                
+                if self.tls.is_some() {
+                    
+                                        //otwarte sesje trzymają swoją kopię starej konfiguracji
+                    self.tls = Some(acceptor);
+                    self.log_mess(&self.token, "tls config reloaded".to_owned());
+                    
+                } else {
+                    
+                    self.log_error(&self.token, "tls reload on a plain http server, ignored".to_owned());
+                }
+            },
+            
+            MioMessage::GetBody(token, request, reader) => {
+                
+                self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
+                    
+                    let (conn, log_mess) = connection_prev.set_body_reader(request, reader);
+                    
                     (conn, None, log_mess)
                 });
             }
@@ -155,10 +381,24 @@ impl Handler for MyHandler {
 
     fn timeout(&mut self, event_loop: &mut EventLoop<Self>, token: Self::Timeout) {
         
+                                                    //request Client-a nie zakończył się w czasie - gniazdo zamykane razem z wymianą
+        if let Some((exchange, _)) = self.clients.remove(&token) {
+            
+            self.log_mess(&token, "client request timeout".to_owned());
+            exchange.fail(io::Error::new(io::ErrorKind::TimedOut, "request timeout"));
+            return;
+        }
+        
+                                                    //timer już się wykonał, przy potrzebie zostanie ustawiony nowy
+        if let Some(&mut (_, _, ref mut timeout)) = self.hash.get_mut(&token) {
+            *timeout = None;
+        }
+        
+        let server_down = self.server.is_none();
         
         self.transform_connection(event_loop, &token, move|connection_prev : Connection| -> TransformOut {
 
-            let (conn, mess) = connection_prev.timeout_trigger();
+            let (conn, mess) = connection_prev.timeout_trigger(server_down);
 
             (conn, None, mess)
         });
@@ -194,9 +434,15 @@ impl MyHandler {
         }
     }
     
-    fn test_close_mio(&self, event_loop: &mut EventLoop<MyHandler>) {
+    fn test_close_mio(&mut self, event_loop: &mut EventLoop<MyHandler>) {
         
         if self.server.is_none() && self.hash.len() == 0 {
+            
+                                                    //requesty Client-a nie wstrzymują wyłączenia
+            for (_, (exchange, _)) in self.clients.drain() {
+                exchange.fail(io::Error::new(io::ErrorKind::Other, "server is shutting down"));
+            }
+            
             event_loop.shutdown();
         }
     }
@@ -239,7 +485,7 @@ impl MyHandler {
             match socket.accept() {
 
                 Ok(Some((stream, addr))) => {
-                    list.push((format!("{}", addr), Connection::new(stream)));
+                    list.push((format!("{}", addr), Connection::new(Stream::accept(stream, self.tls.as_ref()))));
                 }
                 
                 Ok(None) => {
@@ -261,19 +507,14 @@ impl MyHandler {
         
         let pool_opt = PollOpt::edge() | PollOpt::oneshot();
         
-        let new_mode = match *new_event {
-            Event::Init  => None,
-            Event::Write => Some(EventSet::error() | EventSet::hup() | EventSet::writable()),
-            Event::Read  => Some(EventSet::error() | EventSet::hup() | EventSet::readable()),
-            Event::None  => Some(EventSet::error() | EventSet::hup()),
-        };
+        let new_mode = event_set(new_event);
         
         if *old_event == Event::Init {
         
             match new_mode {
                 None => Ok(format!("register: none")),
                 Some(mode) => {
-                    try!(event_loop.register(&connection.stream, token.clone(), mode, pool_opt));
+                    try!(event_loop.register(connection.stream.tcp(), token.clone(), mode, pool_opt));
                     Ok(format!("register: {:?}", mode))
                 },
             }
@@ -283,7 +524,7 @@ impl MyHandler {
             match new_mode {
                 None => Ok(format!("reregister: none")),
                 Some(mode) => {
-                    try!(event_loop.reregister(&connection.stream, token.clone(), mode, pool_opt));
+                    try!(event_loop.reregister(connection.stream.tcp(), token.clone(), mode, pool_opt));
                     Ok(format!("reregister: {:?}", mode))
                 },
             }
@@ -291,6 +532,194 @@ impl MyHandler {
     }
 
     
+                                        //gniazdo wychodzące ma własny token, jego zdarzenia trafiają do połączenia klienta
+    fn set_outbound(&mut self, connection: &Connection, token: &Token, event_loop: &mut EventLoop<MyHandler>) -> Result<String, io::Error> {
+        
+        let pool_opt = PollOpt::edge() | PollOpt::oneshot();
+        
+        match connection.outbound() {
+            
+            Some((socket, outbound_token, event)) => {
+                
+                let mode = match event_set(&event) {
+                    Some(mode) => mode,
+                    None => EventSet::error() | EventSet::hup(),
+                };
+                
+                if self.outbound.contains_key(&outbound_token) {
+                                        //proxy mogło wymienić gniazdo (ponowne połączenie) - nowe trzeba zarejestrować
+                    if event_loop.reregister(socket, outbound_token, mode, pool_opt).is_err() {
+                        try!(event_loop.register(socket, outbound_token, mode, pool_opt));
+                    }
+                    Ok(format!(", outbound reregister: {:?}", mode))
+                } else {
+                    try!(event_loop.register(socket, outbound_token, mode, pool_opt));
+                    self.outbound.insert(outbound_token, token.clone());
+                    Ok(format!(", outbound register: {:?}", mode))
+                }
+            },
+            
+                                        //socket wychodzący zamknięty razem z trybem połączenia
+            None => {
+                self.outbound.retain(|_, client| *client != *token);
+                Ok("".to_owned())
+            }
+        }
+    }
+    
+                                        //połączenie czeka w puli tylko na zamknięcie przez serwer docelowy
+    fn pool_socket(&mut self, idle: IdleSocket, event_loop: &mut EventLoop<MyHandler>) {
+        
+        let IdleSocket { addr, socket, token } = idle;
+        
+        let count = self.pool.get(&addr).map(|list| list.len()).unwrap_or(0);
+        
+        if self.server.is_none() || count >= POOL_LIMIT {
+            return;
+        }
+        
+        let mode = EventSet::error() | EventSet::hup() | EventSet::readable();
+        
+        if let Err(err) = event_loop.reregister(&socket, token, mode, PollOpt::edge() | PollOpt::oneshot()) {
+            self.log_error(&token, format!("pool socket: {}", err));
+            return;
+        }
+        
+        self.log_mess(&token, format!("upstream connection pooled, addr = {}", addr));
+        
+        self.pooled.insert(token, addr);
+        self.pool.entry(addr).or_insert_with(Vec::new).push((socket, token));
+    }
+    
+                                        //ostatnio oddane połączenie jest najmniej narażone na zamknięcie przez serwer docelowy
+    fn take_pooled(&mut self, addr: &SocketAddr) -> Option<(TcpStream, Token)> {
+        
+        let pooled = match self.pool.get_mut(addr) {
+            Some(list) => list.pop(),
+            None => None,
+        };
+        
+        if let Some((_, ref token)) = pooled {
+            self.pooled.remove(token);
+        }
+        
+        pooled
+    }
+    
+    fn drop_pooled(&mut self, token: &Token) {
+        
+        if let Some(addr) = self.pooled.remove(token) {
+            
+            if let Some(list) = self.pool.get_mut(&addr) {
+                list.retain(|&(_, ref pooled)| *pooled != *token);
+            }
+            
+            self.log_mess(token, format!("upstream connection closed, addr = {}", addr));
+        }
+    }
+    
+                                        //socket z puli, jeśli jest, inaczej nowe połączenie
+    fn client_start(&mut self, event_loop: &mut EventLoop<MyHandler>, addr: SocketAddr, request: ClientRequest, callback: ClientCallback, redirects: u32) {
+        
+        let (socket, token, reused) = match self.take_pooled(&addr) {
+            
+            Some((socket, token)) => (socket, token, true),
+            
+            None => match TcpStream::connect(&addr) {
+                Ok(socket) => (socket, self.tokens.get(), false),
+                Err(err) => {
+                    client::call(callback, Err(err));
+                    return;
+                }
+            },
+        };
+        
+        let exchange = ClientExchange::new(addr, request, callback, redirects, socket, token, reused);
+        
+        let timeout = match event_loop.timeout(token, Duration::from_millis(exchange.timeout())) {
+            Ok(timeout) => Some(timeout),
+            Err(err) => {
+                self.log_error(&token, format!("client timer error {:?}", err));
+                None
+            }
+        };
+        
+        self.log_mess(&token, format!("client request to {}, reused = {}", addr, reused));
+        
+        self.client_insert(event_loop, &token, exchange, timeout);
+    }
+    
+    fn client_insert(&mut self, event_loop: &mut EventLoop<MyHandler>, token: &Token, exchange: ClientExchange, timeout: Option<Timeout>) {
+        
+        let pool_opt = PollOpt::edge() | PollOpt::oneshot();
+        
+        let mode = match event_set(&exchange.get_event()) {
+            Some(mode) => mode,
+            None => EventSet::error() | EventSet::hup(),
+        };
+        
+                                        //nowe gniazdo (także po ponownym połączeniu) nie jest jeszcze zarejestrowane
+        let result = match event_loop.reregister(exchange.socket(), token.clone(), mode, pool_opt) {
+            Ok(()) => Ok(()),
+            Err(_) => event_loop.register(exchange.socket(), token.clone(), mode, pool_opt),
+        };
+        
+        if let Err(err) = result {
+            
+            if let Some(ref timeout_value) = timeout {
+                let _ = event_loop.clear_timeout(timeout_value);
+            }
+            
+            exchange.fail(err);
+            return;
+        }
+        
+        self.clients.insert(token.clone(), (exchange, timeout));
+    }
+    
+    fn client_ready(&mut self, event_loop: &mut EventLoop<MyHandler>, token: &Token) {
+        
+        let (mut exchange, timeout) = match self.clients.remove(token) {
+            Some(client) => client,
+            None => return,
+        };
+        
+        let step = exchange.pump();
+        
+        if let ClientStep::Pending = step {
+            self.client_insert(event_loop, token, exchange, timeout);
+            return;
+        }
+        
+        if let Some(ref timeout_value) = timeout {
+            let _ = event_loop.clear_timeout(timeout_value);
+        }
+        
+        match step {
+            
+            ClientStep::Failed(err) => {
+                
+                self.log_error(token, format!("client request failed, {}", err));
+                exchange.fail(err);
+            },
+            
+            _ => {
+                
+                let (next, idle) = exchange.finish();
+                
+                if let Some(idle) = idle {
+                    self.pool_socket(idle, event_loop);
+                }
+                
+                match next {
+                    Next::Deliver(callback, result) => client::call(callback, result),
+                    Next::Redirect(addr, request, callback, redirects) => self.client_start(event_loop, addr, request, callback, redirects),
+                    Next::Resolve(request, callback, redirects) => client::start(event_loop.channel(), request, callback, redirects),
+                }
+            },
+        }
+    }
+    
     fn set_timer(&mut self, token: &Token, timeout: Option<Timeout>, timer_mode: TimerMode, event_loop: &mut EventLoop<MyHandler>) -> (Option<Timeout>, String) {
         
         match timeout {
@@ -302,6 +731,8 @@ impl MyHandler {
                     TimerMode::In   => (Some(timeout), "keep".to_owned()),
                     TimerMode::Out  => (Some(timeout), "keep".to_owned()),
                     TimerMode::Post => (Some(timeout), "keep".to_owned()),
+                    TimerMode::Heartbeat => (Some(timeout), "keep".to_owned()),
+                    TimerMode::Idle(_) => (Some(timeout), "keep".to_owned()),
                     TimerMode::None => {
                         let _ = event_loop.clear_timeout(&timeout);
                         (None, "clear".to_owned())
@@ -342,6 +773,24 @@ impl MyHandler {
                         }
                     },
                     
+                    TimerMode::Heartbeat => {
+                        
+                        match event_loop.timeout(token.clone(), Duration::from_millis(HEARTBEAT_INTERVAL)) {
+                            
+                            Ok(timeout) => (Some(timeout), "set HEARTBEAT".to_owned()),
+                            Err(err)    => (None , format!("error HEARTBEAT {:?}", err)),
+                        }
+                    },
+                    
+                    TimerMode::Idle(idle) => {
+                        
+                        match event_loop.timeout(token.clone(), Duration::from_millis(idle)) {
+                            
+                            Ok(timeout) => (Some(timeout), "set IDLE".to_owned()),
+                            Err(err)    => (None , format!("error IDLE {:?}", err)),
+                        }
+                    },
+                    
                     TimerMode::None => (None, "none".to_owned()),
                 }
             },
@@ -352,29 +801,34 @@ impl MyHandler {
 
         let new_event = connection.get_event();
         
-        let mess_event = if old_event != new_event {
-            match self.set_event(&connection, token, &old_event, &new_event, event_loop) {
-                Ok(str) => str,
-                Err(err) => {
-                    self.log_error(token, format!("set_event: {}", err));
-                    return;
-                }
+                                        //oneshot - po każdym zdarzeniu socket trzeba uzbroić ponownie, także gdy tryb się nie zmienił
+        let mess_event = match self.set_event(&connection, token, &old_event, &new_event, event_loop) {
+            Ok(str) => str,
+            Err(err) => {
+                self.log_error(token, format!("set_event: {}", err));
+                return;
+            }
+        };
+        
+        let mess_outbound = match self.set_outbound(&connection, token, event_loop) {
+            Ok(str) => str,
+            Err(err) => {
+                self.log_error(token, format!("set_outbound: {}", err));
+                return;
             }
-        } else {
-            "none".to_owned()
         };
         
         
         let (new_timer, timer_message) = self.set_timer(token, timeout, connection.get_timer_mode(), event_loop);
         
         
-        self.log_mess(token, format!("set mode {}, {}, timer {}", connection.get_name(), mess_event, timer_message));
+        self.log_mess(token, format!("set mode {}, {}{}, timer {}", connection.get_name(), mess_event, mess_outbound, timer_message));
         
         self.hash.insert(token.clone(), (connection, new_event, new_timer));
     }
     
     fn transform_connection<F>(&mut self, event_loop: &mut EventLoop<MyHandler>, token: &Token, process: F)
-        where F : FnOnce(Connection) -> (Result<Connection, TcpStream>, Option<PreRequest>, LogMessage) {
+        where F : FnOnce(Connection) -> (Result<Connection, Stream>, Option<PreRequest>, LogMessage) {
         
         let res = self.hash.remove(&token);
         
@@ -392,23 +846,40 @@ impl MyHandler {
                 }
                 
                 
+                let mut buffered = false;
+                let mut requests = Vec::new();
+                
                 match conenction_opt {
                     
                     Ok(connection_new) => {
                         
                                         //sprawdź czy dane z posta są kompletne
-                        let connection_new = connection_new.check_post();
+                        let mut connection_new = connection_new.check_post();
+                        
+                        requests = connection_new.take_requests();
+                        buffered = connection_new.has_buffered_input();
+                        
+                        let released = connection_new.take_released();
                         
                         self.insert_connection(&token, connection_new, old_event, timeout, event_loop);
+                        
+                                        //insert_connection zdjął już mapowanie gniazda wychodzącego na klienta
+                        if let Some(idle) = released {
+                            self.pool_socket(idle, event_loop);
+                        }
                     },
                     
-                    Err(stream) => {
+                    Err(mut stream) => {
                         
                         if let Some(ref timeout_value) = timeout {
                             let _ = event_loop.clear_timeout(timeout_value);
                         }
                         
-                        event_loop.deregister(&stream).unwrap();
+                        stream.shutdown();
+                        
+                        event_loop.deregister(stream.tcp()).unwrap();
+                        
+                        self.outbound.retain(|_, client| *client != *token);
                         
                         self.log_mess(token, "close connection".to_owned());
                     }
@@ -421,6 +892,26 @@ impl MyHandler {
                     let request  = pre_request.bind(token.clone(), event_loop.channel());
 
                     (self.fn_receiver)(request);
+                }
+                
+                                        //HTTP/2 - requesty z wielu strumieni naraz
+                for pre_request in requests {
+                    
+                    let request = pre_request.bind(token.clone(), event_loop.channel());
+                    
+                    (self.fn_receiver)(request);
+                }
+                
+                                        //odszyfrowane dane z sesji TLS - epoll ich nie zgłosi, więc czytamy od razu
+                if buffered {
+                    
+                    let server_down = self.server.is_none();
+                    
+                    self.transform_connection(event_loop, token, move|connection_prev : Connection| -> TransformOut {
+                        connection_prev.ready(EventSet::readable(), server_down)
+                    });
+                    
+                    return;
                 }
             },
             
@@ -434,3 +925,14 @@ impl MyHandler {
     }
 }
 
+
+fn event_set(event: &Event) -> Option<EventSet> {
+    
+    match *event {
+        Event::Init      => None,
+        Event::Write     => Some(EventSet::error() | EventSet::hup() | EventSet::writable()),
+        Event::Read      => Some(EventSet::error() | EventSet::hup() | EventSet::readable()),
+        Event::ReadWrite => Some(EventSet::error() | EventSet::hup() | EventSet::readable() | EventSet::writable()),
+        Event::None      => Some(EventSet::error() | EventSet::hup()),
+    }
+}
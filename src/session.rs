@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crypto::hmac::Hmac;
+use crypto::sha2::Sha256;
+use crypto::mac::Mac;
+use crypto::aes::{self, KeySize};
+use crypto::util::fixed_time_eq;
+use rand::{Rng, OsRng};
+use rustc_serialize::base64::{ToBase64, FromBase64, URL_SAFE};
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json;
+use cookie::{Cookie, SameSite};
+use request::Request;
+use response::Response;
+use server::FnReceiver;
+
+
+const MAX_COOKIE_SIZE : usize = 4000;
+
+
+fn now() -> u64 {
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+
+fn random_bytes(len: usize) -> io::Result<Vec<u8>> {
+
+    let mut out = vec![0u8; len];
+
+    let mut rng = try!(OsRng::new());
+    rng.fill_bytes(&mut out);
+
+    Ok(out)
+}
+
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+
+/// Key-value data of one client, available through `Request::session` once a `SessionManager` wraps the receiver.
+pub struct Session {
+    id        : Option<String>,             //identyfikator w magazynie po stronie serwera
+    data      : HashMap<String, String>,
+    expires   : u64,                        //0 - sesja nowa, jeszcze bez ciastka
+    changed   : bool,
+    renew     : bool,                       //ciastko trzeba wystawić ponownie (np. podpisane starym kluczem)
+    destroyed : bool,
+}
+
+
+impl Session {
+
+    pub fn new() -> Session {
+        Session::restore(None, HashMap::new(), 0, false)
+    }
+
+                                        //dla magazynów - sesja odtworzona z ciastka lub z pamięci
+    pub fn restore(id: Option<String>, data: HashMap<String, String>, expires: u64, renew: bool) -> Session {
+
+        Session {
+            id        : id,
+            data      : data,
+            expires   : expires,
+            changed   : false,
+            renew     : renew,
+            destroyed : false,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_owned(), value.to_owned());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.changed = true;
+        self.data.remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.changed = true;
+    }
+
+                                        //usuwa sesję z magazynu i ciastko z przeglądarki
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+
+    pub fn id(&self) -> Option<&String> {
+        self.id.as_ref()
+    }
+
+    pub fn data(&self) -> &HashMap<String, String> {
+        &(self.data)
+    }
+
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+
+/// Where session data lives between requests. `load` gets the value of the session cookie, `save` returns the new one,
+/// or `None` when the session could not be stored (the response then goes without a cookie).
+pub trait SessionStore: Send + Sync {
+
+    fn load(&self, cookie_value: &str) -> Option<Session>;
+
+    fn save(&self, session: &Session, expires: u64) -> Option<String>;
+
+    fn destroy(&self, session: &Session);
+}
+
+
+struct CookieKey {
+    sign    : Vec<u8>,
+    encrypt : Vec<u8>,
+}
+
+impl CookieKey {
+
+    fn new(secret: &[u8]) -> Result<CookieKey, Error> {
+
+        if secret.len() < 32 {
+            return Err(Error::new(ErrorKind::InvalidInput, "session secret must have at least 32 bytes"));
+        }
+
+        Ok(CookieKey {
+            sign    : hmac_sha256(secret, b"miohttp session sign"),
+            encrypt : hmac_sha256(secret, b"miohttp session encrypt"),
+        })
+    }
+}
+
+
+/// Keeps the whole session in the cookie, signed with HMAC-SHA256 and optionally encrypted with AES-256-CTR.
+/// The first key signs new cookies, the ones added with `old_key` are only accepted, which allows rotating the secret.
+/// Secrets shorter than 32 bytes are rejected with `InvalidInput`.
+pub struct CookieStore {
+    keys    : Vec<CookieKey>,
+    encrypt : bool,
+}
+
+
+impl CookieStore {
+
+    pub fn new(secret: &[u8]) -> Result<CookieStore, Error> {
+
+        Ok(CookieStore {
+            keys    : vec![try!(CookieKey::new(secret))],
+            encrypt : false,
+        })
+    }
+
+    pub fn old_key(mut self, secret: &[u8]) -> Result<CookieStore, Error> {
+        self.keys.push(try!(CookieKey::new(secret)));
+        Ok(self)
+    }
+
+    pub fn encrypted(mut self, encrypt: bool) -> CookieStore {
+        self.encrypt = encrypt;
+        self
+    }
+
+    fn open(&self, key: &CookieKey, payload: &[u8]) -> Option<(u64, HashMap<String, String>)> {
+
+        let body = match payload.first() {
+
+            Some(&b'p') => payload[1..].to_vec(),
+
+            Some(&b'e') if payload.len() >= 17 => {
+
+                let mut cipher = aes::ctr(KeySize::KeySize256, &key.encrypt, &payload[1..17]);
+                let mut out    = vec![0u8; payload.len() - 17];
+                cipher.process(&payload[17..], &mut out);
+                out
+            },
+
+            _ => return None,
+        };
+
+        let body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return None,
+        };
+
+        match json::decode(&body) {
+            Ok(decoded) => Some(decoded),
+            Err(_) => None,
+        }
+    }
+}
+
+
+impl SessionStore for CookieStore {
+
+    fn load(&self, cookie_value: &str) -> Option<Session> {
+
+        let pos = match cookie_value.find('.') {
+            Some(pos) => pos,
+            None => return None,
+        };
+
+        let (payload_b64, mac_b64) = (&cookie_value[0..pos], &cookie_value[pos + 1..]);
+
+        let mac = match mac_b64.from_base64() {
+            Ok(mac) => mac,
+            Err(_) => return None,
+        };
+
+        for (index, key) in self.keys.iter().enumerate() {
+
+            if fixed_time_eq(&hmac_sha256(&key.sign, payload_b64.as_bytes()), &mac) == false {
+                continue;
+            }
+
+            let payload = match payload_b64.from_base64() {
+                Ok(payload) => payload,
+                Err(_) => return None,
+            };
+
+            return match self.open(key, &payload) {
+                Some((expires, data)) => {
+                    if expires > now() {
+                        Some(Session::restore(None, data, expires, index > 0))
+                    } else {
+                        None
+                    }
+                },
+                None => None,
+            };
+        }
+
+        None
+    }
+
+    fn save(&self, session: &Session, expires: u64) -> Option<String> {
+
+        let body = match json::encode(&(expires, &session.data)) {
+            Ok(body) => body,
+            Err(_) => return None,
+        };
+
+        let key = &self.keys[0];
+
+        let payload = if self.encrypt {
+
+            let iv = match random_bytes(16) {
+                Ok(iv) => iv,
+                Err(_) => return None,
+            };
+
+            let mut cipher = aes::ctr(KeySize::KeySize256, &key.encrypt, &iv);
+            let mut out    = vec![0u8; body.len()];
+            cipher.process(body.as_bytes(), &mut out);
+
+            let mut payload = vec![b'e'];
+            payload.extend_from_slice(&iv);
+            payload.extend_from_slice(&out);
+            payload
+
+        } else {
+
+            let mut payload = vec![b'p'];
+            payload.extend_from_slice(body.as_bytes());
+            payload
+        };
+
+        let payload_b64 = payload.to_base64(URL_SAFE);
+        let mac         = hmac_sha256(&key.sign, payload_b64.as_bytes());
+        let value       = payload_b64 + "." + &mac.to_base64(URL_SAFE);
+
+                                        //przeglądarki odrzucają zbyt duże ciastka
+        if value.len() > MAX_COOKIE_SIZE {
+            return None;
+        }
+
+        Some(value)
+    }
+
+    fn destroy(&self, _session: &Session) {
+    }
+}
+
+
+/// Keeps sessions in process memory, the cookie only carries a random identifier.
+pub struct MemoryStore {
+    sessions : Mutex<HashMap<String, (HashMap<String, String>, u64)>>,
+}
+
+
+impl MemoryStore {
+
+    pub fn new() -> MemoryStore {
+
+        MemoryStore {
+            sessions : Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+
+impl SessionStore for MemoryStore {
+
+    fn load(&self, cookie_value: &str) -> Option<Session> {
+
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let expired = match sessions.get(cookie_value) {
+            Some(&(ref data, expires)) => {
+                if expires > now() {
+                    return Some(Session::restore(Some(cookie_value.to_owned()), data.clone(), expires, false));
+                }
+                true
+            },
+            None => false,
+        };
+
+        if expired {
+            sessions.remove(cookie_value);
+        }
+
+        None
+    }
+
+    fn save(&self, session: &Session, expires: u64) -> Option<String> {
+
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let id = match session.id {
+            Some(ref id) => id.clone(),
+            None => {
+                                        //nowa sesja - przy okazji sprzątamy wygasłe
+                let current = now();
+                sessions.retain(|_, &mut (_, expires)| expires > current);
+
+                match random_bytes(32) {
+                    Ok(id) => id.to_hex(),
+                    Err(_) => return None,
+                }
+            }
+        };
+
+        sessions.insert(id.clone(), (session.data.clone(), expires));
+
+        Some(id)
+    }
+
+    fn destroy(&self, session: &Session) {
+
+        if let Some(ref id) = session.id {
+            self.sessions.lock().unwrap().remove(id);
+        }
+    }
+}
+
+
+/// Loads the session before the receiver runs and writes it back (setting the cookie) when the request is sent.
+/// A request dropped without a response gets an automatic 500 and its session is not written back: changes made
+/// by a handler that failed halfway are discarded and the client keeps the previous session.
+pub struct SessionManager {
+    store       : Box<SessionStore>,
+    cookie_name : String,
+    max_age     : u64,
+    path        : String,
+    domain      : Option<String>,
+    secure      : bool,
+    same_site   : SameSite,
+}
+
+
+impl SessionManager {
+
+    pub fn new(store: Box<SessionStore>) -> SessionManager {
+
+        SessionManager {
+            store       : store,
+            cookie_name : "miohttp_session".to_owned(),
+            max_age     : 24 * 3600,
+            path        : "/".to_owned(),
+            domain      : None,
+            secure      : false,
+            same_site   : SameSite::Lax,
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> SessionManager {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+                                        //czas życia sesji w sekundach, liczony od ostatniego zapisu
+    pub fn max_age(mut self, seconds: u64) -> SessionManager {
+        self.max_age = seconds;
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> SessionManager {
+        self.path = path.to_owned();
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> SessionManager {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> SessionManager {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> SessionManager {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn wrap(self, fn_receiver: FnReceiver) -> FnReceiver {
+
+        let manager = Arc::new(self);
+
+        Box::new(move|mut request: Request| {
+
+            let session = manager.load(&request);
+
+            request.set_session(session, manager.clone());
+
+            fn_receiver(request);
+        })
+    }
+
+    fn load(&self, request: &Request) -> Session {
+
+        let loaded = match request.cookie(&self.cookie_name) {
+            Some(value) => self.store.load(value),
+            None => None,
+        };
+
+        match loaded {
+            Some(session) => session,
+            None => Session::new(),
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+
+        let cookie = Cookie::new(&self.cookie_name, value)
+            .path(&self.path)
+            .max_age(self.max_age)
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site);
+
+        match self.domain {
+            Some(ref domain) => cookie.domain(domain),
+            None => cookie,
+        }
+    }
+
+                                        //wywoływane z Request::send
+    pub fn finish(&self, session: Session, response: &mut Response) {
+
+        if session.destroyed {
+
+            self.store.destroy(&session);
+
+            if session.expires > 0 {
+                                        //przeglądarka usunie ciastko tylko gdy Path i Domain się zgadzają
+                let removal = Cookie::removal(&self.cookie_name).path(&self.path);
+
+                response.set_cookie(&match self.domain {
+                    Some(ref domain) => removal.domain(domain),
+                    None => removal,
+                });
+            }
+
+            return;
+        }
+
+        let current = now();
+
+        let expiring = session.expires > 0 && session.expires < current + self.max_age / 2;
+
+        if session.changed == false && session.renew == false && expiring == false {
+            return;
+        }
+
+        if let Some(value) = self.store.save(&session, current + self.max_age) {
+            response.set_cookie(&self.cookie(&value));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+    use rustc_serialize::base64::{ToBase64, FromBase64, URL_SAFE};
+    use code::Code;
+    use typemod::Type;
+    use response::Response;
+    use super::{Session, SessionStore, CookieStore, MemoryStore, SessionManager, now};
+
+    const KEY_A : &'static [u8] = b"0123456789abcdef0123456789abcdef";
+    const KEY_B : &'static [u8] = b"fedcba9876543210fedcba9876543210";
+
+    fn session(key: &str, value: &str) -> Session {
+        let mut session = Session::new();
+        session.set(key, value);
+        session
+    }
+
+                                        //podmienia jeden znak base64, nie psując kodowania
+    fn tamper(value: &str, index: usize) -> String {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes[index] = if bytes[index] == b'A' { b'B' } else { b'A' };
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn short_secret_rejected() {
+        assert!(CookieStore::new(b"short").is_err());
+        assert!(CookieStore::new(KEY_A).unwrap().old_key(b"short").is_err());
+    }
+
+    #[test]
+    fn cookie_signed() {
+
+        let store = CookieStore::new(KEY_A).unwrap();
+        let value = store.save(&session("user", "alice"), now() + 60).unwrap();
+
+        let loaded = store.load(&value).unwrap();
+        assert_eq!(loaded.get("user"), Some(&"alice".to_owned()));
+        assert_eq!(loaded.renew, false);
+
+        let dot = value.find('.').unwrap();
+
+        assert!(store.load(&tamper(&value, 2)).is_none());
+        assert!(store.load(&tamper(&value, dot + 2)).is_none());
+        assert!(store.load(&value[0..dot]).is_none());
+        assert!(CookieStore::new(KEY_B).unwrap().load(&value).is_none());
+
+                                        //podpis obejmuje tekst base64, więc zmieniona treść z poprawnym kodowaniem też odpada
+        let payload = value[0..dot].from_base64().unwrap();
+        let forged  = String::from_utf8(payload).unwrap().replace("alice", "admin");
+        assert!(store.load(&(forged.as_bytes().to_base64(URL_SAFE) + &value[dot..])).is_none());
+    }
+
+    #[test]
+    fn encrypted_roundtrip() {
+
+        let store = CookieStore::new(KEY_A).unwrap().encrypted(true);
+        let value = store.save(&session("card", "4111-1111"), now() + 60).unwrap();
+
+        let payload = value[0..value.find('.').unwrap()].from_base64().unwrap();
+        assert_eq!(payload[0], b'e');
+        assert!(String::from_utf8_lossy(&payload).contains("4111") == false);
+
+        assert_eq!(store.load(&value).unwrap().get("card"), Some(&"4111-1111".to_owned()));
+
+                                        //każdy zapis ma nowy wektor IV
+        assert!(store.save(&session("card", "4111-1111"), now() + 60).unwrap() != value);
+
+        assert!(store.load(&tamper(&value, 20)).is_none());
+    }
+
+    #[test]
+    fn expired_cookie() {
+
+        let store = CookieStore::new(KEY_A).unwrap();
+
+        let value = store.save(&session("user", "alice"), now() - 1).unwrap();
+        assert!(store.load(&value).is_none());
+
+        let value = store.save(&session("user", "alice"), now() + 60).unwrap();
+        assert!(store.load(&value).is_some());
+    }
+
+    #[test]
+    fn key_rotation() {
+
+        let old     = CookieStore::new(KEY_A).unwrap();
+        let rotated = CookieStore::new(KEY_B).unwrap().old_key(KEY_A).unwrap();
+
+        let old_value = old.save(&session("user", "alice"), now() + 60).unwrap();
+
+                                        //ciastko podpisane starym kluczem jest przyjmowane, ale wystawiane ponownie
+        let loaded = rotated.load(&old_value).unwrap();
+        assert_eq!(loaded.get("user"), Some(&"alice".to_owned()));
+        assert!(loaded.renew);
+
+        let manager  = SessionManager::new(Box::new(CookieStore::new(KEY_B).unwrap().old_key(KEY_A).unwrap()));
+        let mut response = Response::create(Code::Code200, Type::TextHtml, "ok".to_owned());
+
+        manager.finish(loaded, &mut response);
+
+        let header    = response.header("Set-Cookie").unwrap().clone();
+        let new_value = &header["miohttp_session=".len()..header.find(';').unwrap()];
+
+        assert!(CookieStore::new(KEY_B).unwrap().load(new_value).is_some());
+        assert!(old.load(new_value).is_none());
+        assert_eq!(rotated.load(new_value).unwrap().renew, false);
+    }
+
+    #[test]
+    fn unchanged_session_not_saved() {
+
+        let store   = CookieStore::new(KEY_A).unwrap();
+        let value   = store.save(&session("user", "alice"), now() + 24 * 3600).unwrap();
+        let manager = SessionManager::new(Box::new(CookieStore::new(KEY_A).unwrap()));
+
+        let mut response = Response::create(Code::Code200, Type::TextHtml, "ok".to_owned());
+        manager.finish(store.load(&value).unwrap(), &mut response);
+
+        assert!(response.header("Set-Cookie").is_none());
+    }
+
+    #[test]
+    fn memory_store() {
+
+        let store = MemoryStore::new();
+
+        let id = store.save(&session("user", "alice"), now() + 60).unwrap();
+        assert_eq!(id.len(), 64);
+
+        let mut loaded = store.load(&id).unwrap();
+        assert_eq!(loaded.id(), Some(&id));
+        assert_eq!(loaded.get("user"), Some(&"alice".to_owned()));
+
+        loaded.set("user", "bob");
+        assert_eq!(store.save(&loaded, now() + 60), Some(id.clone()));
+        assert_eq!(store.load(&id).unwrap().get("user"), Some(&"bob".to_owned()));
+
+        store.destroy(&loaded);
+        assert!(store.load(&id).is_none());
+    }
+
+    #[test]
+    fn memory_store_eviction() {
+
+        let store = MemoryStore::new();
+        let live  = store.save(&session("c", "3"), now() + 60).unwrap();
+
+        {
+            let mut sessions = store.sessions.lock().unwrap();
+            sessions.insert("expired".to_owned(), (HashMap::new(), now() - 1));
+            sessions.insert("stale".to_owned(), (HashMap::new(), now() - 1));
+        }
+
+                                        //wygasła sesja znika przy odczycie
+        assert!(store.load("expired").is_none());
+        assert_eq!(store.sessions.lock().unwrap().len(), 2);
+
+                                        //zapis nowej sesji sprząta pozostałe wygasłe
+        store.save(&session("d", "4"), now() + 60).unwrap();
+
+        let sessions = store.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.contains_key("stale") == false);
+        assert!(sessions.contains_key(&live));
+    }
+}
@@ -0,0 +1,481 @@
+use std::io;
+use std::io::ErrorKind;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use server::FnReceiver;
+use urlencoded;
+use compression::{self, Encoding};
+use listing;
+use file_cache::FileCache;
+use libc;
+
+
+/// Serves files from a directory. The request path is mapped below the root only if it stays there:
+/// `..` segments, NUL bytes and symlinks pointing outside of the root are refused.
+pub struct StaticFiles {
+    root          : PathBuf,
+    prefix        : String,
+    index         : Vec<String>,
+    precompressed : bool,
+    listing       : bool,
+    json_listing  : bool,
+    show_hidden   : bool,
+    cache         : Option<FileCache>,
+}
+
+
+pub enum Resolved {
+    File(PathBuf, fs::Metadata),
+    Directory(PathBuf),
+    Redirect(String),                   //katalog bez ukośnika na końcu
+}
+
+
+fn error_response(code: Code) -> Response {
+    Response::create(code, Type::TextHtml, code.to_str().to_owned())
+}
+
+
+impl StaticFiles {
+
+    pub fn new(root: &Path) -> io::Result<StaticFiles> {
+
+        Ok(StaticFiles {
+            root          : try!(fs::canonicalize(root)),
+            prefix        : "".to_owned(),
+            index         : vec!["index.html".to_owned()],
+            precompressed : false,
+            listing       : false,
+            json_listing  : false,
+            show_hidden   : false,
+            cache         : None,
+        })
+    }
+
+                                        //część ścieżki requestu obcinana przed mapowaniem na katalog, np. "/static"
+    pub fn prefix(mut self, prefix: &str) -> StaticFiles {
+        self.prefix = prefix.trim_right_matches('/').to_owned();
+        self
+    }
+
+                                        //pliki szukane w katalogu, w podanej kolejności (domyślnie index.html)
+    pub fn index(mut self, names: Vec<&str>) -> StaticFiles {
+        self.index = names.iter().map(|name| (*name).to_owned()).collect();
+        self
+    }
+
+                                        //plik.gz obok pliku wysyłany zamiast niego, gdy klient akceptuje gzip
+    pub fn precompressed(mut self, precompressed: bool) -> StaticFiles {
+        self.precompressed = precompressed;
+        self
+    }
+
+                                        //lista plików dla katalogu bez pliku index (domyślnie 403)
+    pub fn listing(mut self, listing: bool) -> StaticFiles {
+        self.listing = listing;
+        self
+    }
+
+                                        //lista także jako JSON, dla ?format=json lub Accept: application/json
+    pub fn json_listing(mut self, json_listing: bool) -> StaticFiles {
+        self.json_listing = json_listing;
+        self
+    }
+
+                                        //czy pokazywać na liście pliki zaczynające się od kropki
+    pub fn show_hidden(mut self, show_hidden: bool) -> StaticFiles {
+        self.show_hidden = show_hidden;
+        self
+    }
+
+                                        //małe pliki trzymane w pamięci zamiast czytania z dysku przy każdym requeście
+    pub fn cache(mut self, cache: FileCache) -> StaticFiles {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &(self.root)
+    }
+
+    pub fn build(self) -> FnReceiver {
+
+        Box::new(move|request: Request| {
+            self.handle(request);
+        })
+    }
+
+    pub fn handle(&self, request: Request) {
+
+        if request.method() != "GET" && request.method() != "HEAD" {
+            let mut response = error_response(Code::Code405);
+            response.add_header("Allow", "GET, HEAD");
+            request.send(response);
+            return;
+        }
+
+        let resolved = self.resolve(request.path());
+
+        let response = match resolved {
+
+            Ok(Resolved::File(path, metadata)) => {
+                match self.open_file(&request, &path, &metadata) {
+                    Ok(response) => response,
+                    Err(err) => error_response(error_code(&err)),
+                }
+            },
+
+            Ok(Resolved::Directory(ref path)) if self.listing => {
+                match listing::read(path, &self.root, self.show_hidden) {
+                    Ok(list) => {
+                        let mut response = listing::render(&request, list, *path == self.root, self.json_listing);
+                        if self.json_listing {
+                            response.add_vary("Accept");
+                        }
+                        response
+                    },
+                    Err(err) => error_response(error_code(&err)),
+                }
+            },
+
+            Ok(Resolved::Directory(_)) => {
+                error_response(Code::Code403)
+            },
+
+            Ok(Resolved::Redirect(location)) => {
+                let mut response = Response::create(Code::Code301, Type::TextHtml, "301 Moved Permanently".to_owned());
+                response.add_header("Location", &location);
+                response
+            },
+
+            Err(code) => {
+                error_response(code)
+            }
+        };
+
+        request.send(response);
+    }
+
+    fn open_file(&self, request: &Request, path: &Path, metadata: &fs::Metadata) -> io::Result<Response> {
+
+        let typ  = Type::create_from_path(path);
+        let gzip = request.header("Accept-Encoding").map(|header| compression::accepts(header, Encoding::Gzip)) == Some(true);
+
+        if self.precompressed {
+
+            if let Some(gz_path) = self.gz_sibling(path) {
+
+                if gzip {
+
+                    if let Ok(file) = File::open(&gz_path) {
+
+                        let mut response = try!(Response::create_from_file(Code::Code200, typ, file));
+                        response.add_header("Content-Encoding", Encoding::Gzip.to_str());
+                        response.add_vary("Accept-Encoding");
+                        return Ok(response);
+                    }
+                }
+
+                let file         = try!(File::open(path));
+                let mut response = try!(Response::create_from_file(Code::Code200, typ, file));
+                response.add_vary("Accept-Encoding");
+                return Ok(response);
+            }
+        }
+
+        if let Some(ref cache) = self.cache {
+            if let Some(cached) = try!(cache.get(path, metadata, typ)) {
+                return Ok(cached.into_response(typ, gzip));
+            }
+        }
+
+        let file = try!(File::open(path));
+
+        Response::create_from_file(Code::Code200, typ, file)
+    }
+
+                                        //zwykły plik z dopisanym .gz, też pod root-em
+    fn gz_sibling(&self, path: &Path) -> Option<PathBuf> {
+
+        let mut name = match path.file_name() {
+            Some(name) => name.to_os_string(),
+            None => return None,
+        };
+
+        name.push(".gz");
+
+        let gz_path = path.with_file_name(name);
+
+        match (fs::metadata(&gz_path), fs::canonicalize(&gz_path)) {
+            (Ok(ref metadata), Ok(ref canonical)) if metadata.is_file() && canonical.starts_with(&self.root) => Some(canonical.clone()),
+            _ => None,
+        }
+    }
+
+                                        //mapuje zdekodowaną ścieżkę requestu na plik lub katalog pod root-em
+    pub fn resolve(&self, request_path: &str) -> Result<Resolved, Code> {
+
+        if request_path.contains('\0') {
+            return Err(Code::Code400);
+        }
+
+        let relative = if self.prefix.len() > 0 {
+
+            if request_path == self.prefix {
+                ""
+            } else if request_path.starts_with(&(self.prefix.clone() + "/")) {
+                &request_path[self.prefix.len()..]
+            } else {
+                return Err(Code::Code404);
+            }
+
+        } else {
+            request_path
+        };
+
+        let mut path = self.root.clone();
+
+        for segment in relative.split('/') {
+
+            match segment {
+                "" | "." => {},
+                ".." => return Err(Code::Code403),
+                _ => {
+                    if segment.contains('\\') {
+                        return Err(Code::Code403);
+                    }
+                    path.push(segment);
+                }
+            }
+        }
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) => return Err(error_code(&err)),
+        };
+
+                                        //dowiązania symboliczne nie mogą wyprowadzić poza root
+        let canonical = match fs::canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(err) => return Err(error_code(&err)),
+        };
+
+        if canonical.starts_with(&self.root) == false {
+            return Err(Code::Code403);
+        }
+
+        if metadata.is_dir() {
+
+            if request_path.ends_with('/') == false {
+                                        //"//evil.com" przeglądarka czyta jako adres innego hosta - jeden ukośnik na początku
+                let location = format!("/{}/", request_path.trim_left_matches('/'));
+                return Ok(Resolved::Redirect(encode_path(&location)));
+            }
+
+            for name in self.index.iter() {
+
+                let index_path = canonical.join(name);
+
+                if let Ok(index_metadata) = fs::metadata(&index_path) {
+
+                    if index_metadata.is_file() {
+
+                        match fs::canonicalize(&index_path) {
+                            Ok(ref index_canonical) if index_canonical.starts_with(&self.root) => {
+                                return Ok(Resolved::File(index_canonical.clone(), index_metadata));
+                            },
+                            _ => return Err(Code::Code403),
+                        }
+                    }
+                }
+            }
+
+            return Ok(Resolved::Directory(canonical));
+        }
+
+        if metadata.is_file() {
+            Ok(Resolved::File(canonical, metadata))
+        } else {
+            Err(Code::Code403)
+        }
+    }
+}
+
+
+pub fn encode_path(path: &str) -> String {
+    
+    let segments: Vec<String> = path.split('/').map(|segment| urlencoded::encode(segment)).collect();
+    
+    segments.join("/")
+}
+
+
+fn error_code(err: &io::Error) -> Code {
+
+                                        //ścieżka prowadząca przez zwykły plik, np. /plik.txt/x
+    if err.raw_os_error() == Some(libc::ENOTDIR) {
+        return Code::Code404;
+    }
+
+    match err.kind() {
+        ErrorKind::NotFound => Code::Code404,
+        ErrorKind::PermissionDenied => Code::Code403,
+        _ => Code::Code500,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+    use code::Code;
+    use urlencoded;
+    use super::{StaticFiles, Resolved};
+
+                                        //root/a.txt, root/dir/index.html, root/empty/, root/link -> outside, obok outside/secret.txt
+    fn tree(name: &str) -> (PathBuf, StaticFiles) {
+
+        let base = env::temp_dir().join(format!("miohttp-static-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+
+        let root    = base.join("root");
+        let outside = base.join("outside");
+
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+
+        File::create(root.join("a.txt")).unwrap().write_all(b"a").unwrap();
+        File::create(root.join("dir").join("index.html")).unwrap().write_all(b"index").unwrap();
+        File::create(outside.join("secret.txt")).unwrap().write_all(b"secret").unwrap();
+
+        symlink(&outside, root.join("link")).unwrap();
+        symlink(outside.join("secret.txt"), root.join("secret.txt")).unwrap();
+
+        let files = StaticFiles::new(&root).unwrap();
+
+        (base, files)
+    }
+
+    fn file(files: &StaticFiles, path: &str) -> Option<PathBuf> {
+        match files.resolve(path) {
+            Ok(Resolved::File(path, _)) => Some(path),
+            _ => None,
+        }
+    }
+
+    fn code(files: &StaticFiles, path: &str) -> Option<Code> {
+        match files.resolve(path) {
+            Err(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    fn redirect(files: &StaticFiles, path: &str) -> Option<String> {
+        match files.resolve(path) {
+            Ok(Resolved::Redirect(location)) => Some(location),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn files_and_index() {
+
+        let (base, files) = tree("files");
+
+        assert_eq!(file(&files, "/a.txt"), Some(files.root().join("a.txt")));
+        assert_eq!(file(&files, "/./a.txt"), Some(files.root().join("a.txt")));
+        assert_eq!(file(&files, "/dir/"), Some(files.root().join("dir").join("index.html")));
+
+        match files.resolve("/empty/") {
+            Ok(Resolved::Directory(path)) => assert_eq!(path, files.root().join("empty")),
+            _ => panic!("directory expected"),
+        }
+
+        assert_eq!(code(&files, "/missing.txt"), Some(Code::Code404));
+        assert_eq!(code(&files, "/a.txt/x"), Some(Code::Code404));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn traversal_refused() {
+
+        let (base, files) = tree("traversal");
+
+        assert_eq!(code(&files, "/../outside/secret.txt"), Some(Code::Code403));
+        assert_eq!(code(&files, "/dir/../../outside/secret.txt"), Some(Code::Code403));
+        assert_eq!(code(&files, "/..\\outside\\secret.txt"), Some(Code::Code403));
+
+                                        //ścieżka trafia tu zdekodowana, tak jak z Request::path
+        let decoded = urlencoded::decode("/%2e%2e/outside/secret.txt").unwrap();
+        assert_eq!(code(&files, &decoded), Some(Code::Code403));
+
+        let decoded = urlencoded::decode("/dir%2f..%2f..%2foutside%2fsecret.txt").unwrap();
+        assert_eq!(code(&files, &decoded), Some(Code::Code403));
+
+        let decoded = urlencoded::decode("/dir%2findex.html").unwrap();
+        assert_eq!(file(&files, &decoded), Some(files.root().join("dir").join("index.html")));
+
+        assert_eq!(code(&files, "/a.txt\0.html"), Some(Code::Code400));
+        assert_eq!(code(&files, &urlencoded::decode("/a.txt%00").unwrap()), Some(Code::Code400));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn symlinks_outside_root() {
+
+        let (base, files) = tree("symlinks");
+
+        assert_eq!(code(&files, "/secret.txt"), Some(Code::Code403));
+        assert_eq!(code(&files, "/link/secret.txt"), Some(Code::Code403));
+        assert_eq!(code(&files, "/link/"), Some(Code::Code403));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn prefix() {
+
+        let (base, files) = tree("prefix");
+        let files = files.prefix("/static/");
+
+        assert_eq!(file(&files, "/static/a.txt"), Some(files.root().join("a.txt")));
+        assert_eq!(code(&files, "/a.txt"), Some(Code::Code404));
+        assert_eq!(code(&files, "/staticx/a.txt"), Some(Code::Code404));
+        assert_eq!(redirect(&files, "/static"), Some("/static/".to_owned()));
+        assert_eq!(code(&files, "/static/../a.txt"), Some(Code::Code403));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn directory_redirect() {
+
+        let (base, files) = tree("redirect");
+
+        assert_eq!(redirect(&files, "/dir"), Some("/dir/".to_owned()));
+        assert_eq!(redirect(&files, "//dir"), Some("/dir/".to_owned()));
+
+                                        //"//evil.com" z innym hostem w Location byłby otwartym przekierowaniem
+        fs::create_dir_all(files.root().join("evil.com")).unwrap();
+        assert_eq!(redirect(&files, "//evil.com"), Some("/evil.com/".to_owned()));
+        assert_eq!(redirect(&files, "///evil.com"), Some("/evil.com/".to_owned()));
+
+        fs::create_dir_all(files.root().join("a b")).unwrap();
+        assert_eq!(redirect(&files, "/a b"), Some("/a%20b/".to_owned()));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
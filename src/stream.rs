@@ -0,0 +1,544 @@
+use std::io;
+use std::io::{Read, Write, ErrorKind};
+use mio::tcp::TcpStream;
+#[cfg(feature = "tls")]
+use x509::ClientCert;
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use std::cmp::min;
+#[cfg(feature = "tls")]
+use rustls::{ServerConfig, ServerSession, Session};
+
+
+#[cfg(feature = "tls")]
+pub type TlsAcceptor = Arc<ServerConfig>;
+
+                                        //bez funkcji "tls" ten typ nie ma wartości - serwer zawsze jest zwykłym http
+#[cfg(not(feature = "tls"))]
+pub enum TlsAcceptor {}
+
+
+                                        //ile odszyfrowanych danych trzymamy zanim przestaniemy czytać z socketu
+#[cfg(feature = "tls")]
+const PLAIN_LIMIT : usize = 64 * 1024;
+
+
+/// Details of the TLS session a request came in on.
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+    version           : String,
+    cipher_suite      : String,
+    alpn_protocol     : Option<String>,
+    server_name       : Option<String>,
+    peer_certificates : Vec<Vec<u8>>,
+    #[cfg(feature = "tls")]
+    client_cert       : Option<ClientCert>,
+}
+
+
+impl TlsInfo {
+
+                                        //np. "TLSv1_3"
+    pub fn version(&self) -> &String {
+        &(self.version)
+    }
+
+    pub fn cipher_suite(&self) -> &String {
+        &(self.cipher_suite)
+    }
+
+                                        //protokół wybrany przez ALPN, np. "http/1.1"
+    pub fn alpn_protocol(&self) -> Option<&String> {
+        self.alpn_protocol.as_ref()
+    }
+
+                                        //nazwa z SNI
+    pub fn server_name(&self) -> Option<&String> {
+        self.server_name.as_ref()
+    }
+
+                                        //certyfikaty klienta w DER, pierwszy to certyfikat samego klienta
+    pub fn peer_certificates(&self) -> &Vec<Vec<u8>> {
+        &(self.peer_certificates)
+    }
+
+                                        //certyfikat klienta zweryfikowany względem CA z TlsConfig::client_auth
+    #[cfg(feature = "tls")]
+    pub fn client_cert(&self) -> Option<&ClientCert> {
+        self.client_cert.as_ref()
+    }
+}
+
+
+#[cfg(feature = "tls")]
+pub struct TlsStream {
+    tcp     : TcpStream,
+    session : ServerSession,
+    plain   : Vec<u8>,                  //odszyfrowane dane jeszcze nie odebrane przez Connection
+    eof     : bool,
+}
+
+
+/// Socket of a connection: plain TCP or TCP wrapped in a non-blocking TLS session.
+/// Reads and writes on it never block; `WouldBlock` means the same as for a bare socket.
+pub enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+}
+
+
+impl Stream {
+
+    #[cfg(feature = "tls")]
+    pub fn accept(tcp: TcpStream, acceptor: Option<&TlsAcceptor>) -> Stream {
+
+        match acceptor {
+
+            Some(config) => Stream::Tls(Box::new(TlsStream {
+                tcp     : tcp,
+                session : ServerSession::new(config),
+                plain   : Vec::new(),
+                eof     : false,
+            })),
+
+            None => Stream::Plain(tcp),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn accept(tcp: TcpStream, acceptor: Option<&TlsAcceptor>) -> Stream {
+
+        match acceptor {
+            Some(acceptor) => match *acceptor {},
+            None => Stream::Plain(tcp),
+        }
+    }
+
+                                        //do rejestracji w event_loop-ie
+    pub fn tcp(&self) -> &TcpStream {
+        match *self {
+            Stream::Plain(ref tcp) => tcp,
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref tls) => &(tls.tcp),
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        match *self {
+            Stream::Plain(_) => true,
+            #[cfg(feature = "tls")]
+            Stream::Tls(_) => false,
+        }
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        match *self {
+            Stream::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref tls) => tls.session.is_handshaking(),
+        }
+    }
+
+                                        //zaszyfrowane dane czekające na wysłanie
+    pub fn wants_write(&self) -> bool {
+        match *self {
+            Stream::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref tls) => tls.session.wants_write(),
+        }
+    }
+
+                                        //odszyfrowane dane leżące w buforze - epoll o nich nie powiadomi
+    pub fn has_buffered(&self) -> bool {
+        match *self {
+            Stream::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref tls) => tls.plain.len() > 0,
+        }
+    }
+
+                                        //Ok(true) - handshake zakończony
+    pub fn handshake(&mut self) -> io::Result<bool> {
+
+        match *self {
+
+            Stream::Plain(_) => Ok(true),
+
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => {
+
+                try!(tls.fill());
+
+                if tls.session.is_handshaking() && tls.eof {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed during tls handshake"));
+                }
+
+                Ok(tls.session.is_handshaking() == false)
+            }
+        }
+    }
+
+                                        //Ok(true) - wszystkie zaszyfrowane dane trafiły do socketu
+    pub fn flush_tls(&mut self) -> io::Result<bool> {
+
+        match *self {
+            Stream::Plain(_) => Ok(true),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => tls.flush_tls(),
+        }
+    }
+
+                                        //przy zamykaniu połączenia - próba wysłania close_notify
+    pub fn shutdown(&mut self) {
+
+        match *self {
+            Stream::Plain(_) => {},
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => {
+                tls.session.send_close_notify();
+                let _ = tls.flush_tls();
+            }
+        }
+    }
+
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+
+        match *self {
+
+            Stream::Plain(_) => None,
+
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref tls) => {
+
+                let session = &(tls.session);
+
+                let peer_certificates: Vec<Vec<u8>> = session.get_peer_certificates().unwrap_or_default().into_iter().map(|cert| cert.0).collect();
+
+                let client_cert = peer_certificates.first().and_then(|der| ClientCert::from_der(der));
+
+                Some(TlsInfo {
+                    version           : session.get_protocol_version().map(|version| format!("{:?}", version)).unwrap_or_default(),
+                    cipher_suite      : session.get_negotiated_ciphersuite().map(|suite| format!("{:?}", suite.suite)).unwrap_or_default(),
+                    alpn_protocol     : session.get_alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+                    server_name       : session.get_sni_hostname().map(|name| name.to_owned()),
+                    peer_certificates : peer_certificates,
+                    client_cert       : client_cert,
+                })
+            }
+        }
+    }
+}
+
+
+#[cfg(feature = "tls")]
+impl TlsStream {
+
+                                        //czyta z socketu aż do WouldBlock (lub limitu bufora), odszyfrowuje i wysyła odpowiedzi handshake-u
+    fn fill(&mut self) -> io::Result<()> {
+
+        while self.eof == false && self.plain.len() < PLAIN_LIMIT {
+
+            match self.session.read_tls(&mut self.tcp) {
+
+                Ok(0) => {
+                    self.eof = true;
+                },
+
+                Ok(_) => {
+
+                    if let Err(err) = self.session.process_new_packets() {
+
+                                        //alert dla klienta
+                        let _ = self.flush_tls();
+
+                        return Err(io::Error::new(ErrorKind::InvalidData, format!("tls error: {:?}", err)));
+                    }
+
+                    try!(self.read_plain());
+                },
+
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+
+                Err(err) => return Err(err),
+            }
+        }
+
+        try!(self.flush_tls());
+
+        Ok(())
+    }
+
+    fn read_plain(&mut self) -> io::Result<()> {
+
+        let mut buf = [0u8; 4096];
+
+        loop {
+
+            match self.session.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(size) => self.plain.extend_from_slice(&buf[0..size]),
+                Err(ref err) if err.kind() == ErrorKind::ConnectionAborted => {
+                                        //klient wysłał close_notify
+                    self.eof = true;
+                    return Ok(());
+                },
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn flush_tls(&mut self) -> io::Result<bool> {
+
+        while self.session.wants_write() {
+
+            match self.session.write_tls(&mut self.tcp) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+
+impl Read for Stream {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        match *self {
+
+            Stream::Plain(ref mut tcp) => tcp.read(buf),
+
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => {
+
+                if tls.plain.len() == 0 {
+                    try!(tls.fill());
+                }
+
+                if tls.plain.len() == 0 {
+                    if tls.eof {
+                        return Ok(0);
+                    }
+                    return Err(io::Error::new(ErrorKind::WouldBlock, "no tls data"));
+                }
+
+                let size = min(buf.len(), tls.plain.len());
+
+                buf[0..size].copy_from_slice(&tls.plain[0..size]);
+                tls.plain.drain(0..size);
+
+                Ok(size)
+            }
+        }
+    }
+}
+
+
+impl Write for Stream {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+
+        match *self {
+
+            Stream::Plain(ref mut tcp) => tcp.write(buf),
+
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => {
+
+                                        //nie przyjmujemy nowych danych dopóki poprzednie nie wyszły do socketu
+                if try!(tls.flush_tls()) == false {
+                    return Err(io::Error::new(ErrorKind::WouldBlock, "tls output pending"));
+                }
+
+                let size = try!(tls.session.write(buf));
+
+                try!(tls.flush_tls());
+
+                Ok(size)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+
+        match *self {
+            Stream::Plain(ref mut tcp) => tcp.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut tls) => tls.flush_tls().map(|_| ()),
+        }
+    }
+}
+
+
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+
+    use std::io::{self, Read, Write, ErrorKind};
+    use std::net::{self, SocketAddr};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use mio::tcp::{TcpListener, TcpStream};
+    use rustls::{self, ClientConfig, ClientSession};
+    use webpki::DNSNameRef;
+    use tls::{TlsConfig, load_certs};
+    use super::{Stream, PLAIN_LIMIT};
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+                                        //powtarza krok dopóki zwraca None - zamiast event_loop-a
+    fn retry<T, F>(mut step: F) -> T where F: FnMut() -> io::Result<Option<T>> {
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        loop {
+            match step().unwrap() {
+                Some(value) => return value,
+                None => {
+                    assert!(Instant::now() < deadline, "timeout");
+                    thread::sleep(Duration::from_millis(2));
+                }
+            }
+        }
+    }
+
+                                        //klient rustls na zwykłym, blokującym sockecie
+    fn client(addr: SocketAddr) -> (ClientSession, net::TcpStream) {
+
+        let mut config = ClientConfig::new();
+
+        for cert in load_certs(Path::new(&fixture("tls-ca.pem"))).unwrap() {
+            config.root_store.add(&cert).unwrap();
+        }
+
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+
+        let session = ClientSession::new(&Arc::new(config), DNSNameRef::try_from_ascii_str("localhost").unwrap());
+
+        let socket = net::TcpStream::connect(addr).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        (session, socket)
+    }
+
+                                        //połączenie od klienta uruchomionego w osobnym wątku, po zakończonym handshake-u
+    fn server<F>(run_client: F) -> (Stream, thread::JoinHandle<()>) where F: FnOnce(&mut rustls::Stream<ClientSession, net::TcpStream>) + Send + 'static {
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let (mut session, mut socket) = client(addr);
+            run_client(&mut rustls::Stream::new(&mut session, &mut socket));
+        });
+
+        let acceptor = TlsConfig::new().cert(Path::new(&fixture("tls-server.pem")), Path::new(&fixture("tls-server-key.pem"))).unwrap().build().unwrap();
+
+        let tcp: TcpStream = retry(|| listener.accept().map(|accepted| accepted.map(|(tcp, _)| tcp)));
+
+        let mut stream = Stream::accept(tcp, Some(&acceptor));
+
+        retry(|| stream.handshake().map(|done| if done { Some(()) } else { None }));
+
+        (stream, client)
+    }
+
+    fn send(stream: &mut Stream, data: &[u8]) {
+        assert_eq!(stream.write(data).unwrap(), data.len());
+        retry(|| stream.flush_tls().map(|flushed| if flushed { Some(()) } else { None }));
+    }
+
+    #[test]
+    fn handshake_read_write() {
+
+        let (mut stream, client) = server(|tls| {
+
+            tls.write_all(b"ping").unwrap();
+
+            let mut answer = [0u8; 4];
+            tls.read_exact(&mut answer).unwrap();
+            assert_eq!(&answer, b"pong");
+        });
+
+        let info = stream.tls_info().unwrap();
+
+        assert_eq!(info.server_name(), Some(&"localhost".to_owned()));
+        assert_eq!(info.alpn_protocol(), Some(&"http/1.1".to_owned()));
+        assert_eq!(info.peer_certificates().len(), 0);
+
+        let mut buf = [0u8; 16];
+
+        let size = retry(|| match stream.read(&mut buf) {
+            Ok(size) => Ok(Some(size)),
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        });
+
+        assert_eq!(&buf[0..size], b"ping");
+        assert!(stream.has_buffered() == false);
+
+        send(&mut stream, b"pong");
+
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn buffered_data_drained() {
+
+        let total = 4 * PLAIN_LIMIT;
+
+        let (mut stream, client) = server(move |tls| {
+
+            let data: Vec<u8> = (0..total).map(|index| (index % 251) as u8).collect();
+
+            tls.write_all(&data).unwrap();
+
+                                        //czeka aż serwer odbierze wszystko
+            let mut end = [0u8; 1];
+            tls.read_exact(&mut end).unwrap();
+        });
+
+        let mut received = Vec::new();
+        let mut buf = [0u8; 1000];
+
+                                        //jak po powiadomieniu z epoll (edge): czytamy aż do WouldBlock
+        retry(|| {
+
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => panic!("unexpected eof"),
+                    Ok(size) => received.extend_from_slice(&buf[0..size]),
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+
+                if let Stream::Tls(ref tls) = stream {
+                    assert!(tls.plain.len() < 2 * PLAIN_LIMIT, "{}", tls.plain.len());
+                }
+            }
+
+                                        //WouldBlock tylko przy pustym buforze - inaczej dane czekałyby na zdarzenie, które nie przyjdzie
+            assert!(stream.has_buffered() == false);
+
+            Ok(if received.len() < total { None } else { Some(()) })
+        });
+
+        assert_eq!(received.len(), total);
+        assert!(received.iter().enumerate().all(|(index, byte)| *byte == (index % 251) as u8));
+
+        send(&mut stream, b"!");
+
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+use std::io;
+use std::io::{BufReader, ErrorKind};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::sync::Arc;
+use std::collections::HashMap;
+use rustls::{ServerConfig, NoClientAuth, AllowAnyAuthenticatedClient, AllowAnyAnonymousOrAuthenticatedClient, RootCertStore};
+use rustls::{ResolvesServerCert, SignatureScheme, Certificate, PrivateKey};
+use rustls::sign::{self, CertifiedKey};
+use rustls::internal::pemfile;
+use webpki::DNSNameRef;
+use stream::TlsAcceptor;
+
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+
+pub fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+
+    let file = try!(File::open(path));
+
+    let certs = match pemfile::certs(&mut BufReader::new(file)) {
+        Ok(certs) => certs,
+        Err(_) => return Err(invalid(format!("{}: invalid PEM certificate", path.display()))),
+    };
+
+    if certs.len() == 0 {
+        return Err(invalid(format!("{}: no certificates found", path.display())));
+    }
+
+    Ok(certs)
+}
+
+
+                                        //PKCS#8 lub RSA ("BEGIN RSA PRIVATE KEY"), pierwszy znaleziony klucz
+pub fn load_key(path: &Path) -> io::Result<PrivateKey> {
+
+    let pkcs8 = match pemfile::pkcs8_private_keys(&mut BufReader::new(try!(File::open(path)))) {
+        Ok(keys) => keys,
+        Err(_) => return Err(invalid(format!("{}: invalid PEM key", path.display()))),
+    };
+
+    if let Some(key) = pkcs8.into_iter().next() {
+        return Ok(key);
+    }
+
+    let rsa = match pemfile::rsa_private_keys(&mut BufReader::new(try!(File::open(path)))) {
+        Ok(keys) => keys,
+        Err(_) => return Err(invalid(format!("{}: invalid PEM key", path.display()))),
+    };
+
+    match rsa.into_iter().next() {
+        Some(key) => Ok(key),
+        None => Err(invalid(format!("{}: no private key found", path.display()))),
+    }
+}
+
+
+                                        //certyfikaty CA, którymi muszą być podpisane certyfikaty klientów
+pub fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+
+    let mut roots = RootCertStore::empty();
+
+    let (valid, invalid_count) = match roots.add_pem_file(&mut BufReader::new(try!(File::open(path)))) {
+        Ok(counts) => counts,
+        Err(_) => return Err(invalid(format!("{}: invalid PEM certificate", path.display()))),
+    };
+
+    if valid == 0 || invalid_count > 0 {
+        return Err(invalid(format!("{}: {} usable and {} unusable CA certificates", path.display(), valid, invalid_count)));
+    }
+
+    Ok(roots)
+}
+
+
+fn certified_key(cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
+
+    let certs = try!(load_certs(cert_path));
+    let key   = try!(load_key(key_path));
+
+    let signing_key = match sign::any_supported_type(&key) {
+        Ok(signing_key) => signing_key,
+        Err(_) => return Err(invalid(format!("{}: unsupported private key type", key_path.display()))),
+    };
+
+    let certified = CertifiedKey::new(certs, Arc::new(signing_key));
+
+    if let Err(err) = certified.cross_check_end_entity_cert(None) {
+        return Err(invalid(format!("{}: {:?}", cert_path.display(), err)));
+    }
+
+    Ok(certified)
+}
+
+
+                                        //certyfikat wg nazwy z SNI, w pozostałych przypadkach domyślny
+struct Resolver {
+    by_name : HashMap<String, CertifiedKey>,
+    default : Option<CertifiedKey>,
+}
+
+impl ResolvesServerCert for Resolver {
+
+    fn resolve(&self, server_name: Option<DNSNameRef>, _sigschemes: &[SignatureScheme]) -> Option<CertifiedKey> {
+
+        if let Some(server_name) = server_name {
+
+            let name: &str = server_name.into();
+
+            if let Some(key) = self.by_name.get(&name.to_lowercase()) {
+                return Some(key.clone());
+            }
+        }
+
+        self.default.clone()
+    }
+}
+
+
+/// Certificates and settings of an HTTPS server. Certificates are read from PEM files when added,
+/// so a missing or broken file is reported before the server starts.
+#[derive(Clone)]
+pub struct TlsConfig {
+    default     : Option<CertifiedKey>,
+    by_name     : HashMap<String, CertifiedKey>,
+    protocols   : Vec<String>,
+    files       : Vec<(Option<String>, PathBuf, PathBuf)>,    //nazwa z SNI (None - domyślny), certyfikat, klucz
+    client_auth : Option<(RootCertStore, bool, PathBuf)>,      //CA, czy certyfikat klienta jest wymagany, plik CA
+}
+
+
+impl TlsConfig {
+
+    pub fn new() -> TlsConfig {
+
+        TlsConfig {
+            default     : None,
+            by_name     : HashMap::new(),
+            protocols   : vec!["http/1.1".to_owned()],
+            files       : Vec::new(),
+            client_auth : None,
+        }
+    }
+
+                                        //certyfikat dla klientów bez SNI lub z nieznaną nazwą
+    pub fn cert(mut self, cert_path: &Path, key_path: &Path) -> io::Result<TlsConfig> {
+        self.default = Some(try!(certified_key(cert_path, key_path)));
+        self.files.retain(|&(ref name, _, _)| name.is_some());
+        self.files.push((None, cert_path.to_path_buf(), key_path.to_path_buf()));
+        Ok(self)
+    }
+
+                                        //certyfikat wybierany gdy klient poda tę nazwę w SNI
+    pub fn sni_cert(mut self, server_name: &str, cert_path: &Path, key_path: &Path) -> io::Result<TlsConfig> {
+        let key  = try!(certified_key(cert_path, key_path));
+        let name = server_name.to_lowercase();
+        self.by_name.insert(name.clone(), key);
+        self.files.retain(|&(ref file_name, _, _)| file_name.as_ref() != Some(&name));
+        self.files.push((Some(name), cert_path.to_path_buf(), key_path.to_path_buf()));
+        Ok(self)
+    }
+
+                                        //protokoły ALPN w kolejności preferencji; "h2" włącza HTTP/2, np. vec!["h2", "http/1.1"]
+    pub fn protocols(mut self, protocols: Vec<&str>) -> TlsConfig {
+        self.protocols = protocols.iter().map(|protocol| (*protocol).to_owned()).collect();
+        self
+    }
+
+    /// Asks clients for a certificate signed by one of the CAs in `ca_path`. With `required` the handshake fails
+    /// without one, otherwise clients may connect anonymously. The verified certificate is available from `Request::client_cert`.
+    pub fn client_auth(mut self, ca_path: &Path, required: bool) -> io::Result<TlsConfig> {
+        let roots = try!(load_roots(ca_path));
+        self.client_auth = Some((roots, required, ca_path.to_path_buf()));
+        Ok(self)
+    }
+
+                                        //ta sama konfiguracja wczytana ponownie z tych samych plików, np. po odnowieniu certyfikatów
+    pub fn reload(&self) -> io::Result<TlsConfig> {
+
+        let protocols: Vec<&str> = self.protocols.iter().map(|protocol| &protocol[..]).collect();
+
+        let mut config = TlsConfig::new().protocols(protocols);
+
+        if let Some((_, required, ref ca_path)) = self.client_auth {
+            config = try!(config.client_auth(ca_path, required));
+        }
+
+        for &(ref name, ref cert_path, ref key_path) in self.files.iter() {
+            config = match *name {
+                Some(ref name) => try!(config.sni_cert(name, cert_path, key_path)),
+                None => try!(config.cert(cert_path, key_path)),
+            };
+        }
+
+        Ok(config)
+    }
+
+                                        //najpóźniejsza data modyfikacji plików certyfikatów, kluczy i CA
+    pub fn modified(&self) -> Option<SystemTime> {
+
+        let mut last = None;
+
+        let mut paths: Vec<&PathBuf> = Vec::new();
+
+        for &(_, ref cert_path, ref key_path) in self.files.iter() {
+            paths.push(cert_path);
+            paths.push(key_path);
+        }
+
+        if let Some((_, _, ref ca_path)) = self.client_auth {
+            paths.push(ca_path);
+        }
+
+        for path in paths.iter() {
+            if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                if last.map(|last| modified > last) != Some(false) {
+                    last = Some(modified);
+                }
+            }
+        }
+
+        last
+    }
+
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+
+        if self.default.is_none() && self.by_name.len() == 0 {
+            return Err(invalid("tls config without certificates".to_owned()));
+        }
+
+        let verifier = match self.client_auth {
+            Some((roots, true, _)) => AllowAnyAuthenticatedClient::new(roots),
+            Some((roots, false, _)) => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            None => NoClientAuth::new(),
+        };
+
+        let mut config = ServerConfig::new(verifier);
+
+        config.cert_resolver = Arc::new(Resolver {
+            by_name : self.by_name,
+            default : self.default,
+        });
+
+        let protocols: Vec<Vec<u8>> = self.protocols.into_iter().map(|protocol| protocol.into_bytes()).collect();
+
+        config.set_protocols(&protocols);
+
+        Ok(Arc::new(config))
+    }
+}
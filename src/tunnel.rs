@@ -0,0 +1,409 @@
+use std::io;
+use std::io::{Read, Write, ErrorKind};
+use std::net::{SocketAddr, IpAddr, Shutdown, ToSocketAddrs};
+use std::thread;
+use std::sync::Arc;
+use mio::Token;
+use mio::tcp::TcpStream;
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use server::FnReceiver;
+use stream::Stream;
+
+
+const BUFFER_LIMIT : usize = 64 * 1024;             //dane czekające na drugą stronę - powyżej przestajemy czytać
+const IDLE_TIMEOUT : u64   = 5 * 60 * 1000;
+
+
+/// Handles `CONNECT host:port` requests of a forward proxy. Each target goes through the `allow` hook first;
+/// an allowed one is resolved on a separate thread, connected without blocking the event loop and spliced
+/// with the client connection.
+/// The resolved address is checked again by `allow_address` - by default only public addresses pass, so a name
+/// pointing at loopback or the internal network can't be used to reach it.
+/// The client gets 403 for a denied target, 502 when the connection fails and 504 when it doesn't complete
+/// within the server's reading timeout.
+pub struct Tunnel {
+    allow         : Box<Fn(&str, u16, &Request) -> bool + Send + Sync + 'static>,
+    allow_address : Arc<Box<Fn(&SocketAddr) -> bool + Send + Sync + 'static>>,
+    idle_timeout  : u64,
+}
+
+
+impl Tunnel {
+
+    pub fn new(allow: Box<Fn(&str, u16, &Request) -> bool + Send + Sync + 'static>) -> Tunnel {
+
+        Tunnel {
+            allow         : allow,
+            allow_address : Arc::new(Box::new(|addr: &SocketAddr| is_public(&addr.ip()))),
+            idle_timeout  : IDLE_TIMEOUT,
+        }
+    }
+
+                                        //sprawdzenie adresu po rozwiązaniu nazwy (domyślnie is_public)
+    pub fn allow_address(mut self, allow_address: Box<Fn(&SocketAddr) -> bool + Send + Sync + 'static>) -> Tunnel {
+        self.allow_address = Arc::new(allow_address);
+        self
+    }
+
+                                        //w milisekundach, bez ruchu w obu kierunkach tunel jest zamykany (domyślnie 5 minut)
+    pub fn idle_timeout(mut self, idle_timeout: u64) -> Tunnel {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn build(self) -> FnReceiver {
+
+        Box::new(move|request: Request| {
+            self.handle(request);
+        })
+    }
+
+    pub fn handle(&self, request: Request) {
+
+        if request.method() != "CONNECT" {
+            let mut response = Response::create(Code::Code405, Type::TextHtml, "405 Method Not Allowed".to_owned());
+            response.add_header("Allow", "CONNECT");
+            request.send(response);
+            return;
+        }
+
+        let (host, port) = match parse_target(request.path()) {
+            Some(target) => target,
+            None => {
+                request.send(Response::create_400());
+                return;
+            }
+        };
+
+        if (self.allow)(&host, port, &request) == false {
+            request.send(Response::create(Code::Code403, Type::TextHtml, "403 Forbidden".to_owned()));
+            return;
+        }
+
+        let idle_timeout  = self.idle_timeout;
+        let allow_address = self.allow_address.clone();
+
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            open(request, SocketAddr::new(ip, port), &**allow_address, idle_timeout);
+            return;
+        }
+
+                                        //odbiornik działa w event_loop-ie, a zapytanie DNS blokuje - rozwiązujemy w osobnym wątku
+        thread::spawn(move || {
+
+            match (&host[..], port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+                Ok(Some(addr)) => open(request, addr, &**allow_address, idle_timeout),
+                _ => request.send(bad_gateway()),
+            }
+        });
+    }
+}
+
+
+                                        //dozwolona nazwa może wskazywać na adres wewnętrzny - sprawdzany jest adres, z którym się łączymy
+fn open(request: Request, addr: SocketAddr, allow_address: &Fn(&SocketAddr) -> bool, idle_timeout: u64) {
+
+    if allow_address(&addr) {
+        request.tunnel(addr, idle_timeout);
+    } else {
+        request.send(Response::create(Code::Code403, Type::TextHtml, "403 Forbidden".to_owned()));
+    }
+}
+
+
+/// Whether the address belongs to the public internet: loopback, private, shared (100.64/10), link-local,
+/// unique local, multicast, broadcast and unspecified addresses are not.
+pub fn is_public(ip: &IpAddr) -> bool {
+
+    match *ip {
+
+        IpAddr::V4(ref ip) => {
+
+            let octets = ip.octets();
+
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_broadcast() || ip.is_multicast() ||
+              ip.is_unspecified() || octets[0] == 0 || (octets[0] == 100 && (octets[1] & 0xc0) == 64))
+        },
+
+        IpAddr::V6(ref ip) => {
+
+                                        //::ffff:a.b.c.d - adres IPv4 zapisany jako IPv6
+            if let Some(v4) = ip.to_ipv4() {
+                if ip.segments()[0..5] == [0, 0, 0, 0, 0] {
+                    return is_public(&IpAddr::V4(v4));
+                }
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() ||
+              (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80)
+        },
+    }
+}
+
+
+                                        //"host:port" lub "[::1]:port"; nawiasy IPv6 zostają zdjęte
+pub fn parse_target(target: &str) -> Option<(String, u16)> {
+
+    let pos = match target.rfind(':') {
+        Some(pos) => pos,
+        None => return None,
+    };
+
+    let host = &target[0..pos];
+
+    let port = match target[pos + 1..].parse::<u16>() {
+        Ok(port) if port > 0 => port,
+        _ => return None,
+    };
+
+                                        //adres IPv6 tylko w nawiasach, "::1:443" jest niejednoznaczne
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else if host.contains(':') {
+        return None;
+    } else {
+        host
+    };
+
+    if host.len() == 0 || host.contains('/') || host.contains('@') {
+        return None;
+    }
+
+    Some((host.to_lowercase(), port))
+}
+
+
+pub enum Step {
+    Open,
+    Failed(Response, String),                           //połączenie z celem się nie udało - odpowiedź z błędem, opis do logu
+    Closed,
+}
+
+
+/// Connection side of a tunnel: the outbound socket and the bytes waiting in each direction.
+pub struct TunnelConnection {
+    upstream     : TcpStream,
+    token        : Token,                               //token gniazda wychodzącego w event_loop-ie
+    head         : Option<Response>,                    //odpowiedź 200 wysyłana po nawiązaniu połączenia
+    to_upstream  : Vec<u8>,
+    to_client    : Vec<u8>,
+    client_eof   : bool,
+    upstream_eof : bool,
+    idle_timeout : u64,
+    activity     : bool,                                //ruch od ostatniego sprawdzenia
+}
+
+
+impl TunnelConnection {
+
+                                        //buffered - dane klienta odebrane razem z requestem
+    pub fn connect(addr: &SocketAddr, token: Token, head: Response, buffered: Vec<u8>, idle_timeout: u64) -> io::Result<TunnelConnection> {
+
+        let upstream = try!(TcpStream::connect(addr));
+
+        Ok(TunnelConnection {
+            upstream     : upstream,
+            token        : token,
+            head         : Some(head),
+            to_upstream  : buffered,
+            to_client    : Vec::new(),
+            client_eof   : false,
+            upstream_eof : false,
+            idle_timeout : idle_timeout,
+            activity     : false,
+        })
+    }
+
+    pub fn upstream(&self) -> &TcpStream {
+        &(self.upstream)
+    }
+
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn idle_timeout(&self) -> u64 {
+        self.idle_timeout
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn client_wants_read(&self) -> bool {
+        self.is_connected() && self.client_eof == false && self.to_upstream.len() < BUFFER_LIMIT
+    }
+
+    pub fn client_wants_write(&self) -> bool {
+        self.to_client.len() > 0
+    }
+
+                                        //(czytanie, pisanie); przed nawiązaniem połączenia czekamy na gotowość do zapisu
+    pub fn upstream_interest(&self) -> (bool, bool) {
+
+        if self.is_connected() == false {
+            return (false, true);
+        }
+
+        (self.upstream_eof == false && self.to_client.len() < BUFFER_LIMIT, self.to_upstream.len() > 0)
+    }
+
+    pub fn is_writing(&self) -> bool {
+        self.to_client.len() > 0 || self.to_upstream.len() > 0
+    }
+
+                                        //czy był ruch od poprzedniego wywołania
+    pub fn has_activity(&mut self) -> bool {
+
+        let activity = self.activity;
+        self.activity = false;
+        activity
+    }
+
+                                        //zdarzenie na którymkolwiek z gniazd - przesyłamy co się da w obie strony
+    pub fn pump(&mut self, client: &mut Stream) -> io::Result<Step> {
+
+        if self.head.is_some() {
+
+            if let Err(err) = self.upstream.take_socket_error() {
+                return Ok(Step::Failed(bad_gateway(), format!("connect error: {}", err)));
+            }
+
+            match self.upstream.peer_addr() {
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::NotConnected => return Ok(Step::Open),
+                Err(err) => return Ok(Step::Failed(bad_gateway(), format!("connect error: {}", err))),
+            }
+
+            if let Some(head) = self.head.take() {
+                self.to_client = head.upgrade_head();
+            }
+        }
+
+        try!(transfer(client, &mut self.to_upstream, &mut self.client_eof, &mut self.activity));
+        try!(flush(&mut self.upstream, &mut self.to_upstream, &mut self.activity));
+
+        try!(transfer(&mut self.upstream, &mut self.to_client, &mut self.upstream_eof, &mut self.activity));
+        try!(flush(client, &mut self.to_client, &mut self.activity));
+        try!(client.flush_tls());
+
+                                        //klient skończył wysyłać - cel dostaje FIN, odpowiedź wciąż może płynąć
+        if self.client_eof && self.to_upstream.len() == 0 {
+            let _ = self.upstream.shutdown(Shutdown::Write);
+        }
+
+        if self.upstream_eof && self.to_client.len() == 0 {
+            return Ok(Step::Closed);
+        }
+
+        Ok(Step::Open)
+    }
+}
+
+
+                                        //szczegóły błędu trafiają tylko do logu serwera
+fn bad_gateway() -> Response {
+    Response::create(Code::Code502, Type::TextHtml, "502 Bad Gateway".to_owned())
+}
+
+
+                                        //czytanie do bufora aż do WouldBlock, końca danych lub limitu
+pub fn transfer<R: Read>(from: &mut R, buffer: &mut Vec<u8>, eof: &mut bool, activity: &mut bool) -> io::Result<()> {
+
+    let mut buf = [0u8; 4096];
+
+    while *eof == false && buffer.len() < BUFFER_LIMIT {
+
+        match from.read(&mut buf) {
+            Ok(0) => *eof = true,
+            Ok(size) => {
+                buffer.extend_from_slice(&buf[0..size]);
+                *activity = true;
+            },
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+
+pub fn flush<W: Write>(to: &mut W, buffer: &mut Vec<u8>, activity: &mut bool) -> io::Result<()> {
+
+    while buffer.len() > 0 {
+
+        match to.write(&buffer) {
+            Ok(0) => break,
+            Ok(size) => {
+                buffer.drain(0..size);
+                *activity = true;
+            },
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::IpAddr;
+    use super::{parse_target, is_public};
+
+    #[test]
+    fn host_and_port() {
+        assert_eq!(parse_target("example.com:443"), Some(("example.com".to_owned(), 443)));
+        assert_eq!(parse_target("Example.COM:8080"), Some(("example.com".to_owned(), 8080)));
+        assert_eq!(parse_target("10.0.0.1:22"), Some(("10.0.0.1".to_owned(), 22)));
+    }
+
+    #[test]
+    fn ipv6_brackets() {
+        assert_eq!(parse_target("[::1]:443"), Some(("::1".to_owned(), 443)));
+        assert_eq!(parse_target("[2001:db8::1]:8443"), Some(("2001:db8::1".to_owned(), 8443)));
+    }
+
+    #[test]
+    fn invalid_targets() {
+        assert_eq!(parse_target("example.com"), None);
+        assert_eq!(parse_target(":443"), None);
+        assert_eq!(parse_target("[]:443"), None);
+        assert_eq!(parse_target("example.com:0"), None);
+        assert_eq!(parse_target("example.com:65536"), None);
+        assert_eq!(parse_target("example.com:https"), None);
+        assert_eq!(parse_target("/index.html:80"), None);
+        assert_eq!(parse_target("user@example.com:443"), None);
+        assert_eq!(parse_target("::1:443"), None);
+        assert_eq!(parse_target("2001:db8::1:8443"), None);
+        assert_eq!(parse_target("[::1:443"), None);
+    }
+
+    fn public(ip: &str) -> bool {
+        is_public(&ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn public_addresses() {
+
+        for ip in &["93.184.216.34", "8.8.8.8", "172.32.0.1", "100.128.0.1", "2606:2800:220:1::1", "::ffff:8.8.8.8"] {
+            assert!(public(ip), "{}", ip);
+        }
+
+        for ip in &["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+                    "0.1.2.3", "255.255.255.255", "224.0.0.1", "::1", "::", "fc00::1", "fd12::1", "fe80::1", "ff02::1",
+                    "::ffff:127.0.0.1", "::ffff:10.0.0.1"] {
+            assert!(public(ip) == false, "{}", ip);
+        }
+    }
+}
@@ -1,11 +1,41 @@
 use std::fmt;
 use std::path::Path;
 
+
+                                        //separator części multipart/byteranges - jedno źródło dla Content-Type i ciała
+macro_rules! byteranges_boundary {
+    () => ("miohttp-byteranges-3f9c1a7e52d8b046")
+}
+
+pub const BYTERANGES_BOUNDARY : &'static str = byteranges_boundary!();
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Type {
     TextHtml,
     TextPlain,
+    TextCss,
+    TextJavascript,
+    TextXml,
+    TextCsv,
+    TextEventStream,
+    ApplicationJson,
+    ApplicationPdf,
+    ApplicationWasm,
+    ApplicationZip,
+    ApplicationGzip,
+    ApplicationOctetStream,
     ImageJpeg,
     ImagePng,
+    ImageGif,
+    ImageSvg,
+    ImageIcon,
+    ImageWebp,
+    FontWoff,
+    FontWoff2,
+    AudioMpeg,
+    VideoMp4,
+    VideoWebm,
+    MultipartByteranges,
 }
 
 impl Type {
@@ -13,8 +43,39 @@ impl Type {
         match *self {
             Type::TextHtml => "text/html; charset=utf-8",
             Type::TextPlain => "text/plain",
+            Type::TextCss => "text/css",
+            Type::TextJavascript => "text/javascript",
+            Type::TextXml => "text/xml",
+            Type::TextCsv => "text/csv",
+            Type::TextEventStream => "text/event-stream",
+            Type::ApplicationJson => "application/json",
+            Type::ApplicationPdf => "application/pdf",
+            Type::ApplicationWasm => "application/wasm",
+            Type::ApplicationZip => "application/zip",
+            Type::ApplicationGzip => "application/gzip",
+            Type::ApplicationOctetStream => "application/octet-stream",
             Type::ImageJpeg => "image/jpeg",
             Type::ImagePng => "image/png",
+            Type::ImageGif => "image/gif",
+            Type::ImageSvg => "image/svg+xml",
+            Type::ImageIcon => "image/x-icon",
+            Type::ImageWebp => "image/webp",
+            Type::FontWoff => "font/woff",
+            Type::FontWoff2 => "font/woff2",
+            Type::AudioMpeg => "audio/mpeg",
+            Type::VideoMp4 => "video/mp4",
+            Type::VideoWebm => "video/webm",
+            Type::MultipartByteranges => concat!("multipart/byteranges; boundary=", byteranges_boundary!()),
+        }
+    }
+
+    
+                                        //typy tekstowe, które warto kompresować (obrazy, archiwa i media już są skompresowane)
+    pub fn is_compressible(&self) -> bool {
+        match *self {
+            Type::TextHtml | Type::TextPlain | Type::TextCss | Type::TextJavascript | Type::TextXml | Type::TextCsv |
+            Type::ApplicationJson | Type::ApplicationWasm | Type::ImageSvg | Type::ImageIcon => true,
+            _ => false,
         }
     }
 
@@ -25,16 +86,37 @@ impl Type {
         
         match path.extension() {
             
-            Some(ext) => match ext.to_str() {
-                Some("txt")  => Type::TextPlain,
-                Some("jpg")  => Type::ImageJpeg,
-                Some("png")  => Type::ImagePng,
-                //Some("html") => Type::TextHtml,
-                Some(_)      => Type::TextHtml,
-                None         => Type::TextHtml,
+            Some(ext) => match ext.to_str().map(|ext| ext.to_lowercase()) {
+                Some(ext) => match &ext[..] {
+                    "html" | "htm" => Type::TextHtml,
+                    "txt"          => Type::TextPlain,
+                    "css"          => Type::TextCss,
+                    "js" | "mjs"   => Type::TextJavascript,
+                    "xml"          => Type::TextXml,
+                    "csv"          => Type::TextCsv,
+                    "json" | "map" => Type::ApplicationJson,
+                    "pdf"          => Type::ApplicationPdf,
+                    "wasm"         => Type::ApplicationWasm,
+                    "zip"          => Type::ApplicationZip,
+                    "gz"           => Type::ApplicationGzip,
+                    "jpg" | "jpeg" => Type::ImageJpeg,
+                    "png"          => Type::ImagePng,
+                    "gif"          => Type::ImageGif,
+                    "svg"          => Type::ImageSvg,
+                    "ico"          => Type::ImageIcon,
+                    "webp"         => Type::ImageWebp,
+                    "woff"         => Type::FontWoff,
+                    "woff2"        => Type::FontWoff2,
+                    "mp3"          => Type::AudioMpeg,
+                    "mp4"          => Type::VideoMp4,
+                    "webm"         => Type::VideoWebm,
+                                        //nieznane rozszerzenie - przeglądarka nie powinna tego renderować
+                    _              => Type::ApplicationOctetStream,
+                },
+                None => Type::ApplicationOctetStream,
             },
             
-            None => Type::TextHtml,
+            None => Type::ApplicationOctetStream,
         }
     }
 
@@ -45,3 +127,15 @@ impl fmt::Display for Type {
         write!(f, "{}", self.to_str())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Type, BYTERANGES_BOUNDARY};
+
+    #[test]
+    fn byteranges_boundary() {
+        assert_eq!(Type::MultipartByteranges.to_str(), format!("multipart/byteranges; boundary={}", BYTERANGES_BOUNDARY));
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+use std::io::{Read, Write, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender as ChannelSender};
+use std::thread;
+use mio::{Token, Sender};
+use server::MioMessage;
+use event_stream::OpenGuard;
+use stream::Stream;
+
+
+const READ_LIMIT   : usize = 64 * 1024;             //ile czytamy z socketu w jednym zdarzeniu
+const OUTPUT_LIMIT : usize = 4 * 1024 * 1024;       //dane od aplikacji czekające na wolnego klienta
+const INPUT_LIMIT  : usize = 1024 * 1024;           //dane klienta czekające na handler - powyżej przestajemy czytać
+
+
+/// Handle of a connection taken over after an HTTP handshake (see `Request::upgrade`). Cheap to clone and usable
+/// from any thread; data is queued through the event loop channel. Writing after the connection closed is ignored.
+#[derive(Clone)]
+pub struct Upgraded {
+    token  : Token,
+    sender : Sender<MioMessage>,
+    open   : Arc<AtomicBool>,
+}
+
+
+impl Upgraded {
+
+    pub fn new(token: Token, sender: Sender<MioMessage>) -> (Upgraded, OpenGuard) {
+
+        let (guard, open) = OpenGuard::new();
+
+        let upgraded = Upgraded {
+            token  : token,
+            sender : sender,
+            open   : open,
+        };
+
+        (upgraded, guard)
+    }
+
+    pub fn send(&self, data: Vec<u8>) {
+        if self.is_open() {
+            let _ = self.sender.send(MioMessage::Upgraded(self.token, Some(data)));
+        }
+    }
+
+                                        //połączenie zostanie zamknięte po wysłaniu danych z kolejki
+    pub fn close(&self) {
+        let _ = self.sender.send(MioMessage::Upgraded(self.token, None));
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+
+/// Receives the data of an upgraded connection. All methods run in order on a thread of their own, not on the event loop.
+pub trait UpgradeHandler: Send {
+
+                                        //odpowiedź przekazana do wysłania, można już wysyłać dane
+    fn on_open(&mut self, _socket: &Upgraded) {
+    }
+
+                                        //pierwsze wywołanie może dostać bajty odebrane razem z nagłówkami requestu
+    fn on_data(&mut self, socket: &Upgraded, data: &[u8]);
+
+                                        //klient zamknął swoją stronę; połączenie trwa, dopóki aplikacja nie wywoła close
+    fn on_end(&mut self, socket: &Upgraded) {
+        socket.close();
+    }
+
+                                        //wywoływane dokładnie raz, niezależnie od tego która strona zamknęła połączenie
+    fn on_close(&mut self) {
+    }
+}
+
+
+enum HandlerEvent {
+    Data(Vec<u8>),
+    End,
+}
+
+
+/// Connection side of an upgrade: raw bytes both ways, no framing.
+pub struct UpgradedConnection {
+    events  : ChannelSender<HandlerEvent>,          //do wątku handlera; zamknięcie kanału kończy go wywołaniem on_close
+    pending : Arc<AtomicUsize>,                     //bajty klienta jeszcze nieprzekazane do on_data
+    output  : Vec<u8>,
+    closing : bool,                                 //aplikacja wywołała close
+    eof     : bool,                                 //klient zamknął swoją stronę
+    written : u64,
+    checked : u64,
+    _guard  : OpenGuard,
+}
+
+
+impl UpgradedConnection {
+
+                                        //head - odpowiedź 101/200, buffered - dane klienta przeczytane razem z requestem
+    pub fn new(head: Vec<u8>, buffered: Vec<u8>, socket: Upgraded, guard: OpenGuard, handler: Box<UpgradeHandler>) -> UpgradedConnection {
+
+        let (events, receiver) = channel();
+        let pending            = Arc::new(AtomicUsize::new(buffered.len()));
+        let pending_handler    = pending.clone();
+
+        if buffered.len() > 0 {
+            let _ = events.send(HandlerEvent::Data(buffered));
+        }
+
+        thread::spawn(move || {
+            run_handler(handler, socket, receiver, pending_handler);
+        });
+
+        UpgradedConnection {
+            events  : events,
+            pending : pending,
+            output  : head,
+            closing : false,
+            eof     : false,
+            written : 0,
+            checked : 0,
+            _guard  : guard,
+        }
+    }
+
+                                        //czytanie wznawia handler, gdy odbierze zaległe dane
+    pub fn wants_read(&self) -> bool {
+        self.closing == false && self.eof == false && self.pending.load(Ordering::SeqCst) <= INPUT_LIMIT
+    }
+
+    pub fn wants_write(&self) -> bool {
+        self.output.len() > 0
+    }
+
+                                        //po zamknięciu strony klienta aplikacja może jeszcze wysyłać - kończy dopiero jej close
+    pub fn is_finished(&self) -> bool {
+        self.output.len() == 0 && self.closing
+    }
+
+                                        //klient nie odbiera danych
+    pub fn is_overloaded(&self) -> bool {
+        self.output.len() > OUTPUT_LIMIT
+    }
+
+    pub fn has_progress(&mut self) -> bool {
+
+        let progress = self.written != self.checked;
+        self.checked = self.written;
+        progress
+    }
+
+                                        //None - zamknięcie po wysłaniu kolejki
+    pub fn queue(&mut self, data: Option<Vec<u8>>) {
+
+        if self.closing {
+            return;
+        }
+
+        match data {
+            Some(data) => self.output.extend_from_slice(&data),
+            None => self.closing = true,
+        }
+    }
+
+    pub fn read_from(&mut self, stream: &mut Stream) -> io::Result<()> {
+
+        let mut buf = [0u8; 4096];
+        let mut total = 0;
+
+        while total < READ_LIMIT && self.wants_read() {
+
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    let _ = self.events.send(HandlerEvent::End);
+                },
+                Ok(size) => {
+                    self.pending.fetch_add(size, Ordering::SeqCst);
+                    let _ = self.events.send(HandlerEvent::Data(buf[0..size].to_vec()));
+                    total = total + size;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_to(&mut self, stream: &mut Stream) -> io::Result<()> {
+
+        while self.output.len() > 0 {
+
+            match stream.write(&self.output) {
+                Ok(0) => break,
+                Ok(size) => {
+                    self.output.drain(0..size);
+                    self.written = self.written + size as u64;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        try!(stream.flush_tls());
+
+        Ok(())
+    }
+}
+
+
+                                        //kanał zamyka się, gdy połączenie znika z event_loop-a - wtedy on_close
+fn run_handler(mut handler: Box<UpgradeHandler>, socket: Upgraded, events: Receiver<HandlerEvent>, pending: Arc<AtomicUsize>) {
+
+    handler.on_open(&socket);
+
+    for event in events.iter() {
+
+        match event {
+
+            HandlerEvent::Data(data) => {
+
+                handler.on_data(&socket, &data);
+
+                let before = pending.fetch_sub(data.len(), Ordering::SeqCst);
+
+                                        //event loop przestał czytać - pusta paczka danych go budzi
+                if before > INPUT_LIMIT && before - data.len() <= INPUT_LIMIT {
+                    let _ = socket.sender.send(MioMessage::Upgraded(socket.token, Some(Vec::new())));
+                }
+            },
+
+            HandlerEvent::End => handler.on_end(&socket),
+        }
+    }
+
+    handler.on_close();
+}
@@ -0,0 +1,457 @@
+use std::cmp::min;
+use std::str;
+use httparse;
+
+//https://tools.ietf.org/html/rfc7230#section-3.3.3
+//odpowiedzi serwerów, z którymi łączy się miohttp (proxy, klient)
+
+
+const MAX_HEAD : usize = 64 * 1024;
+
+
+/// Status line and headers of a response received from another server.
+#[derive(Clone, Debug)]
+pub struct ResponseHead {
+    status  : u16,
+    reason  : String,
+    version : u8,
+    headers : Vec<(String, String)>,
+}
+
+
+impl ResponseHead {
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn reason(&self) -> &String {
+        &(self.reason)
+    }
+
+                                        //0 - HTTP/1.0, 1 - HTTP/1.1
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Vec<(String, String)> {
+        &(self.headers)
+    }
+
+                                        //nazwy nagłówków nie rozróżniają wielkości liter, przy powtórzeniach pierwszy
+    pub fn header(&self, name: &str) -> Option<&String> {
+
+        let name = name.to_lowercase();
+
+        self.headers.iter().find(|&&(ref key, _)| key.to_lowercase() == name).map(|&(_, ref value)| value)
+    }
+
+                                        //czy po tej odpowiedzi połączenie można wykorzystać ponownie
+    pub fn keep_alive(&self) -> bool {
+
+        let tokens = connection_tokens(&self.headers);
+
+        if self.version == 0 {
+            tokens.iter().any(|token| token == "keep-alive")
+        } else {
+            tokens.iter().any(|token| token == "close") == false
+        }
+    }
+}
+
+
+                                        //Ok(None) - nagłówki jeszcze niekompletne; Ok(Some(nagłówki, długość nagłówków))
+pub fn parse_head(data: &[u8]) -> Result<Option<(ResponseHead, usize)>, String> {
+
+    let mut headers  = [httparse::EMPTY_HEADER; 100];
+    let mut response = httparse::Response::new(&mut headers);
+
+    match response.parse(data) {
+
+        Ok(httparse::Status::Complete(size)) => {
+
+            let mut list = Vec::new();
+
+            for header in response.headers.iter() {
+                match str::from_utf8(header.value) {
+                    Ok(value) => list.push((header.name.to_owned(), value.trim().to_owned())),
+                    Err(_) => return Err(format!("invalid header value: {}", header.name)),
+                }
+            }
+
+            let head = ResponseHead {
+                status  : response.code.unwrap_or(0),
+                reason  : response.reason.unwrap_or("").to_owned(),
+                version : response.version.unwrap_or(1),
+                headers : list,
+            };
+
+            Ok(Some((head, size)))
+        },
+
+        Ok(httparse::Status::Partial) => {
+
+            if data.len() >= MAX_HEAD {
+                return Err("response head too large".to_owned());
+            }
+
+            Ok(None)
+        },
+
+        Err(err) => Err(format!("invalid response head: {:?}", err)),
+    }
+}
+
+
+                                        //wartości nagłówków Connection, małymi literami
+pub fn connection_tokens(headers: &Vec<(String, String)>) -> Vec<String> {
+
+    let mut tokens = Vec::new();
+
+    for &(ref name, ref value) in headers.iter() {
+        if name.to_lowercase() == "connection" {
+            for token in value.split(',') {
+                let token = token.trim().to_lowercase();
+                if token.len() > 0 {
+                    tokens.push(token);
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+
+                                        //nagłówki dotyczące jednego połączenia - nie są przekazywane dalej (RFC 7230 6.1)
+pub fn is_hop_by_hop(name: &str, tokens: &Vec<String>) -> bool {
+
+    let name = name.to_lowercase();
+
+    match &name[..] {
+        "connection" | "keep-alive" | "proxy-connection" | "proxy-authenticate" | "proxy-authorization" |
+        "te" | "trailer" | "transfer-encoding" | "upgrade" => true,
+        _ => tokens.contains(&name),
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+
+                                        //jak rozpoznać koniec ciała odpowiedzi
+pub fn framing(request_method: &str, head: &ResponseHead) -> Result<Framing, String> {
+
+    let status = head.status;
+
+    if request_method == "HEAD" || (status >= 100 && status < 200) || status == 204 || status == 304 {
+        return Ok(Framing::Empty);
+    }
+
+    if let Some(encoding) = head.header("Transfer-Encoding") {
+
+        if encoding.split(',').last().map(|last| last.trim().to_lowercase() == "chunked") == Some(true) {
+            return Ok(Framing::Chunked);
+        }
+
+        return Ok(Framing::UntilClose);
+    }
+
+    if let Some(length) = head.header("Content-Length") {
+        return match length.trim().parse::<u64>() {
+            Ok(length) => Ok(if length == 0 { Framing::Empty } else { Framing::Length(length) }),
+            Err(_) => Err(format!("invalid Content-Length: {}", length)),
+        };
+    }
+
+    Ok(Framing::UntilClose)
+}
+
+
+enum ChunkState {
+    Size,                               //linia z rozmiarem kawałka
+    Data(u64),                          //pozostało bajtów kawałka
+    DataEnd,                            //CRLF za danymi kawałka
+    Trailer,                            //nagłówki po ostatnim kawałku
+    Done,
+}
+
+
+/// Takes the body out of the bytes received from the socket, according to its framing.
+pub struct BodyDecoder {
+    framing : Framing,
+    left    : u64,
+    chunk   : ChunkState,
+}
+
+
+impl BodyDecoder {
+
+    pub fn new(framing: Framing) -> BodyDecoder {
+
+        let left = match framing {
+            Framing::Length(length) => length,
+            _ => 0,
+        };
+
+        BodyDecoder {
+            framing : framing,
+            left    : left,
+            chunk   : ChunkState::Size,
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.framing {
+            Framing::Empty => true,
+            Framing::Length(_) => self.left == 0,
+            Framing::Chunked => match self.chunk {
+                ChunkState::Done => true,
+                _ => false,
+            },
+            Framing::UntilClose => false,
+        }
+    }
+
+                                        //zużywa bajty z input, dane ciała dopisuje do out; nadmiarowe bajty zostają w input
+    pub fn decode(&mut self, input: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<(), String> {
+
+        match self.framing {
+
+            Framing::Empty => Ok(()),
+
+            Framing::Length(_) => {
+                let size = min(self.left, input.len() as u64) as usize;
+                out.extend(input.drain(0..size));
+                self.left = self.left - size as u64;
+                Ok(())
+            },
+
+            Framing::UntilClose => {
+                out.extend(input.drain(..));
+                Ok(())
+            },
+
+            Framing::Chunked => self.decode_chunked(input, out),
+        }
+    }
+
+                                        //koniec połączenia - dla UntilClose oznacza koniec ciała, w pozostałych przypadkach błąd
+    pub fn finish(&mut self) -> Result<(), String> {
+
+        match self.framing {
+            Framing::UntilClose => {
+                self.framing = Framing::Length(0);
+                self.left    = 0;
+                Ok(())
+            },
+            _ if self.is_done() => Ok(()),
+            _ => Err("connection closed before end of body".to_owned()),
+        }
+    }
+
+    fn decode_chunked(&mut self, input: &mut Vec<u8>, out: &mut Vec<u8>) -> Result<(), String> {
+
+        loop {
+
+            match self.chunk {
+
+                ChunkState::Size => {
+
+                    let line = match take_line(input) {
+                        Some(line) => line,
+                        None => return if input.len() > 1024 { Err("chunk size line too long".to_owned()) } else { Ok(()) },
+                    };
+
+                                        //rozszerzenia po średniku są pomijane
+                    let size_part = line.split(';').next().unwrap_or("").trim().to_owned();
+
+                    let size = match u64::from_str_radix(&size_part, 16) {
+                        Ok(size) => size,
+                        Err(_) => return Err(format!("invalid chunk size: {}", size_part)),
+                    };
+
+                    self.chunk = if size == 0 { ChunkState::Trailer } else { ChunkState::Data(size) };
+                },
+
+                ChunkState::Data(left) => {
+
+                    if input.len() == 0 {
+                        return Ok(());
+                    }
+
+                    let size = min(left, input.len() as u64) as usize;
+
+                    out.extend(input.drain(0..size));
+
+                    self.chunk = if left == size as u64 { ChunkState::DataEnd } else { ChunkState::Data(left - size as u64) };
+                },
+
+                ChunkState::DataEnd => {
+
+                    match take_line(input) {
+                        Some(ref line) if line.len() == 0 => self.chunk = ChunkState::Size,
+                        Some(_) => return Err("missing CRLF after chunk".to_owned()),
+                        None => return Ok(()),
+                    }
+                },
+
+                ChunkState::Trailer => {
+
+                    match take_line(input) {
+                        Some(ref line) if line.len() == 0 => self.chunk = ChunkState::Done,
+                        Some(_) => {},
+                        None => return if input.len() > MAX_HEAD { Err("trailer too large".to_owned()) } else { Ok(()) },
+                    }
+                },
+
+                ChunkState::Done => return Ok(()),
+            }
+        }
+    }
+}
+
+
+                                        //linia zakończona CRLF (lub samym LF), bez znaku końca linii
+fn take_line(input: &mut Vec<u8>) -> Option<String> {
+
+    let pos = match input.iter().position(|byte| *byte == b'\n') {
+        Some(pos) => pos,
+        None => return None,
+    };
+
+    let line: Vec<u8> = input.drain(0..pos + 1).collect();
+
+    let end = if pos > 0 && line[pos - 1] == b'\r' { pos - 1 } else { pos };
+
+    Some(String::from_utf8_lossy(&line[0..end]).into_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{parse_head, framing, is_hop_by_hop, connection_tokens, BodyDecoder, Framing};
+
+    fn decode_all(decoder: &mut BodyDecoder, data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+
+        let mut input = data.to_vec();
+        let mut out   = Vec::new();
+
+        try!(decoder.decode(&mut input, &mut out));
+
+        Ok((out, input))
+    }
+
+    #[test]
+    fn chunked_body() {
+
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        let (out, rest) = decode_all(&mut decoder, b"5\r\nhello\r\n6;name=value\r\n world\r\n0\r\n\r\nHTTP/1.1").unwrap();
+
+        assert_eq!(out, b"hello world".to_vec());
+        assert_eq!(rest, b"HTTP/1.1".to_vec());
+        assert!(decoder.is_done());
+        assert_eq!(decoder.finish(), Ok(()));
+    }
+
+    #[test]
+    fn chunked_byte_by_byte() {
+
+        let data = b"A\r\n0123456789\r\n1\nx\n0\r\nX-Trailer: 1\r\nX-Other: 2\r\n\r\n";
+
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        let mut input   = Vec::new();
+        let mut out     = Vec::new();
+
+        for byte in data.iter() {
+            assert!(decoder.is_done() == false);
+            input.push(*byte);
+            decoder.decode(&mut input, &mut out).unwrap();
+        }
+
+        assert_eq!(out, b"0123456789x".to_vec());
+        assert_eq!(input.len(), 0);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn chunked_errors() {
+
+        assert!(decode_all(&mut BodyDecoder::new(Framing::Chunked), b"zz\r\n").is_err());
+        assert!(decode_all(&mut BodyDecoder::new(Framing::Chunked), b"\r\n").is_err());
+        assert!(decode_all(&mut BodyDecoder::new(Framing::Chunked), b"3\r\nabcX\r\n").is_err());
+        assert!(decode_all(&mut BodyDecoder::new(Framing::Chunked), &[b'1'; 2000]).is_err());
+
+        let mut decoder = BodyDecoder::new(Framing::Chunked);
+        decode_all(&mut decoder, b"5\r\nhel").unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    #[test]
+    fn length_and_until_close() {
+
+        let mut decoder = BodyDecoder::new(Framing::Length(5));
+        let (out, rest) = decode_all(&mut decoder, b"helloextra").unwrap();
+
+        assert_eq!(out, b"hello".to_vec());
+        assert_eq!(rest, b"extra".to_vec());
+        assert!(decoder.is_done());
+
+        let mut decoder = BodyDecoder::new(Framing::UntilClose);
+        let (out, _) = decode_all(&mut decoder, b"all of it").unwrap();
+
+        assert_eq!(out, b"all of it".to_vec());
+        assert_eq!(decoder.is_done(), false);
+        assert_eq!(decoder.finish(), Ok(()));
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn response_framing() {
+
+        let head = |text: &str| parse_head(text.as_bytes()).unwrap().unwrap().0;
+
+        assert_eq!(framing("GET", &head("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")), Ok(Framing::Length(10)));
+        assert_eq!(framing("HEAD", &head("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")), Ok(Framing::Empty));
+        assert_eq!(framing("GET", &head("HTTP/1.1 304 Not Modified\r\n\r\n")), Ok(Framing::Empty));
+        assert_eq!(framing("GET", &head("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\nContent-Length: 10\r\n\r\n")), Ok(Framing::Chunked));
+        assert_eq!(framing("GET", &head("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n")), Ok(Framing::UntilClose));
+        assert_eq!(framing("GET", &head("HTTP/1.0 200 OK\r\n\r\n")), Ok(Framing::UntilClose));
+        assert!(framing("GET", &head("HTTP/1.1 200 OK\r\nContent-Length: ten\r\n\r\n")).is_err());
+    }
+
+    #[test]
+    fn head_and_connection() {
+
+        assert!(parse_head(b"HTTP/1.1 200 OK\r\nServer: x\r\n").unwrap().is_none());
+        assert!(parse_head(b"garbage\r\n\r\n").is_err());
+
+        let (head, size) = parse_head(b"HTTP/1.0 404 Not Found\r\nConnection: Keep-Alive\r\n\r\nbody").unwrap().unwrap();
+
+        assert_eq!(size, 50);
+        assert_eq!(head.status(), 404);
+        assert_eq!(head.reason(), "Not Found");
+        assert!(head.keep_alive());
+
+        let (head, _) = parse_head(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").unwrap().unwrap();
+        assert_eq!(head.keep_alive(), false);
+
+        let tokens = connection_tokens(&vec![("Connection".to_owned(), "close, X-Custom".to_owned())]);
+
+        assert_eq!(tokens, vec!["close".to_owned(), "x-custom".to_owned()]);
+        assert!(is_hop_by_hop("Transfer-Encoding", &tokens));
+        assert!(is_hop_by_hop("X-Custom", &tokens));
+        assert_eq!(is_hop_by_hop("Content-Type", &tokens), false);
+    }
+}
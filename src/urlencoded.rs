@@ -0,0 +1,193 @@
+use std::io::{Error, ErrorKind};
+
+//https://url.spec.whatwg.org/#percent-encoded-bytes
+//https://url.spec.whatwg.org/#application/x-www-form-urlencoded
+
+
+fn hex_value(byte: u8) -> Option<u8> {
+
+    match byte {
+        b'0' ... b'9' => Some(byte - b'0'),
+        b'a' ... b'f' => Some(byte - b'a' + 10),
+        b'A' ... b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+
+fn percent_decode(input: &[u8], plus_as_space: bool) -> Vec<u8> {
+
+    let mut out   = Vec::with_capacity(input.len());
+    let mut index = 0;
+
+    while index < input.len() {
+
+        let byte = input[index];
+
+        if byte == b'%' && index + 2 < input.len() {
+
+            match (hex_value(input[index + 1]), hex_value(input[index + 2])) {
+
+                (Some(high), Some(low)) => {
+                    out.push(high * 16 + low);
+                    index = index + 3;
+                    continue;
+                },
+
+                                            //niepoprawna sekwencja zostaje przepisana bez zmian
+                _ => {}
+            }
+        }
+
+        if byte == b'+' && plus_as_space {
+            out.push(b' ');
+        } else {
+            out.push(byte);
+        }
+
+        index = index + 1;
+    }
+
+    out
+}
+
+
+fn into_utf8(bytes: Vec<u8>) -> Result<String, Error> {
+
+    match String::from_utf8(bytes) {
+        Ok(value) => Ok(value),
+        Err(err) => Err(Error::new(ErrorKind::InvalidInput, format!("error utf8 sequence: {}", err))),
+    }
+}
+
+
+/// Percent-decodes a path or any other URL component. `+` is left untouched.
+pub fn decode(input: &str) -> Result<String, Error> {
+    into_utf8(percent_decode(input.as_bytes(), false))
+}
+
+
+/// Decodes a single name or value of `application/x-www-form-urlencoded` data, where `+` means space.
+pub fn decode_form(input: &str) -> Result<String, Error> {
+    into_utf8(percent_decode(input.as_bytes(), true))
+}
+
+
+                                        //pary (nazwa, wartość) jeszcze jako bajty, po zdekodowaniu procentów
+fn split_form(input: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+
+    let mut out = Vec::new();
+
+    for sequence in input.split(|byte| *byte == b'&') {
+
+        if sequence.len() == 0 {
+            continue;
+        }
+
+        let (name, value) = match sequence.iter().position(|byte| *byte == b'=') {
+            Some(pos) => (&sequence[0..pos], &sequence[pos + 1..]),
+            None      => (sequence, &sequence[sequence.len()..]),
+        };
+
+        out.push((percent_decode(name, true), percent_decode(value, true)));
+    }
+
+    out
+}
+
+
+/// Splits `application/x-www-form-urlencoded` data (a query string or a form body) into decoded pairs, keeping their order.
+pub fn parse_form(input: &[u8]) -> Result<Vec<(String, String)>, Error> {
+
+    let mut out = Vec::new();
+
+    for (name, value) in split_form(input) {
+        out.push((try!(into_utf8(name)), try!(into_utf8(value))));
+    }
+
+    Ok(out)
+}
+
+
+/// Percent-encodes everything except the unreserved characters of RFC 3986, so the result is safe in a path segment or a query.
+pub fn encode(input: &str) -> String {
+
+    let mut out = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+
+        match byte {
+            b'A' ... b'Z' | b'a' ... b'z' | b'0' ... b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char);
+            },
+            _ => {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{decode, decode_form, parse_form, encode};
+
+    #[test]
+    fn decode_percent() {
+        assert_eq!(decode("a%20b%2Fc").unwrap(), "a b/c");
+        assert_eq!(decode("a+b").unwrap(), "a+b");
+        assert_eq!(decode("%C5%BC%C3%B3%C5%82w").unwrap(), "żółw");
+    }
+
+    #[test]
+    fn decode_keeps_invalid_sequences() {
+        assert_eq!(decode("100%").unwrap(), "100%");
+        assert_eq!(decode("%4").unwrap(), "%4");
+        assert_eq!(decode("%zz%41").unwrap(), "%zzA");
+    }
+
+    #[test]
+    fn decode_rejects_invalid_utf8() {
+        assert!(decode("%FF").is_err());
+        assert!(decode_form("a%C5").is_err());
+    }
+
+    #[test]
+    fn decode_form_plus() {
+        assert_eq!(decode_form("a+b%2B").unwrap(), "a b+");
+    }
+
+    #[test]
+    fn parse_form_pairs() {
+        let pairs = parse_form(b"a=1&b=x+y&&c&d=%3D&a=2").unwrap();
+        assert_eq!(pairs, vec![
+            ("a".to_owned(), "1".to_owned()),
+            ("b".to_owned(), "x y".to_owned()),
+            ("c".to_owned(), "".to_owned()),
+            ("d".to_owned(), "=".to_owned()),
+            ("a".to_owned(), "2".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn parse_form_value_with_equals() {
+        let pairs = parse_form(b"k=a=b").unwrap();
+        assert_eq!(pairs, vec![("k".to_owned(), "a=b".to_owned())]);
+    }
+
+    #[test]
+    fn parse_form_invalid_utf8() {
+        assert!(parse_form(b"a=%FF").is_err());
+        assert!(parse_form(b"a=1&b=%FF").is_err());
+        assert!(parse_form(b"%C5=1").is_err());
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        assert_eq!(encode("a b/ż~"), "a%20b%2F%C5%BC~");
+        assert_eq!(decode(&encode("a b/ż~")).unwrap(), "a b/ż~");
+    }
+}
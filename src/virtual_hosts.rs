@@ -0,0 +1,153 @@
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use server::FnReceiver;
+
+
+/// Dispatches requests by the `Host` header (case-insensitive, port stripped). A pattern is either an exact name
+/// or `*.example.com`, matching any subdomain of example.com (but not example.com itself). Exact names win over wildcards,
+/// and among wildcards the longest suffix wins.
+pub struct VirtualHosts {
+    exact     : Vec<(String, FnReceiver)>,
+    wildcards : Vec<(String, FnReceiver)>,      //sufiks z kropką na początku, np. ".example.com"
+    default   : Option<FnReceiver>,
+}
+
+
+impl VirtualHosts {
+
+    pub fn new() -> VirtualHosts {
+
+        VirtualHosts {
+            exact     : Vec::new(),
+            wildcards : Vec::new(),
+            default   : None,
+        }
+    }
+
+    pub fn host(mut self, pattern: &str, handler: FnReceiver) -> VirtualHosts {
+
+        let pattern = pattern.trim().to_lowercase();
+
+        if pattern.starts_with("*.") {
+
+            self.wildcards.push((pattern[1..].to_owned(), handler));
+
+                                        //najdłuższy sufiks sprawdzany jako pierwszy
+            self.wildcards.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        } else {
+            self.exact.push((pattern, handler));
+        }
+
+        self
+    }
+
+                                        //obsługa nieznanych hostów (domyślnie 404)
+    pub fn default(mut self, handler: FnReceiver) -> VirtualHosts {
+        self.default = Some(handler);
+        self
+    }
+
+    pub fn build(self) -> FnReceiver {
+
+        Box::new(move|request: Request| {
+            self.handle(request);
+        })
+    }
+
+    fn find(&self, host: &str) -> Option<&FnReceiver> {
+
+        for &(ref name, ref handler) in self.exact.iter() {
+            if name == host {
+                return Some(handler);
+            }
+        }
+
+        for &(ref suffix, ref handler) in self.wildcards.iter() {
+            if host.len() > suffix.len() && host.ends_with(&suffix[..]) {
+                return Some(handler);
+            }
+        }
+
+        None
+    }
+
+    pub fn handle(&self, request: Request) {
+
+        let handler = match request.host() {
+            Some(host) => self.find(&host),
+            None => None,
+        };
+
+        match handler.or(self.default.as_ref()) {
+            Some(handler) => handler(request),
+            None => request.send(Response::create(Code::Code404, Type::TextHtml, "404 Not Found".to_owned())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::ptr;
+    use request::Request;
+    use super::VirtualHosts;
+
+    fn hosts() -> VirtualHosts {
+        VirtualHosts::new()
+            .host("Example.com", Box::new(|_: Request| {}))
+            .host("*.example.com", Box::new(|_: Request| {}))
+            .host("*.api.example.com", Box::new(|_: Request| {}))
+            .host("www.example.com", Box::new(|_: Request| {}))
+    }
+
+                                        //pattern, którego handler zwrócił find
+    fn found(hosts: &VirtualHosts, host: &str) -> Option<String> {
+
+        let handler = match hosts.find(host) {
+            Some(handler) => handler,
+            None => return None,
+        };
+
+        for &(ref name, ref candidate) in hosts.exact.iter() {
+            if ptr::eq(candidate, handler) {
+                return Some(name.clone());
+            }
+        }
+
+        for &(ref suffix, ref candidate) in hosts.wildcards.iter() {
+            if ptr::eq(candidate, handler) {
+                return Some(format!("*{}", suffix));
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn exact() {
+        let hosts = hosts();
+        assert_eq!(found(&hosts, "example.com"), Some("example.com".to_owned()));
+        assert_eq!(found(&hosts, "www.example.com"), Some("www.example.com".to_owned()));
+        assert_eq!(found(&hosts, "example.org"), None);
+    }
+
+    #[test]
+    fn wildcard() {
+        let hosts = hosts();
+        assert_eq!(found(&hosts, "blog.example.com"), Some("*.example.com".to_owned()));
+        assert_eq!(found(&hosts, "a.b.example.com"), Some("*.example.com".to_owned()));
+        assert_eq!(found(&hosts, "v1.api.example.com"), Some("*.api.example.com".to_owned()));
+        assert_eq!(found(&hosts, "api.example.com"), Some("*.example.com".to_owned()));
+    }
+
+    #[test]
+    fn wildcard_needs_subdomain() {
+        let hosts = hosts();
+        assert_eq!(found(&hosts, ".example.com"), None);
+        assert_eq!(found(&hosts, "badexample.com"), None);
+    }
+}
@@ -0,0 +1,832 @@
+use std::io;
+use std::io::{Read, Write, ErrorKind};
+use std::str;
+use std::cmp::min;
+use mio::{Token, Sender};
+use crypto::sha1::Sha1;
+use crypto::digest::Digest;
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
+use server::MioMessage;
+use request::Request;
+use response::Response;
+use code::Code;
+use typemod::Type;
+use stream::Stream;
+
+//https://tools.ietf.org/html/rfc6455
+
+
+const GUID         : &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_MESSAGE  : usize = 16 * 1024 * 1024;      //razem z fragmentami
+const READ_LIMIT   : usize = 64 * 1024;
+const OUTPUT_LIMIT : usize = 4 * 1024 * 1024;       //ramki czekające na wolnego klienta
+
+const OPCODE_CONTINUATION : u8 = 0x0;
+const OPCODE_TEXT         : u8 = 0x1;
+const OPCODE_BINARY       : u8 = 0x2;
+const OPCODE_CLOSE        : u8 = 0x8;
+const OPCODE_PING         : u8 = 0x9;
+const OPCODE_PONG         : u8 = 0xa;
+
+const CLOSE_NORMAL        : u16 = 1000;
+const CLOSE_GOING_AWAY    : u16 = 1001;
+const CLOSE_PROTOCOL      : u16 = 1002;
+const CLOSE_NO_STATUS     : u16 = 1005;
+const CLOSE_ABNORMAL      : u16 = 1006;
+const CLOSE_INVALID_DATA  : u16 = 1007;
+const CLOSE_POLICY        : u16 = 1008;
+const CLOSE_TOO_BIG       : u16 = 1009;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+
+                                        //polecenia od WebSocket do połączenia w event_loop-ie
+pub enum Frame {
+    Message(Message),
+    Ping(Vec<u8>),
+    Close(u16, String),
+}
+
+
+/// Handle of an open WebSocket. Cheap to clone; messages are queued through the event loop channel,
+/// so it can be used from any thread. Sending on a closed connection is silently ignored.
+#[derive(Clone)]
+pub struct WebSocket {
+    token  : Token,
+    sender : Sender<MioMessage>,
+}
+
+
+impl WebSocket {
+
+    pub fn new(token: Token, sender: Sender<MioMessage>) -> WebSocket {
+
+        WebSocket {
+            token  : token,
+            sender : sender,
+        }
+    }
+
+    pub fn send(&self, message: Message) {
+        let _ = self.sender.send(MioMessage::WebSocket(self.token, Frame::Message(message)));
+    }
+
+    pub fn send_text(&self, text: &str) {
+        self.send(Message::Text(text.to_owned()));
+    }
+
+    pub fn send_binary(&self, data: Vec<u8>) {
+        self.send(Message::Binary(data));
+    }
+
+                                        //maksymalnie 125 bajtów
+    pub fn ping(&self, data: Vec<u8>) {
+        let _ = self.sender.send(MioMessage::WebSocket(self.token, Frame::Ping(data)));
+    }
+
+                                        //zamknięcie z kodem (np. 1000) - połączenie zostanie zerwane po odpowiedzi klienta
+    pub fn close(&self, code: u16, reason: &str) {
+        let _ = self.sender.send(MioMessage::WebSocket(self.token, Frame::Close(code, reason.to_owned())));
+    }
+}
+
+
+/// Receives the events of one WebSocket connection. All methods run on the event loop thread, so they should not block;
+/// longer work should go to another thread with a clone of the `WebSocket` handle.
+pub trait WebSocketHandler: Send {
+
+                                        //handshake wysłany, można już wysyłać wiadomości
+    fn on_open(&mut self, _socket: &WebSocket) {
+    }
+
+    fn on_message(&mut self, socket: &WebSocket, message: Message);
+
+                                        //wywoływane dokładnie raz; 1006 - połączenie zerwane bez ramki close
+    fn on_close(&mut self, _code: u16, _reason: &str) {
+    }
+}
+
+
+fn has_token(value: Option<&String>, token: &str) -> bool {
+
+    match value {
+        Some(value) => value.split(',').any(|item| item.trim().to_lowercase() == token),
+        None => false,
+    }
+}
+
+
+pub fn accept_key(key: &str) -> String {
+
+    let mut hasher = Sha1::new();
+    let mut digest = [0u8; 20];
+
+    hasher.input_str(key);
+    hasher.input_str(GUID);
+    hasher.result(&mut digest);
+
+    digest.to_base64(STANDARD)
+}
+
+
+                                        //Ok - odpowiedź 101, Err - odpowiedź z błędem dla klienta
+pub fn handshake(request: &Request, protocol: Option<&str>) -> Result<Response, Response> {
+
+    let bad_request = || Response::create(Code::Code400, Type::TextHtml, "400 Bad Request".to_owned());
+
+    if request.method() != "GET" || request.version() != 1 {
+        return Err(bad_request());
+    }
+
+    if has_token(request.header("Upgrade"), "websocket") == false || has_token(request.header("Connection"), "upgrade") == false {
+        return Err(bad_request());
+    }
+
+    if request.header("Sec-WebSocket-Version").map(|version| version.trim() == "13") != Some(true) {
+
+        let mut response = Response::create(Code::Code426, Type::TextHtml, "426 Upgrade Required".to_owned());
+        response.add_header("Sec-WebSocket-Version", "13");
+
+        return Err(response);
+    }
+
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) => key.trim().to_owned(),
+        None => return Err(bad_request()),
+    };
+
+    match key.from_base64() {
+        Ok(ref decoded) if decoded.len() == 16 => {},
+        _ => return Err(bad_request()),
+    }
+
+    let mut response = Response::create_from_buf(Code::Code101, Type::TextHtml, Vec::new());
+
+    response.add_header("Upgrade", "websocket");
+    response.add_header("Connection", "Upgrade");
+    response.add_header("Sec-WebSocket-Accept", &accept_key(&key));
+
+    if let Some(protocol) = protocol {
+        response.add_header("Sec-WebSocket-Protocol", protocol);
+    }
+
+    Ok(response)
+}
+
+
+                                        //podprotokoły zaproponowane przez klienta w Sec-WebSocket-Protocol
+pub fn protocols(request: &Request) -> Vec<String> {
+
+    match request.header("Sec-WebSocket-Protocol") {
+        Some(value) => value.split(',').map(|item| item.trim().to_owned()).filter(|item| item.len() > 0).collect(),
+        None => Vec::new(),
+    }
+}
+
+
+fn push_frame(out: &mut Vec<u8>, opcode: u8, payload: &[u8]) {
+
+    let len = payload.len();
+
+    out.push(0x80 | opcode);
+
+    if len < 126 {
+        out.push(len as u8);
+    } else if len <= 0xffff {
+        out.push(126);
+        out.push((len >> 8) as u8);
+        out.push(len as u8);
+    } else {
+        out.push(127);
+        for shift in (0..8).rev() {
+            out.push(((len as u64) >> (shift * 8)) as u8);
+        }
+    }
+
+    out.extend_from_slice(payload);
+}
+
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+
+    let mut payload = vec![(code >> 8) as u8, code as u8];
+
+                                        //ramka kontrolna - najwyżej 125 bajtów
+    let mut end = min(reason.len(), 123);
+
+    while reason.is_char_boundary(end) == false {
+        end = end - 1;
+    }
+
+    payload.extend_from_slice(reason[0..end].as_bytes());
+
+    payload
+}
+
+
+fn valid_close_code(code: u16) -> bool {
+
+    (code >= 1000 && code <= 1003) || (code >= 1007 && code <= 1011) || (code >= 3000 && code <= 4999)
+}
+
+
+/// Connection side of a WebSocket: frame parsing and the closing handshake.
+pub struct WsConnection {
+    socket     : WebSocket,
+    handler    : Box<WebSocketHandler>,
+    input      : Vec<u8>,
+    output     : Vec<u8>,
+    fragments  : Option<(u8, Vec<u8>)>,               //opcode pierwszej ramki, zebrane dane
+    close_sent : bool,
+    close_recv : bool,
+    reported   : bool,                                //czy on_close już wywołane
+    written    : u64,
+    checked    : u64,
+}
+
+
+impl WsConnection {
+
+                                        //head - odpowiedź 101 wysyłana jako pierwsza
+    pub fn new(head: Vec<u8>, socket: WebSocket, mut handler: Box<WebSocketHandler>) -> WsConnection {
+
+        handler.on_open(&socket);
+
+        WsConnection {
+            socket     : socket,
+            handler    : handler,
+            input      : Vec::new(),
+            output     : head,
+            fragments  : None,
+            close_sent : false,
+            close_recv : false,
+            reported   : false,
+            written    : 0,
+            checked    : 0,
+        }
+    }
+
+    pub fn wants_write(&self) -> bool {
+        self.output.len() > 0
+    }
+
+                                        //wysłaliśmy close i czekamy na odpowiedź klienta
+    pub fn is_closing(&self) -> bool {
+        self.close_sent && self.close_recv == false
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.output.len() == 0 && self.close_sent && self.close_recv
+    }
+
+    pub fn has_progress(&mut self) -> bool {
+
+        let progress = self.written != self.checked;
+        self.checked = self.written;
+        progress
+    }
+
+                                        //połączenie zamykane bez wymiany ramek close
+    pub fn abort(&mut self) {
+        self.report(CLOSE_ABNORMAL, "");
+    }
+
+    pub fn queue(&mut self, frame: Frame) {
+
+        if self.close_sent {
+            return;
+        }
+
+        match frame {
+            Frame::Message(Message::Text(text)) => push_frame(&mut self.output, OPCODE_TEXT, text.as_bytes()),
+            Frame::Message(Message::Binary(data)) => push_frame(&mut self.output, OPCODE_BINARY, &data),
+            Frame::Ping(mut data) => {
+                data.truncate(125);
+                push_frame(&mut self.output, OPCODE_PING, &data);
+            },
+            Frame::Close(code, reason) => {
+                push_frame(&mut self.output, OPCODE_CLOSE, &close_payload(code, &reason));
+                self.close_sent = true;
+            },
+        }
+
+        self.check_output();
+    }
+
+                                        //klient nie odbiera danych - kończymy kolejkę ramką close i nie czekamy na jego odpowiedź
+    fn check_output(&mut self) {
+
+        if self.output.len() > OUTPUT_LIMIT && self.close_sent == false {
+
+            push_frame(&mut self.output, OPCODE_CLOSE, &close_payload(CLOSE_POLICY, "client too slow"));
+
+            self.close_sent = true;
+            self.close_recv = true;
+            self.input.clear();
+            self.report(CLOSE_POLICY, "client too slow");
+        }
+    }
+
+                                        //wyłączanie serwera
+    pub fn going_away(&mut self) {
+        self.queue(Frame::Close(CLOSE_GOING_AWAY, "server shutdown".to_owned()));
+    }
+
+    pub fn read_from(&mut self, stream: &mut Stream) -> io::Result<bool> {
+
+        let mut buf = [0u8; 4096];
+        let mut total = 0;
+
+        while total < READ_LIMIT {
+
+            match stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(size) => {
+                    self.input.extend_from_slice(&buf[0..size]);
+                    total = total + size;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn write_to(&mut self, stream: &mut Stream) -> io::Result<()> {
+
+        while self.output.len() > 0 {
+
+            match stream.write(&self.output) {
+                Ok(0) => break,
+                Ok(size) => {
+                    self.output.drain(0..size);
+                    self.written = self.written + size as u64;
+                },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        try!(stream.flush_tls());
+
+        Ok(())
+    }
+
+                                        //Err - błąd protokołu, ramka close z kodem już w kolejce
+    pub fn process(&mut self) -> Result<(), String> {
+
+        loop {
+
+            if self.close_recv {
+                self.input.clear();
+                return Ok(());
+            }
+
+            match self.next_frame() {
+                Ok(Some((fin, opcode, payload))) => try!(self.on_frame(fin, opcode, payload)),
+                Ok(None) => return Ok(()),
+                Err((code, message)) => return self.fail(code, message),
+            }
+        }
+    }
+
+    fn fail(&mut self, code: u16, message: String) -> Result<(), String> {
+
+        self.queue(Frame::Close(code, "".to_owned()));
+        self.close_sent = true;
+        self.close_recv = true;
+        self.input.clear();
+        self.report(code, "");
+
+        Err(message)
+    }
+
+    fn report(&mut self, code: u16, reason: &str) {
+
+        if self.reported == false {
+            self.reported = true;
+            self.handler.on_close(code, reason);
+        }
+    }
+
+                                        //(FIN, opcode, odmaskowane dane)
+    fn next_frame(&mut self) -> Result<Option<(bool, u8, Vec<u8>)>, (u16, String)> {
+
+        if self.input.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin    = self.input[0] & 0x80 != 0;
+        let rsv    = self.input[0] & 0x70;
+        let opcode = self.input[0] & 0x0f;
+        let masked = self.input[1] & 0x80 != 0;
+        let len7   = (self.input[1] & 0x7f) as usize;
+
+        if rsv != 0 {
+            return Err((CLOSE_PROTOCOL, "reserved bits set".to_owned()));
+        }
+
+                                        //ramki od klienta muszą być maskowane
+        if masked == false {
+            return Err((CLOSE_PROTOCOL, "unmasked client frame".to_owned()));
+        }
+
+        let (len, header) = match len7 {
+
+            126 => {
+                if self.input.len() < 4 {
+                    return Ok(None);
+                }
+                (((self.input[2] as usize) << 8) | self.input[3] as usize, 4)
+            },
+
+            127 => {
+                if self.input.len() < 10 {
+                    return Ok(None);
+                }
+
+                let mut len = 0u64;
+
+                for byte in self.input[2..10].iter() {
+                    len = (len << 8) | *byte as u64;
+                }
+
+                if len > MAX_MESSAGE as u64 {
+                    return Err((CLOSE_TOO_BIG, format!("frame too large: {}", len)));
+                }
+
+                (len as usize, 10)
+            },
+
+            len => (len, 2),
+        };
+
+        if len > MAX_MESSAGE {
+            return Err((CLOSE_TOO_BIG, format!("frame too large: {}", len)));
+        }
+
+        if self.input.len() < header + 4 + len {
+            return Ok(None);
+        }
+
+        let mask = [self.input[header], self.input[header + 1], self.input[header + 2], self.input[header + 3]];
+
+        let payload: Vec<u8> = self.input[header + 4..header + 4 + len].iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]).collect();
+
+        self.input.drain(0..header + 4 + len);
+
+        Ok(Some((fin, opcode, payload)))
+    }
+
+    fn on_frame(&mut self, fin: bool, opcode: u8, payload: Vec<u8>) -> Result<(), String> {
+
+        match opcode {
+
+            OPCODE_CONTINUATION => {
+
+                let (first, mut data) = match self.fragments.take() {
+                    Some(fragments) => fragments,
+                    None => return self.fail(CLOSE_PROTOCOL, "continuation without a message".to_owned()),
+                };
+
+                if data.len() + payload.len() > MAX_MESSAGE {
+                    return self.fail(CLOSE_TOO_BIG, "message too large".to_owned());
+                }
+
+                data.extend_from_slice(&payload);
+
+                if fin {
+                    self.deliver(first, data)
+                } else {
+                    self.fragments = Some((first, data));
+                    Ok(())
+                }
+            },
+
+            OPCODE_TEXT | OPCODE_BINARY => {
+
+                if self.fragments.is_some() {
+                    return self.fail(CLOSE_PROTOCOL, "new message inside a fragmented one".to_owned());
+                }
+
+                if fin {
+                    self.deliver(opcode, payload)
+                } else {
+                    self.fragments = Some((opcode, payload));
+                    Ok(())
+                }
+            },
+
+            OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => {
+
+                                        //ramki kontrolne mogą przeplatać fragmenty, ale same nie są dzielone
+                if fin == false || payload.len() > 125 {
+                    return self.fail(CLOSE_PROTOCOL, "invalid control frame".to_owned());
+                }
+
+                match opcode {
+                    OPCODE_CLOSE => self.on_close_frame(payload),
+                    OPCODE_PING => {
+                        if self.close_sent == false {
+                            push_frame(&mut self.output, OPCODE_PONG, &payload);
+                            self.check_output();
+                        }
+                        Ok(())
+                    },
+                    _ => Ok(()),
+                }
+            },
+
+            _ => self.fail(CLOSE_PROTOCOL, format!("unknown opcode {}", opcode)),
+        }
+    }
+
+    fn deliver(&mut self, opcode: u8, data: Vec<u8>) -> Result<(), String> {
+
+        let message = if opcode == OPCODE_TEXT {
+            match String::from_utf8(data) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return self.fail(CLOSE_INVALID_DATA, "invalid utf-8 in text message".to_owned()),
+            }
+        } else {
+            Message::Binary(data)
+        };
+
+                                        //po wysłaniu close nie przekazujemy już wiadomości
+        if self.close_sent == false {
+            self.handler.on_message(&self.socket, message);
+        }
+
+        Ok(())
+    }
+
+    fn on_close_frame(&mut self, payload: Vec<u8>) -> Result<(), String> {
+
+        let (code, reason) = match payload.len() {
+
+            0 => (CLOSE_NO_STATUS, "".to_owned()),
+
+            1 => return self.fail(CLOSE_PROTOCOL, "close frame with one byte".to_owned()),
+
+            _ => {
+
+                let code = ((payload[0] as u16) << 8) | payload[1] as u16;
+
+                if valid_close_code(code) == false {
+                    return self.fail(CLOSE_PROTOCOL, format!("invalid close code {}", code));
+                }
+
+                match str::from_utf8(&payload[2..]) {
+                    Ok(reason) => (code, reason.to_owned()),
+                    Err(_) => return self.fail(CLOSE_INVALID_DATA, "invalid utf-8 in close reason".to_owned()),
+                }
+            }
+        };
+
+                                        //odpowiadamy tym samym kodem, chyba że sami zaczęliśmy zamykanie
+        if self.close_sent == false {
+
+            let echo = if code == CLOSE_NO_STATUS { CLOSE_NORMAL } else { code };
+
+            self.queue(Frame::Close(echo, "".to_owned()));
+        }
+
+        self.close_recv = true;
+        self.report(code, &reason);
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+    use mio::{EventLoop, Token};
+    use server::MyHandler;
+    use super::*;
+
+    struct Recorder {
+        messages : Arc<Mutex<Vec<Message>>>,
+        closed   : Arc<Mutex<Vec<(u16, String)>>>,
+    }
+
+    impl WebSocketHandler for Recorder {
+
+        fn on_message(&mut self, _socket: &WebSocket, message: Message) {
+            self.messages.lock().unwrap().push(message);
+        }
+
+        fn on_close(&mut self, code: u16, reason: &str) {
+            self.closed.lock().unwrap().push((code, reason.to_owned()));
+        }
+    }
+
+    fn connection() -> (WsConnection, Arc<Mutex<Vec<Message>>>, Arc<Mutex<Vec<(u16, String)>>>) {
+
+        let event_loop: EventLoop<MyHandler> = EventLoop::new().unwrap();
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let closed   = Arc::new(Mutex::new(Vec::new()));
+
+        let handler = Recorder {
+            messages : messages.clone(),
+            closed   : closed.clone(),
+        };
+
+        let ws = WsConnection::new(Vec::new(), WebSocket::new(Token(1), event_loop.channel()), Box::new(handler));
+
+        (ws, messages, closed)
+    }
+
+                                        //ramka klienta - zawsze maskowana
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+
+        let mask = [0x12, 0x34, 0x56, 0x78];
+
+        let mut frame = Vec::new();
+        push_frame(&mut frame, opcode, payload);
+
+        if fin == false {
+            frame[0] = frame[0] & 0x7f;
+        }
+
+        let header = frame.len() - payload.len();
+
+        frame[1] = frame[1] | 0x80;
+
+        let masked: Vec<u8> = payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]).collect();
+
+        frame.truncate(header);
+        frame.extend_from_slice(&mask);
+        frame.extend_from_slice(&masked);
+        frame
+    }
+
+    fn closed_with(output: &[u8]) -> Option<u16> {
+
+        let len = output.len();
+
+        if len >= 4 && output[len - 4] == 0x80 | OPCODE_CLOSE {
+            return Some(((output[len - 2] as u16) << 8) | output[len - 1] as u16);
+        }
+
+        None
+    }
+
+    #[test]
+    fn accept_key_example() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frame_lengths() {
+
+        let mut out = Vec::new();
+        push_frame(&mut out, OPCODE_TEXT, b"hello");
+        assert_eq!(out, b"\x81\x05hello".to_vec());
+
+        let mut out = Vec::new();
+        push_frame(&mut out, OPCODE_BINARY, &[0u8; 200]);
+        assert_eq!(&out[0..4], &[0x82, 126, 0, 200]);
+        assert_eq!(out.len(), 204);
+
+        let mut out = Vec::new();
+        push_frame(&mut out, OPCODE_BINARY, &[0u8; 70000]);
+        assert_eq!(&out[0..10], &[0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+        assert_eq!(out.len(), 70010);
+    }
+
+    #[test]
+    fn close_reason_truncated() {
+
+        let reason: String = ::std::iter::repeat('ż').take(100).collect();
+        let payload = close_payload(CLOSE_NORMAL, &reason);
+
+        assert_eq!(&payload[0..2], &[0x03, 0xe8]);
+        assert!(payload.len() <= 125);
+        assert!(str::from_utf8(&payload[2..]).is_ok());
+
+        assert!(valid_close_code(1000));
+        assert!(valid_close_code(4000));
+        assert_eq!(valid_close_code(1005), false);
+        assert_eq!(valid_close_code(1006), false);
+        assert_eq!(valid_close_code(2000), false);
+    }
+
+    #[test]
+    fn masked_messages() {
+
+        let (mut ws, messages, _) = connection();
+
+        let mut input = client_frame(true, OPCODE_TEXT, "zażółć".as_bytes());
+        input.extend_from_slice(&client_frame(true, OPCODE_BINARY, &[0u8; 300]));
+
+                                        //dane przychodzą w kawałkach
+        for chunk in input.chunks(7) {
+            ws.input.extend_from_slice(chunk);
+            assert_eq!(ws.process(), Ok(()));
+        }
+
+        assert_eq!(*messages.lock().unwrap(), vec![Message::Text("zażółć".to_owned()), Message::Binary(vec![0u8; 300])]);
+        assert_eq!(ws.input.len(), 0);
+    }
+
+    #[test]
+    fn fragments_with_ping() {
+
+        let (mut ws, messages, _) = connection();
+
+        ws.input.extend_from_slice(&client_frame(false, OPCODE_TEXT, b"hel"));
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_PING, b"p"));
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_CONTINUATION, b"lo"));
+
+        assert_eq!(ws.process(), Ok(()));
+        assert_eq!(*messages.lock().unwrap(), vec![Message::Text("hello".to_owned())]);
+        assert_eq!(ws.output, b"\x8a\x01p".to_vec());
+    }
+
+    #[test]
+    fn protocol_errors() {
+
+        let (mut ws, _, closed) = connection();
+        ws.input.extend_from_slice(b"\x81\x02hi");
+        assert!(ws.process().is_err());
+        assert_eq!(closed_with(&ws.output), Some(CLOSE_PROTOCOL));
+        assert_eq!(closed.lock().unwrap()[0].0, CLOSE_PROTOCOL);
+
+        let (mut ws, _, _) = connection();
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_TEXT, &[0xff, 0xfe]));
+        assert!(ws.process().is_err());
+        assert_eq!(closed_with(&ws.output), Some(CLOSE_INVALID_DATA));
+
+        let (mut ws, _, _) = connection();
+        ws.input.extend_from_slice(&[0x82, 0xff, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert!(ws.process().is_err());
+        assert_eq!(closed_with(&ws.output), Some(CLOSE_TOO_BIG));
+
+        let (mut ws, _, _) = connection();
+        ws.input.extend_from_slice(&client_frame(false, OPCODE_PING, b""));
+        assert!(ws.process().is_err());
+        assert_eq!(closed_with(&ws.output), Some(CLOSE_PROTOCOL));
+
+        let (mut ws, _, _) = connection();
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_CONTINUATION, b"x"));
+        assert!(ws.process().is_err());
+        assert_eq!(closed_with(&ws.output), Some(CLOSE_PROTOCOL));
+    }
+
+    #[test]
+    fn closing_handshake() {
+
+        let (mut ws, _, closed) = connection();
+
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_CLOSE, &close_payload(CLOSE_GOING_AWAY, "bye")));
+
+        assert_eq!(ws.process(), Ok(()));
+        assert_eq!(ws.output, b"\x88\x02\x03\xe9".to_vec());
+        assert_eq!(*closed.lock().unwrap(), vec![(CLOSE_GOING_AWAY, "bye".to_owned())]);
+
+        ws.output.clear();
+        assert!(ws.is_finished());
+
+                                        //on_close wywoływane tylko raz
+        ws.abort();
+        assert_eq!(closed.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn slow_client() {
+
+        let (mut ws, _, closed) = connection();
+
+        for _ in 0..5 {
+            ws.queue(Frame::Message(Message::Binary(vec![0u8; 1024 * 1024])));
+        }
+
+        let len = ws.output.len();
+
+        assert_eq!(closed_with(&ws.output[..len - 15]), Some(CLOSE_POLICY));
+        assert_eq!(*closed.lock().unwrap(), vec![(CLOSE_POLICY, "client too slow".to_owned())]);
+
+        ws.queue(Frame::Message(Message::Text("ignored".to_owned())));
+        assert_eq!(ws.output.len(), len);
+        assert_eq!(ws.is_closing(), false);
+
+        let (mut ws, _, closed) = connection();
+
+        ws.output = vec![0u8; OUTPUT_LIMIT];
+        ws.input.extend_from_slice(&client_frame(true, OPCODE_PING, b"ping"));
+
+        assert_eq!(ws.process(), Ok(()));
+        assert_eq!(closed_with(&ws.output[..ws.output.len() - 15]), Some(CLOSE_POLICY));
+        assert_eq!(closed.lock().unwrap()[0].0, CLOSE_POLICY);
+    }
+}
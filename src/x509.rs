@@ -0,0 +1,439 @@
+use std::net::Ipv6Addr;
+use crypto::sha2::Sha256;
+use crypto::digest::Digest;
+
+//https://tools.ietf.org/html/rfc5280#section-4.1
+//tylko tyle DER-a, ile trzeba do odczytania podmiotu i SAN - certyfikat jest już zweryfikowany przez TLS
+
+
+/// Identity of a verified client certificate.
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+    subject     : String,
+    common_name : Option<String>,
+    sans        : Vec<String>,
+    fingerprint : String,
+}
+
+
+impl ClientCert {
+
+    /// Parses a DER certificate. `None` when the structure can't be read.
+    pub fn from_der(der: &[u8]) -> Option<ClientCert> {
+
+        let (subject, sans) = match parse(der) {
+            Some(result) => result,
+            None => return None,
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.input(der);
+
+        let common_name = subject.iter().flat_map(|rdn| rdn.iter()).find(|&&(ref key, _)| key == "CN").map(|&(_, ref value)| value.clone());
+
+                                        //atrybuty jednego RDN łączone plusem (RFC 4514)
+        let subject = subject.iter().map(|rdn| {
+            rdn.iter().map(|&(ref key, ref value)| format!("{}={}", key, escape(value))).collect::<Vec<String>>().join("+")
+        }).collect::<Vec<String>>().join(",");
+
+        Some(ClientCert {
+            subject     : subject,
+            common_name : common_name,
+            sans        : sans,
+            fingerprint : hasher.result_str(),
+        })
+    }
+
+                                        //np. "C=PL,O=Firma+OU=IT,CN=serwis-a" - kolejność jak w certyfikacie
+    pub fn subject(&self) -> &String {
+        &(self.subject)
+    }
+
+    pub fn common_name(&self) -> Option<&String> {
+        self.common_name.as_ref()
+    }
+
+                                        //"DNS:...", "IP:...", "email:...", "URI:..."
+    pub fn sans(&self) -> &Vec<String> {
+        &(self.sans)
+    }
+
+    pub fn dns_names(&self) -> Vec<&str> {
+        self.sans.iter().filter(|san| san.starts_with("DNS:")).map(|san| &san[4..]).collect()
+    }
+
+                                        //SHA-256 z DER, hex małymi literami
+    pub fn fingerprint(&self) -> &String {
+        &(self.fingerprint)
+    }
+}
+
+
+fn escape(value: &str) -> String {
+
+    let mut out = String::with_capacity(value.len());
+
+    for ch in value.chars() {
+        if ch == ',' || ch == '+' || ch == '=' || ch == '\\' || ch == '"' || ch == '<' || ch == '>' || ch == ';' {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+
+    out
+}
+
+
+                                        //(tag, zawartość, reszta)
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+
+    if data.len() < 2 {
+        return None;
+    }
+
+    let tag   = data[0];
+    let first = data[1] as usize;
+
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+
+        let count = first & 0x7f;
+
+        if count == 0 || count > 4 || data.len() < 2 + count {
+            return None;
+        }
+
+        let mut len = 0usize;
+
+        for byte in data[2..2 + count].iter() {
+            len = (len << 8) | (*byte as usize);
+        }
+
+        (len, 2 + count)
+    };
+
+    if len > data.len() - header {
+        return None;
+    }
+
+    Some((tag, &data[header..header + len], &data[header + len..]))
+}
+
+
+fn expect(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+
+    match read_tlv(data) {
+        Some((found, content, rest)) if found == tag => Some((content, rest)),
+        _ => None,
+    }
+}
+
+
+fn oid_to_string(oid: &[u8]) -> String {
+
+    if oid.len() == 0 {
+        return "".to_owned();
+    }
+
+    let mut parts = vec![format!("{}", oid[0] / 40), format!("{}", oid[0] % 40)];
+    let mut value = 0u64;
+
+    for byte in oid[1..].iter() {
+
+        value = (value << 7) | ((*byte & 0x7f) as u64);
+
+        if *byte & 0x80 == 0 {
+            parts.push(format!("{}", value));
+            value = 0;
+        }
+    }
+
+    parts.join(".")
+}
+
+
+fn attribute_name(oid: &[u8]) -> String {
+
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_owned(),
+        [0x55, 0x04, 0x05] => "serialNumber".to_owned(),
+        [0x55, 0x04, 0x06] => "C".to_owned(),
+        [0x55, 0x04, 0x07] => "L".to_owned(),
+        [0x55, 0x04, 0x08] => "ST".to_owned(),
+        [0x55, 0x04, 0x0a] => "O".to_owned(),
+        [0x55, 0x04, 0x0b] => "OU".to_owned(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_owned(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC".to_owned(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID".to_owned(),
+        _ => oid_to_string(oid),
+    }
+}
+
+
+fn string_value(tag: u8, content: &[u8]) -> String {
+
+    match tag {
+
+                                        //BMPString - UTF-16BE
+        0x1e => {
+            let units: Vec<u16> = content.chunks(2).filter(|pair| pair.len() == 2).map(|pair| ((pair[0] as u16) << 8) | pair[1] as u16).collect();
+            String::from_utf16_lossy(&units)
+        },
+
+                                        //T61String - w praktyce latin1
+        0x14 => content.iter().map(|byte| *byte as char).collect(),
+
+        _ => String::from_utf8_lossy(content).into_owned(),
+    }
+}
+
+
+                                        //lista RDN, każdy RDN to jeden lub więcej atrybutów
+fn parse_name(mut data: &[u8]) -> Option<Vec<Vec<(String, String)>>> {
+
+    let mut out = Vec::new();
+
+    while data.len() > 0 {
+
+        let (mut set, rest) = match expect(data, 0x31) {
+            Some(result) => result,
+            None => return None,
+        };
+
+        data = rest;
+
+        let mut rdn = Vec::new();
+
+        while set.len() > 0 {
+
+            let (attribute, rest) = match expect(set, 0x30) {
+                Some(result) => result,
+                None => return None,
+            };
+
+            set = rest;
+
+            let (oid, value) = match expect(attribute, 0x06) {
+                Some(result) => result,
+                None => return None,
+            };
+
+            if let Some((tag, content, _)) = read_tlv(value) {
+                rdn.push((attribute_name(oid), string_value(tag, content)));
+            }
+        }
+
+        out.push(rdn);
+    }
+
+    Some(out)
+}
+
+
+fn ip_to_string(data: &[u8]) -> String {
+
+    if data.len() == 4 {
+        return format!("{}.{}.{}.{}", data[0], data[1], data[2], data[3]);
+    }
+
+    if data.len() == 16 {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(data);
+        return format!("{}", Ipv6Addr::from(octets));
+    }
+
+                                        //np. adres z maską w name constraints - zapis szesnastkowy
+    data.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(":")
+}
+
+
+fn parse_sans(data: &[u8]) -> Vec<String> {
+
+    let mut out = Vec::new();
+
+    let mut names = match expect(data, 0x30) {
+        Some((names, _)) => names,
+        None => return out,
+    };
+
+    while let Some((tag, content, rest)) = read_tlv(names) {
+
+        match tag {
+            0x81 => out.push(format!("email:{}", String::from_utf8_lossy(content))),
+            0x82 => out.push(format!("DNS:{}", String::from_utf8_lossy(content))),
+            0x86 => out.push(format!("URI:{}", String::from_utf8_lossy(content))),
+            0x87 => out.push(format!("IP:{}", ip_to_string(content))),
+            _ => {},
+        }
+
+        names = rest;
+    }
+
+    out
+}
+
+
+fn parse_extensions(data: &[u8]) -> Vec<String> {
+
+    let mut list = match expect(data, 0x30) {
+        Some((list, _)) => list,
+        None => return Vec::new(),
+    };
+
+    while let Some((extension, rest)) = expect(list, 0x30) {
+
+        list = rest;
+
+        let (oid, mut value) = match expect(extension, 0x06) {
+            Some(result) => result,
+            None => continue,
+        };
+
+                                        //pole critical jest opcjonalne
+        if let Some((_, rest)) = expect(value, 0x01) {
+            value = rest;
+        }
+
+                                        //2.5.29.17 - subjectAltName
+        if oid == &[0x55u8, 0x1d, 0x11][..] {
+            if let Some((content, _)) = expect(value, 0x04) {
+                return parse_sans(content);
+            }
+        }
+    }
+
+    Vec::new()
+}
+
+
+fn parse(der: &[u8]) -> Option<(Vec<Vec<(String, String)>>, Vec<String>)> {
+
+    let (certificate, _) = match expect(der, 0x30) {
+        Some(result) => result,
+        None => return None,
+    };
+
+    let (mut tbs, _) = match expect(certificate, 0x30) {
+        Some(result) => result,
+        None => return None,
+    };
+
+                                        //[0] version - opcjonalne
+    if let Some((_, rest)) = expect(tbs, 0xa0) {
+        tbs = rest;
+    }
+
+                                        //serialNumber, signature, issuer, validity
+    for _ in 0..4 {
+        tbs = match read_tlv(tbs) {
+            Some((_, _, rest)) => rest,
+            None => return None,
+        };
+    }
+
+    let (subject, rest) = match expect(tbs, 0x30) {
+        Some(result) => result,
+        None => return None,
+    };
+
+    let subject = match parse_name(subject) {
+        Some(subject) => subject,
+        None => return None,
+    };
+
+                                        //subjectPublicKeyInfo
+    let mut tbs = match read_tlv(rest) {
+        Some((_, _, rest)) => rest,
+        None => return Some((subject, Vec::new())),
+    };
+
+    let mut sans = Vec::new();
+
+    while let Some((tag, content, rest)) = read_tlv(tbs) {
+
+        if tag == 0xa3 {
+            sans = parse_extensions(content);
+        }
+
+        tbs = rest;
+    }
+
+    Some((subject, sans))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{ClientCert, read_tlv, ip_to_string};
+
+                                        //openssl req -x509 -multivalue-rdn -subj "/C=PL/O=Firma+OU=Dział IT/CN=serwis-a", SAN z IPv6
+    const MULTI_RDN : &'static [u8] = include_bytes!("../tests/fixtures/client-multi-rdn.der");
+
+                                        //string_mask = default - O jako T61String, CN jako BMPString
+    const BMP_T61 : &'static [u8] = include_bytes!("../tests/fixtures/client-bmp-t61.der");
+
+    #[test]
+    fn multi_valued_rdn() {
+
+        let cert = ClientCert::from_der(MULTI_RDN).unwrap();
+
+        assert_eq!(cert.subject(), "C=PL,O=Firma+OU=Dział IT,CN=serwis-a");
+        assert_eq!(cert.common_name(), Some(&"serwis-a".to_owned()));
+        assert_eq!(cert.fingerprint(), "94a2428af1e14aa473f2c9f307bbec34827ff7cca6de674a34e3c3652b933ed1");
+    }
+
+    #[test]
+    fn subject_alt_names() {
+
+        let cert = ClientCert::from_der(MULTI_RDN).unwrap();
+
+        assert_eq!(cert.sans(), &vec![
+            "DNS:a.example.com".to_owned(),
+            "IP:10.0.0.1".to_owned(),
+            "IP:2001:db8::1".to_owned(),
+            "email:ops@example.com".to_owned(),
+            "URI:https://example.com/id".to_owned(),
+        ]);
+        assert_eq!(cert.dns_names(), vec!["a.example.com"]);
+    }
+
+    #[test]
+    fn bmp_and_t61_strings() {
+
+        let cert = ClientCert::from_der(BMP_T61).unwrap();
+
+        assert_eq!(cert.subject(), "C=PL,O=Café Ünïcode,CN=Zażółć gęślą");
+        assert_eq!(cert.common_name(), Some(&"Zażółć gęślą".to_owned()));
+        assert_eq!(cert.sans().len(), 0);
+    }
+
+    #[test]
+    fn truncated() {
+        for len in 0..MULTI_RDN.len() {
+            assert!(ClientCert::from_der(&MULTI_RDN[0..len]).is_none(), "prefix of {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(read_tlv(&[0x04, 0x02, 1, 2, 3]), Some((0x04, &[1u8, 2][..], &[3u8][..])));
+        assert_eq!(read_tlv(&[0x04, 0x81, 0x02, 1, 2]), Some((0x04, &[1u8, 2][..], &[][..])));
+        assert_eq!(read_tlv(&[0x04, 0x82, 0x00, 0x01, 7]), Some((0x04, &[7u8][..], &[][..])));
+
+        assert_eq!(read_tlv(&[0x04]), None);
+        assert_eq!(read_tlv(&[0x04, 0x03, 1, 2]), None);
+        assert_eq!(read_tlv(&[0x04, 0x80, 1, 2, 0, 0]), None);                 //długość nieokreślona - nie w DER
+        assert_eq!(read_tlv(&[0x04, 0x82, 0x01]), None);
+        assert_eq!(read_tlv(&[0x04, 0x85, 0, 0, 0, 0, 1, 9]), None);
+        assert_eq!(read_tlv(&[0x04, 0x84, 0xff, 0xff, 0xff, 0xff, 1]), None);
+    }
+
+    #[test]
+    fn ip_addresses() {
+        assert_eq!(ip_to_string(&[192, 168, 0, 1]), "192.168.0.1");
+        assert_eq!(ip_to_string(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]), "::1");
+        assert_eq!(ip_to_string(&[1, 2, 3]), "01:02:03");
+    }
+}
@@ -0,0 +1,274 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use miohttp::{Client, ClientRequest, ClientResponse, Request, Response, Code, Type, MioDown};
+use common::{start, read_head};
+
+
+                                        //Client działający na event_loop-ie serwera miohttp - wyjęty z pierwszego requestu
+fn client() -> (Client, MioDown) {
+
+    let (sender, receiver) = channel();
+    let sender = Mutex::new(sender);
+
+    let (addr, miodown) = start(Box::new(move |request: Request| {
+        let _ = sender.lock().unwrap().send(request.client());
+        request.send(Response::create(Code::Code200, Type::TextPlain, "".to_owned()));
+    }));
+
+    let mut socket = TcpStream::connect(&addr).unwrap();
+    socket.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    (receiver.recv().unwrap(), miodown)
+}
+
+
+fn fetch(client: &Client, request: ClientRequest) -> io::Result<ClientResponse> {
+
+    let (sender, receiver) = channel();
+    let sender = Mutex::new(sender);
+
+    client.send(request, Box::new(move |result: io::Result<ClientResponse>| {
+        let _ = sender.lock().unwrap().send(result);
+    }));
+
+    receiver.recv_timeout(Duration::from_secs(10)).expect("no callback")
+}
+
+
+fn get(client: &Client, url: &str) -> io::Result<ClientResponse> {
+    fetch(client, ClientRequest::new("GET", url).unwrap())
+}
+
+
+fn body(result: io::Result<ClientResponse>) -> String {
+    String::from_utf8(result.unwrap().into_body()).unwrap()
+}
+
+
+fn error(result: io::Result<ClientResponse>) -> io::Error {
+    match result {
+        Ok(response) => panic!("unexpected response {}", response.status()),
+        Err(err) => err,
+    }
+}
+
+
+                                        //serwer odpowiadający według respond(numer połączenia, numer requestu w połączeniu, nagłówki);
+                                        //None - zamknięcie bez odpowiedzi, po odpowiedzi z "Connection: close" połączenie też jest zamykane.
+                                        //Połączenia obsługiwane po kolei; nagłówki requestów trafiają do kanału
+fn upstream<F>(respond: F) -> (SocketAddr, Receiver<(usize, usize, String)>)
+    where F: Fn(usize, usize, &str) -> Option<String> + Send + 'static {
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr     = listener.local_addr().unwrap();
+
+    let (heads_sender, heads) = channel();
+
+    thread::spawn(move || {
+        for (connection, socket) in listener.incoming().enumerate() {
+
+            let mut socket = socket.unwrap();
+
+            for number in 0.. {
+
+                let head = read_head(&mut socket);
+
+                if head.len() == 0 {
+                    break;
+                }
+
+                let _ = heads_sender.send((connection, number, head.clone()));
+
+                match respond(connection, number, &head) {
+                    Some(response) => {
+                        if socket.write_all(response.as_bytes()).is_err() || response.contains("Connection: close\r\n") {
+                            break;
+                        }
+                    },
+                    None => break,
+                }
+            }
+        }
+    });
+
+    (addr, heads)
+}
+
+
+fn ok(body: &str) -> Option<String> {
+    Some(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body))
+}
+
+
+#[test]
+fn body_framing() {
+
+    let (client, _client_down) = client();
+
+    let (addr, _) = upstream(|_, _, head| {
+        if head.starts_with("GET /chunked ") {
+            Some("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n".to_owned())
+        } else if head.starts_with("GET /close ") {
+            Some("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nbody until close".to_owned())
+        } else if head.starts_with("GET /http10 ") {
+            Some("HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nold server".to_owned())
+        } else {
+            ok("length")
+        }
+    });
+
+    assert_eq!(body(get(&client, &format!("http://{}/chunked", addr))), "hello world");
+    assert_eq!(body(get(&client, &format!("http://{}/length", addr))), "length");
+    assert_eq!(body(get(&client, &format!("http://{}/close", addr))), "body until close");
+    assert_eq!(body(get(&client, &format!("http://{}/http10", addr))), "old server");
+}
+
+
+#[test]
+fn truncated_body() {
+
+    let (client, _client_down) = client();
+
+    let (addr, _) = upstream(|_, _, _| {
+        Some("HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nshort".to_owned())
+    });
+
+    let err = error(get(&client, &format!("http://{}/", addr)));
+
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+
+#[test]
+fn pooled_connection_replayed() {
+
+    let (client, _client_down) = client();
+
+                                        //drugi request na połączeniu z puli - serwer zamyka je bez odpowiedzi
+    let (addr, heads) = upstream(|connection, number, _| {
+        match (connection, number) {
+            (0, 0) => ok("first"),
+            (0, _) => None,
+            _ => ok("second"),
+        }
+    });
+
+    assert_eq!(body(get(&client, &format!("http://{}/a", addr))), "first");
+    assert_eq!(body(get(&client, &format!("http://{}/b", addr))), "second");
+
+    let heads: Vec<(usize, usize, String)> = (0..3).map(|_| heads.recv().unwrap()).collect();
+
+    assert_eq!((heads[0].0, heads[0].1), (0, 0));
+    assert!(heads[0].2.starts_with("GET /a "));
+    assert_eq!((heads[1].0, heads[1].1), (0, 1));
+    assert!(heads[1].2.starts_with("GET /b "));
+    assert_eq!((heads[2].0, heads[2].1), (1, 0));
+    assert!(heads[2].2.starts_with("GET /b "));
+}
+
+
+#[test]
+fn non_idempotent_request_is_not_replayed() {
+
+    let (client, _client_down) = client();
+
+    let (addr, heads) = upstream(|connection, number, _| {
+        match (connection, number) {
+            (0, 0) => ok("first"),
+            (0, _) => None,
+            _ => ok("sentinel"),
+        }
+    });
+
+    assert_eq!(body(get(&client, &format!("http://{}/", addr))), "first");
+
+    let post = ClientRequest::new("POST", &format!("http://{}/order", addr)).unwrap().body(b"x".to_vec());
+    assert!(fetch(&client, post).is_err());
+
+                                        //powtórzenie nastąpiłoby przed wywołaniem callbacku, więc kolejne
+                                        //połączenie przyjęte przez serwer musi już być tym z testu
+    let mut sentinel = TcpStream::connect(&addr).unwrap();
+    sentinel.write_all(b"GET /sentinel HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+    let heads: Vec<(usize, usize, String)> = (0..3).map(|_| heads.recv().unwrap()).collect();
+
+    assert!(heads[1].2.starts_with("POST /order "));
+    assert_eq!(heads[2].0, 1);
+    assert!(heads[2].2.starts_with("GET /sentinel "));
+}
+
+
+#[test]
+fn timeout() {
+
+    let (client, _client_down) = client();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr     = listener.local_addr().unwrap();
+
+    let (_hold, release) = channel::<()>();
+
+                                        //połączenie przyjęte, odpowiedź nigdy nie przychodzi - gniazdo otwarte do końca testu
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        read_head(&mut socket);
+        let _ = release.recv();
+    });
+
+    let request = ClientRequest::new("GET", &format!("http://{}/", addr)).unwrap().timeout(300);
+
+    assert_eq!(error(fetch(&client, request)).kind(), io::ErrorKind::TimedOut);
+}
+
+
+#[test]
+fn redirects() {
+
+    let (client, _client_down) = client();
+
+    let (addr, heads) = upstream(|_, _, head| {
+
+        let path = head.split(' ').nth(1).unwrap().to_owned();
+
+        if path == "/start" {
+            Some("HTTP/1.1 301 Moved Permanently\r\nLocation: /end\r\nContent-Length: 0\r\n\r\n".to_owned())
+        } else if path.starts_with("/loop/") {
+            let next = path[6..].parse::<u32>().unwrap() + 1;
+            Some(format!("HTTP/1.1 302 Found\r\nLocation: /loop/{}\r\nContent-Length: 0\r\n\r\n", next))
+        } else {
+            ok("end")
+        }
+    });
+
+    let response = get(&client, &format!("http://{}/start", addr)).unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(*response.url(), format!("http://{}/end", addr));
+    assert_eq!(response.body(), &b"end".to_vec());
+
+                                        //max_redirects(0) - odpowiedź 3xx trafia do callbacku
+    let request  = ClientRequest::new("GET", &format!("http://{}/loop/0", addr)).unwrap().max_redirects(0);
+    let response = fetch(&client, request).unwrap();
+
+    assert_eq!(response.status(), 302);
+    assert_eq!(response.header("Location"), Some(&"/loop/1".to_owned()));
+
+                                        //pierwszy request i dwa przekierowania, trzecie już nie
+    let request = ClientRequest::new("GET", &format!("http://{}/loop/0", addr)).unwrap().max_redirects(2);
+    let err     = error(fetch(&client, request));
+
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+
+    let loops = heads.try_iter().filter(|&(_, _, ref head)| head.starts_with("GET /loop/")).count();
+
+    assert_eq!(loops, 1 + 3);
+}
@@ -0,0 +1,48 @@
+use std::io::Read;
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+use miohttp::{new_server, FnReceiver, MioDown};
+
+
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+
+                                        //serwer miohttp w osobnym wątku; wraca, gdy przyjmuje już połączenia
+pub fn start(fn_receiver: FnReceiver) -> (SocketAddr, MioDown) {
+
+    let addr = free_addr();
+
+    let (miostart, miodown) = new_server(format!("{}", addr), 4000, 4000, None, fn_receiver);
+
+    thread::spawn(move || {
+        miostart.start();
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    while TcpStream::connect(&addr).is_err() {
+        assert!(Instant::now() < deadline, "server not listening on {}", addr);
+        thread::yield_now();
+    }
+
+    (addr, miodown)
+}
+
+
+pub fn read_head(socket: &mut TcpStream) -> String {
+
+    let mut data = Vec::new();
+    let mut buf  = [0u8; 1];
+
+    while data.ends_with(b"\r\n\r\n") == false {
+        match socket.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => data.push(buf[0]),
+        }
+    }
+
+    String::from_utf8_lossy(&data).into_owned()
+}
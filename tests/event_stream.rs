@@ -0,0 +1,85 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use miohttp::{Request, MioDown};
+use common::{start, read_head};
+
+
+                                        //trzy zdarzenia wysłane zaraz po otwarciu strumienia, potem koniec
+fn start_events() -> (SocketAddr, MioDown) {
+
+    start(Box::new(|request: Request| {
+
+        let events = request.event_stream();
+
+        events.data("one");
+        events.event("tick", "two\nlines");
+        events.send(None, "three", Some("3"));
+        events.close();
+    }))
+}
+
+
+const EVENTS : [&'static str; 3] = ["data: one\n\n", "event: tick\ndata: two\ndata: lines\n\n", "data: three\nid: 3\n\n"];
+
+
+fn get(addr: &SocketAddr, version: &str) -> (String, String) {
+
+    let mut socket = TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    socket.write_all(format!("GET /events HTTP/{}\r\nHost: localhost\r\n\r\n", version).as_bytes()).unwrap();
+
+    let head = read_head(&mut socket);
+
+                                        //serwer zamyka połączenie po końcu strumienia
+    let mut body = String::new();
+    socket.read_to_string(&mut body).unwrap();
+
+    (head, body)
+}
+
+
+#[test]
+fn chunked_http11() {
+
+    let (addr, miodown) = start_events();
+
+    let (head, body) = get(&addr, "1.1");
+
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: text/event-stream\r\n"), "{}", head);
+    assert!(head.contains("Cache-Control: no-cache\r\n"), "{}", head);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(head.to_lowercase().contains("content-length") == false, "{}", head);
+
+    let chunks: Vec<String> = EVENTS.iter().map(|event| format!("{:x}\r\n{}\r\n", event.len(), event)).collect();
+
+    assert_eq!(body, chunks.concat() + "0\r\n\r\n");
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn raw_http10() {
+
+    let (addr, miodown) = start_events();
+
+    let (head, body) = get(&addr, "1.0");
+
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: text/event-stream\r\n"), "{}", head);
+    assert!(head.contains("Connection: close\r\n"), "{}", head);
+    assert!(head.to_lowercase().contains("transfer-encoding") == false, "{}", head);
+    assert!(head.to_lowercase().contains("content-length") == false, "{}", head);
+
+                                        //bez ramek chunked - koniec ciała to zamknięcie połączenia
+    assert_eq!(body, EVENTS.concat());
+
+    miodown.shoutdown();
+}
@@ -0,0 +1,92 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use miohttp::{Request, Response, Form, Code, Type, MioDown};
+use common::{start, read_head};
+
+
+                                        //odsyła pola formularza posortowane po nazwie, limit ciała 64 bajty
+fn start_form() -> (SocketAddr, MioDown) {
+
+    start(Box::new(|request: Request| {
+
+        request.get_form(64, Box::new(|request: Request, form: Form| {
+
+            let mut names = form.names();
+            names.sort();
+
+            let lines: Vec<String> = names.iter().map(|name| format!("{}={}\n", name, form.get_all(name).join(","))).collect();
+
+            request.send(Response::create(Code::Code200, Type::TextPlain, lines.concat()));
+        }));
+    }))
+}
+
+
+fn post(addr: &SocketAddr, content_type: &str, body: &[u8]) -> (String, String) {
+
+    let mut socket = TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    let head = format!("POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", content_type, body.len());
+
+    socket.write_all(head.as_bytes()).unwrap();
+    socket.write_all(body).unwrap();
+
+    let head = read_head(&mut socket);
+
+    let len = head.lines()
+        .find(|line| line.to_lowercase().starts_with("content-length:"))
+        .map(|line| line[15..].trim().parse().unwrap())
+        .unwrap_or(0);
+
+    let mut body = vec![0u8; len];
+    socket.read_exact(&mut body).unwrap();
+
+    (head.lines().next().unwrap_or("").to_owned(), String::from_utf8(body).unwrap())
+}
+
+
+const URLENCODED : &'static str = "application/x-www-form-urlencoded";
+
+
+#[test]
+fn fields() {
+
+    let (addr, miodown) = start_form();
+
+    let (status, body) = post(&addr, URLENCODED, b"tag=a&name=J%C3%B3zef+Nowak&tag=b%2Bc&flag");
+
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, "flag=\nname=Józef Nowak\ntag=a,b+c\n");
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn empty_body() {
+
+    let (addr, miodown) = start_form();
+
+    assert_eq!(post(&addr, URLENCODED, b""), ("HTTP/1.1 200 OK".to_owned(), "".to_owned()));
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn rejected_bodies() {
+
+    let (addr, miodown) = start_form();
+
+    assert_eq!(post(&addr, "text/plain", b"a=1").0, "HTTP/1.1 415 Unsupported Media Type");
+    assert_eq!(post(&addr, URLENCODED, &[b'a'; 65]).0, "HTTP/1.1 413 Payload Too Large");
+    assert_eq!(post(&addr, URLENCODED, b"a=%FF").0, "HTTP/1.1 400 Bad Request");
+
+    miodown.shoutdown();
+}
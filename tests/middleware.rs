@@ -0,0 +1,130 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use miohttp::{Request, Response, Code, Type, Middleware, Chain, MioDown};
+use common::{start, read_head};
+
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+
+                                        //zapisuje wywołania; stop - before odpowiada sam na ścieżkę /stop
+struct Trace {
+    name : &'static str,
+    stop : bool,
+    log  : Log,
+}
+
+
+impl Middleware for Trace {
+
+    fn before(&self, request: &mut Request) -> Option<Response> {
+
+        self.log.lock().unwrap().push(format!("before {}", self.name));
+
+        if self.stop && request.path() == "/stop" {
+            return Some(Response::create(Code::Code403, Type::TextPlain, format!("stopped by {}", self.name)));
+        }
+
+        None
+    }
+
+    fn after(&self, _request: &Request, response: &mut Response) {
+        self.log.lock().unwrap().push(format!("after {}", self.name));
+        response.add_header(&format!("X-After-{}", self.name), "1");
+    }
+}
+
+
+fn start_chain() -> (SocketAddr, MioDown, Log) {
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let trace = |name: &'static str, stop: bool| Trace {
+        name : name,
+        stop : stop,
+        log  : log.clone(),
+    };
+
+    let chain = Chain::new().with(trace("a", false)).with(trace("b", true)).with(trace("c", false));
+
+    let log_handler = log.clone();
+
+    let (addr, miodown) = start(chain.wrap(Box::new(move |request: Request| {
+        log_handler.lock().unwrap().push("handler".to_owned());
+        request.send(Response::create(Code::Code200, Type::TextPlain, "handler".to_owned()));
+    })));
+
+    (addr, miodown, log)
+}
+
+
+fn get(addr: &SocketAddr, path: &str) -> (String, String) {
+
+    let mut socket = TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    socket.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).unwrap();
+
+    let head = read_head(&mut socket);
+
+    let len = head.lines()
+        .find(|line| line.to_lowercase().starts_with("content-length:"))
+        .map(|line| line[15..].trim().parse().unwrap())
+        .unwrap_or(0);
+
+    let mut body = vec![0u8; len];
+    socket.read_exact(&mut body).unwrap();
+
+    (head, String::from_utf8(body).unwrap())
+}
+
+
+fn entries(log: &Log) -> Vec<String> {
+    log.lock().unwrap().clone()
+}
+
+
+#[test]
+fn order() {
+
+    let (addr, miodown, log) = start_chain();
+
+    let (head, body) = get(&addr, "/page");
+
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+    assert_eq!(body, "handler");
+
+                                        //before od pierwszego do ostatniego, after w odwrotnej kolejności
+    assert_eq!(entries(&log), vec!["before a", "before b", "before c", "handler", "after c", "after b", "after a"]);
+
+    assert!(head.contains("X-After-a: 1\r\n"), "{}", head);
+    assert!(head.contains("X-After-c: 1\r\n"), "{}", head);
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn before_short_circuits() {
+
+    let (addr, miodown, log) = start_chain();
+
+    let (head, body) = get(&addr, "/stop");
+
+    assert!(head.starts_with("HTTP/1.1 403 "), "{}", head);
+    assert_eq!(body, "stopped by b");
+
+                                        //ani handler, ani kolejne middleware; after tylko dla wcześniejszych
+    assert_eq!(entries(&log), vec!["before a", "before b", "after a"]);
+
+    assert!(head.contains("X-After-a: 1\r\n"), "{}", head);
+    assert!(head.contains("X-After-b") == false, "{}", head);
+
+    miodown.shoutdown();
+}
@@ -0,0 +1,385 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr, IpAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use miohttp::{ProxyHandler, Balance, Request, Response, Code, Type, MioDown};
+use common::{free_addr, start, read_head};
+
+
+fn start_proxy(upstreams: Vec<SocketAddr>, balance: Balance) -> (SocketAddr, MioDown) {
+    start(ProxyHandler::new(upstreams).balance(balance).timeout(500).build())
+}
+
+
+                                        //serwer miohttp odsyłający nazwę, request i posortowane nagłówki; zapisuje adresy połączeń
+fn echo_upstream(name: &'static str) -> (SocketAddr, MioDown, Arc<Mutex<Vec<SocketAddr>>>) {
+
+    let peers = Arc::new(Mutex::new(Vec::new()));
+    let peers_receiver = peers.clone();
+
+    let (addr, miodown) = start(Box::new(move |request: Request| {
+
+        if let Some(remote_addr) = request.remote_addr() {
+            peers_receiver.lock().unwrap().push(remote_addr);
+        }
+
+        let mut headers: Vec<String> = request.headers().iter().map(|&(name, value)| format!("{}: {}\n", name.to_lowercase(), value)).collect();
+        headers.sort();
+
+        let text = format!("{} {} {}\n{}", name, request.method(), request.raw_target(), headers.concat());
+
+        request.send(Response::create(Code::Code200, Type::TextPlain, text));
+    }));
+
+    (addr, miodown, peers)
+}
+
+
+                                        //serwer odpowiadający gotowymi bajtami, po jednym połączeniu na odpowiedź
+fn raw_upstream(responses: Vec<&'static [u8]>) -> SocketAddr {
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr     = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for response in responses {
+            let (mut socket, _) = listener.accept().unwrap();
+            read_head(&mut socket);
+            socket.write_all(response).unwrap();
+        }
+    });
+
+    addr
+}
+
+
+                                        //request z Connection: close - odpowiedź czytana do zamknięcia połączenia; (nagłówki, ciało)
+fn send(addr: &SocketAddr, request: &str) -> (String, Vec<u8>) {
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+
+    let pos  = data.windows(4).position(|window| window == b"\r\n\r\n").expect("no response head");
+    let head = String::from_utf8_lossy(&data[0..pos]).into_owned();
+    let body = data[pos + 4..].to_vec();
+
+    if head.to_lowercase().contains("transfer-encoding: chunked") {
+        (head, dechunk(&body))
+    } else {
+        (head, body)
+    }
+}
+
+
+fn get(addr: &SocketAddr, path: &str) -> (String, String) {
+
+    let (head, body) = send(addr, &format!("GET {} HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n", path));
+
+    (head, String::from_utf8(body).unwrap())
+}
+
+
+fn dechunk(mut data: &[u8]) -> Vec<u8> {
+
+    let mut out = Vec::new();
+
+    loop {
+        let pos  = data.windows(2).position(|window| window == b"\r\n").expect("no chunk size");
+        let line = String::from_utf8_lossy(&data[0..pos]).into_owned();
+        let size = usize::from_str_radix(line.split(';').next().unwrap().trim(), 16).unwrap();
+
+        data = &data[pos + 2..];
+
+        if size == 0 {
+            return out;
+        }
+
+        out.extend_from_slice(&data[0..size]);
+        data = &data[size + 2..];
+    }
+}
+
+
+fn status(head: &str) -> &str {
+    &head[9..12]
+}
+
+
+#[test]
+fn request_headers() {
+
+    let (upstream, _upstream_down, _) = echo_upstream("a");
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let request = "GET /echo?x=1 HTTP/1.1\r\n\
+                   Host: example.com\r\n\
+                   Connection: close, X-Secret\r\n\
+                   X-Secret: 1\r\n\
+                   Keep-Alive: timeout=5\r\n\
+                   Proxy-Authorization: Basic eDp5\r\n\
+                   X-Forwarded-For: 10.0.0.1\r\n\
+                   X-Forwarded-Host: spoofed.example\r\n\
+                   Forwarded: for=10.0.0.1\r\n\
+                   X-Keep: yes\r\n\r\n";
+
+    let (head, body) = send(&proxy, request);
+    let body = String::from_utf8(body).unwrap();
+
+    assert_eq!(status(&head), "200");
+    assert!(body.starts_with("a GET /echo?x=1\n"));
+    assert!(body.contains("x-keep: yes\n"));
+    assert!(body.contains("host: example.com\n"));
+                                        //klient nie jest zaufanym proxy - jego łańcuch jest odrzucany
+    assert!(body.contains("x-forwarded-for: 127.0.0.1\n"));
+    assert!(body.contains("x-forwarded-host: example.com\n"));
+    assert!(body.contains("x-forwarded-proto: http\n"));
+    assert!(body.contains("forwarded: for=127.0.0.1;host=\"example.com\";proto=http\n"));
+
+    for name in vec!["x-secret", "keep-alive", "proxy-authorization"] {
+        assert!(body.contains(&format!("{}:", name)) == false, "{} forwarded", name);
+    }
+}
+
+
+#[test]
+fn trusted_proxy_chain() {
+
+    let (upstream, _upstream_down, _) = echo_upstream("a");
+
+    let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+    let (proxy, _proxy_down) = start(ProxyHandler::new(vec![upstream]).trusted_proxies(vec![localhost]).build());
+
+    let request = "GET / HTTP/1.1\r\n\
+                   Host: example.com\r\n\
+                   Connection: close\r\n\
+                   X-Forwarded-For: 10.0.0.1\r\n\
+                   Forwarded: for=10.0.0.1\r\n\r\n";
+
+    let (head, body) = send(&proxy, request);
+    let body = String::from_utf8(body).unwrap();
+
+    assert_eq!(status(&head), "200");
+    assert!(body.contains("x-forwarded-for: 10.0.0.1, 127.0.0.1\n"));
+    assert!(body.contains("forwarded: for=10.0.0.1, for=127.0.0.1;host=\"example.com\";proto=http\n"));
+}
+
+
+#[test]
+fn response_headers() {
+
+    let upstream = raw_upstream(vec![
+        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close, X-Internal\r\nX-Internal: 1\r\nKeep-Alive: timeout=5\r\nX-Public: 1\r\n\r\nok",
+    ]);
+
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, body) = get(&proxy, "/");
+    let head = head.to_lowercase();
+
+    assert_eq!(status(&head), "200");
+    assert_eq!(body, "ok");
+    assert!(head.contains("x-public: 1"));
+    assert!(head.contains("x-internal") == false);
+    assert!(head.contains("keep-alive: timeout") == false);
+}
+
+
+#[test]
+fn keep_alive_reuse() {
+
+    let (upstream, _upstream_down, peers) = echo_upstream("a");
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    for _ in 0..3 {
+        let (head, _) = get(&proxy, "/");
+        assert_eq!(status(&head), "200");
+    }
+
+                                        //wszystkie requesty przeszły jednym połączeniem z puli
+    let peers = peers.lock().unwrap();
+
+    assert_eq!(peers.len(), 3);
+    assert!(peers.iter().all(|peer| *peer == peers[0]));
+}
+
+
+#[test]
+fn chunked_and_until_close_bodies() {
+
+    let upstream = raw_upstream(vec![
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nbody until close",
+    ]);
+
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, body) = get(&proxy, "/chunked");
+
+    assert_eq!(status(&head), "200");
+    assert_eq!(body, "hello world");
+
+    let (head, body) = get(&proxy, "/close");
+
+    assert_eq!(status(&head), "200");
+    assert!(head.to_lowercase().contains("transfer-encoding: chunked"));
+    assert_eq!(body, "body until close");
+
+                                        //HTTP/1.0 nie zna chunked - ciało do zamknięcia połączenia
+    let upstream = raw_upstream(vec![
+        b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nbody until close",
+    ]);
+
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, body) = send(&proxy, "GET / HTTP/1.0\r\nHost: example.com\r\n\r\n");
+
+    assert_eq!(status(&head), "200");
+    assert!(head.to_lowercase().contains("transfer-encoding") == false);
+    assert_eq!(body, b"body until close".to_vec());
+}
+
+
+#[test]
+fn non_idempotent_request_is_not_replayed() {
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+
+    let (heads_upstream, heads) = channel();
+
+                                        //pierwszy request dostaje odpowiedź, drugi na tym samym połączeniu - zamknięcie bez odpowiedzi
+    thread::spawn(move || {
+        for socket in listener.incoming() {
+
+            let mut socket = socket.unwrap();
+
+            heads_upstream.send(read_head(&mut socket)).unwrap();
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+
+            let next = read_head(&mut socket);
+
+            if next.len() > 0 {
+                heads_upstream.send(next).unwrap();
+            }
+        }
+    });
+
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, _) = get(&proxy, "/");
+    assert_eq!(status(&head), "200");
+
+    let (head, _) = send(&proxy, "POST /order HTTP/1.1\r\nHost: example.com\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    assert_eq!(status(&head), "502");
+
+                                        //powtórzenie nastąpiłoby przed odpowiedzią 502, więc kolejne połączenie
+                                        //przyjęte przez serwer docelowy musi już być tym z testu
+    let mut sentinel = TcpStream::connect(&upstream).unwrap();
+    sentinel.write_all(b"GET /sentinel HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+
+    let heads: Vec<String> = (0..3).map(|_| heads.recv().unwrap()).collect();
+
+    assert!(heads[0].starts_with("GET / "));
+    assert!(heads[1].starts_with("POST /order "));
+    assert!(heads[2].starts_with("GET /sentinel "));
+}
+
+
+#[test]
+fn bad_gateway() {
+
+    let (proxy, _proxy_down) = start_proxy(vec![free_addr()], Balance::RoundRobin);
+
+    let (head, _) = get(&proxy, "/");
+    assert_eq!(status(&head), "502");
+
+    let upstream = raw_upstream(vec![b"garbage\r\n\r\n"]);
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, _) = get(&proxy, "/");
+    assert_eq!(status(&head), "502");
+}
+
+
+#[test]
+fn gateway_timeout() {
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+
+    let (_hold, release) = channel::<()>();
+
+                                        //połączenie przyjęte, odpowiedź nigdy nie przychodzi - gniazdo otwarte do końca testu
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        read_head(&mut socket);
+        let _ = release.recv();
+    });
+
+    let (proxy, _proxy_down) = start_proxy(vec![upstream], Balance::RoundRobin);
+
+    let (head, _) = get(&proxy, "/");
+    assert_eq!(status(&head), "504");
+}
+
+
+#[test]
+fn round_robin() {
+
+    let (first, _first_down, _) = echo_upstream("a");
+    let (second, _second_down, _) = echo_upstream("b");
+    let (proxy, _proxy_down) = start_proxy(vec![first, second], Balance::RoundRobin);
+
+    let names: Vec<String> = (0..4).map(|_| get(&proxy, "/").1[0..1].to_owned()).collect();
+
+    assert_eq!(names, vec!["a", "b", "a", "b"]);
+}
+
+
+#[test]
+fn least_connections() {
+
+    let (started_upstream, started) = channel();
+    let (release, released) = channel::<()>();
+
+    let started_upstream = Mutex::new(started_upstream);
+    let released         = Arc::new(Mutex::new(released));
+
+                                        //"a" odpowiada dopiero po zwolnieniu przez test, więc do tego czasu ma jedną trwającą wymianę
+    let (slow, _slow_down) = start(Box::new(move |request: Request| {
+
+        started_upstream.lock().unwrap().send(()).unwrap();
+
+        let released = released.clone();
+
+        thread::spawn(move || {
+            let _ = released.lock().unwrap().recv();
+            request.send(Response::create(Code::Code200, Type::TextPlain, "a".to_owned()));
+        });
+    }));
+
+    let (fast, _fast_down, _) = echo_upstream("b");
+    let (proxy, _proxy_down) = start(ProxyHandler::new(vec![slow, fast]).balance(Balance::LeastConnections).build());
+
+    let slow_request = thread::spawn(move || get(&proxy, "/").1);
+
+    started.recv().unwrap();
+
+    let names: Vec<String> = (0..2).map(|_| get(&proxy, "/").1[0..1].to_owned()).collect();
+
+    release.send(()).unwrap();
+
+    assert_eq!(names, vec!["b", "b"]);
+    assert_eq!(slow_request.join().unwrap(), "a");
+}
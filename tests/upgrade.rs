@@ -0,0 +1,171 @@
+extern crate miohttp;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::Duration;
+use miohttp::{Request, Response, Code, Type, Upgraded, UpgradeHandler, MioDown};
+use common::{start, read_head};
+
+
+                                        //odsyła dane dużymi literami; "quit" zamyka połączenie, koniec strony klienta - "bye" i zamknięcie
+struct Echo {
+    events : Sender<String>,
+}
+
+
+impl UpgradeHandler for Echo {
+
+    fn on_data(&mut self, socket: &Upgraded, data: &[u8]) {
+
+        let text = String::from_utf8_lossy(data).into_owned();
+
+        if text == "quit" {
+            socket.send(b"closing".to_vec());
+            socket.close();
+        } else {
+            socket.send(text.to_uppercase().into_bytes());
+        }
+    }
+
+    fn on_end(&mut self, socket: &Upgraded) {
+        socket.send(b"bye".to_vec());
+        socket.close();
+    }
+
+    fn on_close(&mut self) {
+        let _ = self.events.send("close".to_owned());
+    }
+}
+
+
+fn start_echo() -> (SocketAddr, MioDown, Receiver<String>) {
+
+    let (events, receiver) = channel();
+    let events = Mutex::new(events);
+
+    let (addr, miodown) = start(Box::new(move |request: Request| {
+
+        let mut response = Response::create(Code::Code101, Type::TextPlain, "".to_owned());
+        response.add_header("Upgrade", "echo");
+        response.add_header("Connection", "Upgrade");
+
+        let handler = Echo {
+            events : events.lock().unwrap().clone(),
+        };
+
+        request.upgrade(response, Box::new(handler));
+    }));
+
+    (addr, miodown, receiver)
+}
+
+
+fn connect(addr: &SocketAddr, extra: &[u8]) -> TcpStream {
+
+    let mut socket = TcpStream::connect(addr).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    socket.write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n").unwrap();
+    socket.write_all(extra).unwrap();
+
+    socket
+}
+
+
+fn read_exact(socket: &mut TcpStream, len: usize) -> String {
+
+    let mut data = vec![0u8; len];
+    socket.read_exact(&mut data).unwrap();
+
+    String::from_utf8(data).unwrap()
+}
+
+
+fn read_to_end(socket: &mut TcpStream) -> String {
+
+    let mut data = String::new();
+    socket.read_to_string(&mut data).unwrap();
+
+    data
+}
+
+
+#[test]
+fn handshake() {
+
+    let (addr, miodown, _) = start_echo();
+    let mut socket = connect(&addr, b"");
+
+    let head = read_head(&mut socket);
+
+    assert!(head.starts_with("HTTP/1.1 101 "), "{}", head);
+    assert!(head.contains("Upgrade: echo\r\n"), "{}", head);
+    assert!(head.contains("Connection: Upgrade\r\n"), "{}", head);
+    assert!(head.to_lowercase().contains("content-length") == false, "{}", head);
+    assert!(head.to_lowercase().contains("transfer-encoding") == false, "{}", head);
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn bidirectional_data() {
+
+    let (addr, miodown, _) = start_echo();
+
+                                        //bajty wysłane razem z requestem trafiają do pierwszego on_data
+    let mut socket = connect(&addr, b"early");
+
+    read_head(&mut socket);
+    assert_eq!(read_exact(&mut socket, 5), "EARLY");
+
+    for word in &["one", "two", "three"] {
+        socket.write_all(word.as_bytes()).unwrap();
+        assert_eq!(read_exact(&mut socket, word.len()), word.to_uppercase());
+    }
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn half_close() {
+
+    let (addr, miodown, events) = start_echo();
+    let mut socket = connect(&addr, b"");
+
+    read_head(&mut socket);
+
+    socket.write_all(b"last").unwrap();
+    assert_eq!(read_exact(&mut socket, 4), "LAST");
+
+                                        //handler nadal może pisać po zamknięciu strony klienta
+    socket.shutdown(Shutdown::Write).unwrap();
+
+    assert_eq!(read_to_end(&mut socket), "bye");
+    assert_eq!(events.recv_timeout(Duration::from_secs(10)).unwrap(), "close");
+
+    miodown.shoutdown();
+}
+
+
+#[test]
+fn handler_close() {
+
+    let (addr, miodown, events) = start_echo();
+    let mut socket = connect(&addr, b"");
+
+    read_head(&mut socket);
+
+    socket.write_all(b"quit").unwrap();
+
+                                        //dane z kolejki są wysyłane przed zamknięciem
+    assert_eq!(read_to_end(&mut socket), "closing");
+    assert_eq!(events.recv_timeout(Duration::from_secs(10)).unwrap(), "close");
+
+    miodown.shoutdown();
+}